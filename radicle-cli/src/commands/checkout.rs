@@ -4,8 +4,11 @@ use std::path::PathBuf;
 use anyhow::anyhow;
 use anyhow::Context as _;
 
+use radicle::cob::patch::{self, PatchId};
+use radicle::git;
 use radicle::prelude::*;
 use radicle::storage::git::transport;
+use radicle::storage::git::Repository;
 use radicle::storage::RemoteId;
 
 use crate::project;
@@ -24,6 +27,7 @@ Usage
 Options
 
     --remote <id>   Remote namespace to checkout
+    --patch <id>    Checkout the patch's proposed commit, in detached HEAD mode
     --no-confirm    Don't ask for confirmation during checkout
     --help          Print help
 "#,
@@ -32,6 +36,7 @@ Options
 pub struct Options {
     pub id: Id,
     pub remote: Option<RemoteId>,
+    pub patch: Option<PatchId>,
 }
 
 impl Args for Options {
@@ -42,6 +47,7 @@ impl Args for Options {
         let mut parser = lexopt::Parser::from_args(args);
         let mut id = None;
         let mut remote = None;
+        let mut patch = None;
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -59,6 +65,10 @@ impl Args for Options {
                         return Err(anyhow!("invalid Node ID '{}'", val));
                     }
                 }
+                Long("patch") => {
+                    let val = parser.value()?;
+                    patch = Some(term::cob::parse_patch_id(val)?);
+                }
                 Value(val) if id.is_none() => {
                     id = Some(term::args::rid(&val)?);
                 }
@@ -70,6 +80,7 @@ impl Args for Options {
             Options {
                 id: id.ok_or_else(|| anyhow!("a project id to checkout must be provided"))?,
                 remote,
+                patch,
             },
             vec![],
         ))
@@ -123,6 +134,16 @@ pub fn execute(options: Options, profile: &Profile) -> anyhow::Result<PathBuf> {
     };
     spinner.finish();
 
+    if let Some(patch_id) = options.patch {
+        let head = checkout_patch(&repo, &storage.repository(id)?, profile, &patch_id)?;
+
+        term::success!(
+            "Switched to patch {} at {} (detached)",
+            term::format::tertiary(term::format::cob(&patch_id)),
+            term::format::secondary(term::format::oid(head)),
+        );
+    }
+
     let remotes = doc
         .delegates
         .into_iter()
@@ -145,6 +166,34 @@ pub fn execute(options: Options, profile: &Profile) -> anyhow::Result<PathBuf> {
     Ok(path)
 }
 
+/// Checkout the head of the given patch in detached HEAD mode.
+/// If the patch commit isn't part of the working copy, it is fetched from the patch author.
+pub fn checkout_patch(
+    repo: &git::raw::Repository,
+    storage: &Repository,
+    profile: &Profile,
+    patch_id: &PatchId,
+) -> anyhow::Result<git::Oid> {
+    let patches = patch::Patches::open(*profile.id(), storage)?;
+    let patch = patches
+        .get(patch_id)?
+        .ok_or_else(|| anyhow!("patch `{patch_id}` not found"))?;
+    let head = *patch.head();
+
+    if repo.find_commit(head.into()).is_err() {
+        let url = git::Url::from(storage.id).with_namespace(*patch.author().id);
+
+        repo.remote_anonymous(&url.to_string())?
+            .fetch(&["refs/heads/*"], None, None)?;
+    }
+    let commit = repo.find_commit(head.into())?;
+
+    repo.checkout_tree(commit.as_object(), None)?;
+    repo.set_head_detached(commit.id())?;
+
+    Ok(head)
+}
+
 /// Setup a remote and tracking branch for each given remote.
 pub fn setup_remotes(setup: project::SetupRemote, remotes: &[NodeId]) -> anyhow::Result<()> {
     for remote_id in remotes {
@@ -1,7 +1,8 @@
 #![allow(clippy::or_fun_call)]
 use std::ffi::OsString;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::{fs, io};
 
 use anyhow::anyhow;
 use thiserror::Error;
@@ -10,8 +11,9 @@ use radicle::git::raw;
 use radicle::identity::doc;
 use radicle::identity::doc::{DocError, Id};
 use radicle::node;
-use radicle::node::{FetchResult, Handle as _, Node};
+use radicle::node::{FetchResult, Handle as _, Node, RemoteTracking};
 use radicle::prelude::*;
+use radicle::profile::Home;
 use radicle::rad;
 use radicle::storage;
 use radicle::storage::git::{ProjectError, Storage};
@@ -33,9 +35,17 @@ Usage
 
 Options
 
-    --no-announce   Do not announce our new refs to the network
-    --no-confirm    Don't ask for confirmation during clone
-    --help          Print help
+    --seed <nid@addr>           Fetch from the given seed first (may be specified multiple times)
+    --track-remotes <policy>    Set which remotes of the project are tracked, once it is cloned
+    --no-announce               Do not announce our new refs to the network
+    --no-confirm                Don't ask for confirmation during clone
+    --help                      Print help
+
+Remote tracking policies
+
+    delegates-only              Only track the remotes of the project delegates
+    all                         Track all remotes
+    <nid>[,<nid>...]            Track the remotes of the project delegates and of the given nodes
 
 "#,
 };
@@ -46,6 +56,8 @@ pub struct Options {
     #[allow(dead_code)]
     interactive: Interactive,
     announce: bool,
+    seeds: Vec<(NodeId, node::Address)>,
+    track_remotes: Option<RemoteTracking>,
 }
 
 impl Args for Options {
@@ -56,6 +68,8 @@ impl Args for Options {
         let mut id: Option<Id> = None;
         let mut interactive = Interactive::Yes;
         let mut announce = true;
+        let mut seeds = Vec::new();
+        let mut track_remotes = None;
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -68,6 +82,23 @@ impl Args for Options {
                 Long("announce") => {
                     announce = true;
                 }
+                Long("seed") => {
+                    let val = parser.value()?;
+                    let val = val.to_string_lossy();
+                    let (nid, addr) = val
+                        .split_once('@')
+                        .ok_or_else(|| anyhow!("invalid seed `{val}`, expected <nid>@<addr>"))?;
+
+                    seeds.push((NodeId::from_str(nid)?, node::Address::from_str(addr)?));
+                }
+                Long("track-remotes") => {
+                    let val = parser.value()?;
+                    let val = val.to_string_lossy();
+                    let policy = RemoteTracking::from_str(&val)
+                        .map_err(|_| anyhow!("invalid remote tracking policy `{val}`"))?;
+
+                    track_remotes = Some(policy);
+                }
                 Long("help") => {
                     return Err(Error::Help.into());
                 }
@@ -90,6 +121,8 @@ impl Args for Options {
                 id,
                 interactive,
                 announce,
+                seeds,
+                track_remotes,
             },
             vec![],
         ))
@@ -103,8 +136,10 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     let (working, doc, proj) = clone(
         options.id,
         &signer,
+        &profile.home,
         &profile.storage,
         &mut node,
+        &options.seeds,
         options.announce,
     )?;
     let delegates = doc
@@ -128,6 +163,17 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
         &delegates,
     )?;
 
+    // Only set the remote tracking policy once the clone succeeded, so that a failed clone
+    // doesn't leave it behind.
+    if let Some(policy) = options.track_remotes {
+        node.set_remote_tracking(options.id, policy.clone())?;
+        term::success!(
+            "Remote tracking policy for {} set to {}",
+            term::format::tertiary(options.id),
+            term::format::tertiary(policy)
+        );
+    }
+
     term::headline(&format!(
         "🌱 Project successfully cloned under {}",
         term::format::highlight(Path::new(".").join(path).display())
@@ -154,13 +200,23 @@ pub enum CloneError {
     Project(#[from] ProjectError),
     #[error("no seeds found for {0}")]
     NotFound(Id),
+    #[error(
+        "the interrupted clone of {0} can't be resumed, as its repository is inconsistent: {1}"
+    )]
+    Inconsistent(Id, String),
+    #[error("checkout in {0} is incomplete; remove it to start over")]
+    IncompleteCheckout(PathBuf),
+    #[error("i/o: {0}")]
+    Io(#[from] io::Error),
 }
 
 pub fn clone<G: Signer>(
     id: Id,
     signer: &G,
+    home: &Home,
     storage: &Storage,
     node: &mut Node,
+    hints: &[(NodeId, node::Address)],
     announce: bool,
 ) -> Result<(raw::Repository, Doc<Verified>, Project), CloneError> {
     let me = *signer.public_key();
@@ -173,9 +229,61 @@ pub fn clone<G: Signer>(
         );
     }
 
+    // Check for state left behind by an interrupted clone. Otherwise, mark the clone as
+    // started, so that it can be resumed if it is interrupted.
+    let marker = marker(home, &id);
+    let stored = storage::git::paths::repository(storage, &id);
+    let mut exists = stored.exists();
+    if marker.exists() && exists {
+        // An inconsistent repository left behind by an interrupted clone can't be resumed
+        // from. Since the clone never completed, it's removed, and we clone from scratch.
+        if let Err(e) = verify(id, storage) {
+            term::warning(&format!("{e}; starting over"));
+
+            fs::remove_dir_all(&stored)?;
+            fs::remove_file(&marker)?;
+            exists = false;
+        }
+    }
+    if marker.exists() {
+        term::success!(
+            "Resuming interrupted clone of {}",
+            term::format::tertiary(id)
+        );
+    } else {
+        if let Some(dir) = marker.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&marker, [])?;
+    }
+
+    // Fetch from the seeds we were given first. These don't need to be in our routing table.
+    for (seed, addr) in hints {
+        let spinner = term::spinner(format!(
+            "Fetching {} from {}@{}..",
+            term::format::tertiary(id),
+            term::format::tertiary(term::format::node(seed)),
+            addr
+        ));
+
+        match node.fetch_hinted(id, *seed, addr.clone())? {
+            FetchResult::Success { .. } => {
+                spinner.finish();
+            }
+            FetchResult::Failed { reason } => {
+                spinner.error(reason);
+            }
+        }
+    }
+
     // Get seeds. This consults the local routing table only.
-    let seeds = node.seeds(id)?;
-    if seeds.is_empty() {
+    let seeds = node
+        .seeds(id)?
+        .into_iter()
+        .filter(|seed| !hints.iter().any(|(hint, _)| hint == seed))
+        .collect::<Vec<_>>();
+    if seeds.is_empty() && hints.is_empty() && !exists {
+        fs::remove_file(&marker)?;
         return Err(CloneError::NotFound(id));
     }
     // Fetch from all seeds.
@@ -200,11 +308,20 @@ pub fn clone<G: Signer>(
 
     // Create a local fork of the project, under our own id.
     {
-        let mut spinner = term::spinner(format!(
-            "Forking under {}..",
-            term::format::tertiary(term::format::node(&me))
-        ));
-        rad::fork(id, signer, &storage)?;
+        // Never overwrite an existing fork, which may hold refs that weren't announced yet.
+        let mut spinner = if storage.repository(id)?.remote(&me).is_ok() {
+            term::spinner(format!(
+                "Using existing fork under {}..",
+                term::format::tertiary(term::format::node(&me))
+            ))
+        } else {
+            let spinner = term::spinner(format!(
+                "Forking under {}..",
+                term::format::tertiary(term::format::node(&me))
+            ));
+            rad::fork(id, signer, &storage)?;
+            spinner
+        };
 
         if announce {
             if let Err(e) = node.announce_refs(id) {
@@ -222,14 +339,50 @@ pub fn clone<G: Signer>(
     let proj = doc.project()?;
     let path = Path::new(proj.name());
 
-    // Checkout.
-    let spinner = term::spinner(format!(
-        "Creating checkout in ./{}..",
-        term::format::tertiary(path.display())
-    ));
-    let repo = rad::checkout(id, &me, path, &storage)?;
+    // Checkout. If a previous clone was interrupted after the checkout was fully created,
+    // we re-use it. An incomplete checkout is never removed, as it may hold the user's work.
+    let repo = match rad::repo(path) {
+        Ok((repo, rid)) if rid == id && repo.head().is_ok() => {
+            term::success!(
+                "Using existing checkout in ./{}",
+                term::format::tertiary(path.display())
+            );
+            repo
+        }
+        Ok((_, rid)) if rid == id => {
+            return Err(CloneError::IncompleteCheckout(path.to_path_buf()));
+        }
+        _ => {
+            let spinner = term::spinner(format!(
+                "Creating checkout in ./{}..",
+                term::format::tertiary(path.display())
+            ));
+            let repo = rad::checkout(id, &me, path, &storage)?;
 
-    spinner.finish();
+            spinner.finish();
+            repo
+        }
+    };
+    fs::remove_file(&marker)?;
 
     Ok((repo, doc, proj))
 }
+
+/// Path of the marker file of a clone in progress. The marker is created before anything is
+/// fetched, and removed once the clone has succeeded, so that an interrupted clone can be
+/// told apart from a repository that was already in storage.
+pub fn marker(home: &Home, id: &Id) -> PathBuf {
+    home.path().join("clones").join(id.canonical())
+}
+
+/// Check that the repository left behind by an interrupted clone can be resumed from.
+fn verify(id: Id, storage: &Storage) -> Result<(), CloneError> {
+    let repo = storage.repository(id)?;
+
+    repo.verify()
+        .map_err(|e| CloneError::Inconsistent(id, e.to_string()))?;
+    repo.identity_doc()
+        .map_err(|e| CloneError::Inconsistent(id, e.to_string()))?;
+
+    Ok(())
+}
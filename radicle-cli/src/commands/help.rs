@@ -23,6 +23,7 @@ const COMMANDS: &[Help] = &[
     rad_issue::HELP,
     rad_ls::HELP,
     rad_merge::HELP,
+    rad_node::HELP,
     rad_patch::HELP,
     rad_path::HELP,
     rad_push::HELP,
@@ -1,5 +1,8 @@
 #![allow(clippy::or_fun_call)]
+use std::collections::BTreeMap;
 use std::ffi::OsString;
+use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use std::str::FromStr;
@@ -7,10 +10,14 @@ use std::str::FromStr;
 use anyhow::{anyhow, Context as _};
 use chrono::prelude::*;
 use json_color::{Color, Colorizer};
+use serde_json::json;
 
+use radicle::cob::{ObjectId, TypeName};
 use radicle::crypto::Unverified;
 use radicle::identity::Untrusted;
 use radicle::identity::{Doc, Id};
+use radicle::node::NodeId;
+use radicle::storage::git::Storage;
 use radicle::storage::{ReadRepository, ReadStorage};
 
 use crate::terminal as term;
@@ -30,12 +37,23 @@ Usage
     Inspects the given path or ID. If neither is specified,
     the current project is inspected.
 
+    When `--refs` is followed by a Node ID, only the refs of
+    that remote are shown.
+
+    With `--cob <typename>:<oid>`, the change graph of the given
+    collaborative object is shown, either in the graphviz `dot`
+    format with `--graph`, or summarized with `--stats`.
+
 Options
 
     --id        Return the ID in simplified form
     --payload   Inspect the object's payload
-    --refs      Inspect the object's refs on the local device (requires `tree`)
+    --refs      Inspect the object's refs on the local device
     --history   Show object's history
+    --cob       Inspect a collaborative object, as `<typename>:<oid>`
+    --graph     Print the object's change graph, in the `dot` format
+    --stats     Print the number of changes and tips of the object
+    --json      Output JSON, without colors
     --help      Print help
 "#,
 };
@@ -44,9 +62,14 @@ Options
 pub struct Options {
     pub id: Option<Id>,
     pub refs: bool,
+    pub remote: Option<NodeId>,
     pub payload: bool,
     pub history: bool,
+    pub cob: Option<(TypeName, ObjectId)>,
+    pub graph: bool,
+    pub stats: bool,
     pub id_only: bool,
+    pub json: bool,
 }
 
 impl Args for Options {
@@ -56,9 +79,14 @@ impl Args for Options {
         let mut parser = lexopt::Parser::from_args(args);
         let mut id: Option<Id> = None;
         let mut refs = false;
+        let mut remote: Option<NodeId> = None;
         let mut payload = false;
         let mut history = false;
+        let mut cob = None;
+        let mut graph = false;
+        let mut stats = false;
         let mut id_only = false;
+        let mut json = false;
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -74,9 +102,39 @@ impl Args for Options {
                 Long("history") => {
                     history = true;
                 }
+                Long("cob") => {
+                    let val = parser.value()?;
+                    let val = val.to_string_lossy();
+                    let (typename, oid) = val.split_once(':').ok_or(anyhow!(
+                        "invalid object '{val}', expected `<typename>:<oid>`"
+                    ))?;
+                    let typename = TypeName::from_str(typename)
+                        .context("Supplied argument is not a valid `typename`")?;
+                    let oid = ObjectId::from_str(oid)
+                        .context("Supplied argument is not a valid `oid`")?;
+
+                    cob = Some((typename, oid));
+                }
+                Long("graph") => {
+                    graph = true;
+                }
+                Long("stats") => {
+                    stats = true;
+                }
                 Long("id") => {
                     id_only = true;
                 }
+                Long("json") => {
+                    json = true;
+                }
+                // A Node ID following `--refs` restricts the refs to that remote.
+                Value(val)
+                    if refs
+                        && remote.is_none()
+                        && NodeId::from_str(&val.to_string_lossy()).is_ok() =>
+                {
+                    remote = NodeId::from_str(&val.to_string_lossy()).ok();
+                }
                 Value(val) if id.is_none() => {
                     let val = val.to_string_lossy();
 
@@ -93,14 +151,29 @@ impl Args for Options {
                 _ => return Err(anyhow::anyhow!(arg.unexpected())),
             }
         }
+        if (graph || stats) && cob.is_none() {
+            return Err(anyhow!(
+                "`--graph` and `--stats` require an object to be given with `--cob`"
+            ));
+        }
+        if cob.is_some() && !graph && !stats {
+            return Err(anyhow!(
+                "`--cob` must be used with either `--graph` or `--stats`"
+            ));
+        }
 
         Ok((
             Options {
                 id,
                 payload,
                 history,
+                cob,
+                graph,
+                stats,
                 refs,
+                remote,
                 id_only,
+                json,
             },
             vec![],
         ))
@@ -109,7 +182,8 @@ impl Args for Options {
 
 pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     let profile = ctx.profile()?;
-    let storage = &profile.storage;
+    // We only read from storage, so we don't get in the way of a running node.
+    let storage = &Storage::open_read_only(profile.home.storage())?;
     let signer = term::signer(&profile)?;
 
     let id = match options.id {
@@ -126,21 +200,92 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
         .get(signer.public_key(), id)?
         .context("No project with such `id` exists")?;
 
-    if options.refs {
-        let path = profile
+    if let Some((typename, oid)) = &options.cob {
+        let repo = storage.repository(id)?;
+        let info = radicle::cob::info::changegraph(&repo, typename, oid)?
+            .ok_or(anyhow!("object {typename}:{oid} not found in {id}"))?;
+
+        if options.stats && options.json {
+            println!(
+                "{}",
+                json!({
+                    "nodes": info.number_of_nodes,
+                    "tips": info.tips.iter().map(|t| t.to_string()).collect::<Vec<_>>(),
+                })
+            );
+        } else if options.stats {
+            println!("nodes {}", info.number_of_nodes);
+            println!("tips  {}", info.tips.len());
+
+            for tip in &info.tips {
+                term::indented(term::format::dim(tip));
+            }
+        } else {
+            println!("{}", info.dotviz);
+        }
+    } else if options.refs && options.json {
+        let repo = storage.repository(id)?;
+        let glob = match &options.remote {
+            Some(remote) => format!("refs/namespaces/{remote}/*"),
+            None => String::from("refs/namespaces/*"),
+        };
+        // Namespaces, and the refs under them, mapped to the objects they point to.
+        let mut namespaces: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+
+        for r in repo.raw().references_glob(&glob)? {
+            let r = r?;
+            // Symbolic refs don't point to an object directly.
+            let (Some(name), Some(oid)) = (r.name(), r.target()) else {
+                continue;
+            };
+            let (remote, refname) = radicle::git::parse_ref_namespaced::<NodeId>(name)?;
+
+            namespaces
+                .entry(remote.to_string())
+                .or_default()
+                .insert(refname.to_string(), oid.to_string());
+        }
+        if let Some(remote) = &options.remote {
+            if namespaces.is_empty() {
+                anyhow::bail!("no refs found for remote {remote}");
+            }
+        }
+        println!("{}", serde_json::to_string_pretty(&namespaces)?);
+    } else if options.refs {
+        let mut path = profile
             .home
             .storage()
             .join(id.urn())
             .join("refs")
             .join("namespaces");
 
-        Command::new("tree")
-            .current_dir(path)
+        if let Some(remote) = &options.remote {
+            path = path.join(remote.to_string());
+
+            if !path.is_dir() {
+                anyhow::bail!("no refs found for remote {remote}");
+            }
+        }
+
+        match Command::new("tree")
+            .current_dir(&path)
             .args(["--noreport", "--prune"])
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
-            .spawn()?
-            .wait()?;
+            .spawn()
+        {
+            Ok(mut child) => {
+                child.wait()?;
+            }
+            // Fall back to our own implementation if `tree` isn't installed.
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                println!(".");
+                tree(&path, "")?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    } else if options.payload && options.json {
+        println!("{}", serde_json::to_string_pretty(&project.payload)?);
     } else if options.payload {
         println!(
             "{}",
@@ -150,24 +295,38 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
         let repo = storage.repository(id)?;
         let head = Doc::<Untrusted>::head(signer.public_key(), &repo)?;
         let history = repo.revwalk(head)?;
+        let mut entries = Vec::new();
 
         for oid in history {
             let oid = oid?.into();
             let tip = repo.commit(oid)?;
             let blob = Doc::<Unverified>::blob_at(oid, &repo)?;
-            let content: serde_json::Value = serde_json::from_slice(blob.content())?;
-            let timezone = if tip.time().sign() == '+' {
-                #[allow(deprecated)]
-                FixedOffset::east(tip.time().offset_minutes() * 60)
-            } else {
-                #[allow(deprecated)]
-                FixedOffset::west(tip.time().offset_minutes() * 60)
+            let time = commit_time(&tip);
+
+            if options.json {
+                let payload = match Doc::<Unverified>::from_json(blob.content()) {
+                    Ok(doc) => serde_json::to_value(doc.payload)?,
+                    Err(_) => serde_json::Value::Null,
+                };
+                entries.push(json!({
+                    "commit": oid.to_string(),
+                    "blob": blob.id().to_string(),
+                    "date": time.to_rfc3339(),
+                    "payload": payload,
+                }));
+                continue;
+            }
+
+            let content = match Doc::<Unverified>::from_json(blob.content()) {
+                Ok(doc) => colorizer().colorize_json_str(&serde_json::to_string_pretty(&doc)?)?,
+                Err(err) => {
+                    term::warning(&format!(
+                        "failed to parse identity document at {oid}: {err}"
+                    ));
+                    String::from_utf8_lossy(blob.content()).into_owned()
+                }
             };
-            let time = DateTime::<Utc>::from(
-                std::time::UNIX_EPOCH + std::time::Duration::from_secs(tip.time().seconds() as u64),
-            )
-            .with_timezone(&timezone)
-            .to_rfc2822();
+            let time = time.to_rfc2822();
 
             println!(
                 "{} {}",
@@ -188,12 +347,21 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
                 term::blank();
             }
 
-            let json = colorizer().colorize_json_str(&serde_json::to_string_pretty(&content)?)?;
-            for line in json.lines() {
+            for line in content.lines() {
                 println!(" {line}");
             }
             println!();
         }
+        if options.json {
+            println!("{}", serde_json::to_string_pretty(&entries)?);
+        }
+    } else if options.json {
+        let id = if options.id_only {
+            id.urn()
+        } else {
+            id.to_string()
+        };
+        println!("{}", json!({ "id": id }));
     } else if options.id_only {
         term::info!("{}", term::format::highlight(id.urn()));
     } else {
@@ -203,6 +371,62 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Print the contents of a directory, the way `tree --noreport --prune` does.
+fn tree(path: &Path, prefix: &str) -> io::Result<()> {
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Like `--prune`, leave out directories that don't contain any files.
+    entries.retain(|p| !p.is_dir() || has_files(p));
+    entries.sort();
+
+    for (i, entry) in entries.iter().enumerate() {
+        let last = i == entries.len() - 1;
+        let name = entry
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        println!("{prefix}{}{name}", if last { "└── " } else { "├── " });
+
+        if entry.is_dir() {
+            tree(
+                entry,
+                &format!("{prefix}{}", if last { "    " } else { "│   " }),
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Check whether a directory contains files, at any depth.
+fn has_files(path: &Path) -> bool {
+    fs::read_dir(path)
+        .map(|entries| {
+            entries.flatten().any(|e| {
+                let path = e.path();
+                !path.is_dir() || has_files(&path)
+            })
+        })
+        .unwrap_or(false)
+}
+
+/// Get the time of a commit, in the commit's timezone.
+fn commit_time(commit: &radicle::git::raw::Commit) -> DateTime<FixedOffset> {
+    let timezone = if commit.time().sign() == '+' {
+        #[allow(deprecated)]
+        FixedOffset::east(commit.time().offset_minutes() * 60)
+    } else {
+        #[allow(deprecated)]
+        FixedOffset::west(commit.time().offset_minutes() * 60)
+    };
+    DateTime::<Utc>::from(
+        std::time::UNIX_EPOCH + std::time::Duration::from_secs(commit.time().seconds() as u64),
+    )
+    .with_timezone(&timezone)
+}
+
 // Used for JSON Colorizing
 fn colorizer() -> Colorizer {
     Colorizer::new()
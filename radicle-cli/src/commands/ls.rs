@@ -3,6 +3,10 @@ use std::ffi::OsString;
 use crate::terminal as term;
 use crate::terminal::args::{Args, Error, Help};
 
+use radicle::git;
+use radicle::identity::Id;
+use radicle::node::Handle as _;
+use radicle::storage::git::Storage;
 use radicle::storage::{ReadRepository, ReadStorage};
 
 pub const HELP: Help = Help {
@@ -16,20 +20,43 @@ Usage
 
 Options
 
-    --help    Print help
+    --tracked-only    Only list projects tracked by the node (requires a running node)
+    --json            Output JSON, without colors
+    --help            Print help
 "#,
 };
 
-pub struct Options {}
+#[derive(Default, Debug)]
+pub struct Options {
+    pub json: bool,
+    pub tracked_only: bool,
+}
+
+/// Project entry, as output by `--json`.
+#[derive(serde::Serialize, Debug)]
+struct Entry {
+    id: Id,
+    name: String,
+    description: String,
+    head: git::Oid,
+}
 
 impl Args for Options {
     fn from_args(args: Vec<OsString>) -> anyhow::Result<(Self, Vec<OsString>)> {
         use lexopt::prelude::*;
 
         let mut parser = lexopt::Parser::from_args(args);
+        let mut json = false;
+        let mut tracked_only = false;
 
-        if let Some(arg) = parser.next()? {
+        while let Some(arg) = parser.next()? {
             match arg {
+                Long("json") => {
+                    json = true;
+                }
+                Long("tracked-only") => {
+                    tracked_only = true;
+                }
                 Long("help") => {
                     return Err(Error::Help.into());
                 }
@@ -37,16 +64,54 @@ impl Args for Options {
             }
         }
 
-        Ok((Options {}, vec![]))
+        Ok((Options { json, tracked_only }, vec![]))
     }
 }
 
-pub fn run(_options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
+pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     let profile = ctx.profile()?;
-    let storage = &profile.storage;
+    // We only read from storage, so we don't get in the way of a running node.
+    let storage = &Storage::open_read_only(profile.home.storage())?;
+    let node = radicle::Node::new(profile.socket());
     let mut table = term::Table::default();
 
-    storage.repositories()?.into_iter().for_each(|id| {
+    // If the node is running, list the repositories it's serving, otherwise read them from storage.
+    let mut repos: Vec<_> = if options.tracked_only {
+        if !node.is_running() {
+            anyhow::bail!("the node must be running to list tracked projects");
+        }
+        node.tracked_inventory()?.iter().collect()
+    } else if node.is_running() {
+        node.inventory()?.iter().collect()
+    } else {
+        storage.repositories()?
+    };
+
+    if options.json {
+        // Sort by id, so that the output is stable across runs.
+        repos.sort();
+
+        let entries = repos
+            .into_iter()
+            .filter_map(|id| {
+                let repo = storage.repository(id).ok()?;
+                let (_, head) = repo.head().ok()?;
+                let proj = repo.project_of(profile.id()).ok()?;
+
+                Some(Entry {
+                    id,
+                    name: proj.name().to_owned(),
+                    description: proj.description().to_owned(),
+                    head,
+                })
+            })
+            .collect::<Vec<_>>();
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+
+        return Ok(());
+    }
+
+    repos.into_iter().for_each(|id| {
         let Ok(repo) = storage.repository(id) else { return };
         let Ok((_, head)) = repo.head() else { return };
         let Ok(proj) = repo.project_of(profile.id()) else { return };
@@ -0,0 +1,264 @@
+use std::ffi::OsString;
+use std::str::FromStr;
+
+use anyhow::anyhow;
+
+use radicle::cob::Timestamp;
+use radicle::node::{Address, ConnectOptions, Handle as _, Link, NodeId, SessionState};
+use radicle::prelude::Id;
+use radicle::Node;
+
+use crate::terminal as term;
+use crate::terminal::args::{Args, Error, Help};
+
+pub const HELP: Help = Help {
+    name: "node",
+    description: "Query the running node",
+    version: env!("CARGO_PKG_VERSION"),
+    usage: r#"
+Usage
+
+    rad node peers [<option>...]
+    rad node connect <nid>@<addr> [--persistent] [<option>...]
+    rad node unpersist <nid> [<option>...]
+    rad node request-seeding [<rid>] [<option>...]
+
+    The `peers` command lists the node's peer sessions: their node id,
+    address, connection direction, state, latency and last activity.
+
+    The `connect` command asks the node to connect to the given peer. With
+    `--persistent`, the node maintains the connection: it reconnects to the
+    peer when the connection drops, and after the node restarts.
+
+    The `unpersist` command asks the node to stop maintaining the connection
+    to the given peer. The peer stays connected until the connection drops.
+
+    The `request-seeding` command asks the node's connected peers to fetch
+    the given project from it, if they track it. This is useful after a push,
+    to have the project seeded by more nodes. If no project is specified,
+    the project in the current working directory is used.
+
+Options
+
+    --persistent      Maintain the connection to the peer (connect)
+    --json            Output JSON, without colors
+    --help            Print help
+"#,
+};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Operation {
+    #[default]
+    Peers,
+    Connect {
+        peer: Option<(NodeId, Address)>,
+        persistent: bool,
+    },
+    Unpersist {
+        nid: Option<NodeId>,
+    },
+    RequestSeeding {
+        rid: Option<Id>,
+    },
+}
+
+#[derive(Debug, Default)]
+pub struct Options {
+    pub op: Operation,
+    pub json: bool,
+}
+
+impl Args for Options {
+    fn from_args(args: Vec<OsString>) -> anyhow::Result<(Self, Vec<OsString>)> {
+        use lexopt::prelude::*;
+
+        let mut parser = lexopt::Parser::from_args(args);
+        let mut op: Option<Operation> = None;
+        let mut json = false;
+
+        while let Some(arg) = parser.next()? {
+            match arg {
+                Long("json") => {
+                    json = true;
+                }
+                Long("persistent") => {
+                    if let Some(Operation::Connect { persistent, .. }) = &mut op {
+                        *persistent = true;
+                    } else {
+                        return Err(anyhow!(arg.unexpected()));
+                    }
+                }
+                Long("help") => {
+                    return Err(Error::Help.into());
+                }
+                Value(val) if op.is_none() => match val.to_string_lossy().as_ref() {
+                    "peers" => op = Some(Operation::Peers),
+                    "connect" => {
+                        op = Some(Operation::Connect {
+                            peer: None,
+                            persistent: false,
+                        })
+                    }
+                    "unpersist" => op = Some(Operation::Unpersist { nid: None }),
+                    "request-seeding" => op = Some(Operation::RequestSeeding { rid: None }),
+
+                    unknown => anyhow::bail!("unknown operation '{}'", unknown),
+                },
+                Value(val) if matches!(op, Some(Operation::Connect { peer: None, .. })) => {
+                    if let Some(Operation::Connect { peer, .. }) = &mut op {
+                        *peer = Some(self::peer(&val)?);
+                    }
+                }
+                Value(val) if matches!(op, Some(Operation::Unpersist { nid: None })) => {
+                    let val = val.to_string_lossy();
+                    let nid =
+                        NodeId::from_str(&val).map_err(|_| anyhow!("invalid node ID '{val}'"))?;
+                    op = Some(Operation::Unpersist { nid: Some(nid) });
+                }
+                Value(val) if matches!(op, Some(Operation::RequestSeeding { rid: None })) => {
+                    let rid = term::args::rid(&val)?;
+                    op = Some(Operation::RequestSeeding { rid: Some(rid) });
+                }
+                _ => return Err(anyhow!(arg.unexpected())),
+            }
+        }
+
+        if let Some(Operation::Connect { peer: None, .. }) = op {
+            anyhow::bail!("a peer to connect to must be specified, eg. `<nid>@<addr>`");
+        }
+        if let Some(Operation::Unpersist { nid: None }) = op {
+            anyhow::bail!("a peer must be specified, eg. `<nid>`");
+        }
+
+        Ok((
+            Options {
+                op: op.unwrap_or_default(),
+                json,
+            },
+            vec![],
+        ))
+    }
+}
+
+pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
+    let profile = ctx.profile()?;
+    let mut node = Node::new(profile.socket());
+
+    if !node.is_running() {
+        anyhow::bail!(
+            "the node must be running to query it; no node is listening on {}",
+            profile.socket().display()
+        );
+    }
+
+    match options.op {
+        Operation::Peers => peers(&node, options.json),
+        Operation::Connect { peer, persistent } => {
+            let (nid, addr) =
+                peer.ok_or_else(|| anyhow!("a peer to connect to must be specified"))?;
+            node.connect(nid, addr.clone(), ConnectOptions { persistent })?;
+
+            if persistent {
+                term::success!(
+                    "Connecting to {}@{}, and maintaining the connection",
+                    term::format::tertiary(nid),
+                    addr
+                );
+            } else {
+                term::success!("Connecting to {}@{}", term::format::tertiary(nid), addr);
+            }
+            Ok(())
+        }
+        Operation::Unpersist { nid } => {
+            let nid = nid.ok_or_else(|| anyhow!("a peer must be specified"))?;
+
+            if node.unpersist(nid)? {
+                term::success!(
+                    "No longer maintaining the connection to {}",
+                    term::format::tertiary(nid)
+                );
+            } else {
+                term::info!(
+                    "The connection to {} isn't maintained",
+                    term::format::tertiary(nid)
+                );
+            }
+            Ok(())
+        }
+        Operation::RequestSeeding { rid } => {
+            let rid = match rid {
+                Some(rid) => rid,
+                None => {
+                    let (_, rid) = radicle::rad::cwd().map_err(|_| {
+                        anyhow!("this command must be run in the context of a project")
+                    })?;
+                    rid
+                }
+            };
+            node.request_seeding(rid)?;
+
+            term::success!(
+                "Requested seeding of {} from connected peers",
+                term::format::tertiary(rid)
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Parse a peer address of the form `<nid>@<addr>`.
+fn peer(val: &OsString) -> anyhow::Result<(NodeId, Address)> {
+    let val = val.to_string_lossy();
+    let Some((nid, addr)) = val.split_once('@') else {
+        anyhow::bail!("invalid peer '{val}', expected `<nid>@<addr>`");
+    };
+    let nid = NodeId::from_str(nid).map_err(|_| anyhow!("invalid node ID '{nid}'"))?;
+    let addr = Address::from_str(addr).map_err(|_| anyhow!("invalid address '{addr}'"))?;
+
+    Ok((nid, addr))
+}
+
+fn peers(node: &Node, json: bool) -> anyhow::Result<()> {
+    let mut sessions = node.list_sessions()?;
+    // Sort by node id, so that the output is stable across runs.
+    sessions.sort_by_key(|s| s.nid);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&sessions)?);
+
+        return Ok(());
+    }
+
+    let mut table = term::Table::default();
+    for session in sessions {
+        let link = match session.link {
+            Link::Inbound => "inbound",
+            Link::Outbound => "outbound",
+        };
+        let state = match session.state {
+            SessionState::Connecting => term::format::yellow("connecting"),
+            SessionState::Connected => term::format::positive("connected"),
+            SessionState::Fetch => term::format::positive("fetching"),
+            SessionState::Disconnected => term::format::negative("disconnected"),
+        };
+        let latency = session
+            .latency
+            .map(|ms| format!("{ms} ms"))
+            .unwrap_or_default();
+        let last_active = Timestamp::new(session.last_active / 1000);
+        let last_sent = Timestamp::new(session.last_sent / 1000);
+
+        table.push([
+            term::format::tertiary(term::format::node(&session.nid)),
+            session.addr.to_string(),
+            term::format::dim(link),
+            state,
+            latency,
+            term::format::italic(term::format::timestamp(&last_active)),
+            term::format::italic(term::format::timestamp(&last_sent)),
+        ]);
+    }
+    table.render();
+
+    Ok(())
+}
@@ -21,17 +21,22 @@ Usage
 
     Removes a project from storage.
 
+    Unless `--force` is given, asks for confirmation before removing the
+    project. When no user is attending the terminal, `--force` is required.
+
 Options
 
-    --no-confirm        Do not ask for confirmation before removal
+    --force, -f         Do not ask for confirmation before removal
                         (default: false)
+    --dry-run           Print what would be removed, without removing it
     --help              Print help
 "#,
 };
 
 pub struct Options {
     id: Id,
-    confirm: bool,
+    force: bool,
+    dry_run: bool,
 }
 
 impl Args for Options {
@@ -40,12 +45,17 @@ impl Args for Options {
 
         let mut parser = lexopt::Parser::from_args(args);
         let mut id: Option<Id> = None;
-        let mut confirm = true;
+        let mut force = false;
+        let mut dry_run = false;
 
         while let Some(arg) = parser.next()? {
             match arg {
-                Long("no-confirm") => {
-                    confirm = false;
+                // `--no-confirm` is kept for compatibility.
+                Long("force") | Short('f') | Long("no-confirm") => {
+                    force = true;
+                }
+                Long("dry-run") => {
+                    dry_run = true;
                 }
                 Long("help") => {
                     return Err(Error::Help.into());
@@ -60,7 +70,8 @@ impl Args for Options {
         Ok((
             Options {
                 id: id.ok_or_else(|| anyhow!("an `id` must be provided; see `rad rm --help`"))?,
-                confirm,
+                force,
+                dry_run,
             },
             vec![],
         ))
@@ -73,25 +84,43 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     let signer = term::signer(&profile)?;
     let id = options.id;
 
-    if let Ok(Some(_)) = storage.get(signer.public_key(), id.to_owned()) {
-        let path = radicle::storage::git::paths::repository(storage, &id);
-
-        if !options.confirm
-            || term::confirm(format!(
-                "Are you sure you would like to delete {}?",
-                term::format::dim(id.urn())
-            ))
-        {
-            if let Err(e) = rad_untrack::untrack(id.to_owned(), &profile) {
-                term::warning(&format!("Failed to untrack repository: {e}"));
-                term::warning("Make sure to untrack this repository when your node is running");
-            }
-            fs::remove_dir_all(path)?;
-            term::success!("Successfully removed project {id} from storage");
-        }
-    } else {
+    let Ok(Some(doc)) = storage.get(signer.public_key(), id.to_owned()) else {
         anyhow::bail!("project {} does not exist", &id)
+    };
+    // Repositories without a project payload, or with an invalid one, can still be removed.
+    let label = match doc.project() {
+        Ok(project) => format!(
+            "{} ({})",
+            term::format::highlight(project.name()),
+            term::format::dim(id.urn())
+        ),
+        Err(_) => term::format::highlight(id.urn()).to_string(),
+    };
+    let path = radicle::storage::git::paths::repository(storage, &id);
+
+    if options.dry_run {
+        term::info!("Would remove project {label} from storage");
+        return Ok(());
+    }
+
+    if !options.force {
+        if !console::user_attended() {
+            anyhow::bail!(
+                "refusing to remove {id} without confirmation, as no user is attending the terminal; \
+                 use `--force` to remove it anyway"
+            );
+        }
+        if !term::confirm(format!("Are you sure you would like to delete {label}?")) {
+            return Ok(());
+        }
+    }
+
+    if let Err(e) = rad_untrack::untrack(id.to_owned(), &profile) {
+        term::warning(&format!("Failed to untrack repository: {e}"));
+        term::warning("Make sure to untrack this repository when your node is running");
     }
+    fs::remove_dir_all(path)?;
+    term::success!("Successfully removed project {id} from storage");
 
     Ok(())
 }
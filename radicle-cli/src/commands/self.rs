@@ -77,6 +77,11 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
 fn all(profile: &Profile) -> anyhow::Result<()> {
     let mut table = term::Table::default();
 
+    let alias = profile
+        .alias()?
+        .unwrap_or_else(|| String::from("anonymous"));
+    table.push(["Alias", &term::format::tertiary(alias)]);
+
     let did = profile.did();
     table.push(["ID", &term::format::tertiary(did)]);
 
@@ -1,10 +1,12 @@
+use std::collections::BTreeSet;
 use std::ffi::OsString;
 use std::str::FromStr;
 
 use anyhow::{anyhow, Context as _};
 
-use radicle::node::{Handle, NodeId};
-use radicle::storage::ReadStorage;
+use radicle::identity::Id;
+use radicle::node::{FetchResult, Handle, NodeId};
+use radicle::storage::{ReadStorage, RefUpdate};
 
 use crate::terminal as term;
 use crate::terminal::args::{Args, Error, Help};
@@ -17,11 +19,29 @@ pub const HELP: Help = Help {
 Usage
 
     rad track <peer> [--fetch] [--alias <name>]
+    rad track [<rid>] --remote <peer>
+    rad track [<rid>] --relay | --no-relay
+    rad track <rid> --dry-run
+
+    When `--remote` is used, the peer is added to the project's remote tracking
+    allow-list. If no project is given, the project in the current directory is used.
+
+    When `--no-relay` is used, the project is still fetched, but its announcements
+    are no longer relayed to the node's peers, and it is left out of the node's
+    inventory. This is meant for projects that are mirrored privately. Use `--relay`
+    to relay the project again.
+
+    When `--dry-run` is used, the seeds of the given project are asked for the refs
+    they would serve, and nothing is tracked or fetched.
 
 Options
 
     --alias <name>         Add an alias to this peer identifier
+    --remote <peer>        Track the given peer's remote for a project
     --fetch                Fetch the peer's refs into the working copy
+    --relay                Relay the project's announcements (default)
+    --no-relay             Don't relay the project's announcements
+    --dry-run              Show what would be fetched from the project's seeds
     --verbose, -v          Verbose output
     --help                 Print help
 "#,
@@ -29,9 +49,13 @@ Options
 
 #[derive(Debug)]
 pub struct Options {
-    pub peer: NodeId,
+    pub peer: Option<NodeId>,
+    pub rid: Option<Id>,
+    pub remote: Option<NodeId>,
     pub alias: Option<String>,
     pub fetch: bool,
+    pub relay: Option<bool>,
+    pub dry_run: bool,
     pub verbose: bool,
 }
 
@@ -41,8 +65,12 @@ impl Args for Options {
 
         let mut parser = lexopt::Parser::from_args(args);
         let mut peer: Option<NodeId> = None;
+        let mut rid: Option<Id> = None;
+        let mut remote: Option<NodeId> = None;
         let mut alias: Option<String> = None;
         let mut fetch = true;
+        let mut relay: Option<bool> = None;
+        let mut dry_run = false;
         let mut verbose = false;
 
         while let Some(arg) = parser.next()? {
@@ -56,8 +84,24 @@ impl Args for Options {
 
                     alias = Some(name.to_owned());
                 }
+                Long("remote") => {
+                    let val = parser.value()?;
+                    let val = val.to_string_lossy();
+
+                    if let Ok(val) = NodeId::from_str(&val) {
+                        remote = Some(val);
+                    } else {
+                        return Err(anyhow!("invalid Node ID '{}'", val));
+                    }
+                }
                 Long("no-fetch") => fetch = false,
+                Long("relay") => relay = Some(true),
+                Long("no-relay") => relay = Some(false),
+                Long("dry-run") => dry_run = true,
                 Long("verbose") | Short('v') => verbose = true,
+                Value(val) if rid.is_none() && val.to_string_lossy().starts_with("rad:") => {
+                    rid = Some(term::args::rid(&val)?);
+                }
                 Value(val) if peer.is_none() => {
                     let val = val.to_string_lossy();
 
@@ -76,11 +120,40 @@ impl Args for Options {
             }
         }
 
+        if dry_run {
+            if rid.is_none() {
+                return Err(anyhow!("a project must be supplied with `--dry-run`"));
+            }
+            if peer.is_some() || remote.is_some() {
+                return Err(anyhow!(
+                    "a peer cannot be supplied with `--dry-run`, only a project"
+                ));
+            }
+        } else if relay.is_some() {
+            if peer.is_some() || remote.is_some() {
+                return Err(anyhow!(
+                    "a peer cannot be supplied with `--relay` or `--no-relay`, only a project"
+                ));
+            }
+        } else if remote.is_some() {
+            if peer.is_some() {
+                return Err(anyhow!("a peer cannot be supplied with `--remote`"));
+            }
+        } else if peer.is_none() {
+            return Err(anyhow!("a peer to track must be supplied"));
+        } else if rid.is_some() {
+            return Err(anyhow!("a project can only be supplied with `--remote`"));
+        }
+
         Ok((
             Options {
-                peer: peer.ok_or_else(|| anyhow!("a peer to track must be supplied"))?,
+                peer,
+                rid,
+                remote,
                 alias,
                 fetch,
+                relay,
+                dry_run,
                 verbose,
             },
             vec![],
@@ -89,13 +162,57 @@ impl Args for Options {
 }
 
 pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
-    let peer = options.peer;
     let profile = ctx.profile()?;
     let storage = &profile.storage;
-    let (_, rid) = radicle::rad::cwd().context("this command must be run within a project")?;
-    let project = storage.repository(rid)?.project_of(profile.id())?;
     let mut node = radicle::Node::new(profile.socket());
 
+    if options.dry_run {
+        let Some(rid) = options.rid else {
+            anyhow::bail!("a project must be supplied with `--dry-run`");
+        };
+        return dry_run(rid, &mut node);
+    }
+    let rid = match options.rid {
+        Some(rid) => rid,
+        None => {
+            let (_, rid) =
+                radicle::rad::cwd().context("this command must be run within a project")?;
+            rid
+        }
+    };
+    let project = storage.repository(rid)?.project_of(profile.id())?;
+
+    if let Some(relay) = options.relay {
+        let updated = node.set_repo_relay(rid, relay)?;
+        let outcome = match (relay, updated) {
+            (true, true) => "enabled",
+            (false, true) => "disabled",
+            (_, false) => "unchanged",
+        };
+
+        term::success!(
+            "Relaying of {} {}",
+            term::format::highlight(project.name()),
+            outcome
+        );
+        return Ok(());
+    }
+    if let Some(remote) = options.remote {
+        let tracked = node.track_remote(rid, remote)?;
+        let outcome = if tracked { "added" } else { "exists" };
+
+        term::success!(
+            "Remote {} for {} {}",
+            term::format::tertiary(term::format::node(&remote)),
+            term::format::highlight(project.name()),
+            outcome
+        );
+        return Ok(());
+    }
+    let Some(peer) = options.peer else {
+        anyhow::bail!("a peer to track must be supplied");
+    };
+
     term::info!(
         "Establishing 🌱 tracking relationship for {}",
         term::format::highlight(project.name())
@@ -123,3 +240,44 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// Show what tracking the given project would fetch from each of its seeds, without
+/// tracking or fetching anything.
+fn dry_run(rid: Id, node: &mut radicle::Node) -> anyhow::Result<()> {
+    let seeds = node.seeds(rid)?;
+    if seeds.is_empty() {
+        anyhow::bail!("no seeds found for {rid}");
+    }
+
+    for seed in seeds {
+        match node.fetch_dry_run(rid, seed)? {
+            FetchResult::Success { updated } => {
+                let remotes = updated
+                    .iter()
+                    .filter_map(|update| match update {
+                        RefUpdate::Skipped { name, .. } => name
+                            .as_str()
+                            .strip_prefix("refs/namespaces/")
+                            .and_then(|refs| refs.split('/').next()),
+                        _ => None,
+                    })
+                    .collect::<BTreeSet<_>>();
+
+                term::info!(
+                    "{} would fetch {} remote(s) with {} ref(s) from {}",
+                    term::format::tertiary(rid),
+                    remotes.len(),
+                    updated.len(),
+                    term::format::tertiary(term::format::node(&seed))
+                );
+            }
+            FetchResult::Failed { reason } => {
+                term::warning(&format!(
+                    "failed to list refs from {}: {reason}",
+                    term::format::node(&seed)
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,10 @@
 use std::ffi::OsString;
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context as _};
 
 use radicle::identity::Id;
-use radicle::node::Handle;
+use radicle::node::{Handle, NodeId};
 use radicle::prelude::*;
 use radicle::storage::ReadStorage;
 
@@ -18,9 +19,14 @@ pub const HELP: Help = Help {
 Usage
 
     rad untrack [<id>]
+    rad untrack [<id>] --remote <peer>
+
+    When `--remote` is used, the peer is removed from the project's remote tracking
+    allow-list, and the project itself stays tracked.
 
 Options
 
+    --remote <peer>     Untrack the given peer's remote for a project
     --help              Print help
 "#,
 };
@@ -28,6 +34,7 @@ Options
 #[derive(Debug)]
 pub struct Options {
     pub id: Option<Id>,
+    pub remote: Option<NodeId>,
 }
 
 impl Args for Options {
@@ -36,9 +43,20 @@ impl Args for Options {
 
         let mut parser = lexopt::Parser::from_args(args);
         let mut id: Option<Id> = None;
+        let mut remote: Option<NodeId> = None;
 
         while let Some(arg) = parser.next()? {
             match arg {
+                Long("remote") => {
+                    let val = parser.value()?;
+                    let val = val.to_string_lossy();
+
+                    if let Ok(val) = NodeId::from_str(&val) {
+                        remote = Some(val);
+                    } else {
+                        return Err(anyhow!("invalid Node ID '{}'", val));
+                    }
+                }
                 Value(val) if id.is_none() => {
                     let val = val.to_string_lossy();
 
@@ -57,7 +75,7 @@ impl Args for Options {
             }
         }
 
-        Ok((Options { id }, vec![]))
+        Ok((Options { id, remote }, vec![]))
     }
 }
 
@@ -70,6 +88,24 @@ pub fn run(options: Options, ctx: impl term::Context) -> anyhow::Result<()> {
     let storage = &profile.storage;
     let project = storage.repository(id)?.project_of(profile.id())?;
 
+    if let Some(remote) = options.remote {
+        let mut node = radicle::Node::new(profile.socket());
+        let untracked = node.untrack_remote(id, remote)?;
+        let outcome = if untracked {
+            "removed"
+        } else {
+            "doesn't exist"
+        };
+
+        term::success!(
+            "Remote {} for {} {}",
+            term::format::tertiary(term::format::node(&remote)),
+            term::format::highlight(project.name()),
+            outcome
+        );
+        return Ok(());
+    }
+
     if untrack(id, &profile)? {
         term::success!(
             "Tracking relationships for {} ({}) removed",
@@ -4,13 +4,16 @@ use std::str::FromStr;
 use std::{thread, time};
 
 use radicle::git;
+use radicle::node::{Handle as _, RemoteTracking};
 use radicle::prelude::Id;
 use radicle::profile::Home;
 use radicle::storage::{ReadRepository, ReadStorage};
 use radicle::test::fixtures;
 
 use radicle_cli_test::TestFormula;
-use radicle_node::service::tracking::Policy;
+use radicle_node::runtime::TRACKING_DB_FILE;
+use radicle_node::service::tracking;
+use radicle_node::service::tracking::{Policy, Scope};
 use radicle_node::test::{
     environment::{Config, Environment},
     logger,
@@ -203,6 +206,29 @@ fn rad_patch() {
     test("examples/rad-patch.md", working.path(), Some(home), []).unwrap();
 }
 
+#[test]
+fn rad_checkout_patch() {
+    let mut environment = Environment::new();
+    let profile = environment.profile("alice");
+    let working = tempfile::tempdir().unwrap();
+    let copy = tempfile::tempdir().unwrap();
+    let home = &profile.home;
+
+    // Setup a test repository.
+    fixtures::repository(working.path());
+
+    test("examples/rad-init.md", working.path(), Some(home), []).unwrap();
+    test("examples/rad-issue.md", working.path(), Some(home), []).unwrap();
+    test("examples/rad-patch.md", working.path(), Some(home), []).unwrap();
+    test(
+        "examples/rad-checkout-patch.md",
+        copy.path(),
+        Some(home),
+        [],
+    )
+    .unwrap();
+}
+
 #[test]
 fn rad_rm() {
     let mut environment = Environment::new();
@@ -237,6 +263,283 @@ fn rad_clone() {
     test("examples/rad-clone.md", working, Some(&bob.home), []).unwrap();
 }
 
+#[test]
+fn rad_track_dry_run() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    test(
+        "examples/rad-track-dry-run.md",
+        working,
+        Some(&bob.home),
+        [],
+    )
+    .unwrap();
+
+    // Nothing was written to Bob's storage.
+    assert!(!bob.storage.contains(&rid).unwrap());
+}
+
+#[test]
+fn rad_track_no_relay() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let _ = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+
+    test(
+        "examples/rad-track-no-relay.md",
+        working,
+        Some(&alice.home),
+        [],
+    )
+    .unwrap();
+}
+
+#[test]
+fn rad_clone_track_remotes() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    test(
+        "examples/rad-clone-track-remotes.md",
+        working,
+        Some(&bob.home),
+        [],
+    )
+    .unwrap();
+
+    let tracking = tracking::Store::open(bob.home.node().join(TRACKING_DB_FILE)).unwrap();
+    assert_eq!(
+        tracking.repo_entry(&rid).unwrap(),
+        Some((Scope::DelegatesOnly, Policy::Track))
+    );
+}
+
+#[test]
+fn rad_clone_track_remotes_nids() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let eve = environment.node("eve");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    // Set a policy that the clone should replace.
+    bob.handle
+        .set_remote_tracking(rid, RemoteTracking::Remotes(vec![eve.id]))
+        .unwrap();
+
+    test(
+        "examples/rad-clone-track-remotes-nids.md",
+        working,
+        Some(&bob.home),
+        [],
+    )
+    .unwrap();
+
+    let tracking = tracking::Store::open(bob.home.node().join(TRACKING_DB_FILE)).unwrap();
+    assert_eq!(
+        tracking.repo_entry(&rid).unwrap(),
+        Some((Scope::DelegatesOnly, Policy::Track))
+    );
+    assert_eq!(
+        tracking.repo_remotes(&rid).unwrap(),
+        [alice.id, bob.id].into_iter().collect()
+    );
+}
+
+#[test]
+fn rad_clone_resume() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    // Simulate an interrupted clone: the repository was fetched into storage,
+    // but the project was neither forked nor checked out.
+    bob.handle.track_repo(rid).unwrap();
+    bob.handle.fetch(rid, alice.id).unwrap();
+
+    let marker = radicle_cli::commands::rad_clone::marker(&bob.home, &rid);
+    std::fs::create_dir_all(marker.parent().unwrap()).unwrap();
+    std::fs::write(&marker, []).unwrap();
+
+    test("examples/rad-clone-resume.md", working, Some(&bob.home), []).unwrap();
+
+    assert!(
+        !marker.exists(),
+        "the marker is removed once the clone succeeded"
+    );
+}
+
+#[test]
+fn rad_clone_resume_inconsistent() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    // Simulate an interrupted clone, whose repository was corrupted: one of the signed
+    // refs is missing.
+    bob.handle.track_repo(rid).unwrap();
+    bob.handle.fetch(rid, alice.id).unwrap();
+    bob.storage
+        .repository(rid)
+        .unwrap()
+        .backend
+        .find_reference(&format!("refs/namespaces/{}/refs/heads/master", alice.id))
+        .unwrap()
+        .delete()
+        .unwrap();
+    assert!(bob.storage.repository(rid).unwrap().verify().is_err());
+
+    let marker = radicle_cli::commands::rad_clone::marker(&bob.home, &rid);
+    std::fs::create_dir_all(marker.parent().unwrap()).unwrap();
+    std::fs::write(&marker, []).unwrap();
+
+    test(
+        "examples/rad-clone-resume-inconsistent.md",
+        working,
+        Some(&bob.home),
+        [],
+    )
+    .unwrap();
+
+    assert!(bob.storage.repository(rid).unwrap().verify().is_ok());
+    assert!(!marker.exists());
+}
+
+#[test]
+fn rad_clone_existing() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let rid = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let alice = alice.spawn(Config::default());
+    let mut bob = bob.spawn(Config::default());
+
+    bob.connect(&alice).converge([&alice]);
+
+    // The repository is already in storage, and forked, without a clone having been
+    // interrupted.
+    bob.handle.track_repo(rid).unwrap();
+    bob.handle.fetch(rid, alice.id).unwrap();
+    radicle::rad::fork(rid, &bob.signer, &bob.storage).unwrap();
+
+    let before = bob
+        .storage
+        .repository(rid)
+        .unwrap()
+        .remote(&bob.id)
+        .unwrap();
+
+    test(
+        "examples/rad-clone-existing.md",
+        working,
+        Some(&bob.home),
+        [],
+    )
+    .unwrap();
+
+    let repo = bob.storage.repository(rid).unwrap();
+    let after = repo.remote(&bob.id).unwrap();
+
+    assert!(repo.verify().is_ok());
+    assert_eq!(before.refs, after.refs);
+}
+
+#[test]
+fn rad_node_request_seeding() {
+    logger::init(log::Level::Debug);
+
+    let mut environment = Environment::new();
+    let mut alice = environment.node("alice");
+    let bob = environment.node("bob");
+    let working = environment.tmp().join("working");
+
+    // Setup a test project.
+    let _ = alice.project("heartwood", "Radicle Heartwood Protocol & Stack");
+
+    let mut alice = alice.spawn(Config::default());
+    let bob = bob.spawn(Config::default());
+
+    alice.connect(&bob).converge([&bob]);
+
+    test(
+        "examples/rad-node-request-seeding.md",
+        working,
+        Some(&alice.home),
+        [],
+    )
+    .unwrap();
+}
+
 #[test]
 fn rad_clone_unknown() {
     logger::init(log::Level::Debug);
@@ -3,7 +3,12 @@
 // This file is part of radicle-link, distributed under the GPLv3 with Radicle
 // Linking Exception. For full terms see the included LICENSE file.
 
-use std::{collections::BTreeSet, convert::TryInto};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryInto,
+    num::NonZeroUsize,
+    thread,
+};
 
 use git_ext::Oid;
 use radicle_dag::{Dag, Node};
@@ -83,6 +88,78 @@ impl ChangeGraph {
         builder.build(*oid)
     }
 
+    /// Load the change graph like [`ChangeGraph::load`], loading the changes of
+    /// each generation of the graph concurrently, on at most `parallelism` threads.
+    ///
+    /// Storage handles can't be shared between threads, so each thread loads
+    /// changes through its own handle, obtained with `open`. Changes are added
+    /// to the graph in the same order, however the loading is scheduled.
+    pub(crate) fn load_concurrent<'a, S, E>(
+        mut open: impl FnMut() -> Result<S, E>,
+        parallelism: NonZeroUsize,
+        tip_refs: impl Iterator<Item = &'a object::Reference> + 'a,
+        typename: &TypeName,
+        oid: &ObjectId,
+    ) -> Result<Option<ChangeGraph>, E>
+    where
+        S: change::Storage<ObjectId = Oid, Resource = Oid, Signatures = ExtendedSignature> + Send,
+    {
+        log::info!(
+            "loading object '{}' '{}' on up to {} threads",
+            typename,
+            oid,
+            parallelism
+        );
+        let mut builder = GraphBuilder::default();
+        let mut handles = Vec::with_capacity(parallelism.get());
+        // Commits to load, along with the child commit depending on them, if any.
+        let mut generation: Vec<(object::Commit, Option<Oid>)> = tip_refs
+            .map(|reference| (reference.target.clone(), None))
+            .collect();
+
+        while !generation.is_empty() {
+            // Each change is loaded once, even if it has more than one child.
+            let mut pending = generation
+                .iter()
+                .map(|(commit, _)| commit.id)
+                .filter(|id| !builder.contains(id))
+                .collect::<Vec<_>>();
+            pending.sort();
+            pending.dedup();
+
+            let mut loaded = load_changes(&mut handles, &mut open, parallelism, &pending)?;
+            let mut next = Vec::new();
+
+            for (commit, child) in generation {
+                let commit_id = commit.id;
+
+                match loaded.remove(&commit_id) {
+                    Some(Ok(change)) => {
+                        let new_edges = builder.add_change(commit, change);
+                        next.extend(new_edges.map(|(parent, child)| (parent, Some(child))));
+                    }
+                    Some(Err(e)) => {
+                        log::warn!(
+                            "unable to load change from commit '{}', error '{}'",
+                            commit_id,
+                            e
+                        );
+                        continue;
+                    }
+                    // The change failed to load earlier in this generation.
+                    None if !builder.contains(&commit_id) => continue,
+                    // The change is already in the graph, only the edge is new.
+                    None => {}
+                }
+                if let Some(child) = child {
+                    builder.add_edge(child, commit_id);
+                }
+            }
+            generation = next;
+        }
+        Ok(builder.build(*oid))
+    }
+
     /// Given a graph evaluate it to produce a collaborative object. This will
     /// filter out branches of the graph which do not have valid signatures.
     pub(crate) fn evaluate(&self) -> CollaborativeObject {
@@ -107,9 +184,82 @@ impl ChangeGraph {
         self.graph.tips().map(|(_, change)| *change.id()).collect()
     }
 
+    pub(crate) fn object_id(&self) -> ObjectId {
+        self.object_id
+    }
+
     pub(crate) fn number_of_nodes(&self) -> u64 {
         self.graph.len().try_into().unwrap()
     }
+
+    /// Render the graph in the graphviz `dot` format, with an edge from each change
+    /// to the changes it depends on.
+    pub(crate) fn graphviz(&self) -> String {
+        let mut visited = BTreeSet::new();
+        let mut stack = self.graph.tips().map(|(k, _)| *k).collect::<Vec<_>>();
+
+        while let Some(key) = stack.pop() {
+            if visited.insert(key) {
+                stack.extend(self.graph[&key].dependencies.iter().copied());
+            }
+        }
+
+        let mut dot = String::from("digraph {\n");
+        for key in &visited {
+            let mut dependencies = self.graph[key].dependencies.iter().collect::<Vec<_>>();
+            dependencies.sort();
+
+            dot.push_str(&format!(
+                "    \"{key}\" [label=\"{}\"];\n",
+                &key.to_string()[..7]
+            ));
+            for dependency in dependencies {
+                dot.push_str(&format!("    \"{key}\" -> \"{dependency}\";\n"));
+            }
+        }
+        dot.push('}');
+        dot
+    }
+}
+
+/// Load the given changes, spreading them over at most `parallelism` threads,
+/// each with its own storage handle. Handles are opened as needed, and kept in
+/// `handles` for subsequent calls.
+#[allow(clippy::type_complexity)]
+fn load_changes<S, E>(
+    handles: &mut Vec<S>,
+    open: &mut impl FnMut() -> Result<S, E>,
+    parallelism: NonZeroUsize,
+    ids: &[Oid],
+) -> Result<BTreeMap<Oid, Result<Change, S::LoadError>>, E>
+where
+    S: change::Storage<ObjectId = Oid, Resource = Oid, Signatures = ExtendedSignature> + Send,
+{
+    let chunk_size = (ids.len() + parallelism.get() - 1) / parallelism.get();
+    let chunks = ids.chunks(chunk_size.max(1)).collect::<Vec<_>>();
+
+    while handles.len() < chunks.len() {
+        handles.push(open()?);
+    }
+    let loaded = thread::scope(|scope| {
+        let workers = handles
+            .iter_mut()
+            .zip(chunks)
+            .map(|(storage, ids)| {
+                scope.spawn(move || {
+                    ids.iter()
+                        .map(|id| (*id, storage.load(*id)))
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("load_changes: worker thread panicked"))
+            .collect()
+    });
+    Ok(loaded)
 }
 
 struct GraphBuilder {
@@ -144,6 +294,11 @@ impl GraphBuilder {
         })
     }
 
+    /// Whether the change is already in the graph.
+    fn contains(&self, id: &Oid) -> bool {
+        self.graph.get(id).is_some()
+    }
+
     fn add_edge(&mut self, child: Oid, parent: Oid) {
         self.graph.dependency(child, parent);
     }
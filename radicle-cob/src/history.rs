@@ -114,6 +114,16 @@ impl History {
         pruning_fold::pruning_fold(init, items, f)
     }
 
+    /// Get the distinct actors that contributed changes to this history, in key order.
+    pub fn actors(&self) -> Vec<PublicKey> {
+        self.traverse(BTreeSet::new(), |mut acc, entry| {
+            acc.insert(*entry.actor());
+            ControlFlow::Continue(acc)
+        })
+        .into_iter()
+        .collect()
+    }
+
     pub fn tips(&self) -> BTreeSet<Oid> {
         self.graph
             .tips()
@@ -40,6 +40,11 @@ pub enum Retrieve {
         #[source]
         err: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
+    #[error("failed to open storage during object retrieval")]
+    Open {
+        #[source]
+        err: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
     #[error(transparent)]
     Io(#[from] std::io::Error),
 }
@@ -7,11 +7,13 @@
 //! representing a single [`crate::CollaborativeObject`]'s underlying
 //! change graph.
 
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, num::NonZeroUsize};
 
 use git_ext::Oid;
 
-use crate::{change_graph::ChangeGraph, ObjectId, Store, TypeName};
+use crate::{
+    change, change_graph::ChangeGraph, signatures::ExtendedSignature, ObjectId, Store, TypeName,
+};
 
 use super::error;
 
@@ -24,6 +26,8 @@ pub struct ChangeGraphInfo {
     /// The "tips" of the change graph, i.e the object IDs pointed to by
     /// references to the object
     pub tips: BTreeSet<Oid>,
+    /// The change graph, in the graphviz `dot` format
+    pub dotviz: String,
 }
 
 /// Retrieve additional information about the change graph of an object. This
@@ -46,11 +50,43 @@ where
     let tip_refs = storage
         .objects(typename, oid)
         .map_err(|err| error::Retrieve::Refs { err: Box::new(err) })?;
-    Ok(
-        ChangeGraph::load(storage, tip_refs.iter(), typename, oid).map(|graph| ChangeGraphInfo {
-            object_id: *oid,
+    Ok(ChangeGraph::load(storage, tip_refs.iter(), typename, oid).map(ChangeGraphInfo::new))
+}
+
+/// Like [`changegraph`], but loads the changes of the object concurrently, on
+/// at most `parallelism` threads. This is faster for objects with wide change
+/// graphs, eg. with many tips.
+///
+/// Since storage handles can't be shared between threads, each thread loads
+/// changes through its own handle, obtained with `open`.
+pub fn changegraph_concurrent<S, H, E>(
+    storage: &S,
+    open: impl FnMut() -> Result<H, E>,
+    parallelism: NonZeroUsize,
+    typename: &TypeName,
+    oid: &ObjectId,
+) -> Result<Option<ChangeGraphInfo>, error::Retrieve>
+where
+    S: Store,
+    H: change::Storage<ObjectId = Oid, Resource = Oid, Signatures = ExtendedSignature> + Send,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    let tip_refs = storage
+        .objects(typename, oid)
+        .map_err(|err| error::Retrieve::Refs { err: Box::new(err) })?;
+    let graph = ChangeGraph::load_concurrent(open, parallelism, tip_refs.iter(), typename, oid)
+        .map_err(|err| error::Retrieve::Open { err: Box::new(err) })?;
+
+    Ok(graph.map(ChangeGraphInfo::new))
+}
+
+impl ChangeGraphInfo {
+    fn new(graph: ChangeGraph) -> Self {
+        Self {
+            object_id: graph.object_id(),
             number_of_nodes: graph.number_of_nodes(),
             tips: graph.tips(),
-        }),
-    )
+            dotviz: graph.graphviz(),
+        }
+    }
 }
@@ -1,4 +1,4 @@
-use std::ops::ControlFlow;
+use std::{num::NonZeroUsize, ops::ControlFlow};
 
 use crypto::test::signer::MockSigner;
 use git_ref_format::{refname, Component, RefString};
@@ -7,7 +7,11 @@ use qcheck::Arbitrary;
 use radicle_crypto::Signer;
 
 use crate::{
-    create, get, list, object, test::arbitrary::Invalid, update, Create, ObjectId, TypeName, Update,
+    change::{self, Storage as _},
+    create, get, info, list,
+    object::{self, Storage as _},
+    test::arbitrary::Invalid,
+    update, Create, ObjectId, TypeName, Update,
 };
 
 use super::test;
@@ -144,6 +148,143 @@ fn update_cob() {
     assert_eq!(updated, expected);
 }
 
+#[test]
+fn changegraph_info() {
+    let storage = test::Storage::new();
+    let signer = gen::<MockSigner>(1);
+    let terry = test::Person::new(&storage, "terry", *signer.public_key()).unwrap();
+    let proj = test::Project::new(&storage, "discworld", *signer.public_key()).unwrap();
+    let proj = test::RemoteProject {
+        project: proj,
+        person: terry,
+    };
+    let typename = "xyz.rad.issue".parse::<TypeName>().unwrap();
+    let cob = create(
+        &storage,
+        &signer,
+        &proj,
+        &proj.identifier(),
+        Create {
+            history_type: "test".to_string(),
+            contents: nonempty!(Vec::new()),
+            typename: typename.clone(),
+            message: "creating xyz.rad.issue".to_string(),
+        },
+    )
+    .unwrap();
+    update(
+        &storage,
+        &signer,
+        &proj,
+        &proj.identifier(),
+        Update {
+            changes: nonempty!(b"issue 1".to_vec()),
+            history_type: "test".to_string(),
+            object_id: *cob.id(),
+            typename: typename.clone(),
+            message: "commenting xyz.rad.issue".to_string(),
+        },
+    )
+    .unwrap();
+
+    let info = info::changegraph(&storage, &typename, cob.id())
+        .unwrap()
+        .expect("BUG: cob was missing");
+    let tip = info.tips.iter().next().unwrap();
+
+    assert_eq!(info.number_of_nodes, 2);
+    assert_eq!(info.tips.len(), 1);
+    assert!(info.dotviz.starts_with("digraph {"));
+    assert!(info
+        .dotviz
+        .contains(&format!("\"{tip}\" -> \"{}\";", cob.id())));
+
+    let missing = gen::<ObjectId>(1);
+    assert!(info::changegraph(&storage, &typename, &missing)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn changegraph_concurrent() {
+    let storage = test::Storage::new();
+    let signer = gen::<MockSigner>(1);
+    let terry = test::Person::new(&storage, "terry", *signer.public_key()).unwrap();
+    let proj = test::Project::new(&storage, "discworld", *signer.public_key()).unwrap();
+    let terry_proj = test::RemoteProject {
+        project: proj.clone(),
+        person: terry,
+    };
+    let typename = "xyz.rad.issue".parse::<TypeName>().unwrap();
+    let cob = create(
+        &storage,
+        &signer,
+        &terry_proj,
+        &terry_proj.identifier(),
+        Create {
+            history_type: "test".to_string(),
+            contents: nonempty!(Vec::new()),
+            typename: typename.clone(),
+            message: "creating xyz.rad.issue".to_string(),
+        },
+    )
+    .unwrap();
+    let root = info::changegraph(&storage, &typename, cob.id())
+        .unwrap()
+        .expect("BUG: cob was missing")
+        .tips;
+
+    // Branch off the root change from many remotes, each with a chain of changes,
+    // so that the graph is both wide and deep.
+    for i in 0..16 {
+        let person =
+            test::Person::new(&storage, &format!("actor-{i}"), *signer.public_key()).unwrap();
+        let remote = test::RemoteProject {
+            project: proj.clone(),
+            person,
+        };
+        let mut tips = root.iter().copied().collect::<Vec<_>>();
+
+        for j in 0..4 {
+            let change = storage
+                .store(
+                    proj.content_id,
+                    &signer,
+                    change::Template {
+                        typename: typename.clone(),
+                        history_type: "test".to_string(),
+                        tips,
+                        message: "commenting xyz.rad.issue".to_string(),
+                        contents: nonempty!(format!("comment {i}.{j}").into_bytes()),
+                    },
+                )
+                .unwrap();
+            storage
+                .update(&remote.identifier(), &typename, cob.id(), &change)
+                .unwrap();
+            tips = vec![change.id];
+        }
+    }
+
+    let expected = info::changegraph(&storage, &typename, cob.id())
+        .unwrap()
+        .expect("BUG: cob was missing");
+    let open = || git2::Repository::open(storage.as_raw().path());
+
+    for parallelism in [1, 3, 16, 64] {
+        let parallelism = NonZeroUsize::new(parallelism).unwrap();
+        let info = info::changegraph_concurrent(&storage, open, parallelism, &typename, cob.id())
+            .unwrap()
+            .expect("BUG: cob was missing");
+
+        assert_eq!(info.number_of_nodes, 1 + 16 * 4);
+        assert_eq!(info.tips.len(), 16);
+        assert_eq!(info.number_of_nodes, expected.number_of_nodes);
+        assert_eq!(info.tips, expected.tips);
+        assert_eq!(info.dotviz, expected.dotviz);
+    }
+}
+
 #[test]
 fn traverse_cobs() {
     let storage = test::Storage::new();
@@ -217,6 +358,67 @@ fn traverse_cobs() {
     assert_eq!(contents, vec![b"issue 1".to_vec(), b"issue 2".to_vec()]);
 }
 
+#[test]
+fn cob_actors() {
+    let storage = test::Storage::new();
+    let neil_signer = gen::<MockSigner>(2);
+    let neil = test::Person::new(&storage, "gaiman", *neil_signer.public_key()).unwrap();
+    let terry_signer = gen::<MockSigner>(1);
+    let terry = test::Person::new(&storage, "pratchett", *terry_signer.public_key()).unwrap();
+    let proj = test::Project::new(&storage, "discworld", *terry_signer.public_key()).unwrap();
+    let terry_proj = test::RemoteProject {
+        project: proj.clone(),
+        person: terry,
+    };
+    let neil_proj = test::RemoteProject {
+        project: proj,
+        person: neil,
+    };
+    let typename = "xyz.rad.issue".parse::<TypeName>().unwrap();
+    let cob = create(
+        &storage,
+        &terry_signer,
+        &terry_proj,
+        &terry_proj.identifier(),
+        Create {
+            contents: nonempty!(b"issue 1".to_vec()),
+            history_type: "test".to_string(),
+            typename: typename.clone(),
+            message: "creating xyz.rad.issue".to_string(),
+        },
+    )
+    .unwrap();
+    copy_to(
+        storage.as_raw(),
+        &terry_proj,
+        &neil_proj,
+        &typename,
+        *cob.id(),
+    )
+    .unwrap();
+
+    let updated = update(
+        &storage,
+        &neil_signer,
+        &neil_proj,
+        &neil_proj.identifier(),
+        Update {
+            changes: nonempty!(b"issue 2".to_vec()),
+            history_type: "test".to_string(),
+            object_id: *cob.id(),
+            typename,
+            message: "commenting on xyz.rad.issue".to_string(),
+        },
+    )
+    .unwrap();
+
+    let mut expected = vec![*terry_signer.public_key(), *neil_signer.public_key()];
+    expected.sort();
+
+    assert_eq!(cob.history().actors(), vec![*terry_signer.public_key()]);
+    assert_eq!(updated.history().actors(), expected);
+}
+
 #[quickcheck]
 fn parse_refstr(oid: ObjectId, typename: TypeName) {
     let suffix = refname!("refs/cobs")
@@ -22,6 +22,18 @@ impl MockSigner {
 
         Self::from(SecretKey::from(keypair.sk))
     }
+
+    /// Create a deterministic signer from an index. The same index always yields the
+    /// same signer, and different indices yield distinct signers.
+    pub fn from_index(index: u8) -> Self {
+        Self::from_seed([index; 32])
+    }
+
+    /// Create `n` deterministic, distinct signers, eg. for the nodes of a simulated
+    /// network.
+    pub fn sequence(n: u8) -> impl Iterator<Item = Self> {
+        (0..n).map(Self::from_index)
+    }
 }
 
 impl From<SecretKey> for MockSigner {
@@ -100,3 +112,40 @@ impl cyphernet::Ecdh for MockSigner {
         self.sk.ecdh(pk).map_err(|_| cyphernet::EcdhError::WeakPk)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_from_index() {
+        assert_eq!(
+            MockSigner::from_index(7).public_key(),
+            MockSigner::from_index(7).public_key()
+        );
+        assert_ne!(
+            MockSigner::from_index(7).public_key(),
+            MockSigner::from_index(8).public_key()
+        );
+
+        let signers = MockSigner::sequence(3).collect::<Vec<_>>();
+        assert_eq!(signers.len(), 3);
+        assert_eq!(signers[1], MockSigner::from_index(1));
+        assert_ne!(signers[0], signers[2]);
+    }
+
+    #[test]
+    #[cfg(feature = "cyphernet")]
+    fn test_ecdh() {
+        use cyphernet::Ecdh as _;
+
+        let alice = MockSigner::from_index(1);
+        let bob = MockSigner::from_index(2);
+        let eve = MockSigner::from_index(3);
+        let shared = alice.ecdh(bob.public_key()).unwrap();
+
+        assert_eq!(shared, bob.ecdh(alice.public_key()).unwrap());
+        assert_ne!(shared, alice.ecdh(eve.public_key()).unwrap());
+        assert_ne!(shared, [0; 32]);
+    }
+}
@@ -35,3 +35,19 @@ create table if not exists "addresses" (
   unique ("node", "type", "value")
   --
 ) strict;
+
+create table if not exists "timestamps" (
+  -- What the timestamp marks, eg. our own announcements.
+  "key"                text      primary key not null,
+  -- Timestamp in milliseconds since epoch.
+  "timestamp"          integer   not null
+  --
+) strict;
+
+create table if not exists "persistent" (
+  -- Node ID.
+  "node"               text      primary key not null,
+  -- Address we maintain a connection to the node at.
+  "address"            text      not null
+  --
+) strict;
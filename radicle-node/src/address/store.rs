@@ -12,6 +12,7 @@ use crate::clock::Timestamp;
 use crate::service::NodeId;
 use crate::sql::transaction;
 use crate::wire::AddressType;
+use crate::LocalTime;
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -40,7 +41,8 @@ impl Book {
     /// Open an address book at the given path. Creates a new address book if it
     /// doesn't exist.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let db = sql::Connection::open(path)?;
+        let mut db = sql::Connection::open(path)?;
+        db.set_busy_timeout(crate::sql::BUSY_TIMEOUT)?;
         db.execute(Self::SCHEMA)?;
 
         Ok(Self { db })
@@ -69,9 +71,10 @@ impl Store for Book {
             let timestamp = row.read::<i64, _>("timestamp") as Timestamp;
             let mut addrs = Vec::new();
 
-            let mut stmt = self
-                .db
-                .prepare("SELECT type, value, source FROM addresses WHERE node = ?")?;
+            let mut stmt = self.db.prepare(
+                "SELECT type, value, source, last_success, last_attempt
+                 FROM addresses WHERE node = ?",
+            )?;
             stmt.bind((1, node))?;
 
             for row in stmt.into_iter() {
@@ -79,12 +82,14 @@ impl Store for Book {
                 let _typ = row.read::<AddressType, _>("type");
                 let addr = row.read::<Address, _>("value");
                 let source = row.read::<Source, _>("source");
+                let last_success = row.read::<Option<i64>, _>("last_success");
+                let last_attempt = row.read::<Option<i64>, _>("last_attempt");
 
                 addrs.push(KnownAddress {
                     addr,
                     source,
-                    last_success: None,
-                    last_attempt: None,
+                    last_success: last_success.map(|t| LocalTime::from_millis(t as u128)),
+                    last_attempt: last_attempt.map(|t| LocalTime::from_millis(t as u128)),
                 });
             }
 
@@ -197,6 +202,145 @@ impl Store for Book {
         }
         Ok(Box::new(entries.into_iter()))
     }
+
+    fn attempted(&mut self, node: &NodeId, addr: &Address, time: LocalTime) -> Result<(), Error> {
+        let mut stmt = self.db.prepare(
+            "UPDATE addresses SET last_attempt = ?3
+             WHERE node = ?1 AND value = ?2",
+        )?;
+
+        stmt.bind((1, node))?;
+        stmt.bind((2, addr.clone()))?;
+        stmt.bind((3, time.as_millis() as i64))?;
+        stmt.next()?;
+
+        Ok(())
+    }
+
+    fn connected(&mut self, node: &NodeId, time: LocalTime) -> Result<(), Error> {
+        let mut stmt = self.db.prepare(
+            "UPDATE addresses SET last_success = ?2
+             WHERE node = ?1 AND last_attempt = (
+                SELECT MAX(last_attempt) FROM addresses WHERE node = ?1
+             )",
+        )?;
+
+        stmt.bind((1, node))?;
+        stmt.bind((2, time.as_millis() as i64))?;
+        stmt.next()?;
+
+        Ok(())
+    }
+
+    fn candidates(&self) -> Result<Box<dyn Iterator<Item = (NodeId, KnownAddress)>>, Error> {
+        // Addresses are ordered in three groups: addresses that were successfully connected
+        // to after they were last attempted, addresses that were never attempted, and
+        // finally, addresses whose last attempt failed. Within these groups, the most
+        // recently successful addresses come first, followed by the least recently attempted.
+        let mut stmt = self
+            .db
+            .prepare(
+                "SELECT node, type, value, source, last_success, last_attempt FROM addresses
+                 ORDER BY
+                    CASE
+                        WHEN last_attempt IS NULL THEN 1
+                        WHEN last_success >= last_attempt THEN 0
+                        ELSE 2
+                    END,
+                    last_success DESC,
+                    last_attempt ASC",
+            )?
+            .into_iter();
+        let mut entries = Vec::new();
+
+        while let Some(Ok(row)) = stmt.next() {
+            let node = row.read::<NodeId, _>("node");
+            let _typ = row.read::<AddressType, _>("type");
+            let addr = row.read::<Address, _>("value");
+            let source = row.read::<Source, _>("source");
+            let last_success = row.read::<Option<i64>, _>("last_success");
+            let last_attempt = row.read::<Option<i64>, _>("last_attempt");
+
+            entries.push((
+                node,
+                KnownAddress {
+                    addr,
+                    source,
+                    last_success: last_success.map(|t| LocalTime::from_millis(t as u128)),
+                    last_attempt: last_attempt.map(|t| LocalTime::from_millis(t as u128)),
+                },
+            ));
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+
+    fn persist(&mut self, node: &NodeId, addr: &Address) -> Result<bool, Error> {
+        let mut stmt = self.db.prepare(
+            "INSERT INTO persistent (node, address)
+             VALUES (?1, ?2)
+             ON CONFLICT DO UPDATE
+             SET address = ?2
+             WHERE address <> ?2",
+        )?;
+
+        stmt.bind((1, node))?;
+        stmt.bind((2, addr.clone()))?;
+        stmt.next()?;
+
+        Ok(self.db.change_count() > 0)
+    }
+
+    fn unpersist(&mut self, node: &NodeId) -> Result<bool, Error> {
+        let mut stmt = self.db.prepare("DELETE FROM persistent WHERE node = ?")?;
+
+        stmt.bind((1, node))?;
+        stmt.next()?;
+
+        Ok(self.db.change_count() > 0)
+    }
+
+    fn persistent(&self) -> Result<Vec<(NodeId, Address)>, Error> {
+        let stmt = self
+            .db
+            .prepare("SELECT node, address FROM persistent ORDER BY node")?;
+        let mut peers = Vec::new();
+
+        for row in stmt.into_iter() {
+            let row = row?;
+            let node = row.read::<NodeId, _>("node");
+            let addr = row.read::<Address, _>("address");
+
+            peers.push((node, addr));
+        }
+        Ok(peers)
+    }
+
+    fn announced(&self) -> Result<Option<Timestamp>, Error> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT timestamp FROM timestamps WHERE key = 'announced'")?
+            .into_iter();
+
+        match stmt.next() {
+            Some(row) => Ok(Some(row?.read::<i64, _>("timestamp") as Timestamp)),
+            None => Ok(None),
+        }
+    }
+
+    fn set_announced(&mut self, timestamp: Timestamp) -> Result<(), Error> {
+        let mut stmt = self.db.prepare(
+            "INSERT INTO timestamps (key, timestamp)
+             VALUES ('announced', ?1)
+             ON CONFLICT DO UPDATE
+             SET timestamp = ?1
+             WHERE timestamp < ?1",
+        )?;
+
+        stmt.bind((1, timestamp as i64))?;
+        stmt.next()?;
+
+        Ok(())
+    }
 }
 
 /// Address store.
@@ -226,6 +370,29 @@ pub trait Store {
     }
     /// Get the address entries in the store.
     fn entries(&self) -> Result<Box<dyn Iterator<Item = (NodeId, KnownAddress)>>, Error>;
+    /// Mark a node address as attempted at the given time.
+    fn attempted(&mut self, node: &NodeId, addr: &Address, time: LocalTime) -> Result<(), Error>;
+    /// Mark the last attempted address of a node as successfully connected to.
+    /// An address that was attempted but not connected to is considered to have failed.
+    fn connected(&mut self, node: &NodeId, time: LocalTime) -> Result<(), Error>;
+    /// Get the address entries in the store, ordered by reachability, ie. addresses that we
+    /// recently connected to successfully come before addresses that recently failed.
+    fn candidates(&self) -> Result<Box<dyn Iterator<Item = (NodeId, KnownAddress)>>, Error>;
+    /// Record a peer we maintain a connection to, so that we reconnect to it on restart,
+    /// replacing its previous address, if any.
+    ///
+    /// Returns `true` if the peer or its address were updated, and `false` otherwise.
+    fn persist(&mut self, node: &NodeId, addr: &Address) -> Result<bool, Error>;
+    /// Stop maintaining a connection to a peer, so that we don't reconnect to it on restart.
+    ///
+    /// Returns `true` if the peer was persistent, and `false` otherwise.
+    fn unpersist(&mut self, node: &NodeId) -> Result<bool, Error>;
+    /// Get the peers we maintain a connection to.
+    fn persistent(&self) -> Result<Vec<(NodeId, Address)>, Error>;
+    /// Get the last recorded timestamp of our own announcements, if any.
+    fn announced(&self) -> Result<Option<Timestamp>, Error>;
+    /// Record the timestamp of our own announcements. Earlier timestamps are ignored.
+    fn set_announced(&mut self, timestamp: Timestamp) -> Result<(), Error>;
 }
 
 impl TryFrom<&sql::Value> for Source {
@@ -296,7 +463,7 @@ mod test {
 
     use super::*;
     use crate::test::arbitrary;
-    use crate::LocalTime;
+    use crate::LocalDuration;
 
     #[test]
     fn test_empty() {
@@ -351,6 +518,34 @@ mod test {
         assert_eq!(node.addrs, vec![ka]);
     }
 
+    #[test]
+    fn test_insert_and_get_ipv6() {
+        let alice = arbitrary::gen::<NodeId>(1);
+        let mut cache = Book::memory().unwrap();
+        let timestamp = LocalTime::now().as_millis();
+        let ip = net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x4);
+        let ka = KnownAddress::new(net::SocketAddr::from((ip, 8776)).into(), Source::Peer);
+
+        cache
+            .insert(
+                &alice,
+                node::Features::SEED,
+                "alice",
+                timestamp,
+                [ka.clone()],
+            )
+            .unwrap();
+
+        let node = cache.get(&alice).unwrap().unwrap();
+        assert_eq!(node.addrs, vec![ka.clone()]);
+
+        cache.attempted(&alice, &ka.addr, LocalTime::now()).unwrap();
+        let (id, candidate) = cache.candidates().unwrap().next().unwrap();
+        assert_eq!(id, alice);
+        assert_eq!(candidate.addr, ka.addr);
+        assert!(candidate.last_attempt.is_some());
+    }
+
     #[test]
     fn test_insert_duplicate() {
         let alice = arbitrary::gen::<NodeId>(1);
@@ -463,6 +658,46 @@ mod test {
         assert_eq!(cache.len().unwrap(), 0);
     }
 
+    #[test]
+    fn test_persistent() {
+        let alice = arbitrary::gen::<NodeId>(1);
+        let mut cache = Book::memory().unwrap();
+        let addr: Address = net::SocketAddr::from(([4, 4, 4, 4], 8776)).into();
+        let other: Address = net::SocketAddr::from(([7, 7, 7, 7], 8776)).into();
+
+        assert!(cache.persistent().unwrap().is_empty());
+        assert!(cache.persist(&alice, &addr).unwrap());
+        assert!(!cache.persist(&alice, &addr).unwrap());
+        assert_eq!(cache.persistent().unwrap(), vec![(alice, addr)]);
+
+        // Persisting a peer again replaces its address.
+        assert!(cache.persist(&alice, &other).unwrap());
+        assert_eq!(cache.persistent().unwrap(), vec![(alice, other)]);
+
+        assert!(cache.unpersist(&alice).unwrap());
+        assert!(!cache.unpersist(&alice).unwrap());
+        assert!(cache.persistent().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_announced() {
+        let mut cache = Book::memory().unwrap();
+        let timestamp = LocalTime::now().as_millis();
+
+        assert_eq!(cache.announced().unwrap(), None);
+
+        cache.set_announced(timestamp).unwrap();
+        assert_eq!(cache.announced().unwrap(), Some(timestamp));
+
+        cache.set_announced(timestamp - 1).unwrap();
+        assert_eq!(
+            cache.announced().unwrap(),
+            Some(timestamp),
+            "The timestamp can't go back"
+        );
+        assert!(cache.is_empty().unwrap(), "No node is added");
+    }
+
     #[test]
     fn test_entries() {
         let ids = arbitrary::vec::<NodeId>(16);
@@ -496,4 +731,51 @@ mod test {
         assert_eq!(cache.len().unwrap(), actual.len());
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_candidates_by_reachability() {
+        let alice = arbitrary::gen::<NodeId>(1);
+        let bob = arbitrary::gen::<NodeId>(1);
+        let eve = arbitrary::gen::<NodeId>(1);
+        let mut cache = Book::memory().unwrap();
+        let now = LocalTime::now();
+        let features = node::Features::SEED;
+        let addr = |ip: [u8; 4]| -> Address { net::SocketAddr::from((ip, 8776)).into() };
+
+        for (id, alias, ip) in [
+            (alice, "alice", [4, 4, 4, 4]),
+            (bob, "bob", [7, 7, 7, 7]),
+            (eve, "eve", [9, 9, 9, 9]),
+        ] {
+            let ka = KnownAddress::new(addr(ip), Source::Peer);
+            cache
+                .insert(&id, features, alias, now.as_millis(), [ka])
+                .unwrap();
+        }
+
+        // Alice and Bob are both attempted, but only Bob is connected to.
+        // Eve is never attempted.
+        cache.attempted(&alice, &addr([4, 4, 4, 4]), now).unwrap();
+        cache.attempted(&bob, &addr([7, 7, 7, 7]), now).unwrap();
+        cache
+            .connected(&bob, now + LocalDuration::from_secs(1))
+            .unwrap();
+
+        let candidates = cache
+            .candidates()
+            .unwrap()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(candidates, vec![bob, eve, alice]);
+
+        let node = cache.get(&bob).unwrap().unwrap();
+        assert_eq!(
+            node.addrs[0].last_attempt.unwrap().as_millis(),
+            now.as_millis()
+        );
+        assert_eq!(
+            node.addrs[0].last_success.unwrap().as_millis(),
+            (now + LocalDuration::from_secs(1)).as_millis()
+        );
+    }
 }
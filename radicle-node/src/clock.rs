@@ -1,2 +1,123 @@
+use std::collections::HashMap;
+
+use localtime::{LocalDuration, LocalTime};
+
+use crate::node::NodeId;
+
 /// Milliseconds since epoch.
 pub type Timestamp = u64;
+
+/// Timestamp helpers.
+pub trait TimestampExt {
+    /// Check whether a timestamp is neither older than `max_age`, nor further than `max_skew`
+    /// in the future, relative to `now`.
+    fn is_fresh(&self, now: LocalTime, max_age: LocalDuration, max_skew: LocalDuration) -> bool;
+
+    /// Check whether a timestamp is further than `max_skew` in the future, relative to `now`.
+    fn is_future(&self, now: LocalTime, max_skew: LocalDuration) -> bool;
+}
+
+impl TimestampExt for Timestamp {
+    fn is_fresh(&self, now: LocalTime, max_age: LocalDuration, max_skew: LocalDuration) -> bool {
+        let age = now.as_millis().saturating_sub(*self) as u128;
+
+        age <= max_age.as_millis() && !self.is_future(now, max_skew)
+    }
+
+    fn is_future(&self, now: LocalTime, max_skew: LocalDuration) -> bool {
+        let skew = self.saturating_sub(now.as_millis()) as u128;
+
+        skew > max_skew.as_millis()
+    }
+}
+
+/// Estimate of the network time, based on the clocks of our peers.
+#[derive(Debug, Default, Clone)]
+pub struct NetworkClock {
+    /// Last observed offset of each peer's clock relative to ours, in milliseconds.
+    offsets: HashMap<NodeId, i64>,
+}
+
+impl NetworkClock {
+    /// Record a timestamp set by a peer, received at the given local time.
+    pub fn record(&mut self, peer: NodeId, timestamp: Timestamp, now: LocalTime) {
+        let offset = timestamp as i128 - now.as_millis() as i128;
+
+        self.offsets.insert(peer, offset as i64);
+    }
+
+    /// Stop taking a peer's clock into account, eg. when it disconnects.
+    pub fn remove(&mut self, peer: &NodeId) {
+        self.offsets.remove(peer);
+    }
+
+    /// Estimated offset of the network time relative to ours, in milliseconds.
+    /// This is the median of our peers' offsets, or zero if we have none.
+    pub fn offset(&self) -> i64 {
+        let mut offsets = self.offsets.values().copied().collect::<Vec<_>>();
+        offsets.sort_unstable();
+
+        offsets.get(offsets.len() / 2).copied().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::arbitrary;
+
+    #[test]
+    fn test_is_fresh() {
+        let now = LocalTime::from_secs(1_000_000);
+        let max_age = LocalDuration::from_mins(10);
+        let max_skew = LocalDuration::from_mins(1);
+        let t = now.as_millis();
+
+        // In-window.
+        assert!(t.is_fresh(now, max_age, max_skew));
+        assert!((t - 1000 * 60 * 10).is_fresh(now, max_age, max_skew));
+        assert!((t + 1000 * 60).is_fresh(now, max_age, max_skew));
+
+        // Too far in the past.
+        assert!(!(t - 1000 * 60 * 10 - 1).is_fresh(now, max_age, max_skew));
+        assert!(!Timestamp::MIN.is_fresh(now, max_age, max_skew));
+
+        // Too far in the future.
+        assert!(!(t + 1000 * 60 + 1).is_fresh(now, max_age, max_skew));
+        assert!(!Timestamp::MAX.is_fresh(now, max_age, max_skew));
+    }
+
+    #[test]
+    fn test_is_future() {
+        let now = LocalTime::from_secs(1_000_000);
+        let max_skew = LocalDuration::from_mins(1);
+        let t = now.as_millis();
+
+        assert!(!Timestamp::MIN.is_future(now, max_skew));
+        assert!(!t.is_future(now, max_skew));
+        assert!(!(t + 1000 * 60).is_future(now, max_skew));
+        assert!((t + 1000 * 60 + 1).is_future(now, max_skew));
+        assert!(Timestamp::MAX.is_future(now, max_skew));
+    }
+
+    #[test]
+    fn test_network_clock_offset() {
+        let now = LocalTime::from_secs(1_000_000);
+        let t = now.as_millis();
+        let mut clock = NetworkClock::default();
+        let peers = arbitrary::vec::<NodeId>(3);
+
+        assert_eq!(clock.offset(), 0);
+
+        clock.record(peers[0], t + 1000, now);
+        assert_eq!(clock.offset(), 1000);
+
+        clock.record(peers[1], t - 2000, now);
+        clock.record(peers[2], t + 1000 * 3600, now);
+        assert_eq!(clock.offset(), 1000, "Outliers don't skew the estimate");
+
+        clock.remove(&peers[0]);
+        clock.record(peers[1], t - 4000, now);
+        assert_eq!(clock.offset(), 1000 * 3600);
+    }
+}
@@ -6,14 +6,14 @@ use std::os::unix::net::UnixListener;
 use std::os::unix::net::UnixStream;
 use std::path::PathBuf;
 use std::str::FromStr;
-use std::{io, net};
+use std::{io, net, thread};
 
 use radicle::node::Handle;
 use serde_json as json;
 
 use crate::identity::Id;
-use crate::node::NodeId;
-use crate::node::{Command, CommandName, CommandResult, FetchResult};
+use crate::node::{Address, ConnectOptions, NodeId};
+use crate::node::{Command, CommandName, CommandResult, FetchResult, RemoteTracking};
 use crate::runtime;
 
 #[derive(thiserror::Error, Debug)]
@@ -90,12 +90,75 @@ fn command<H: Handle<Error = runtime::HandleError, FetchResult = FetchResult>>(
 
     match cmd.name {
         CommandName::Connect => {
-            todo!();
+            let (nid, addr, persistent): (NodeId, Address, bool) = parse::args3(cmd)?;
+
+            match handle.connect(nid, addr, ConnectOptions { persistent }) {
+                Ok(()) => {
+                    CommandResult::ok().to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
+        CommandName::Unpersist => {
+            let nid: NodeId = parse::arg(cmd)?;
+
+            match handle.unpersist(nid) {
+                Ok(updated) => {
+                    CommandResult::Okay { updated }.to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
         }
         CommandName::Fetch => {
             let (rid, nid): (Id, NodeId) = parse::args(cmd)?;
             fetch(rid, nid, LineWriter::new(stream), handle)?;
         }
+        CommandName::FetchDryRun => {
+            let (rid, nid): (Id, NodeId) = parse::args(cmd)?;
+
+            match handle.fetch_dry_run(rid, nid) {
+                Ok(result) => {
+                    json::to_writer(writer, &result)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
+        CommandName::FetchDebug => {
+            let rid: Id = parse::arg(cmd)?;
+            let log = handle.fetch_debug(rid)?;
+            let mut writer = LineWriter::new(stream.try_clone()?);
+
+            // The log is streamed from another thread, so that we can keep processing
+            // commands in the meantime, eg. the one that initiates the fetch.
+            thread::spawn(move || {
+                for line in log {
+                    let Ok(line) = json::to_string(&line) else {
+                        break;
+                    };
+                    if writeln!(writer, "{line}").is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        CommandName::FetchHinted => {
+            let (rid, nid, addr): (Id, NodeId, Address) = parse::args3(cmd)?;
+
+            match handle.fetch_hinted(rid, nid, addr) {
+                Ok(result) => {
+                    json::to_writer(writer, &result)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
         CommandName::Seeds => {
             let rid: Id = parse::arg(cmd)?;
             let seeds = handle.seeds(rid)?;
@@ -157,6 +220,54 @@ fn command<H: Handle<Error = runtime::HandleError, FetchResult = FetchResult>>(
                 }
             }
         }
+        CommandName::TrackRemote => {
+            let (rid, nid): (Id, NodeId) = parse::args(cmd)?;
+
+            match handle.track_remote(rid, nid) {
+                Ok(updated) => {
+                    CommandResult::Okay { updated }.to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
+        CommandName::UntrackRemote => {
+            let (rid, nid): (Id, NodeId) = parse::args(cmd)?;
+
+            match handle.untrack_remote(rid, nid) {
+                Ok(updated) => {
+                    CommandResult::Okay { updated }.to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
+        CommandName::SetRemoteTracking => {
+            let (rid, policy): (Id, RemoteTracking) = parse::args(cmd)?;
+
+            match handle.set_remote_tracking(rid, policy) {
+                Ok(updated) => {
+                    CommandResult::Okay { updated }.to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
+        CommandName::SetRepoRelay => {
+            let (rid, relay): (Id, bool) = parse::args(cmd)?;
+
+            match handle.set_repo_relay(rid, relay) {
+                Ok(updated) => {
+                    CommandResult::Okay { updated }.to_writer(writer)?;
+                }
+                Err(e) => {
+                    return Err(CommandError::Runtime(e));
+                }
+            }
+        }
         CommandName::AnnounceRefs => {
             let rid: Id = parse::arg(cmd)?;
 
@@ -165,6 +276,14 @@ fn command<H: Handle<Error = runtime::HandleError, FetchResult = FetchResult>>(
             }
             CommandResult::ok().to_writer(writer).ok();
         }
+        CommandName::RequestSeeding => {
+            let rid: Id = parse::arg(cmd)?;
+
+            if let Err(e) = handle.request_seeding(rid) {
+                return Err(CommandError::Runtime(e));
+            }
+            CommandResult::ok().to_writer(writer).ok();
+        }
         CommandName::SyncInventory => match handle.sync_inventory() {
             Ok(updated) => {
                 CommandResult::Okay { updated }.to_writer(writer)?;
@@ -173,9 +292,28 @@ fn command<H: Handle<Error = runtime::HandleError, FetchResult = FetchResult>>(
                 return Err(CommandError::Runtime(e));
             }
         },
+        CommandName::Reconcile => match handle.reconcile() {
+            Ok(updated) => {
+                CommandResult::Okay { updated }.to_writer(writer)?;
+            }
+            Err(e) => {
+                return Err(CommandError::Runtime(e));
+            }
+        },
+        CommandName::Sessions => {
+            let sessions = handle.list_sessions()?;
+
+            json::to_writer(writer, &sessions)?;
+        }
         CommandName::Status => {
             CommandResult::ok().to_writer(writer).ok();
         }
+        CommandName::Compact => match handle.compact() {
+            Ok(reclaimed) => {
+                json::to_writer(writer, &reclaimed)?;
+            }
+            Err(e) => return Err(CommandError::Runtime(e)),
+        },
         CommandName::Routing => match handle.routing() {
             Ok(c) => {
                 for (id, seed) in c.iter() {
@@ -186,9 +324,17 @@ fn command<H: Handle<Error = runtime::HandleError, FetchResult = FetchResult>>(
         },
         CommandName::Inventory => match handle.inventory() {
             Ok(c) => {
-                for id in c.iter() {
-                    writeln!(writer, "{id}")?;
-                }
+                let inventory = c.iter().collect::<Vec<_>>();
+
+                json::to_writer(writer, &inventory)?;
+            }
+            Err(e) => return Err(CommandError::Runtime(e)),
+        },
+        CommandName::TrackedInventory => match handle.tracked_inventory() {
+            Ok(c) => {
+                let inventory = c.iter().collect::<Vec<_>>();
+
+                json::to_writer(writer, &inventory)?;
             }
             Err(e) => return Err(CommandError::Runtime(e)),
         },
@@ -253,6 +399,33 @@ mod parse {
 
         Ok((arg1, arg2))
     }
+
+    pub(super) fn args3<S: FromStr, T: FromStr, U: FromStr>(
+        cmd: Command,
+    ) -> Result<(S, T, U), CommandError>
+    where
+        <S as FromStr>::Err: std::error::Error + 'static,
+        <T as FromStr>::Err: std::error::Error + 'static,
+        <U as FromStr>::Err: std::error::Error + 'static,
+    {
+        let [arg1, arg2, arg3]: [String; 3] = cmd
+            .args
+            .clone()
+            .try_into()
+            .map_err(|_| CommandError::InvalidCommandArgs(cmd.args))?;
+
+        let arg1 = arg1
+            .parse()
+            .map_err(|e| CommandError::InvalidCommandArg(arg1, Box::new(e)))?;
+        let arg2 = arg2
+            .parse()
+            .map_err(|e| CommandError::InvalidCommandArg(arg2, Box::new(e)))?;
+        let arg3 = arg3
+            .parse()
+            .map_err(|e| CommandError::InvalidCommandArg(arg3, Box::new(e)))?;
+
+        Ok((arg1, arg2, arg3))
+    }
 }
 
 #[cfg(test)]
@@ -305,6 +478,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_request_seeding() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("node.sock");
+        let proj = test::arbitrary::gen::<Id>(1);
+        let listener = UnixListener::bind(&socket).unwrap();
+        let mut node = Node::new(&socket);
+        let handle = crate::test::handle::Handle::default();
+
+        thread::spawn({
+            let handle = handle.clone();
+
+            move || crate::control::listen(listener, handle)
+        });
+
+        // Wait for node to be online.
+        while !node.is_running() {}
+
+        node.request_seeding(proj).unwrap();
+        assert_eq!(*handle.seed_requests.lock().unwrap(), vec![proj]);
+    }
+
+    #[test]
+    fn test_connect() {
+        let tmp = tempfile::tempdir().unwrap();
+        let socket = tmp.path().join("node.sock");
+        let peer = test::arbitrary::gen::<NodeId>(1);
+        let addr: Address = "127.0.0.1:8776".parse().unwrap();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let mut node = Node::new(&socket);
+        let handle = crate::test::handle::Handle::default();
+
+        thread::spawn({
+            let handle = handle.clone();
+
+            move || crate::control::listen(listener, handle)
+        });
+
+        // Wait for node to be online.
+        while !node.is_running() {}
+
+        let opts = ConnectOptions { persistent: true };
+        node.connect(peer, addr.clone(), opts).unwrap();
+        assert_eq!(
+            *handle.connections.lock().unwrap(),
+            vec![(peer, addr, opts)]
+        );
+
+        assert!(node.unpersist(peer).unwrap());
+        assert!(!node.unpersist(peer).unwrap());
+    }
+
     #[test]
     fn test_track_untrack() {
         let tmp = tempfile::tempdir().unwrap();
@@ -336,5 +561,15 @@ mod tests {
             .unwrap());
         assert!(handle.untrack_node(peer).unwrap());
         assert!(!handle.untrack_node(peer).unwrap());
+
+        assert!(handle.track_remote(proj, peer).unwrap());
+        assert!(!handle.track_remote(proj, peer).unwrap());
+        assert!(handle.untrack_remote(proj, peer).unwrap());
+        assert!(!handle.untrack_remote(proj, peer).unwrap());
+
+        assert!(handle.set_repo_relay(proj, false).unwrap());
+        assert!(!handle.set_repo_relay(proj, false).unwrap());
+        assert!(handle.set_repo_relay(proj, true).unwrap());
+        assert!(!handle.set_repo_relay(proj, true).unwrap());
     }
 }
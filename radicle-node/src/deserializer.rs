@@ -1,5 +1,4 @@
 use std::io;
-use std::marker::PhantomData;
 
 use crate::service::message::Message;
 use crate::wire;
@@ -8,27 +7,49 @@ use crate::wire;
 ///
 /// Used to for example turn a byte stream into network messages.
 #[derive(Debug)]
-pub struct Deserializer<D = Message> {
+pub struct Deserializer<D: wire::Resume = Message> {
     unparsed: Vec<u8>,
-    item: PhantomData<D>,
+    /// Progress made decoding the next message, up to `offset`.
+    state: D::State,
+    /// Number of unparsed bytes already consumed by the decoding progress.
+    offset: usize,
+    /// Number of unparsed bytes needed before decoding can make progress.
+    /// This is known from the last decoding attempt that ran out of input, and lets us
+    /// skip attempts that would fail again, when a message arrives in small chunks.
+    needed: usize,
+    /// Maximum size of a message, in bytes.
+    limit: usize,
 }
 
-impl<D> From<Vec<u8>> for Deserializer<D> {
+impl<D: wire::Resume> Default for Deserializer<D> {
+    fn default() -> Self {
+        Self::from(Vec::new())
+    }
+}
+
+impl<D: wire::Resume> From<Vec<u8>> for Deserializer<D> {
     fn from(unparsed: Vec<u8>) -> Self {
         Self {
             unparsed,
-            item: PhantomData,
+            state: D::State::default(),
+            offset: 0,
+            needed: 0,
+            limit: usize::MAX,
         }
     }
 }
 
-impl<D: wire::Decode> Deserializer<D> {
+impl<D: wire::Resume> Deserializer<D> {
     /// Create a new stream decoder.
     pub fn new(capacity: usize) -> Self {
-        Self {
-            unparsed: Vec::with_capacity(capacity),
-            item: PhantomData,
-        }
+        Self::from(Vec::with_capacity(capacity))
+    }
+
+    /// Set the maximum size of a message. Messages known to be larger are rejected with
+    /// [`wire::Error::MessageTooLarge`], without waiting for the rest of their input.
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
     }
 
     /// Input bytes into the decoder.
@@ -36,24 +57,57 @@ impl<D: wire::Decode> Deserializer<D> {
         self.unparsed.extend_from_slice(bytes);
     }
 
+    /// Number of bytes that weren't decoded yet.
+    pub fn len(&self) -> usize {
+        self.unparsed.len()
+    }
+
+    /// Whether there are no bytes left to decode.
+    pub fn is_empty(&self) -> bool {
+        self.unparsed.is_empty()
+    }
+
+    /// Take the bytes that weren't decoded yet, leaving the decoder empty.
+    pub fn unparsed(&mut self) -> Vec<u8> {
+        self.state = D::State::default();
+        self.offset = 0;
+        self.needed = 0;
+        std::mem::take(&mut self.unparsed)
+    }
+
     /// Decode and return the next message. Returns [`None`] if nothing was decoded.
     pub fn deserialize_next(&mut self) -> Result<Option<D>, wire::Error> {
-        let mut reader = io::Cursor::new(self.unparsed.as_mut_slice());
+        if self.unparsed.len() < self.needed {
+            return Ok(None);
+        }
+        let mut input = wire::Input::new(&self.unparsed, self.offset);
 
-        match D::decode(&mut reader) {
+        match D::resume(&mut self.state, &mut input) {
+            Ok(_) if input.position() > self.limit => Err(wire::Error::MessageTooLarge(self.limit)),
             Ok(msg) => {
-                let pos = reader.position() as usize;
+                let pos = input.position();
                 self.unparsed.drain(..pos);
+                self.offset = 0;
+                self.needed = 0;
 
                 Ok(Some(msg))
             }
-            Err(err) if err.is_eof() => Ok(None),
+            Err(err) if err.is_eof() && input.needed() > self.limit => {
+                Err(wire::Error::MessageTooLarge(self.limit))
+            }
+            Err(err) if err.is_eof() => {
+                // Resume from where decoding stopped, once more input is available.
+                self.offset = input.position();
+                self.needed = input.needed();
+
+                Ok(None)
+            }
             Err(err) => Err(err),
         }
     }
 }
 
-impl<D: wire::Decode> io::Write for Deserializer<D> {
+impl<D: wire::Resume> io::Write for Deserializer<D> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
         self.input(buf);
 
@@ -65,7 +119,7 @@ impl<D: wire::Decode> io::Write for Deserializer<D> {
     }
 }
 
-impl<D: wire::Decode> Iterator for Deserializer<D> {
+impl<D: wire::Resume> Iterator for Deserializer<D> {
     type Item = Result<D, wire::Error>;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -76,6 +130,7 @@ impl<D: wire::Decode> Iterator for Deserializer<D> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::test::assert_matches;
     use qcheck_macros::quickcheck;
 
     const MSG_HELLO: &[u8] = &[5, b'h', b'e', b'l', b'l', b'o'];
@@ -105,4 +160,83 @@ mod test {
         assert_eq!(msgs[0], String::from("hello"));
         assert_eq!(msgs[1], String::from("bye"));
     }
+
+    #[test]
+    fn test_decode_limit() {
+        let mut decoder = Deserializer::<String>::new(8).with_limit(MSG_BYE.len());
+
+        decoder.input(MSG_BYE);
+        assert_eq!(
+            decoder.deserialize_next().unwrap(),
+            Some(String::from("bye"))
+        );
+
+        // Rejected as soon as the length prefix is known, not once the input is complete.
+        decoder.input(&MSG_HELLO[..2]);
+        assert_matches!(
+            decoder.deserialize_next(),
+            Err(wire::Error::MessageTooLarge(limit)) if limit == MSG_BYE.len()
+        );
+
+        let mut decoder = Deserializer::<String>::new(8).with_limit(MSG_BYE.len());
+        decoder.input(MSG_HELLO);
+        assert_matches!(
+            decoder.deserialize_next(),
+            Err(wire::Error::MessageTooLarge(_))
+        );
+    }
+
+    #[test]
+    fn test_decode_progress() {
+        use crate::crypto::test::signer::MockSigner;
+        use crate::prelude::Id;
+        use crate::service::message::InventoryAnnouncement;
+        use crate::test::arbitrary;
+        use crate::wire::Encode as _;
+
+        let signer = MockSigner::default();
+        let inventory = arbitrary::vec::<Id>(300);
+        let msg = Message::inventory(
+            InventoryAnnouncement {
+                inventory: inventory.try_into().unwrap(),
+                timestamp: 0,
+            },
+            &signer,
+        );
+        let mut bytes = Vec::new();
+        msg.encode(&mut bytes).unwrap();
+
+        let mut decoder = Deserializer::<Message>::new(8);
+        let mut attempts = 0;
+        let mut work = 0;
+        let mut decoded = None;
+
+        for byte in &bytes {
+            decoder.input(&[*byte]);
+
+            if decoder.unparsed.len() >= decoder.needed {
+                attempts += 1;
+                // Decoding resumes where the last attempt stopped.
+                work += decoder.unparsed.len() - decoder.offset;
+            }
+            if let Some(msg) = decoder.deserialize_next().unwrap() {
+                decoded = Some(msg);
+            }
+        }
+        assert_eq!(decoded, Some(msg));
+        assert!(decoder.unparsed.is_empty());
+        // Decoding is only attempted once enough bytes for the next field arrived,
+        // instead of once per byte.
+        assert!(
+            attempts * 8 < bytes.len(),
+            "{attempts} attempts for {} bytes",
+            bytes.len()
+        );
+        // Decoded bytes aren't decoded again, so the work done is linear in the input size.
+        assert!(
+            work < 2 * bytes.len(),
+            "{work} bytes decoded for {} bytes",
+            bytes.len()
+        );
+    }
 }
@@ -1,43 +1,144 @@
 //! Logging module.
 use std::io;
+use std::str::FromStr;
 
 use chrono::prelude::*;
 use colored::*;
-use log::{Level, Log, Metadata, Record, SetLoggerError};
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde_json as json;
 
-struct Logger {
-    level: Level,
+/// Level of targets without their own entry, if the filter doesn't set one.
+pub const DEFAULT_LEVEL: LevelFilter = LevelFilter::Debug;
+
+/// Log output format.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    /// Colorized lines, meant to be read by humans.
+    #[default]
+    Human,
+    /// One JSON object per line, with the `ts`, `level`, `target` and `msg` keys.
+    /// Meant to be read by log aggregators.
+    Json,
 }
 
-impl Log for Logger {
-    fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid log format `{s}`")),
+        }
     }
+}
 
-    fn log(&self, record: &Record) {
-        if self.enabled(record.metadata()) {
-            let target = record.target();
+/// Log level filter, with optional per-target levels.
+///
+/// Parsed from a comma-separated list of `<target>=<level>` pairs, eg.
+/// `debug,transport=warn`, where `off` disables logging. A bare `<level>` sets the level
+/// of targets without their own entry, which is otherwise [`DEFAULT_LEVEL`], and a bare
+/// `<target>` logs everything from that target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    /// Level of targets without their own entry.
+    level: LevelFilter,
+    /// Per-target levels.
+    targets: Vec<(String, LevelFilter)>,
+}
 
-            if record.level() == Level::Error {
-                write(record, target, io::stderr());
-            } else {
-                write(record, target, io::stdout());
+impl Filter {
+    /// Level records of the given target must be at or below to be logged.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(t, _)| {
+                target
+                    .strip_prefix(t.as_str())
+                    .map_or(false, |rest| rest.is_empty() || rest.starts_with("::"))
+            })
+            // The most specific target wins.
+            .max_by_key(|(t, _)| t.len())
+            .map_or(self.level, |(_, level)| *level)
+    }
+
+    /// Most verbose level of any target.
+    pub fn max_level(&self) -> LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.level, LevelFilter::max)
+    }
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self::from(DEFAULT_LEVEL)
+    }
+}
+
+impl From<LevelFilter> for Filter {
+    fn from(level: LevelFilter) -> Self {
+        Self {
+            level,
+            targets: Vec::new(),
+        }
+    }
+}
+
+impl From<Level> for Filter {
+    fn from(level: Level) -> Self {
+        Self::from(level.to_level_filter())
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |level: &str| {
+            LevelFilter::from_str(level).map_err(|_| format!("invalid log level `{level}`"))
+        };
+        let mut filter = Self::default();
+
+        for directive in s.split(',').map(str::trim).filter(|d| !d.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    filter
+                        .targets
+                        .push((target.trim().to_owned(), parse(level.trim())?));
+                }
+                None => match LevelFilter::from_str(directive) {
+                    Ok(level) => filter.level = level,
+                    Err(_) => filter
+                        .targets
+                        .push((directive.to_owned(), LevelFilter::Trace)),
+                },
             }
+        }
+        Ok(filter)
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    format: Format,
+}
+
+impl Logger {
+    fn write(&self, record: &Record, mut stream: impl io::Write) {
+        let target = record.target();
+        let ts = Local::now().to_rfc3339_opts(SecondsFormat::Millis, true);
 
-            fn write(record: &log::Record, target: &str, mut stream: impl io::Write) {
+        match self.format {
+            Format::Human => {
                 let message = format!(
                     "{:<5} {:<8} {}",
                     record.level(),
                     target.cyan(),
                     record.args()
                 );
-
-                let message = format!(
-                    "{} {}",
-                    Local::now().to_rfc3339_opts(SecondsFormat::Millis, true),
-                    message,
-                );
-
+                let message = format!("{ts} {message}");
                 let message = match record.level() {
                     Level::Error => message.red(),
                     Level::Warn => message.yellow(),
@@ -47,6 +148,31 @@ impl Log for Logger {
                 };
                 writeln!(stream, "{message}").expect("write shouldn't fail");
             }
+            Format::Json => {
+                let message = json::json!({
+                    "ts": ts,
+                    "level": record.level().as_str(),
+                    "target": target,
+                    "msg": record.args().to_string(),
+                });
+                writeln!(stream, "{message}").expect("write shouldn't fail");
+            }
+        }
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.filter.level(metadata.target())
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            if record.level() == Level::Error {
+                self.write(record, io::stderr());
+            } else {
+                self.write(record, io::stdout());
+            }
         }
     }
 
@@ -54,11 +180,116 @@ impl Log for Logger {
 }
 
 /// Initialize a new logger.
-pub fn init(level: Level) -> Result<(), SetLoggerError> {
-    let logger = Logger { level };
+pub fn init(filter: impl Into<Filter>, format: Format) -> Result<(), SetLoggerError> {
+    let filter = filter.into();
+    let max_level = filter.max_level();
+    let logger = Logger { filter, format };
 
     log::set_boxed_logger(Box::new(logger))?;
-    log::set_max_level(level.to_level_filter());
+    log::set_max_level(max_level);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_format() {
+        let logger = Logger {
+            filter: Filter::from(Level::Debug),
+            format: Format::Json,
+        };
+        let mut buf = Vec::new();
+
+        logger.write(
+            &Record::builder()
+                .args(format_args!("Connected to \"bob\""))
+                .level(Level::Info)
+                .target("service")
+                .build(),
+            &mut buf,
+        );
+        logger.write(
+            &Record::builder()
+                .args(format_args!("Dropping\nmessage"))
+                .level(Level::Error)
+                .target("wire")
+                .build(),
+            &mut buf,
+        );
+
+        let lines = String::from_utf8(buf).unwrap();
+        let lines = lines
+            .lines()
+            .map(|l| json::from_str::<json::Value>(l).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 2, "Each record is on its own line");
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["target"], "service");
+        assert_eq!(lines[0]["msg"], "Connected to \"bob\"");
+        assert!(lines[0]["ts"].is_string());
+        assert_eq!(lines[1]["level"], "ERROR");
+        assert_eq!(lines[1]["msg"], "Dropping\nmessage");
+    }
+
+    #[test]
+    fn test_target_filter() {
+        let filter = Filter::from_str("debug,transport=warn").unwrap();
+        let logger = Logger {
+            filter,
+            format: Format::Human,
+        };
+        let enabled = |target, level| {
+            logger.enabled(&Metadata::builder().target(target).level(level).build())
+        };
+
+        assert!(enabled("service", Level::Debug));
+        assert!(!enabled("service", Level::Trace));
+        assert!(enabled("transport", Level::Warn));
+        assert!(!enabled("transport", Level::Info));
+        assert!(
+            !enabled("transport::socks5", Level::Info),
+            "Sub-targets are covered"
+        );
+        assert!(
+            enabled("transports", Level::Info),
+            "Only whole targets match"
+        );
+        assert_eq!(logger.filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn test_filter_from_str() {
+        assert_eq!(Filter::from_str("debug"), Ok(Filter::from(Level::Debug)));
+        assert_eq!(Filter::from_str(""), Ok(Filter::from(DEFAULT_LEVEL)));
+        assert_eq!(
+            Filter::from_str("wire=error").unwrap().level("service"),
+            DEFAULT_LEVEL
+        );
+        assert_eq!(
+            Filter::from_str("service=trace, wire=error")
+                .unwrap()
+                .targets,
+            vec![
+                (String::from("service"), LevelFilter::Trace),
+                (String::from("wire"), LevelFilter::Error),
+            ]
+        );
+        assert!(Filter::from_str("service=loud").is_err());
+
+        let filter = Filter::from_str("off,worker").unwrap();
+        assert_eq!(filter.level("service"), LevelFilter::Off);
+        assert_eq!(
+            filter.level("worker"),
+            LevelFilter::Trace,
+            "A bare target logs everything"
+        );
+        assert_eq!(
+            Filter::from_str("wire=off").unwrap().level("wire"),
+            LevelFilter::Off
+        );
+    }
+}
@@ -23,6 +23,9 @@ Options
     --git-daemon       <address>     Address to bind git-daemon to (default 0.0.0.0:9418)
     --help                           Print help
     --listen           <address>     Address to listen on
+    --log              <filter>      Log level, optionally per target, eg. `info,service=debug` (default `RUST_LOG`, or debug)
+    --log-format       <format>      Log output format, `human` or `json` (default human)
+    --standby          <nid>         Run as a hot standby of the given primary node
 
 "#;
 
@@ -31,8 +34,14 @@ struct Options {
     connect: Vec<(NodeId, Address)>,
     external_addresses: Vec<Address>,
     daemon: Option<net::SocketAddr>,
-    limits: service::config::Limits,
+    routing_max_age: Option<LocalDuration>,
+    routing_max_size: Option<usize>,
     listen: Vec<net::SocketAddr>,
+    log: logger::Filter,
+    log_format: logger::Format,
+    /// Error parsing `RUST_LOG`, which is then ignored.
+    log_env_error: Option<String>,
+    standby: Option<NodeId>,
 }
 
 impl Options {
@@ -42,9 +51,21 @@ impl Options {
         let mut parser = lexopt::Parser::from_env();
         let mut connect = Vec::new();
         let mut external_addresses = Vec::new();
-        let mut limits = service::config::Limits::default();
+        let mut routing_max_age = None;
+        let mut routing_max_size = None;
         let mut listen = Vec::new();
         let mut daemon = None;
+        let mut log_env_error = None;
+        let mut log = match env::var("RUST_LOG").map(|filter| filter.parse()) {
+            Ok(Ok(filter)) => filter,
+            Ok(Err(e)) => {
+                log_env_error = Some(e);
+                logger::Filter::default()
+            }
+            Err(_) => logger::Filter::default(),
+        };
+        let mut log_format = logger::Format::default();
+        let mut standby = None;
 
         while let Some(arg) = parser.next()? {
             match arg {
@@ -62,15 +83,24 @@ impl Options {
                 }
                 Long("limit-routing-max-age") => {
                     let secs: u64 = parser.value()?.parse()?;
-                    limits.routing_max_age = LocalDuration::from_secs(secs);
+                    routing_max_age = Some(LocalDuration::from_secs(secs));
                 }
                 Long("limit-routing-max-size") => {
-                    limits.routing_max_size = parser.value()?.parse()?;
+                    routing_max_size = Some(parser.value()?.parse()?);
                 }
                 Long("listen") => {
                     let addr = parser.value()?.parse()?;
                     listen.push(addr);
                 }
+                Long("log") => {
+                    log = parser.value()?.parse()?;
+                }
+                Long("log-format") => {
+                    log_format = parser.value()?.parse()?;
+                }
+                Long("standby") => {
+                    standby = Some(parser.value()?.parse()?);
+                }
                 Long("help") => {
                     println!("{HELP_MSG}");
                     process::exit(0);
@@ -90,28 +120,47 @@ impl Options {
             connect,
             daemon,
             external_addresses,
-            limits,
+            routing_max_age,
+            routing_max_size,
             listen,
+            log,
+            log_format,
+            log_env_error,
+            standby,
         })
     }
 }
 
 fn execute() -> anyhow::Result<()> {
-    logger::init(log::Level::Debug)?;
-
     let options = Options::from_env()?;
+
+    logger::init(options.log, options.log_format)?;
+
+    if let Some(e) = options.log_env_error {
+        log::warn!(target: "node", "Ignoring invalid `RUST_LOG` value: {e}");
+    }
+
     let home = profile::home()?;
     let passphrase = env::var(profile::env::RAD_PASSPHRASE)
         .context("`RAD_PASSPHRASE` is required to be set for the node to establish connections")?
         .into();
     let keystore = Keystore::new(&home.keys());
     let signer = MemorySigner::load(&keystore, passphrase)?;
-    let config = service::Config {
-        connect: options.connect.into_iter().collect(),
-        external_addresses: options.external_addresses,
-        limits: options.limits,
-        ..service::Config::default()
-    };
+    // Options passed on the command line take precedence over the configuration file.
+    let mut config = service::Config::load(&home)?;
+    config.connect.extend(options.connect);
+    if !options.external_addresses.is_empty() {
+        config.external_addresses = options.external_addresses;
+    }
+    if let Some(age) = options.routing_max_age {
+        config.limits.routing_max_age = age;
+    }
+    if let Some(size) = options.routing_max_size {
+        config.limits.routing_max_size = size;
+    }
+    if let Some(primary) = options.standby {
+        config.standby = Some(primary);
+    }
     let proxy = net::SocketAddr::new(net::Ipv4Addr::LOCALHOST.into(), 9050);
     let daemon = options.daemon.unwrap_or_else(|| {
         net::SocketAddr::new(
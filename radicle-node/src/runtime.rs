@@ -15,6 +15,7 @@ use thiserror::Error;
 use radicle::git;
 use radicle::node::Handle as _;
 use radicle::profile::Home;
+use radicle::storage::ReadStorage as _;
 use radicle::Storage;
 
 use crate::address;
@@ -101,6 +102,7 @@ impl<G: Signer + Ecdh + 'static> Runtime<G> {
         let id = *signer.public_key();
         let node_dir = home.node();
         let network = config.network;
+        let quota = config.limits.max_storage_bytes;
         let rng = fastrand::Rng::new();
         let clock = LocalTime::now();
         let storage = Storage::open(home.storage())?;
@@ -145,6 +147,27 @@ impl<G: Signer + Ecdh + 'static> Runtime<G> {
         }
         let reactor = Reactor::named(wire, popol::Poller::new(), id.to_human())?;
         let handle = Handle::new(home.clone(), reactor.controller());
+
+        // Measuring the storage means walking all of it, so it's done on its own thread,
+        // and the result is fed back to the service. The thread exits with the reactor.
+        if quota.is_some() {
+            thread::Builder::new().name(id.to_human()).spawn({
+                let storage = storage.clone();
+                let mut handle = handle.clone();
+
+                move || loop {
+                    match storage.size() {
+                        Ok(size) => {
+                            if handle.storage_size(size).is_err() {
+                                break;
+                            }
+                        }
+                        Err(e) => log::error!(target: "node", "Error measuring storage: {e}"),
+                    }
+                    thread::sleep(service::IDLE_INTERVAL.into());
+                }
+            })?;
+        }
         let atomic = git::version()? >= git::VERSION_REQUIRED;
 
         if !atomic {
@@ -3,6 +3,7 @@ use std::io;
 use std::os::unix::net::UnixStream;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time;
 
 use crossbeam_channel as chan;
 use cyphernet::Ecdh;
@@ -10,14 +11,19 @@ use thiserror::Error;
 
 use crate::crypto::Signer;
 use crate::identity::Id;
-use crate::node::{Command, FetchResult};
+use crate::node::{Command, ConnectOptions, FetchResult, RemoteTracking, SessionInfo};
 use crate::profile::Home;
+use crate::runtime::{ADDRESS_DB_FILE, ROUTING_DB_FILE};
 use crate::service;
 use crate::service::{CommandError, QueryState};
 use crate::service::{NodeId, Sessions};
+use crate::sql;
 use crate::wire;
 use crate::worker::TaskResult;
 
+/// How long to wait for in-flight fetches to complete when shutting down.
+pub const SHUTDOWN_TIMEOUT: time::Duration = time::Duration::from_secs(9);
+
 /// An error resulting from a handle method.
 #[derive(Error, Debug)]
 pub enum Error {
@@ -33,6 +39,9 @@ pub enum Error {
     /// An I/O error occured.
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    /// Compacting a database failed.
+    #[error("compaction failed: {0}")]
+    Compact(#[from] sql::CompactError),
 }
 
 impl From<chan::RecvError> for Error {
@@ -98,6 +107,11 @@ impl<G: Signer + Ecdh + 'static> Handle<G> {
         Ok(())
     }
 
+    /// Report the size of our storage, as measured off the reactor thread.
+    pub fn storage_size(&mut self, size: u64) -> Result<(), Error> {
+        self.command(service::Command::StorageSize(size))
+    }
+
     fn command(&self, cmd: service::Command) -> Result<(), Error> {
         self.controller.cmd(wire::Control::User(cmd))?;
         Ok(())
@@ -113,12 +127,23 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         true
     }
 
-    fn connect(&mut self, node: NodeId, addr: radicle::node::Address) -> Result<(), Error> {
-        self.command(service::Command::Connect(node, addr))?;
+    fn connect(
+        &mut self,
+        node: NodeId,
+        addr: radicle::node::Address,
+        opts: ConnectOptions,
+    ) -> Result<(), Error> {
+        self.command(service::Command::Connect(node, addr, opts))?;
 
         Ok(())
     }
 
+    fn unpersist(&mut self, node: NodeId) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::Unpersist(node, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
     fn seeds(&mut self, id: Id) -> Result<Vec<NodeId>, Self::Error> {
         let (sender, receiver) = chan::bounded(1);
         self.command(service::Command::Seeds(id, sender))?;
@@ -131,6 +156,30 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         receiver.recv().map_err(Error::from)
     }
 
+    fn fetch_dry_run(&mut self, id: Id, from: NodeId) -> Result<FetchResult, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::FetchDryRun(id, from, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
+    fn fetch_debug(&mut self, id: Id) -> Result<chan::Receiver<String>, Error> {
+        let (sender, receiver) = chan::unbounded();
+        self.command(service::Command::FetchDebug(id, sender))?;
+
+        Ok(receiver)
+    }
+
+    fn fetch_hinted(
+        &mut self,
+        id: Id,
+        from: NodeId,
+        addr: radicle::node::Address,
+    ) -> Result<FetchResult, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::FetchHinted(id, from, addr, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
     fn track_node(&mut self, id: NodeId, alias: Option<String>) -> Result<bool, Error> {
         let (sender, receiver) = chan::bounded(1);
         self.command(service::Command::TrackNode(id, alias, sender))?;
@@ -143,6 +192,30 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         receiver.recv().map_err(Error::from)
     }
 
+    fn track_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::TrackRemote(id, remote, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
+    fn untrack_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::UntrackRemote(id, remote, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
+    fn set_remote_tracking(&mut self, id: Id, policy: RemoteTracking) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::SetRemoteTracking(id, policy, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
+    fn set_repo_relay(&mut self, id: Id, relay: bool) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::SetRepoRelay(id, relay, sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
     fn track_repo(&mut self, id: Id) -> Result<bool, Error> {
         let (sender, receiver) = chan::bounded(1);
         self.command(service::Command::TrackRepo(id, sender))?;
@@ -159,12 +232,22 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         self.command(service::Command::AnnounceRefs(id))
     }
 
+    fn request_seeding(&mut self, id: Id) -> Result<(), Error> {
+        self.command(service::Command::RequestSeeding(id))
+    }
+
     fn sync_inventory(&mut self) -> Result<bool, Error> {
         let (sender, receiver) = chan::bounded(1);
         self.command(service::Command::SyncInventory(sender))?;
         receiver.recv().map_err(Error::from)
     }
 
+    fn reconcile(&mut self) -> Result<bool, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::Reconcile(sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
     fn routing(&self) -> Result<chan::Receiver<(Id, NodeId)>, Error> {
         let (sender, receiver) = chan::unbounded();
         let query: Arc<QueryState> = Arc::new(move |state| {
@@ -197,23 +280,54 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         Ok(sessions)
     }
 
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::ListSessions(sender))?;
+        receiver.recv().map_err(Error::from)
+    }
+
     fn inventory(&self) -> Result<chan::Receiver<Id>, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::Inventory(sender))?;
+
+        let inventory = receiver.recv()?;
         let (sender, receiver) = chan::unbounded();
-        let query: Arc<QueryState> = Arc::new(move |state| {
-            for id in state.inventory()?.iter() {
-                if sender.send(*id).is_err() {
-                    break;
-                }
-            }
-            Ok(())
-        });
-        let (err_sender, err_receiver) = chan::bounded(1);
-        self.command(service::Command::QueryState(query, err_sender))?;
-        err_receiver.recv()??;
+        for id in inventory {
+            sender.send(id).ok();
+        }
+        Ok(receiver)
+    }
+
+    fn tracked_inventory(&self) -> Result<chan::Receiver<Id>, Error> {
+        let (sender, receiver) = chan::bounded(1);
+        self.command(service::Command::TrackedInventory(sender))?;
 
+        let inventory = receiver.recv()?;
+        let (sender, receiver) = chan::unbounded();
+        for id in inventory {
+            sender.send(id).ok();
+        }
         Ok(receiver)
     }
 
+    /// Compact the routing and address databases. This uses its own database connections,
+    /// and runs on the caller's thread, eg. the control socket's, so that the reactor isn't
+    /// blocked meanwhile. The service's own connections wait for compaction to complete,
+    /// up to [`sql::BUSY_TIMEOUT`], instead of failing on a locked database.
+    fn compact(&mut self) -> Result<u64, Error> {
+        let dir = self.home.node();
+        let mut reclaimed = 0;
+
+        for file in [ROUTING_DB_FILE, ADDRESS_DB_FILE] {
+            let path = dir.join(file);
+            let bytes = sql::compact(&path)?;
+
+            log::info!(target: "node", "Compacted {}, reclaiming {bytes} byte(s)", path.display());
+            reclaimed += bytes;
+        }
+        Ok(reclaimed)
+    }
+
     fn shutdown(self) -> Result<(), Error> {
         // If the current value is `false`, set it to `true`, otherwise error.
         if self
@@ -223,6 +337,14 @@ impl<G: Signer + Ecdh + 'static> radicle::node::Handle for Handle<G> {
         {
             return Ok(());
         }
+        // Let the node wind down first, so that ongoing fetches aren't interrupted. If it
+        // takes too long, we shut down anyway.
+        let (sender, receiver) = chan::bounded(1);
+        if self.controller.cmd(wire::Control::Shutdown(sender)).is_ok()
+            && receiver.recv_timeout(SHUTDOWN_TIMEOUT).is_err()
+        {
+            log::warn!(target: "node", "Timed out waiting for fetches to complete; shutting down..");
+        }
         // Send a shutdown request to our own control socket. This is the only way to kill the
         // control thread gracefully. Since the control thread may have called this function,
         // the control socket may already be disconnected. Ignore errors.
@@ -10,30 +10,33 @@ pub mod session;
 pub mod tracking;
 
 use std::collections::hash_map::Entry;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::ops::{Deref, DerefMut};
 use std::sync::Arc;
 use std::{fmt, net, str};
 
 use crossbeam_channel as chan;
+use cyphernet::addr::HostName;
 use fastrand::Rng;
 use localtime::{LocalDuration, LocalTime};
 use log::*;
 
 use crate::address;
 use crate::address::AddressBook;
-use crate::clock::Timestamp;
+use crate::clock::{NetworkClock, Timestamp, TimestampExt as _};
 use crate::crypto;
 use crate::crypto::{Signer, Verified};
+use crate::git;
 use crate::identity::{Doc, Id};
 use crate::node;
-use crate::node::{Address, Features, FetchResult};
+use crate::node::{Address, ConnectOptions, Features, FetchResult, RemoteTracking, SessionInfo};
 use crate::prelude::*;
-use crate::service::message::{Announcement, AnnouncementMessage, Ping};
+use crate::service::message::{Announcement, AnnouncementMessage, Ping, SignatureScheme};
 use crate::service::message::{NodeAnnouncement, RefsAnnouncement};
 use crate::storage;
 use crate::storage::{Inventory, ReadRepository, RefUpdate, WriteStorage};
 use crate::storage::{Namespaces, ReadStorage};
+use crate::wire;
 use crate::worker::FetchError;
 use crate::Link;
 
@@ -53,18 +56,35 @@ pub const TARGET_OUTBOUND_PEERS: usize = 8;
 pub const IDLE_INTERVAL: LocalDuration = LocalDuration::from_secs(30);
 /// How often to run the "announce" task.
 pub const ANNOUNCE_INTERVAL: LocalDuration = LocalDuration::from_secs(30);
-/// How often to run the "sync" task.
+/// How often to run the "sync" task, which sends a digest of our recent announcements
+/// to our peers.
 pub const SYNC_INTERVAL: LocalDuration = LocalDuration::from_secs(60);
 /// How often to run the "prune" task.
 pub const PRUNE_INTERVAL: LocalDuration = LocalDuration::from_mins(30);
-/// Duration to wait on an unresponsive peer before dropping its connection.
-pub const STALE_CONNECTION_TIMEOUT: LocalDuration = LocalDuration::from_secs(60);
 /// How much time should pass after a peer was last active for a *ping* to be sent.
 pub const KEEP_ALIVE_DELTA: LocalDuration = LocalDuration::from_secs(30);
 /// Maximum time difference between the local time, and an announcement timestamp.
 pub const MAX_TIME_DELTA: LocalDuration = LocalDuration::from_mins(60);
 /// Maximum attempts to connect to a peer before we give up.
 pub const MAX_CONNECTION_ATTEMPTS: usize = 3;
+/// Number of consecutive fetches from a seed failing because the repository is missing,
+/// before the seed is removed from the repository's routing entries.
+pub const MAX_MISSING_FETCHES: usize = 3;
+/// Minimum time between two fetches of the same repository triggered by seed requests.
+pub const SEED_REQUEST_INTERVAL: LocalDuration = LocalDuration::from_mins(1);
+/// Minimum time between two connect requests for the same pair of nodes that we act on.
+pub const CONNECT_REQUEST_INTERVAL: LocalDuration = LocalDuration::from_mins(1);
+/// Minimum time between two digests from the same peer that we reply to.
+pub const DIGEST_REPLY_INTERVAL: LocalDuration = LocalDuration::from_secs(30);
+/// Maximum number of announcements sent in reply to a digest. Peers get the rest in
+/// reply to their next digests.
+pub const DIGEST_REPLY_LIMIT: usize = 256;
+/// Maximum number of fetch requests from peers waiting for an upload slot. Peers
+/// requesting a fetch once the queue is full are disconnected, so that their fetch fails
+/// instead of waiting forever.
+pub const UPLOAD_QUEUE_LIMIT: usize = 64;
+/// How long a request for the log of the next fetch of a repository waits for that fetch.
+pub const FETCH_DEBUG_TIMEOUT: LocalDuration = LocalDuration::from_mins(10);
 /// How far back from the present time should we request gossip messages when connecting to a peer.
 pub const SUBSCRIBE_BACKLOG_DELTA: LocalDuration = LocalDuration::from_mins(60);
 
@@ -83,6 +103,32 @@ pub enum Event {
         rid: Id,
         updated: Vec<RefUpdate>,
     },
+    /// The routing table hasn't changed for the configured quiet window,
+    /// after startup or after a new connection was established.
+    RoutingConverged {
+        /// Number of entries in the routing table.
+        size: usize,
+    },
+    /// Routing table entries were removed by the periodic pruning task.
+    RoutingPruned {
+        /// Number of entries removed.
+        removed: usize,
+    },
+    /// A repository in our storage was written to, by a fetch or a local push.
+    /// External tools, eg. search indexers, can use this to process the repository again.
+    StorageChanged {
+        rid: Id,
+        /// References updated by the write. Empty if unknown, eg. after a local push.
+        updated: Vec<RefUpdate>,
+    },
+    /// A fetch was refused because our storage reached its quota.
+    StorageQuotaExceeded {
+        rid: Id,
+        /// Size of our storage, in bytes.
+        size: u64,
+        /// Configured quota, in bytes.
+        quota: u64,
+    },
 }
 
 /// General service error.
@@ -94,6 +140,8 @@ pub enum Error {
     Routing(#[from] routing::Error),
     #[error(transparent)]
     Tracking(#[from] tracking::Error),
+    #[error(transparent)]
+    Addresses(#[from] address::Error),
 }
 
 /// Function used to query internal service state.
@@ -103,22 +151,61 @@ pub type QueryState = dyn Fn(&dyn ServiceState) -> Result<(), CommandError> + Se
 pub enum Command {
     /// Announce repository references for given repository to peers.
     AnnounceRefs(Id),
+    /// Ask our connected peers to fetch the given repository from us.
+    RequestSeeding(Id),
     /// Announce local inventory to peers.
     SyncInventory(chan::Sender<bool>),
+    /// Reconcile our routing table with our local inventory, re-announce it, and request
+    /// our peers' inventories again.
+    Reconcile(chan::Sender<bool>),
     /// Connect to node with the given address.
-    Connect(NodeId, Address),
+    Connect(NodeId, Address, ConnectOptions),
+    /// Stop maintaining a connection to the given node. Returns whether it was persistent.
+    Unpersist(NodeId, chan::Sender<bool>),
     /// Lookup seeds for the given repository in the routing table.
     Seeds(Id, chan::Sender<Vec<NodeId>>),
+    /// Lookup seeds for the given repository, partitioned by whether we're connected to them.
+    FetchSources(Id, chan::Sender<FetchSources>),
     /// Fetch the given repository from the network.
     Fetch(Id, NodeId, chan::Sender<FetchResult>),
+    /// List the refs a fetch of the given repository would retrieve, without fetching them.
+    FetchDryRun(Id, NodeId, chan::Sender<FetchResult>),
+    /// Stream the git protocol log of our next fetch of the given repository.
+    FetchDebug(Id, chan::Sender<String>),
+    /// Fetch the given repository from a seed at the given address, connecting to it first
+    /// if necessary. The seed doesn't have to be in our routing table.
+    FetchHinted(Id, NodeId, Address, chan::Sender<FetchResult>),
     /// Track the given repository.
     TrackRepo(Id, chan::Sender<bool>),
     /// Untrack the given repository.
     UntrackRepo(Id, chan::Sender<bool>),
+    /// Track the given repositories, returning the number of policies updated.
+    TrackMany(Vec<Id>, chan::Sender<usize>),
+    /// Untrack the given repositories, returning the number of policies updated.
+    UntrackMany(Vec<Id>, chan::Sender<usize>),
     /// Track the given node.
     TrackNode(NodeId, Option<String>, chan::Sender<bool>),
     /// Untrack the given node.
     UntrackNode(NodeId, chan::Sender<bool>),
+    /// Add the given remote to a repository's remote tracking allow-list.
+    TrackRemote(Id, NodeId, chan::Sender<bool>),
+    /// Remove the given remote from a repository's remote tracking allow-list.
+    UntrackRemote(Id, NodeId, chan::Sender<bool>),
+    /// Set which remotes of the given repository are tracked, tracking it if necessary.
+    SetRemoteTracking(Id, RemoteTracking, chan::Sender<bool>),
+    /// Set whether announcements for the given repository are relayed to our peers.
+    SetRepoRelay(Id, bool, chan::Sender<bool>),
+    /// Get the node's local inventory.
+    Inventory(chan::Sender<Inventory>),
+    /// Get the repositories of the node's local inventory that are tracked.
+    TrackedInventory(chan::Sender<Inventory>),
+    /// Get the node's local time, and the estimated offset of the network time
+    /// relative to it, in milliseconds.
+    Time(chan::Sender<(LocalTime, i64)>),
+    /// List a snapshot of our peer sessions.
+    ListSessions(chan::Sender<Vec<SessionInfo>>),
+    /// Record the size of our storage in bytes, as measured outside of the service.
+    StorageSize(u64),
     /// Query the internal service state.
     QueryState(Arc<QueryState>, chan::Sender<Result<(), CommandError>>),
 }
@@ -127,14 +214,36 @@ impl fmt::Debug for Command {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::AnnounceRefs(id) => write!(f, "AnnounceRefs({id})"),
+            Self::RequestSeeding(id) => write!(f, "RequestSeeding({id})"),
             Self::SyncInventory(_) => write!(f, "SyncInventory(..)"),
-            Self::Connect(id, addr) => write!(f, "Connect({id}, {addr})"),
+            Self::Reconcile(_) => write!(f, "Reconcile(..)"),
+            Self::Connect(id, addr, opts) => write!(f, "Connect({id}, {addr}, {opts:?})"),
+            Self::Unpersist(id, _) => write!(f, "Unpersist({id})"),
             Self::Seeds(id, _) => write!(f, "Seeds({id})"),
+            Self::FetchSources(id, _) => write!(f, "FetchSources({id})"),
             Self::Fetch(id, node, _) => write!(f, "Fetch({id}, {node})"),
+            Self::FetchDryRun(id, node, _) => write!(f, "FetchDryRun({id}, {node})"),
+            Self::FetchDebug(id, _) => write!(f, "FetchDebug({id})"),
+            Self::FetchHinted(id, node, addr, _) => {
+                write!(f, "FetchHinted({id}, {node}, {addr})")
+            }
             Self::TrackRepo(id, _) => write!(f, "TrackRepo({id})"),
             Self::UntrackRepo(id, _) => write!(f, "UntrackRepo({id})"),
+            Self::TrackMany(ids, _) => write!(f, "TrackMany({} repo(s))", ids.len()),
+            Self::UntrackMany(ids, _) => write!(f, "UntrackMany({} repo(s))", ids.len()),
             Self::TrackNode(id, _, _) => write!(f, "TrackNode({id})"),
             Self::UntrackNode(id, _) => write!(f, "UntrackNode({id})"),
+            Self::TrackRemote(id, remote, _) => write!(f, "TrackRemote({id}, {remote})"),
+            Self::UntrackRemote(id, remote, _) => write!(f, "UntrackRemote({id}, {remote})"),
+            Self::SetRemoteTracking(id, policy, _) => {
+                write!(f, "SetRemoteTracking({id}, {policy})")
+            }
+            Self::SetRepoRelay(id, relay, _) => write!(f, "SetRepoRelay({id}, {relay})"),
+            Self::Inventory(_) => write!(f, "Inventory(..)"),
+            Self::TrackedInventory(_) => write!(f, "TrackedInventory(..)"),
+            Self::Time(_) => write!(f, "Time(..)"),
+            Self::ListSessions(_) => write!(f, "ListSessions(..)"),
+            Self::StorageSize(size) => write!(f, "StorageSize({size})"),
             Self::QueryState { .. } => write!(f, "QueryState(..)"),
         }
     }
@@ -171,6 +280,8 @@ pub struct Service<R, A, S, G> {
     nodes: BTreeMap<NodeId, Node>,
     /// Clock. Tells the time.
     clock: LocalTime,
+    /// Highest timestamp given to one of our own announcements.
+    last_timestamp: Timestamp,
     /// Interface to the I/O reactor.
     reactor: Reactor,
     /// Source of entropy.
@@ -179,8 +290,22 @@ pub struct Service<R, A, S, G> {
     out_of_sync: bool,
     /// Fetch requests initiated by user, which are waiting for results.
     fetch_reqs: HashMap<Id, chan::Sender<FetchResult>>,
+    /// Dry-run fetch requests initiated by user, which are waiting for results.
+    dry_run_reqs: HashMap<Id, chan::Sender<FetchResult>>,
+    /// Debug log requests initiated by user, which are waiting for the next fetch of their
+    /// repository, along with the time they were made. They expire after
+    /// [`FETCH_DEBUG_TIMEOUT`].
+    debug_reqs: HashMap<Id, Vec<(chan::Sender<String>, LocalTime)>>,
+    /// Repositories to fetch from hinted seeds, once the connection to them is established.
+    hints: HashMap<NodeId, Id>,
     /// Current tracked repository bloom filter.
     filter: Filter,
+    /// Size of our storage in bytes, as of the last time it was measured. It is only
+    /// measured when a storage quota is configured, and refreshed by the idle task.
+    storage_size: Option<u64>,
+    /// Delegates of the repositories we track, according to our copy of their identity
+    /// documents.
+    delegates: HashMap<Id, HashSet<NodeId>>,
     /// Last time the service was idle.
     last_idle: LocalTime,
     /// Last time the service synced.
@@ -189,8 +314,41 @@ pub struct Service<R, A, S, G> {
     last_prune: LocalTime,
     /// Last time the service announced its inventory.
     last_announce: LocalTime,
+    /// Last time the service re-sent its own announcements.
+    last_reannounce: LocalTime,
+    /// Last time the routing table changed, if it hasn't converged since.
+    last_routing_change: Option<LocalTime>,
+    /// Last time we announced refs, per repository.
+    last_refs_announce: HashMap<Id, LocalTime>,
+    /// Last time we fetched a repository in response to a seed request, per repository.
+    last_seed_request: HashMap<Id, LocalTime>,
+    /// Last time we acted on a connect request, per target and requesting node.
+    last_connect_request: HashMap<(NodeId, NodeId), LocalTime>,
+    /// Last time we replied to a digest, per peer.
+    last_digest_reply: HashMap<NodeId, LocalTime>,
+    /// Refs announcements deferred until the announcement interval is over.
+    pending_refs: HashMap<Id, Namespaces>,
+    /// Inventory announcements held back during a reconnect storm, per announcer,
+    /// along with the peer who relayed them to us.
+    pending_inventories: HashMap<NodeId, (NodeId, Announcement)>,
+    /// Time at which we started coalescing inventory announcements, if we're in a
+    /// reconnect storm.
+    storm_since: Option<LocalTime>,
+    /// Announcements waiting to be relayed, along with the peer to relay them to.
+    relay_queue: VecDeque<(NodeId, Announcement)>,
+    /// Number of relays we can still send in the current relay interval.
+    relay_budget: usize,
+    /// Peers we're currently serving a fetch to.
+    uploads: HashSet<NodeId>,
+    /// Fetch requests from peers, waiting for an upload slot. Peers have at most one
+    /// request in the queue.
+    upload_queue: VecDeque<(NodeId, Id)>,
+    /// Start of the current relay interval.
+    last_relay: LocalTime,
     /// Time when the service was initialized.
     start_time: LocalTime,
+    /// Clocks of our peers, used to estimate the network time.
+    network_clock: NetworkClock,
 }
 
 impl<R, A, S, G> Service<R, A, S, G>
@@ -243,19 +401,47 @@ where
             sessions,
             out_of_sync: false,
             fetch_reqs: HashMap::new(),
+            dry_run_reqs: HashMap::new(),
+            debug_reqs: HashMap::new(),
+            hints: HashMap::new(),
             filter: Filter::empty(),
+            storage_size: None,
+            delegates: HashMap::new(),
+            last_timestamp: Timestamp::default(),
             last_idle: LocalTime::default(),
             last_sync: LocalTime::default(),
             last_prune: LocalTime::default(),
             last_announce: LocalTime::default(),
+            last_reannounce: LocalTime::default(),
+            last_routing_change: None,
+            last_refs_announce: HashMap::new(),
+            last_seed_request: HashMap::new(),
+            last_connect_request: HashMap::new(),
+            last_digest_reply: HashMap::new(),
+            pending_refs: HashMap::new(),
+            pending_inventories: HashMap::new(),
+            storm_since: None,
+            relay_queue: VecDeque::new(),
+            relay_budget: 0,
+            uploads: HashSet::new(),
+            upload_queue: VecDeque::new(),
+            last_relay: LocalTime::default(),
             start_time: LocalTime::default(),
+            network_clock: NetworkClock::default(),
         }
     }
 
     /// Return the next i/o action to execute.
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<reactor::Io> {
-        self.reactor.next()
+        let io = self.reactor.next()?;
+
+        if let reactor::Io::Write(remote, _) = &io {
+            if let Some(session) = self.sessions.get_mut(remote) {
+                session.last_sent = self.clock;
+            }
+        }
+        Some(io)
     }
 
     /// Track a repository.
@@ -263,10 +449,27 @@ where
     pub fn track_repo(&mut self, id: &Id, scope: tracking::Scope) -> Result<bool, tracking::Error> {
         self.out_of_sync = self.tracking.track_repo(id, scope)?;
         self.filter.insert(id);
+        self.update_delegates(id);
 
         Ok(self.out_of_sync)
     }
 
+    /// Track a repository with the given scope, replacing its allow-list with the given remotes.
+    /// Returns whether or not the tracking policy was updated.
+    pub fn track_repo_remotes(
+        &mut self,
+        id: &Id,
+        scope: tracking::Scope,
+        remotes: &[NodeId],
+    ) -> Result<bool, tracking::Error> {
+        let updated = self.tracking.track_repo_remotes(id, scope, remotes)?;
+        self.out_of_sync |= updated;
+        self.filter.insert(id);
+        self.update_delegates(id);
+
+        Ok(updated)
+    }
+
     /// Untrack a repository.
     /// Returns whether or not the tracking policy was updated.
     /// Note that when untracking, we don't announce anything to the network. This is because by
@@ -284,6 +487,54 @@ where
                 .filter(|(_, _, policy)| *policy == tracking::Policy::Track)
                 .map(|(e, _, _)| e),
         );
+        self.delegates.remove(id);
+
+        Ok(updated)
+    }
+
+    /// Set whether announcements for a tracked repository are relayed to our peers.
+    /// Repositories that aren't relayed are also left out of our inventory announcements.
+    /// Returns whether or not the setting was updated.
+    pub fn set_repo_relay(&mut self, id: &Id, relay: bool) -> Result<bool, tracking::Error> {
+        let updated = self.tracking.set_repo_relay(id, relay)?;
+        self.out_of_sync |= updated;
+
+        Ok(updated)
+    }
+
+    /// Track many repositories at once. Either all repositories are tracked, or none are.
+    /// Returns the number of tracking policies that were updated.
+    pub fn track_repos(
+        &mut self,
+        ids: &[Id],
+        scope: tracking::Scope,
+    ) -> Result<usize, tracking::Error> {
+        let updated = self.tracking.track_repos(ids, scope)?;
+        if updated > 0 {
+            self.out_of_sync = true;
+        }
+        for id in ids {
+            self.filter.insert(id);
+            self.update_delegates(id);
+        }
+        Ok(updated)
+    }
+
+    /// Untrack many repositories at once. Either all repositories are untracked, or none are.
+    /// Returns the number of tracking policies that were removed.
+    pub fn untrack_repos(&mut self, ids: &[Id]) -> Result<usize, tracking::Error> {
+        let updated = self.tracking.untrack_repos(ids)?;
+        if updated > 0 {
+            self.filter = Filter::new(
+                self.tracking
+                    .repo_entries()?
+                    .filter(|(_, _, policy)| *policy == tracking::Policy::Track)
+                    .map(|(e, _, _)| e),
+            );
+            for id in ids {
+                self.delegates.remove(id);
+            }
+        }
         Ok(updated)
     }
 
@@ -345,11 +596,45 @@ where
         })
     }
 
+    /// Get the seeds of a repository, partitioned by whether we're connected to them.
+    pub fn fetch_sources(&self, rid: &Id) -> Result<FetchSources, routing::Error> {
+        let (connected, disconnected) = self
+            .routing
+            .get(rid)?
+            .into_iter()
+            .filter(|node| *node != self.node_id())
+            .partition::<Vec<_>, _>(|node| self.sessions.is_connected(node));
+
+        debug!(
+            target: "service",
+            "Found {} connected seed(s) and {} unconnected seed(s) for {}",
+            connected.len(), disconnected.len(), rid
+        );
+
+        Ok(FetchSources {
+            connected,
+            disconnected,
+        })
+    }
+
     pub fn initialize(&mut self, time: LocalTime) -> Result<(), Error> {
         debug!(target: "service", "Init @{}", time.as_millis());
 
         self.start_time = time;
+        // Our announcements must be newer than the ones we made before we were restarted.
+        // Since the timestamp is only recorded periodically, we may have used later ones
+        // if we weren't shut down cleanly, hence the margin.
+        if let Some(timestamp) = self.addresses.announced()? {
+            self.last_timestamp = timestamp + IDLE_INTERVAL.as_millis() as Timestamp;
+        }
 
+        // Peers we were asked to maintain a connection to are added to the configured ones,
+        // unless those are configured with a different address.
+        for (id, addr) in self.addresses.persistent()? {
+            if !self.config.connect.iter().any(|(peer, _)| *peer == id) {
+                self.config.connect.push((id, addr));
+            }
+        }
         // Connect to configured peers.
         let addrs = self.config.connect.clone();
         for (id, addr) in addrs {
@@ -366,6 +651,12 @@ where
                 .filter(|(_, _, policy)| *policy == tracking::Policy::Track)
                 .map(|(e, _, _)| e),
         );
+        // Record the delegates of tracked repos, who get preferential treatment.
+        for id in self.storage.inventory()? {
+            self.update_delegates(&id);
+        }
+        // Wait for the routing table to converge.
+        self.routing_changed(time);
 
         Ok(())
     }
@@ -387,13 +678,15 @@ where
             self.keep_alive(&now);
             self.disconnect_unresponsive_peers(&now);
             self.maintain_connections();
+            self.expire_debug_requests(&now);
+            self.record_timestamp();
             self.reactor.wakeup(IDLE_INTERVAL);
             self.last_idle = now;
         }
         if now - self.last_sync >= SYNC_INTERVAL {
             debug!(target: "service", "Running 'sync' task...");
 
-            // TODO: What do we do here?
+            self.send_digests(&now);
             self.reactor.wakeup(SYNC_INTERVAL);
             self.last_sync = now;
         }
@@ -410,44 +703,145 @@ where
             self.reactor.wakeup(ANNOUNCE_INTERVAL);
             self.last_announce = now;
         }
+        if let Some(interval) = self.config.reannounce_interval {
+            if now - self.last_reannounce >= interval {
+                debug!(target: "service", "Running 'reannounce' task...");
+
+                if let Err(err) = self.reannounce() {
+                    error!("Error re-announcing ourselves: {}", err);
+                }
+                self.reactor.wakeup(interval);
+                self.last_reannounce = now;
+            }
+        }
         if now - self.last_prune >= PRUNE_INTERVAL {
             debug!(target: "service", "Running 'prune' task...");
 
             if let Err(err) = self.prune_routing_entries(&now) {
                 error!("Error pruning routing entries: {}", err);
             }
+            if let Err(err) = self.verify_routing_entry() {
+                error!("Error verifying routing entries: {}", err);
+            }
             self.reactor.wakeup(PRUNE_INTERVAL);
             self.last_prune = now;
         }
+        if let Some(since) = self.storm_since {
+            if now - since >= self.config.storm_window {
+                self.apply_inventories();
+            }
+        }
+        if !self.relay_queue.is_empty() {
+            self.flush_relays(false);
+        }
+        if !self.pending_refs.is_empty() {
+            let interval = self.config.refs_announce_interval;
+            let ready = self
+                .pending_refs
+                .keys()
+                .filter(|rid| {
+                    self.last_refs_announce
+                        .get(rid)
+                        .map_or(true, |last| now - *last >= interval)
+                })
+                .copied()
+                .collect::<Vec<_>>();
+
+            for rid in ready {
+                if let Some(namespaces) = self.pending_refs.remove(&rid) {
+                    if let Err(err) = self.broadcast_refs(rid, namespaces) {
+                        error!(target: "service", "Error announcing refs for {rid}: {err}");
+                    }
+                }
+            }
+        }
+        if let Some(last) = self.last_routing_change {
+            if now - last >= self.config.routing_quiet_window {
+                match self.routing.len() {
+                    Ok(size) => {
+                        debug!(target: "service", "Routing table converged with {size} entries");
+
+                        self.reactor.event(Event::RoutingConverged { size });
+                    }
+                    Err(err) => error!("Error accessing routing table: {}", err),
+                }
+                self.last_routing_change = None;
+            }
+        }
+    }
+
+    /// Record a routing table change, and wait for the table to become quiet again.
+    fn routing_changed(&mut self, time: LocalTime) {
+        self.last_routing_change = Some(time);
+        self.reactor.wakeup(self.config.routing_quiet_window);
+    }
+
+    /// Announce to connected peers that we are going offline.
+    ///
+    /// This is done by announcing an empty inventory, which causes peers to drop the
+    /// routing entries they have for us, and relay the announcement further.
+    pub fn shutdown(&mut self) {
+        debug!(target: "service", "Announcing shutdown to connected peers..");
+
+        if let Err(err) = self.announce_inventory(vec![]) {
+            error!(target: "service", "Error announcing shutdown: {err}");
+        }
+        self.record_timestamp();
     }
 
     pub fn command(&mut self, cmd: Command) {
         debug!(target: "service", "Received command {:?}", cmd);
 
         match cmd {
-            Command::Connect(id, addr) => {
+            Command::Connect(id, addr, opts) => {
+                if opts.persistent {
+                    self.config.connect.retain(|(peer, _)| *peer != id);
+                    self.config.connect.push((id, addr.clone()));
+
+                    // Remember the peer, so that we connect to it again after a restart.
+                    if let Err(e) = self.addresses.persist(&id, &addr) {
+                        error!(target: "service", "Error persisting peer {id}: {e}");
+                    }
+
+                    if let Some(session) = self.sessions.get_mut(&id) {
+                        session.persistent = true;
+                    }
+                }
                 self.connect(id, addr);
             }
-            Command::Seeds(rid, resp) => {
-                let (connected, unconnected) = match self.routing.get(&rid) {
-                    Ok(seeds) => seeds
-                        .into_iter()
-                        .filter(|node| *node != self.node_id())
-                        .partition::<Vec<_>, _>(|node| self.sessions.is_connected(node)),
-                    Err(err) => {
-                        error!(target: "service", "Error reading routing table for {rid}: {err}");
-                        drop(resp);
+            Command::Unpersist(id, resp) => {
+                // Peers from the configuration file are persistent again after a restart.
+                let configured = self.config.connect.iter().any(|(peer, _)| *peer == id);
+                self.config.connect.retain(|(peer, _)| *peer != id);
 
-                        return;
+                let persisted = match self.addresses.unpersist(&id) {
+                    Ok(persisted) => persisted,
+                    Err(e) => {
+                        error!(target: "service", "Error un-persisting peer {id}: {e}");
+                        false
                     }
                 };
-                debug!(
-                    target: "service",
-                    "Found {} connected seed(s) and {} unconnected seed(s) for {}",
-                    connected.len(), unconnected.len(), rid
-                );
-                resp.send(connected).ok();
+                if let Some(session) = self.sessions.get_mut(&id) {
+                    session.persistent = false;
+                }
+                resp.send(configured || persisted).ok();
             }
+            Command::Seeds(rid, resp) => match self.fetch_sources(&rid) {
+                Ok(sources) => {
+                    resp.send(sources.connected).ok();
+                }
+                Err(err) => {
+                    error!(target: "service", "Error reading routing table for {rid}: {err}");
+                }
+            },
+            Command::FetchSources(rid, resp) => match self.fetch_sources(&rid) {
+                Ok(sources) => {
+                    resp.send(sources).ok();
+                }
+                Err(err) => {
+                    error!(target: "service", "Error reading routing table for {rid}: {err}");
+                }
+            },
             Command::Fetch(rid, seed, resp) => {
                 // TODO: Establish connections to unconnected seeds, and retry.
                 // TODO: Fetch requests should be queued and re-checked to see if they can
@@ -455,6 +849,28 @@ where
                 self.fetch_reqs.insert(rid, resp);
                 self.fetch(rid, &seed);
             }
+            Command::FetchDryRun(rid, seed, resp) => {
+                self.dry_run_reqs.insert(rid, resp);
+                self.fetch_dry_run(rid, &seed);
+            }
+            Command::FetchDebug(rid, resp) => {
+                self.debug_reqs
+                    .entry(rid)
+                    .or_default()
+                    .push((resp, self.clock));
+            }
+            Command::FetchHinted(rid, seed, addr, resp) => {
+                self.fetch_reqs.insert(rid, resp);
+
+                if self.sessions.get(&seed).map_or(false, |s| s.is_connected()) {
+                    self.fetch(rid, &seed);
+                } else {
+                    // Nb. The hinted address is only used for this connection; it isn't
+                    // added to our address book.
+                    self.hints.insert(seed, rid);
+                    self.connect(seed, addr);
+                }
+            }
             Command::TrackRepo(rid, resp) => {
                 let tracked = self
                     .track_repo(&rid, tracking::Scope::All)
@@ -474,6 +890,25 @@ where
                     .expect("Service::command: error untracking repository");
                 resp.send(untracked).ok();
             }
+            Command::TrackMany(rids, resp) => {
+                let updated = self
+                    .track_repos(&rids, tracking::Scope::All)
+                    .expect("Service::command: error tracking repositories");
+                resp.send(updated).ok();
+
+                if updated > 0 {
+                    self.reactor.broadcast(
+                        Message::subscribe(self.filter(), self.time(), Timestamp::MAX),
+                        self.sessions.connected().map(|(_, s)| s),
+                    );
+                }
+            }
+            Command::UntrackMany(rids, resp) => {
+                let updated = self
+                    .untrack_repos(&rids)
+                    .expect("Service::command: error untracking repositories");
+                resp.send(updated).ok();
+            }
             Command::TrackNode(id, alias, resp) => {
                 let tracked = self
                     .tracking
@@ -488,17 +923,101 @@ where
                     .expect("Service::command: error untracking node");
                 resp.send(untracked).ok();
             }
+            Command::TrackRemote(id, remote, resp) => {
+                let tracked = self
+                    .tracking
+                    .track_remote(&id, &remote)
+                    .expect("Service::command: error tracking remote");
+                resp.send(tracked).ok();
+            }
+            Command::UntrackRemote(id, remote, resp) => {
+                let untracked = self
+                    .tracking
+                    .untrack_remote(&id, &remote)
+                    .expect("Service::command: error untracking remote");
+                resp.send(untracked).ok();
+            }
+            Command::SetRemoteTracking(rid, policy, resp) => {
+                let (scope, remotes) = match policy {
+                    RemoteTracking::DelegatesOnly => (tracking::Scope::DelegatesOnly, vec![]),
+                    RemoteTracking::All => (tracking::Scope::All, vec![]),
+                    RemoteTracking::Remotes(remotes) => (tracking::Scope::DelegatesOnly, remotes),
+                };
+                let updated = self
+                    .track_repo_remotes(&rid, scope, &remotes)
+                    .expect("Service::command: error setting remote tracking");
+                resp.send(updated).ok();
+
+                if updated {
+                    self.reactor.broadcast(
+                        Message::subscribe(self.filter(), self.time(), Timestamp::MAX),
+                        self.sessions.connected().map(|(_, s)| s),
+                    );
+                }
+            }
+            Command::SetRepoRelay(rid, relay, resp) => {
+                let updated = self
+                    .set_repo_relay(&rid, relay)
+                    .expect("Service::command: error setting repository relay");
+                resp.send(updated).ok();
+            }
             Command::AnnounceRefs(id) => {
+                // Our refs are announced after a local push to our storage.
+                self.reactor.event(Event::StorageChanged {
+                    rid: id,
+                    updated: vec![],
+                });
                 if let Err(err) = self.announce_refs(id, Namespaces::One(self.node_id())) {
                     error!("Error announcing refs: {}", err);
                 }
             }
+            Command::RequestSeeding(id) => self.request_seeding(id),
             Command::SyncInventory(resp) => {
                 let updated = self
                     .sync_and_announce_inventory()
                     .expect("Service::command: error syncing and announcing inventory");
                 resp.send(!updated.is_empty()).ok();
             }
+            Command::Reconcile(resp) => {
+                let updated = self
+                    .reconcile()
+                    .expect("Service::command: error reconciling inventory");
+                resp.send(!updated.is_empty()).ok();
+            }
+            Command::Inventory(resp) => match self.storage.inventory() {
+                Ok(inventory) => {
+                    resp.send(inventory).ok();
+                }
+                Err(err) => {
+                    error!(target: "service", "Error reading inventory from storage: {err}");
+                }
+            },
+            Command::TrackedInventory(resp) => match self.storage.inventory() {
+                Ok(inventory) => {
+                    let tracked = inventory
+                        .into_iter()
+                        .filter(|rid| {
+                            self.tracking
+                                .is_repo_tracked(rid)
+                                .expect("Service::command: error accessing tracking configuration")
+                        })
+                        .collect();
+                    resp.send(tracked).ok();
+                }
+                Err(err) => {
+                    error!(target: "service", "Error reading inventory from storage: {err}");
+                }
+            },
+            Command::Time(resp) => {
+                resp.send((self.clock, self.network_clock.offset())).ok();
+            }
+            Command::ListSessions(resp) => {
+                let sessions = self.sessions.values().map(Session::info).collect();
+                resp.send(sessions).ok();
+            }
+            Command::StorageSize(size) => {
+                self.storage_size = Some(size);
+            }
             Command::QueryState(query, sender) => {
                 sender.send(query(self)).ok();
             }
@@ -506,60 +1025,166 @@ where
     }
 
     pub fn fetch(&mut self, rid: Id, from: &NodeId) {
-        let Some(session) = self.sessions.get_mut(from) else {
-            error!(target: "service", "Session {from} does not exist; cannot initiate fetch");
-            return;
-        };
-        debug_assert!(session.is_connected());
+        self.fetch_at(rid, from, None)
+    }
 
-        let seed = session.id;
+    /// Fetch a repository from a peer, optionally pinned to a specific object. If an object is
+    /// given, the fetch is only considered successful if the object was fetched.
+    pub fn fetch_at(&mut self, rid: Id, from: &NodeId, oid: Option<git::Oid>) {
+        self.request_fetch(rid, from, oid, false)
+    }
 
-        match session.fetch(rid) {
-            session::FetchResult::Ready(fetch) => {
-                debug!(target: "service", "Fetch initiated for {rid} with {seed}..");
+    /// List the refs a fetch of a repository from a peer would retrieve, without fetching
+    /// them or writing anything to storage.
+    pub fn fetch_dry_run(&mut self, rid: Id, from: &NodeId) {
+        self.request_fetch(rid, from, None, true)
+    }
 
-                self.reactor.write(session, fetch);
-                session.to_requesting(rid);
+    fn request_fetch(&mut self, rid: Id, from: &NodeId, oid: Option<git::Oid>, dry_run: bool) {
+        // Dry runs don't write anything to storage, so they aren't subject to the quota.
+        if !dry_run && !self.has_storage_quota(rid) {
+            if let Some(resp) = self.fetch_reqs.remove(&rid) {
+                resp.send(FetchResult::Failed {
+                    reason: String::from("storage quota exceeded"),
+                })
+                .ok();
             }
-            session::FetchResult::AlreadyFetching(other) => {
-                if other == rid {
-                    debug!(target: "service", "Ignoring redundant attempt to fetch {rid} from {from}");
-                } else {
-                    // TODO: If we can't fetch, it's because we're already fetching from
-                    // this peer. So we need to queue the request, or find another peer.
-                    error!(
-                        target: "service",
-                        "Dropping fetch for {rid} from {from}: another fetch is ongoing"
-                    );
+            return;
+        }
+        let failure = match self.sessions.get_mut(from) {
+            Some(session) => {
+                debug_assert!(session.is_connected());
+
+                let seed = session.id;
+
+                match session.fetch(rid, oid) {
+                    session::FetchResult::Ready(fetch) => {
+                        debug!(target: "service", "Fetch initiated for {rid} with {seed}..");
+
+                        self.reactor.write(session, fetch);
+                        session.to_requesting(rid, oid, dry_run);
+
+                        None
+                    }
+                    session::FetchResult::AlreadyFetching(other) => {
+                        if other == rid {
+                            debug!(target: "service", "Ignoring redundant attempt to fetch {rid} from {from}");
+                        } else {
+                            // TODO: If we can't fetch, it's because we're already fetching from
+                            // this peer. So we need to queue the request, or find another peer.
+                            error!(
+                                target: "service",
+                                "Dropping fetch for {rid} from {from}: another fetch is ongoing"
+                            );
+                        }
+                        Some(format!("already fetching from {from}"))
+                    }
+                    session::FetchResult::NotConnected => {
+                        error!(target: "service", "Unable to fetch {rid} from peer {seed}: peer is not connected");
+
+                        Some(format!("{from} is not connected"))
+                    }
                 }
             }
-            session::FetchResult::NotConnected => {
-                error!(target: "service", "Unable to fetch {rid} from peer {seed}: peer is not connected");
+            None => {
+                error!(target: "service", "Session {from} does not exist; cannot initiate fetch");
+
+                Some(format!("{from} is not connected"))
+            }
+        };
+
+        // Unlike fetch requests, which other seeds may still answer, dry runs only ever
+        // target one seed, so they are answered right away if they can't go ahead.
+        if let (true, Some(reason)) = (dry_run, failure) {
+            if let Some(resp) = self.dry_run_reqs.remove(&rid) {
+                resp.send(FetchResult::Failed { reason }).ok();
             }
         }
     }
 
+    /// Check whether our storage is below its configured quota, if any. If it isn't,
+    /// an event is emitted and `false` is returned.
+    ///
+    /// Since measuring the storage means walking all of it, it's done periodically outside
+    /// of the service, and the last measured size is used. Storage may therefore go over
+    /// quota by the amount fetched in one measuring interval. Until the first measurement
+    /// comes in, fetches are allowed.
+    fn has_storage_quota(&mut self, rid: Id) -> bool {
+        let Some(quota) = self.config.limits.max_storage_bytes else {
+            return true;
+        };
+        let Some(size) = self.storage_size else {
+            return true;
+        };
+        if size < quota {
+            return true;
+        }
+        warn!(
+            target: "service",
+            "Refusing to fetch {rid}: storage size of {size} byte(s) exceeds quota of {quota} byte(s)"
+        );
+        self.reactor
+            .event(Event::StorageQuotaExceeded { rid, size, quota });
+
+        false
+    }
+
     pub fn fetched(&mut self, fetch: Fetch, result: Result<Vec<RefUpdate>, FetchError>) {
         let remote = fetch.remote;
         let rid = fetch.rid;
         let initiated = fetch.initiated;
 
-        if initiated {
+        if initiated && fetch.dry_run {
+            let result = match result {
+                Ok(refs) => {
+                    debug!(target: "service", "Listed {} ref(s) of {rid} from {remote}", refs.len());
+                    FetchResult::Success { updated: refs }
+                }
+                Err(err) => {
+                    error!(target: "service", "Dry-run fetch failed for {rid} from {remote}: {err}");
+                    FetchResult::Failed {
+                        reason: err.to_string(),
+                    }
+                }
+            };
+            if let Some(resp) = self.dry_run_reqs.remove(&rid) {
+                resp.send(result).ok();
+            }
+        } else if initiated {
             let result = match result {
                 Ok(updated) => {
                     log::debug!(target: "service", "Fetched {rid} from {remote}");
 
+                    if let Some(node) = self.nodes.get_mut(&remote) {
+                        node.missing.remove(&rid);
+                    }
+                    // The identity document may have changed.
+                    self.update_delegates(&rid);
+
                     self.reactor.event(Event::RefsFetched {
                         remote,
                         rid,
                         updated: updated.clone(),
                     });
+                    if updated
+                        .iter()
+                        .any(|u| !matches!(u, RefUpdate::Skipped { .. }))
+                    {
+                        self.reactor.event(Event::StorageChanged {
+                            rid,
+                            updated: updated.clone(),
+                        });
+                    }
                     FetchResult::Success { updated }
                 }
                 Err(err) => {
                     let reason = err.to_string();
                     error!(target: "service", "Fetch failed for {rid} from {remote}: {reason}");
 
+                    if err.is_not_found() {
+                        self.repo_missing(rid, remote);
+                    }
+
                     // For now, we only disconnect the remote in case of timeout. In the future,
                     // there may be other reasons to disconnect.
                     if err.is_timeout() {
@@ -611,33 +1236,167 @@ where
         } else {
             log::debug!(target: "service", "Session not found for {remote}");
         }
+
+        if !initiated {
+            self.uploads.remove(&remote);
+            self.serve_queued_fetches();
+        }
+    }
+
+    /// A fetch ended without its session returning to the gossip protocol, eg. because the
+    /// peer disconnected in the meantime. Its requester is told it failed, or if we were
+    /// serving it, its upload slot is freed.
+    pub fn fetch_aborted(&mut self, fetch: Fetch, err: FetchError) {
+        let (rid, remote) = (fetch.rid, fetch.remote);
+
+        error!(target: "service", "Fetch of {rid} with {remote} aborted: {err}");
+
+        if fetch.initiated {
+            // As in `fetched`, fetch requests are kept, since other seeds may still answer
+            // them, while dry runs are answered once.
+            let resp = if fetch.dry_run {
+                self.dry_run_reqs.remove(&rid)
+            } else {
+                self.fetch_reqs.get(&rid).cloned()
+            };
+            if let Some(resp) = resp {
+                resp.send(FetchResult::Failed {
+                    reason: err.to_string(),
+                })
+                .ok();
+            }
+        } else if self.uploads.remove(&remote) {
+            self.serve_queued_fetches();
+        }
     }
 
-    pub fn accepted(&mut self, _addr: net::SocketAddr) {
-        // Inbound connection attempt.
+    /// Check whether we can serve one more fetch to our peers.
+    fn has_upload_slot(&self) -> bool {
+        self.config
+            .limits
+            .max_uploads
+            .map_or(true, |max| self.uploads.len() < max)
+    }
+
+    /// Serve the fetch requests waiting for an upload slot, while slots are available.
+    fn serve_queued_fetches(&mut self) {
+        while self.has_upload_slot() {
+            let Some((remote, rid)) = self
+                .next_queued_fetch()
+                .and_then(|ix| self.upload_queue.remove(ix))
+            else {
+                break;
+            };
+            let Some(session) = self.sessions.get_mut(&remote).filter(|s| s.is_connected()) else {
+                continue;
+            };
+            debug!(target: "service", "Serving queued fetch of {rid} to {remote}..");
+
+            self.uploads.insert(remote);
+            self.reactor.write(session, Message::FetchOk { rid });
+            self.reactor.fetch(
+                session,
+                rid,
+                Namespaces::default(),
+                false,
+                None,
+                false,
+                vec![],
+            );
+        }
+    }
+
+    /// Position of the next fetch request to serve in the upload queue. Requests are served
+    /// in the order they were received, except that if [`Config::rare_seed_threshold`] is
+    /// set, requests for rare repositories come first.
+    fn next_queued_fetch(&self) -> Option<usize> {
+        if self.upload_queue.is_empty() {
+            return None;
+        }
+        if let Some(threshold) = self.config.rare_seed_threshold {
+            let rare = self.upload_queue.iter().position(|(_, rid)| {
+                self.routing
+                    .get(rid)
+                    .map_or(false, |seeds| seeds.len() < threshold)
+            });
+            if rare.is_some() {
+                return rare;
+            }
+        }
+        Some(0)
+    }
+
+    /// Inbound connection attempt. Returns `false` if the connection should be refused.
+    /// The addresses of inbound connections that haven't completed their handshake yet,
+    /// and thus have no session, are given in `pending`.
+    pub fn accepted(&mut self, addr: net::SocketAddr, pending: &[net::IpAddr]) -> bool {
+        let limits = &self.config.limits.subnet;
+        let ip = addr.ip();
+        let established = self
+            .sessions
+            .values()
+            .filter(|s| s.link.is_inbound() && !s.is_disconnected())
+            .filter(|s| match &s.addr.host {
+                HostName::Ip(other) => limits.is_same_subnet(&ip, other),
+                _ => false,
+            })
+            .count();
+        let handshaking = pending
+            .iter()
+            .filter(|other| limits.is_same_subnet(&ip, other))
+            .count();
+        let inbound = established + handshaking;
+
+        let max = limits.max_inbound;
+
+        if inbound >= max {
+            warn!(target: "service", "Refusing inbound connection from {addr}: subnet limit of {max} reached");
+            return false;
+        }
+        true
     }
 
     pub fn attempted(&mut self, id: NodeId, addr: &Address) {
         debug!(target: "service", "Attempted connection to {id} ({addr})");
 
+        if let Err(e) = self.addresses.attempted(&id, addr, self.clock) {
+            error!(target: "service", "Error updating address book with connection attempt: {e}");
+        }
+
         let persistent = self.config.is_persistent(&id);
         self.sessions
             .entry(id)
             .and_modify(|sess| sess.to_connecting())
-            .or_insert_with(|| Session::connecting(id, persistent, self.rng.clone()));
+            .or_insert_with(|| Session::connecting(id, addr.clone(), persistent, self.rng.clone()));
     }
 
-    pub fn connected(&mut self, remote: NodeId, link: Link) {
-        info!(target: "service", "Connected to {} ({:?})", remote, link);
+    pub fn connected(&mut self, remote: NodeId, addr: Address, link: Link) {
+        info!(target: "service", "Connected to {} at {} ({:?})", remote, addr, link);
 
         let msgs = self.initial(link);
 
+        // New peers are likely to update our routing table.
+        self.routing_changed(self.clock);
+
         if link.is_outbound() {
+            if let Err(e) = self.addresses.connected(&remote, self.clock) {
+                error!(target: "service", "Error updating address book with connection: {e}");
+            }
             if let Some(peer) = self.sessions.get_mut(&remote) {
                 peer.to_connected(self.clock);
                 self.reactor.write_all(peer, msgs);
             }
+            if let Some(rid) = self.hints.remove(&remote) {
+                self.fetch(rid, &remote);
+            }
         } else {
+            if !self.has_inbound_slot(&remote) {
+                warn!(target: "service", "Refusing inbound connection from {remote}: no slots available");
+
+                self.reactor
+                    .disconnect(remote, DisconnectReason::Session(session::Error::SlotsFull));
+                return;
+            }
             match self.sessions.entry(remote) {
                 Entry::Occupied(e) => {
                     warn!(
@@ -648,6 +1407,7 @@ where
                 Entry::Vacant(e) => {
                     let peer = e.insert(Session::connected(
                         remote,
+                        addr,
                         Link::Inbound,
                         self.config.is_persistent(&remote),
                         self.rng.clone(),
@@ -659,11 +1419,178 @@ where
         }
     }
 
+    /// Check whether there is an inbound connection slot available for the given peer.
+    /// Reserved slots are only available to delegates of the repositories we track.
+    fn has_inbound_slot(&self, remote: &NodeId) -> bool {
+        let limits = &self.config.limits;
+        let inbound = self
+            .sessions
+            .values()
+            .filter(|s| s.link.is_inbound() && s.is_connected())
+            .count();
+
+        let Some(max) = limits.max_inbound else {
+            return true;
+        };
+        if inbound >= max {
+            return false;
+        }
+        if inbound >= max.saturating_sub(limits.reserved_delegate_slots) {
+            return self.is_delegate(remote);
+        }
+        true
+    }
+
+    /// Check whether the given node is a delegate of any of the repositories we track.
+    fn is_delegate(&self, node: &NodeId) -> bool {
+        self.delegates.values().any(|ds| ds.contains(node))
+    }
+
+    /// Update the recorded delegates of a repository from our copy of its identity document.
+    fn update_delegates(&mut self, rid: &Id) {
+        if !self.tracking.is_repo_tracked(rid).unwrap_or_default() {
+            self.delegates.remove(rid);
+            return;
+        }
+        match self.storage.get(&self.node_id(), *rid) {
+            Ok(Some(doc)) => {
+                self.delegates
+                    .insert(*rid, doc.delegates.iter().map(|d| **d).collect());
+            }
+            Ok(None) => {
+                self.delegates.remove(rid);
+            }
+            Err(err) => {
+                error!(target: "service", "Error reading identity document of {rid}: {err}");
+            }
+        }
+    }
+
+    /// Check whether the given node is authorized to announce refs for a repository we track.
+    /// Unless the repository is tracked with [`tracking::Scope::All`], only its delegates
+    /// and tracked remotes are, according to our copy of its identity document.
+    fn is_authorized(&self, rid: &Id, node: &NodeId) -> bool {
+        let scope = match self.tracking.repo_scope(rid) {
+            Ok(scope) => scope,
+            Err(err) => {
+                error!(target: "service", "Error accessing tracking configuration: {err}");
+                return false;
+            }
+        };
+        if scope == tracking::Scope::All {
+            return true;
+        }
+        let doc = match self.storage.repository(*rid) {
+            Ok(repo) => match repo.identity_doc() {
+                Ok((_, doc)) => doc,
+                Err(err) => {
+                    error!(target: "service", "Error reading identity document of {rid}: {err}");
+                    return false;
+                }
+            },
+            // We don't have the repository yet, so there's nothing to check against.
+            Err(err) if err.is_not_found() => return true,
+            Err(err) => {
+                error!(target: "service", "Error opening repository {rid}: {err}");
+                return false;
+            }
+        };
+        if doc.is_delegate(node) {
+            return true;
+        }
+        let tracked = match scope {
+            tracking::Scope::Trusted => self.tracking.is_node_tracked(node),
+            _ => self.tracking.is_remote_tracked(rid, node),
+        };
+        tracked.unwrap_or_default()
+    }
+
+    /// Disconnect peers until at most `target` connected sessions remain, eg. when
+    /// the node is running out of resources. Persistent peers and delegates of the
+    /// repositories we track are never shed. Of the others, peers that never announced
+    /// any inventory are shed before those serving content, inbound peers before
+    /// outbound ones, and idle peers before active ones.
+    pub fn shed_connections(&mut self, target: usize) {
+        let connected = self.sessions.connected().count();
+        let Some(excess) = connected.checked_sub(target).filter(|n| *n > 0) else {
+            return;
+        };
+        let mut candidates = self
+            .sessions
+            .connected()
+            .filter(|(_, s)| !s.persistent)
+            .map(|(id, s)| {
+                let has_inventory = self.nodes.get(id).map_or(false, |n| n.has_inventory);
+                (*id, (has_inventory, s.link.is_outbound(), s.last_active))
+            })
+            .collect::<Vec<_>>();
+
+        candidates.retain(|(id, _)| !self.is_delegate(id));
+        candidates.sort_by_key(|(_, priority)| *priority);
+
+        warn!(
+            target: "service",
+            "Shedding up to {excess} connection(s) out of {connected}.."
+        );
+        for (id, _) in candidates.into_iter().take(excess) {
+            self.reactor
+                .disconnect(id, DisconnectReason::Session(session::Error::Overloaded));
+        }
+    }
+
     pub fn disconnected(&mut self, remote: NodeId, reason: &DisconnectReason) {
         let since = self.local_time();
 
         debug!(target: "service", "Disconnected from {} ({})", remote, reason);
 
+        // If we couldn't dial the peer, ask our peers to have it connect to us instead.
+        if reason.is_dial_err() {
+            self.request_connection(remote);
+        }
+        self.network_clock.remove(&remote);
+        // Fetch requests from this peer can no longer be served, and if we were serving one,
+        // its upload slot is free.
+        self.upload_queue.retain(|(node, _)| *node != remote);
+        if self.uploads.remove(&remote) {
+            self.serve_queued_fetches();
+        }
+
+        // Fail any fetch that was waiting on a connection to this hinted seed.
+        if let Some(rid) = self.hints.remove(&remote) {
+            if let Some(resp) = self.fetch_reqs.remove(&rid) {
+                resp.send(FetchResult::Failed {
+                    reason: format!("could not connect to {remote}: {reason}"),
+                })
+                .ok();
+            }
+        }
+
+        // Fail the fetch we requested from this peer, if it didn't accept it, eg. because
+        // it was overloaded.
+        let requested = self.sessions.get(&remote).and_then(|s| match &s.state {
+            session::State::Connected {
+                protocol:
+                    session::Protocol::Gossip {
+                        requested: Some((rid, _, dry_run)),
+                    },
+                ..
+            } => Some((*rid, *dry_run)),
+            _ => None,
+        });
+        if let Some((rid, dry_run)) = requested {
+            let resp = if dry_run {
+                self.dry_run_reqs.remove(&rid)
+            } else {
+                self.fetch_reqs.get(&rid).cloned()
+            };
+            if let Some(resp) = resp {
+                resp.send(FetchResult::Failed {
+                    reason: format!("{remote} disconnected before accepting the fetch: {reason}"),
+                })
+                .ok();
+            }
+        }
+
         if let Some(session) = self.sessions.get_mut(&remote) {
             session.to_disconnected(since);
 
@@ -707,8 +1634,10 @@ where
                 self.reactor
                     .disconnect(remote, DisconnectReason::Session(err));
 
-                // FIXME: The peer should be set in a state such that we don't
-                // process further messages.
+                // Ignore any further messages received before the connection is closed.
+                if let Some(session) = self.sessions.get_mut(&remote) {
+                    session.to_disconnected(self.clock);
+                }
             }
             Ok(()) => {}
         }
@@ -723,9 +1652,30 @@ where
         relayer: &NodeId,
         announcement: &Announcement,
     ) -> Result<bool, session::Error> {
+        // Announcements signed with a scheme we don't know about can't be verified, but
+        // aren't a sign of misbehavior either, eg. if they come from newer nodes.
+        if let SignatureScheme::Unknown(tag) = announcement.scheme {
+            debug!(
+                target: "service",
+                "Ignoring announcement from {} with unknown signature scheme {tag}", announcement.node
+            );
+            return Ok(false);
+        }
         if !announcement.verify() {
             return Err(session::Error::Misbehavior);
         }
+        // Whether or not we store it, peers don't need to send us this announcement again.
+        self.gossip.seen(announcement);
+        // The path isn't signed, so it could have been forged by any relay. We only log
+        // apparent loops. Announcements that really loop are dropped as duplicates.
+        if announcement.path.contains(&self.node_id()) {
+            debug!(
+                target: "service",
+                "Received announcement from {} with our node in its path (path={:?})",
+                announcement.node,
+                announcement.path
+            );
+        }
         let Announcement {
             node: announcer,
             message,
@@ -739,10 +1689,16 @@ where
         let now = self.clock;
         let timestamp = message.timestamp();
         let relay = self.config.relay;
+
+        // Announcements signed by the peer who sent them tell us about that peer's clock.
+        if announcer == relayer {
+            self.network_clock.record(*relayer, timestamp, now);
+        }
         let peer = self.nodes.entry(*announcer).or_insert_with(Node::default);
 
-        // Don't allow messages from too far in the future.
-        if timestamp.saturating_sub(now.as_millis()) > MAX_TIME_DELTA.as_millis() as u64 {
+        // Don't allow messages from too far in the future. Stale messages are
+        // handled per-announcer below.
+        if timestamp.is_future(now, MAX_TIME_DELTA) {
             return Err(session::Error::InvalidTimestamp(timestamp));
         }
 
@@ -754,49 +1710,33 @@ where
                     debug!(target: "service", "Ignoring stale inventory announcement from {announcer} (t={})", self.time());
                     return Ok(false);
                 }
-
-                match self.sync_routing(&message.inventory, *announcer, message.timestamp) {
-                    Ok(updated) => {
-                        if updated.is_empty() {
-                            return Ok(false);
-                        }
-                    }
-                    Err(e) => {
-                        error!("Error processing inventory from {}: {}", announcer, e);
-                        return Ok(false);
-                    }
+                if !message.inventory.is_empty() {
+                    peer.has_inventory = true;
                 }
 
-                for id in message.inventory.as_slice() {
-                    // TODO: Move this out (good luck with the borrow checker).
-                    if let Some(sess) = self.sessions.get_mut(announcer) {
-                        // If we are connected to the announcer of this inventory, update the peer's
-                        // subscription filter to include all inventory items. This way, we'll
-                        // relay messages relating to the peer's inventory.
-                        if let Some(sub) = &mut sess.subscribe {
-                            sub.filter.insert(id);
-                        }
-
-                        // If we're tracking and connected to the announcer, and we don't have
-                        // the inventory, fetch it from the announcer.
-                        if self.tracking.is_repo_tracked(id).expect(
-                            "Service::handle_announcement: error accessing tracking configuration",
-                        ) {
-                            // Only if we do not have the repository locally do we fetch here.
-                            // If we do have it, only fetch after receiving a ref announcement.
-                            if let Ok(true) = self.storage.contains(id) {
-                                // Do nothing.
-                            } else {
-                                // We may hit this branch due to an error returned by storage.
-                                // We attempt to fetch in case of error because it's likely
-                                // the repository was corrupted, and fetching will fix it.
-                                self.fetch(*id, announcer);
+                // When running as a standby, mirror everything the primary has.
+                if self.config.standby == Some(*announcer) {
+                    for id in message.inventory.as_slice() {
+                        match self.track_repo(id, tracking::Scope::All) {
+                            Ok(true) => {
+                                info!(target: "service", "Tracking {id} from primary {announcer}");
+                            }
+                            Ok(false) => {}
+                            Err(e) => {
+                                error!(target: "service", "Error tracking {id} from primary {announcer}: {e}");
                             }
                         }
                     }
                 }
 
-                return Ok(relay);
+                // During a reconnect storm, hold back routing updates so that they can be
+                // applied together, instead of churning the routing table on every announcement.
+                if self.storm_since.is_some() || self.is_reconnect_storm() {
+                    self.coalesce_inventory(*relayer, announcement.clone());
+
+                    return Ok(false);
+                }
+                return Ok(self.process_inventory(announcer, message) && relay);
             }
             // Process a peer inventory update announcement by (maybe) fetching.
             AnnouncementMessage::Refs(message) => {
@@ -806,6 +1746,12 @@ where
                         return Err(session::Error::Misbehavior);
                     }
                 }
+                // Refs pointing to commits from the future could be used to appear fresher
+                // than they are.
+                if let Some(oid) = message.future_dated(&self.storage, now, MAX_TIME_DELTA) {
+                    warn!(target: "service", "Peer {relayer} relayed refs announcement with future-dated commit {oid}");
+                    return Err(session::Error::Misbehavior);
+                }
 
                 // We update inventories when receiving ref announcements, as these could come
                 // from a new repository being initialized.
@@ -834,11 +1780,20 @@ where
                     // therefore we only check whether we are connected to the *announcer*,
                     // which is required by the protocol to only announce refs it has.
                     if self.sessions.is_connected(announcer) {
+                        if !self.is_authorized(&message.rid, announcer) {
+                            debug!(
+                                target: "service",
+                                "Ignoring refs announcement from {announcer}: not authorized for {}",
+                                message.rid
+                            );
+                            return Ok(false);
+                        }
                         match message.is_fresh(&self.storage) {
                             Ok(is_fresh) => {
                                 if is_fresh {
                                     // TODO: Only fetch if the refs announced are for peers we're tracking.
-                                    self.fetch(message.rid, announcer);
+                                    let target = message.target(&self.storage, announcer);
+                                    self.fetch_at(message.rid, announcer, target);
                                 }
                             }
                             Err(e) => {
@@ -846,7 +1801,12 @@ where
                             }
                         }
                     }
-                    return Ok(relay);
+                    // Some tracked repositories, eg. private mirrors, are fetched but never
+                    // relayed to the rest of the network.
+                    let relayed = self.tracking.is_repo_relayed(&message.rid).expect(
+                        "Service::handle_announcement: error accessing tracking configuration",
+                    );
+                    return Ok(relay && relayed);
                 } else {
                     debug!(
                         target: "service",
@@ -883,6 +1843,11 @@ where
                     }
                 };
 
+                // Record what the peer supports, if we're connected to it.
+                if let Some(session) = self.sessions.get_mut(announcer) {
+                    session.features = *features;
+                }
+
                 // If this node isn't a seed, we're not interested in adding it
                 // to our address book, but other nodes may be, so we relay the message anyway.
                 if !features.has(Features::SEED) {
@@ -929,6 +1894,17 @@ where
 
         debug!(target: "service", "Received message {:?} from {}", &message, peer.id);
 
+        // The `version` message must be the first message sent by a peer, so that we never
+        // process gossip from peers speaking another protocol, or on another network.
+        if peer.is_connected()
+            && peer.version.is_none()
+            && !matches!(message, Message::Version { .. })
+        {
+            warn!(target: "service", "Peer {remote} sent {message:?} before its version");
+
+            return Err(session::Error::Misbehavior);
+        }
+
         match (&mut peer.state, message) {
             (
                 session::State::Connected {
@@ -949,32 +1925,54 @@ where
 
                 // Returning true here means that the message should be relayed.
                 if self.handle_announcement(&relayer, &ann)? {
-                    self.gossip.received(ann.clone(), ann.message.timestamp());
-
-                    // Choose peers we should relay this message to.
-                    // 1. Don't relay to the peer who sent us this message.
-                    // 2. Don't relay to the peer who signed this announcement.
-                    let relay_to = self
-                        .sessions
-                        .connected()
-                        .filter(|(id, _)| *id != remote && *id != &ann.node);
-
-                    self.reactor.relay(ann.clone(), relay_to.map(|(_, p)| p));
+                    self.relay_announcement(relayer, ann);
 
                     return Ok(());
                 }
-            }
-            (session::State::Connected { .. }, Message::Subscribe(subscribe)) => {
+            }
+            (session::State::Connected { .. }, Message::Subscribe(subscribe)) => {
+                for ann in self
+                    .gossip
+                    // Filter announcements by interest.
+                    .filtered(&subscribe.filter, subscribe.since, subscribe.until)
+                    // Don't send announcements authored by the remote, back to the remote.
+                    .filter(|ann| &ann.node != remote)
+                {
+                    self.reactor.write(peer, ann.into());
+                }
+                peer.subscribe = Some(subscribe);
+            }
+            (session::State::Connected { .. }, Message::Digest(digest)) => {
+                // Like relayed announcements, missed announcements are only sent to peers
+                // who subscribed to them.
+                let Some(subscribe) = &peer.subscribe else {
+                    return Ok(());
+                };
+                let now = self.clock;
+                if let Some(last) = self.last_digest_reply.get(remote) {
+                    if now - *last < DIGEST_REPLY_INTERVAL {
+                        debug!(target: "service", "Ignoring digest from {remote}: rate limited");
+                        return Ok(());
+                    }
+                }
+                self.last_digest_reply
+                    .retain(|_, last| now - *last < DIGEST_REPLY_INTERVAL);
+                self.last_digest_reply.insert(*remote, now);
+
+                // Only announcements within the backlog are sent, whatever the digest asks.
+                let oldest = now
+                    .as_millis()
+                    .saturating_sub(SUBSCRIBE_BACKLOG_DELTA.as_millis() as u64);
+
                 for ann in self
                     .gossip
-                    // Filter announcements by interest.
-                    .filtered(&subscribe.filter, subscribe.since, subscribe.until)
+                    .missing(&digest, subscribe, oldest)
                     // Don't send announcements authored by the remote, back to the remote.
                     .filter(|ann| &ann.node != remote)
+                    .take(DIGEST_REPLY_LIMIT)
                 {
                     self.reactor.write(peer, ann.into());
                 }
-                peer.subscribe = Some(subscribe);
             }
             (session::State::Connected { .. }, Message::Ping(Ping { ponglen, .. })) => {
                 // Ignore pings which ask for too much data.
@@ -988,40 +1986,157 @@ where
                     },
                 );
             }
-            (session::State::Connected { ping, .. }, Message::Pong { zeroes }) => {
-                if let session::PingState::AwaitingResponse(ponglen) = *ping {
-                    if (ponglen as usize) == zeroes.len() {
-                        *ping = session::PingState::Ok;
-                    }
-                }
+            (session::State::Connected { .. }, Message::Pong { zeroes }) => {
+                peer.pong(zeroes.len(), self.clock);
             }
-            (session::State::Connected { .. }, Message::Fetch { rid }) => {
+            (session::State::Connected { .. }, Message::Fetch { rid, .. }) => {
                 debug!(target: "service", "Fetch requested for {rid} from {remote}..");
 
                 // TODO: Check that we have the repo first?
 
+                if !self.has_upload_slot() {
+                    // Peers only have one fetch request outstanding, so a new request
+                    // replaces the one it had queued, if any.
+                    if let Some((_, queued)) = self
+                        .upload_queue
+                        .iter_mut()
+                        .find(|(node, _)| node == remote)
+                    {
+                        debug!(target: "service", "Replacing queued fetch of {queued} from {remote} with {rid}");
+                        *queued = rid;
+
+                        return Ok(());
+                    }
+                    if self.upload_queue.len() >= UPLOAD_QUEUE_LIMIT {
+                        debug!(target: "service", "Refusing fetch of {rid} from {remote}: upload queue is full");
+
+                        return Err(session::Error::Overloaded);
+                    }
+                    debug!(target: "service", "Queueing fetch of {rid} from {remote}: too many fetches ongoing");
+
+                    self.upload_queue.push_back((*remote, rid));
+                    return Ok(());
+                }
+                self.uploads.insert(*remote);
+
                 // Accept the request and instruct the transport to handover the socket to the worker.
                 self.reactor.write(peer, Message::FetchOk { rid });
-                self.reactor.fetch(peer, rid, Namespaces::default(), false);
+                self.reactor
+                    .fetch(peer, rid, Namespaces::default(), false, None, false, vec![]);
             }
             (session::State::Connected { protocol, .. }, Message::FetchOk { rid }) => {
-                if *protocol
-                    != (session::Protocol::Gossip {
-                        requested: Some(rid),
-                    })
-                {
-                    // As long as we disconnect peers who don't respond to our fetch requests within
-                    // the alloted time, this shouldn't happen by mistake.
-                    error!(
-                        "Received unexpected message `fetch-ok` from peer {}",
-                        peer.id
-                    );
-                    return Err(session::Error::Misbehavior);
-                }
+                let (oid, dry_run) = match *protocol {
+                    session::Protocol::Gossip {
+                        requested: Some((requested, oid, dry_run)),
+                    } if requested == rid => (oid, dry_run),
+                    _ => {
+                        // As long as we disconnect peers who don't respond to our fetch requests
+                        // within the alloted time, this shouldn't happen by mistake.
+                        error!(
+                            "Received unexpected message `fetch-ok` from peer {}",
+                            peer.id
+                        );
+                        return Err(session::Error::Misbehavior);
+                    }
+                };
                 debug!(target: "service", "Fetch accepted for {rid} from {remote}..");
 
+                // Only this fetch is debugged, the next one isn't.
+                let debug = self
+                    .debug_reqs
+                    .remove(&rid)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|(resp, _)| resp)
+                    .collect();
+
                 // Instruct the transport to handover the socket to the worker.
-                self.reactor.fetch(peer, rid, Namespaces::default(), true);
+                self.reactor
+                    .fetch(peer, rid, Namespaces::default(), true, oid, dry_run, debug);
+            }
+            (session::State::Connected { .. }, Message::Version { version, magic }) => {
+                if magic != self.config.network.magic() {
+                    return Err(session::Error::WrongMagic(magic));
+                }
+                if version != wire::PROTOCOL_VERSION {
+                    return Err(session::Error::WrongVersion(version));
+                }
+                peer.version = Some(version);
+            }
+            (session::State::Connected { .. }, Message::SeedRequest(rid)) => {
+                let relayer = peer.id;
+
+                if !self
+                    .tracking
+                    .is_repo_tracked(&rid)
+                    .expect("Service::handle_message: error accessing tracking configuration")
+                {
+                    return Ok(());
+                }
+                // Only act on one request per repository and interval, so that peers can't
+                // keep us busy fetching.
+                if let Some(last) = self.last_seed_request.get(&rid) {
+                    if self.clock - *last < SEED_REQUEST_INTERVAL {
+                        debug!(target: "service", "Ignoring seed request for {rid} from {relayer}: rate limited");
+                        return Ok(());
+                    }
+                }
+                self.last_seed_request.insert(rid, self.clock);
+                self.fetch(rid, &relayer);
+            }
+            (session::State::Connected { .. }, Message::ConnectRequest { node, target, addr }) => {
+                let relayer = peer.id;
+
+                // Only act on one request per pair of nodes and interval, so that peers can't
+                // use us to flood others with requests, or to have us dial repeatedly.
+                if let Some(last) = self.last_connect_request.get(&(target, node)) {
+                    if self.clock - *last < CONNECT_REQUEST_INTERVAL {
+                        debug!(target: "service", "Ignoring connect request for {target} from {node}: rate limited");
+                        return Ok(());
+                    }
+                }
+                let now = self.clock;
+                self.last_connect_request
+                    .retain(|_, last| now - *last < CONNECT_REQUEST_INTERVAL);
+                self.last_connect_request.insert((target, node), now);
+
+                if target == self.node_id() {
+                    if node == self.node_id() || !self.sessions.is_unconnected(&node) {
+                        return Ok(());
+                    }
+                    // The address in the request isn't signed by the requesting node, so we
+                    // only dial it if the node announced it itself.
+                    let known = match self.addresses.get(&node) {
+                        Ok(known) => known.map_or(false, |known| {
+                            known
+                                .addrs
+                                .iter()
+                                .any(|ka| ka.addr == addr && ka.source == address::Source::Peer)
+                        }),
+                        Err(e) => {
+                            error!(target: "service", "Error looking up addresses of {node}: {e}");
+                            false
+                        }
+                    };
+                    if !known {
+                        debug!(
+                            target: "service",
+                            "Ignoring connect request from {node} via {relayer}: address {addr} wasn't announced by {node}"
+                        );
+                        return Ok(());
+                    }
+                    info!(target: "service", "Connecting to {node} ({addr}) as requested via {relayer}");
+                    self.connect(node, addr);
+                } else if node == relayer {
+                    // Only relay requests coming from the requesting node itself, so that
+                    // requests travel a single hop.
+                    if let Some(session) = self.sessions.get(&target).filter(|s| s.is_connected()) {
+                        debug!(target: "service", "Relaying connect request from {node} to {target}..");
+
+                        self.reactor
+                            .write(session, Message::ConnectRequest { node, target, addr });
+                    }
+                }
             }
             (session::State::Connecting { .. }, msg) => {
                 error!("Received {:?} from connecting peer {}", msg, peer.id);
@@ -1034,19 +2149,133 @@ where
     }
 
     /// Set of initial messages to send to a peer.
-    fn initial(&self, _link: Link) -> Vec<Message> {
+    fn initial(&mut self, _link: Link) -> Vec<Message> {
         let filter = self.filter();
+        let timestamp = self.timestamp();
 
         // TODO: Only subscribe to outbound connections, otherwise we will consume too
         // much bandwidth.
 
-        gossip::handshake(
-            self.clock.as_millis(),
-            &self.storage,
-            &self.signer,
-            filter,
-            &self.config,
-        )
+        let inventory = match self.storage.inventory() {
+            Ok(i) => self.relayed(i),
+            Err(e) => {
+                error!("Error getting local inventory for handshake: {}", e);
+                // Other than crashing the node completely, there's nothing we can do
+                // here besides returning an empty inventory and logging an error.
+                vec![]
+            }
+        };
+        gossip::handshake(timestamp, inventory, &self.signer, filter, &self.config)
+    }
+
+    /// Leave out the repositories that opted out of relaying from the given inventory.
+    fn relayed(&self, inventory: Vec<Id>) -> Vec<Id> {
+        inventory
+            .into_iter()
+            .filter(|rid| {
+                self.tracking.is_repo_relayed(rid).unwrap_or_else(|err| {
+                    error!(target: "service", "Error accessing tracking configuration: {err}");
+                    true
+                })
+            })
+            .collect()
+    }
+
+    /// Process an inventory announcement by updating our routing table, and fetching the
+    /// tracked repositories we're missing from the announcer.
+    ///
+    /// Returns `true` if our routing table was updated.
+    fn process_inventory(&mut self, announcer: &NodeId, message: &InventoryAnnouncement) -> bool {
+        match self.sync_routing(&message.inventory, *announcer, message.timestamp) {
+            Ok(updated) => {
+                if updated.is_empty() {
+                    return false;
+                }
+            }
+            Err(e) => {
+                error!("Error processing inventory from {}: {}", announcer, e);
+                return false;
+            }
+        }
+
+        for id in message.inventory.as_slice() {
+            // TODO: Move this out (good luck with the borrow checker).
+            if let Some(sess) = self.sessions.get_mut(announcer) {
+                // If we are connected to the announcer of this inventory, update the peer's
+                // subscription filter to include all inventory items. This way, we'll
+                // relay messages relating to the peer's inventory.
+                if let Some(sub) = &mut sess.subscribe {
+                    sub.filter.insert(id);
+                }
+
+                // If we're tracking and connected to the announcer, and we don't have
+                // the inventory, fetch it from the announcer.
+                if self
+                    .tracking
+                    .is_repo_tracked(id)
+                    .expect("Service::process_inventory: error accessing tracking configuration")
+                {
+                    // Only if we do not have the repository locally do we fetch here.
+                    // If we do have it, only fetch after receiving a ref announcement.
+                    if let Ok(true) = self.storage.contains(id) {
+                        // Do nothing.
+                    } else {
+                        // We may hit this branch due to an error returned by storage.
+                        // We attempt to fetch in case of error because it's likely
+                        // the repository was corrupted, and fetching will fix it.
+                        self.fetch(*id, announcer);
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    /// Hold back an inventory announcement until the reconnect storm is over. Only the latest
+    /// announcement of each node is kept, since inventories replace each other.
+    fn coalesce_inventory(&mut self, relayer: NodeId, ann: Announcement) {
+        if self.storm_since.is_none() {
+            debug!(target: "service", "Reconnect storm detected, coalescing inventory announcements..");
+
+            self.storm_since = Some(self.clock);
+            self.reactor.wakeup(self.config.storm_window);
+        }
+        self.pending_inventories.insert(ann.node, (relayer, ann));
+    }
+
+    /// Apply the inventory announcements held back during a reconnect storm, in one pass,
+    /// and relay the ones that updated our routing table.
+    fn apply_inventories(&mut self) {
+        let pending = std::mem::take(&mut self.pending_inventories);
+        self.storm_since = None;
+
+        debug!(target: "service", "Applying {} coalesced inventory announcement(s)..", pending.len());
+
+        for (announcer, (relayer, ann)) in pending {
+            let AnnouncementMessage::Inventory(message) = &ann.message else {
+                continue;
+            };
+            if self.process_inventory(&announcer, message) && self.config.relay {
+                self.relay_announcement(relayer, ann);
+            }
+        }
+    }
+
+    /// Whether we're in a reconnect storm, ie. a lot of peers connected to us within
+    /// the storm window.
+    fn is_reconnect_storm(&self) -> bool {
+        let now = self.clock;
+        let window = self.config.storm_window;
+        let recent = self
+            .sessions
+            .connected()
+            .filter(|(_, s)| {
+                matches!(s.state, session::State::Connected { since, .. } if now - since < window)
+            })
+            .count();
+
+        recent >= self.config.limits.storm_connections
     }
 
     /// Sync, and if needed, announce our local inventory.
@@ -1060,6 +2289,28 @@ where
         Ok(updated)
     }
 
+    /// Force a full reconciliation of our inventory with the network, eg. after our storage
+    /// was restored. Unlike [`Service::sync_and_announce_inventory`], our inventory is
+    /// announced even if our routing table didn't change, and we ask our peers to send us
+    /// the announcements we may have missed.
+    fn reconcile(&mut self) -> Result<Vec<Id>, Error> {
+        let inventory = self.storage.inventory()?;
+        let updated = self.sync_routing(&inventory, self.node_id(), self.time())?;
+
+        info!(target: "service", "Reconciling inventory of {} repository(s)..", inventory.len());
+
+        self.announce_inventory(inventory)?;
+        self.reactor.broadcast(
+            Message::subscribe(
+                self.filter(),
+                self.time() - SUBSCRIBE_BACKLOG_DELTA.as_millis() as u64,
+                Timestamp::MAX,
+            ),
+            self.sessions.connected().map(|(_, s)| s),
+        );
+        Ok(updated)
+    }
+
     /// Process a peer inventory announcement by updating our routing table.
     /// This function expects the peer's full inventory, and prunes entries that are not in the
     /// given inventory.
@@ -1095,14 +2346,150 @@ where
                 }
             }
         }
+        if !updated.is_empty() {
+            self.routing_changed(self.clock);
+        }
         Ok(updated)
     }
 
-    /// Announce local refs for given id.
+    /// Store an announcement received from the given relayer, and relay it to our other peers.
+    fn relay_announcement(&mut self, relayer: NodeId, ann: Announcement) {
+        // The hop limit is capped by our own, and decremented for this hop.
+        // Announcements that have run out of hops are stored, but not relayed.
+        let ttl = ann.ttl.min(self.config.announcement_ttl);
+        // The path is advisory: if our node is already in it, the announcement is relayed
+        // as is, without counting us as a hop again, or recording us twice.
+        let ann = if ann.path.contains(&self.node_id()) {
+            ann.with_ttl(ttl)
+        } else {
+            let ann = ann.with_ttl(ttl.saturating_sub(1));
+
+            if self.config.announcement_paths {
+                ann.relayed_by(self.node_id())
+            } else {
+                ann
+            }
+        };
+
+        self.gossip.received(ann.clone(), ann.message.timestamp());
+
+        if ttl == 0 {
+            return;
+        }
+
+        // Choose peers we should relay this message to.
+        // 1. Don't relay to the peer who sent us this message.
+        // 2. Don't relay to the peer who signed this announcement.
+        let relay_to = self
+            .sessions
+            .connected()
+            .filter(|(id, _)| **id != relayer && *id != &ann.node)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+
+        self.relay(ann, relay_to);
+    }
+
+    /// Queue an announcement to be relayed to the given peers, and send as many
+    /// relays as our relay budget allows. If there are more peers than the relay
+    /// fan-out limit, only a random subset of them is relayed to.
+    fn relay(&mut self, ann: Announcement, mut peers: Vec<NodeId>) {
+        let fanout = self.config.limits.relay_fanout;
+
+        if peers.len() > fanout {
+            self.rng.shuffle(&mut peers);
+            peers.truncate(fanout);
+        }
+        // If there were already relays queued, a wakeup is already scheduled for them.
+        let idle = self.relay_queue.is_empty();
+
+        self.relay_queue
+            .extend(peers.into_iter().map(|id| (id, ann.clone())));
+        self.flush_relays(idle);
+    }
+
+    /// Send queued relays, up to the relay burst limit per relay interval.
+    /// Relays that don't fit in the current interval are sent on a later wakeup.
+    fn flush_relays(&mut self, mut schedule: bool) {
+        let now = self.clock;
+        let interval = self.config.relay_interval;
+
+        if now - self.last_relay >= interval {
+            self.last_relay = now;
+            self.relay_budget = self.config.limits.relay_burst;
+            schedule = true;
+        }
+        while self.relay_budget > 0 {
+            let Some((id, ann)) = self.relay_queue.pop_front() else {
+                break;
+            };
+            // Peers may have disconnected since the relay was queued.
+            if let Some(session) = self.sessions.get(&id).filter(|s| s.is_connected()) {
+                self.reactor.relay(ann, Some(session));
+                self.relay_budget -= 1;
+            }
+        }
+        if schedule && !self.relay_queue.is_empty() {
+            debug!(
+                target: "service",
+                "Relay burst limit reached, deferring {} relay(s)..", self.relay_queue.len()
+            );
+            self.reactor.wakeup(interval);
+        }
+    }
+
+    /// Announce our refs for the given repository to our peers. If we've already announced
+    /// refs for this repository within the configured interval, the announcement is deferred
+    /// until the interval is over, and coalesced with any other announcement in the meantime.
     fn announce_refs(&mut self, rid: Id, namespaces: Namespaces) -> Result<(), storage::Error> {
+        let now = self.clock;
+        let interval = self.config.refs_announce_interval;
+
+        if let Some(last) = self.last_refs_announce.get(&rid) {
+            let elapsed = now - *last;
+
+            if elapsed < interval {
+                match self.pending_refs.entry(rid) {
+                    Entry::Occupied(mut e) => {
+                        if !matches!(
+                            (e.get(), &namespaces),
+                            (Namespaces::One(a), Namespaces::One(b)) if a == b
+                        ) {
+                            e.insert(Namespaces::All);
+                        }
+                    }
+                    Entry::Vacant(e) => {
+                        e.insert(namespaces);
+                        self.reactor.wakeup(LocalDuration::from_millis(
+                            interval.as_millis() - elapsed.as_millis(),
+                        ));
+                    }
+                }
+                debug!(target: "service", "Deferring refs announcement for {rid}..");
+
+                return Ok(());
+            }
+        }
+        self.broadcast_refs(rid, namespaces)
+    }
+
+    /// Broadcast a refs announcement for the given repository to all connected peers.
+    /// Repositories that opted out of relaying aren't announced, to keep them private.
+    fn broadcast_refs(&mut self, rid: Id, namespaces: Namespaces) -> Result<(), storage::Error> {
+        match self.tracking.is_repo_relayed(&rid) {
+            Ok(true) => {}
+            Ok(false) => {
+                debug!(target: "service", "Not announcing refs of {rid}: repository isn't relayed");
+                return Ok(());
+            }
+            Err(err) => {
+                error!(target: "service", "Error accessing tracking configuration: {err}");
+                return Ok(());
+            }
+        }
+        let timestamp = self.timestamp();
         let repo = self.storage.repository(rid)?;
         let peers = self.sessions.connected().map(|(_, p)| p);
-        let timestamp = self.time();
         let mut refs = BoundedVec::<_, REF_REMOTE_LIMIT>::new();
 
         match namespaces {
@@ -1130,9 +2517,12 @@ where
             refs,
             timestamp,
         });
-        let ann = msg.signed(&self.signer);
+        let ann = msg
+            .signed(&self.signer)
+            .with_ttl(self.config.announcement_ttl);
 
         self.reactor.broadcast(ann, peers);
+        self.last_refs_announce.insert(rid, self.clock);
 
         Ok(())
     }
@@ -1147,6 +2537,40 @@ where
         false
     }
 
+    /// Ask our connected peers to fetch the given repository from us, if they track it.
+    /// This can be used after a push, to have the repository seeded by more nodes.
+    pub fn request_seeding(&mut self, rid: Id) {
+        debug!(target: "service", "Requesting seeding of {rid} from our peers..");
+
+        self.reactor.broadcast(
+            Message::SeedRequest(rid),
+            self.sessions.connected().map(|(_, s)| s),
+        );
+    }
+
+    /// Ask our connected peers to have the given node connect to us, via a connection
+    /// reversal request. This is used when we're unable to dial the node ourselves.
+    /// Only peers connected to the node relay the request.
+    fn request_connection(&mut self, target: NodeId) {
+        let Some(addr) = self.config.external_addresses.first().cloned() else {
+            return;
+        };
+        let msg = Message::ConnectRequest {
+            node: self.node_id(),
+            target,
+            addr,
+        };
+        debug!(target: "service", "Requesting connection from {target} via our peers..");
+
+        self.reactor.broadcast(
+            msg,
+            self.sessions
+                .connected()
+                .filter(|(id, _)| **id != target)
+                .map(|(_, s)| s),
+        );
+    }
+
     /// Return a new filter object, based on our tracking policy.
     fn filter(&self) -> Filter {
         if self.config.policy == tracking::Policy::Track {
@@ -1162,20 +2586,61 @@ where
         self.clock.as_millis()
     }
 
+    /// Get a timestamp for one of our own announcements.
+    ///
+    /// Peers ignore announcements that aren't newer than the ones they have, so if our clock
+    /// goes backwards, we keep counting from the last timestamp we used.
+    fn timestamp(&mut self) -> Timestamp {
+        let timestamp = self.time().max(self.last_timestamp + 1);
+        self.last_timestamp = timestamp;
+
+        timestamp
+    }
+
+    /// Record the last timestamp we used, so that we keep counting from it after a restart.
+    fn record_timestamp(&mut self) {
+        if let Err(e) = self.addresses.set_announced(self.last_timestamp) {
+            error!(target: "service", "Error recording announcement timestamp: {e}");
+        }
+    }
+
     ////////////////////////////////////////////////////////////////////////////
     // Periodic tasks
     ////////////////////////////////////////////////////////////////////////////
 
     /// Announce our inventory to all connected peers.
     fn announce_inventory(&mut self, inventory: Vec<Id>) -> Result<(), storage::Error> {
-        let time = self.time();
-        let inv = Message::inventory(gossip::inventory(time, inventory), &self.signer);
+        let inventory = self.relayed(inventory);
+        let time = self.timestamp();
+        let inv: Message = AnnouncementMessage::from(gossip::inventory(time, inventory))
+            .signed(&self.signer)
+            .with_ttl(self.config.announcement_ttl)
+            .into();
         for (_, sess) in self.sessions.connected() {
             self.reactor.write(sess, inv.clone());
         }
         Ok(())
     }
 
+    /// Re-send our inventory and node announcements to all connected peers, with fresh
+    /// timestamps, so that peers who missed them still learn about us.
+    fn reannounce(&mut self) -> Result<(), Error> {
+        let inventory = self.storage.inventory()?;
+        self.announce_inventory(inventory)?;
+
+        let timestamp = self.timestamp();
+        if let Some(ann) = gossip::node(timestamp, &self.config) {
+            let msg: Message = AnnouncementMessage::from(ann)
+                .signed(&self.signer)
+                .with_ttl(self.config.announcement_ttl)
+                .into();
+            for (_, sess) in self.sessions.connected() {
+                self.reactor.write(sess, msg.clone());
+            }
+        }
+        Ok(())
+    }
+
     fn prune_routing_entries(&mut self, now: &LocalTime) -> Result<(), routing::Error> {
         let count = self.routing.len()?;
         if count <= self.config.limits.routing_max_size {
@@ -1183,18 +2648,74 @@ where
         }
 
         let delta = count - self.config.limits.routing_max_size;
-        self.routing.prune(
+        let removed = self.routing.prune(
             (*now - self.config.limits.routing_max_age).as_millis(),
             Some(delta),
         )?;
+        if removed > 0 {
+            debug!(target: "service", "Pruned {removed} routing table entries");
+
+            self.reactor.event(Event::RoutingPruned { removed });
+        }
+        Ok(())
+    }
+
+    /// Pick a random routing entry for a tracked repository, and fetch it from its seed if
+    /// we're connected to it. Seeds that no longer have the repository will eventually be
+    /// removed from the routing table as these fetches fail.
+    fn verify_routing_entry(&mut self) -> Result<(), Error> {
+        let mut candidates = Vec::new();
+
+        for (rid, seed) in self.routing.entries()? {
+            if seed == self.node_id() || !self.sessions.is_connected(&seed) {
+                continue;
+            }
+            if self.tracking.is_repo_tracked(&rid)? {
+                candidates.push((rid, seed));
+            }
+        }
+        if candidates.is_empty() {
+            return Ok(());
+        }
+        let (rid, seed) = candidates[self.rng.usize(..candidates.len())];
+
+        debug!(target: "service", "Verifying routing entry for {rid} with seed {seed}..");
+        self.fetch(rid, &seed);
+
         Ok(())
     }
 
+    /// Record that a seed doesn't have the given repository. Once this has happened
+    /// [`MAX_MISSING_FETCHES`] times in a row, the routing entry is removed.
+    fn repo_missing(&mut self, rid: Id, seed: NodeId) {
+        let node = self.nodes.entry(seed).or_default();
+        let count = node.missing.entry(rid).or_default();
+
+        *count += 1;
+
+        if *count < MAX_MISSING_FETCHES {
+            return;
+        }
+        node.missing.remove(&rid);
+
+        match self.routing.remove(&rid, &seed) {
+            Ok(true) => {
+                info!(target: "service", "Removed routing entry for {rid} with seed {seed}: repository is missing");
+                self.routing_changed(self.clock);
+            }
+            Ok(false) => {}
+            Err(err) => {
+                error!(target: "service", "Error removing routing entry for {rid}: {err}");
+            }
+        }
+    }
+
     fn disconnect_unresponsive_peers(&mut self, now: &LocalTime) {
+        let timeout = self.config.stale_connection_timeout;
         let stale = self
             .sessions
             .connected()
-            .filter(|(_, session)| session.last_active < *now - STALE_CONNECTION_TIMEOUT);
+            .filter(|(_, session)| session.last_active + timeout < *now);
 
         for (_, session) in stale {
             self.reactor.disconnect(
@@ -1204,6 +2725,33 @@ where
         }
     }
 
+    /// Drop debug log requests that weren't followed by a fetch in time. Their requesters'
+    /// logs end without any output.
+    fn expire_debug_requests(&mut self, now: &LocalTime) {
+        self.debug_reqs.retain(|rid, reqs| {
+            reqs.retain(|(_, time)| *now - *time < FETCH_DEBUG_TIMEOUT);
+
+            if reqs.is_empty() {
+                debug!(target: "service", "Debug log request(s) for {rid} expired");
+            }
+            !reqs.is_empty()
+        });
+    }
+
+    /// Send a digest of the announcements we received recently to our peers, so that they
+    /// can send us the ones we missed.
+    fn send_digests(&mut self, now: &LocalTime) {
+        let since = now
+            .as_millis()
+            .saturating_sub(SUBSCRIBE_BACKLOG_DELTA.as_millis() as u64);
+        let digest = self.gossip.digest(since);
+
+        self.reactor.broadcast(
+            Message::Digest(digest),
+            self.sessions.connected().map(|(_, s)| s),
+        );
+    }
+
     /// Ensure connection health by pinging connected peers.
     fn keep_alive(&mut self, now: &LocalTime) {
         let inactive_sessions = self
@@ -1212,7 +2760,7 @@ where
             .filter(|(_, session)| *now - session.last_active >= KEEP_ALIVE_DELTA)
             .map(|(_, session)| session);
         for session in inactive_sessions {
-            session.ping(&mut self.reactor).ok();
+            session.ping(&mut self.reactor, *now).ok();
         }
     }
 
@@ -1230,7 +2778,7 @@ where
         }
 
         self.addresses
-            .entries()
+            .candidates()
             .unwrap()
             .filter(|(node_id, _)| !sessions.contains_key(node_id))
             .take(wanted)
@@ -1356,6 +2904,15 @@ pub struct Lookup {
     pub remote: Vec<NodeId>,
 }
 
+/// Seeds of a repository that we could fetch from.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FetchSources {
+    /// Seeds we're currently connected to.
+    pub connected: Vec<NodeId>,
+    /// Seeds known from the routing table, that we aren't connected to.
+    pub disconnected: Vec<NodeId>,
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum LookupError {
     #[error(transparent)]
@@ -1375,6 +2932,11 @@ pub struct Node {
     pub last_inventory: Timestamp,
     /// Last node announcement.
     pub last_node: Timestamp,
+    /// Consecutive fetches that failed because the repository was missing (per project).
+    pub missing: HashMap<Id, usize>,
+    /// Whether the node ever announced a non-empty inventory. Nodes that didn't are pure
+    /// consumers, and are the first to go when we're short on connections.
+    pub has_inventory: bool,
 }
 
 impl Node {
@@ -1471,10 +3033,18 @@ impl DerefMut for Sessions {
 mod gossip {
     use super::*;
     use crate::service::filter::Filter;
+    use crate::service::message::{Digest, Subscribe, DIGEST_LIMIT};
+
+    /// Maximum number of fingerprints kept for digests. Going past it trims the set back
+    /// down to [`DIGEST_LIMIT`], so that trimming doesn't happen on every announcement.
+    pub const SEEN_LIMIT: usize = DIGEST_LIMIT * 2;
 
     #[derive(Default, Debug)]
     pub struct Gossip {
         received: Vec<(Timestamp, Announcement)>,
+        /// Fingerprints of the valid announcements we received, stored or not, along with
+        /// their timestamp.
+        seen: HashMap<u64, Timestamp>,
     }
 
     impl Gossip {
@@ -1484,6 +3054,21 @@ mod gossip {
             self.received.push((time, ann));
         }
 
+        /// Record that we received an announcement, so that it's included in our digests.
+        /// Past [`SEEN_LIMIT`], the oldest fingerprints are forgotten, since digests only
+        /// ever include the most recent [`DIGEST_LIMIT`] of them.
+        pub fn seen(&mut self, ann: &Announcement) {
+            self.seen.insert(ann.fingerprint(), ann.message.timestamp());
+
+            if self.seen.len() > SEEN_LIMIT {
+                let mut timestamps = self.seen.values().copied().collect::<Vec<_>>();
+                timestamps.sort_unstable();
+
+                let oldest = timestamps[timestamps.len() - DIGEST_LIMIT];
+                self.seen.retain(|_, t| *t >= oldest);
+            }
+        }
+
         pub fn filtered<'a>(
             &'a self,
             filter: &'a Filter,
@@ -1497,27 +3082,59 @@ mod gossip {
                 .cloned()
                 .map(|(_, ann)| ann)
         }
+
+        /// Digest of the announcements seen since the given time. If there are too
+        /// many, only the most recent ones are included. Older fingerprints are forgotten.
+        pub fn digest(&mut self, since: Timestamp) -> Digest {
+            self.seen.retain(|_, t| *t >= since);
+
+            let mut received = self.seen.iter().map(|(f, t)| (*t, *f)).collect::<Vec<_>>();
+            received.sort_unstable();
+
+            let excess = received.len().saturating_sub(DIGEST_LIMIT);
+            let since = if excess > 0 {
+                received[excess].0
+            } else {
+                since
+            };
+            let fingerprints = received.split_off(excess).into_iter().map(|(_, f)| f);
+
+            Digest {
+                since,
+                fingerprints: BoundedVec::truncate(fingerprints.collect()),
+            }
+        }
+
+        /// Announcements matching the subscription, that are missing from the digest.
+        /// Announcements older than `oldest` are left out.
+        pub fn missing<'a>(
+            &'a self,
+            digest: &Digest,
+            subscribe: &'a Subscribe,
+            oldest: Timestamp,
+        ) -> impl Iterator<Item = Announcement> + 'a {
+            let fingerprints = digest.fingerprints.iter().copied().collect::<HashSet<_>>();
+            let since = subscribe.since.max(digest.since).max(oldest);
+
+            self.filtered(&subscribe.filter, since, subscribe.until)
+                .filter(move |ann| !fingerprints.contains(&ann.fingerprint()))
+        }
     }
 
-    pub fn handshake<G: Signer, S: ReadStorage>(
+    pub fn handshake<G: Signer>(
         now: Timestamp,
-        storage: &S,
+        inventory: Vec<Id>,
         signer: &G,
         filter: Filter,
         config: &Config,
     ) -> Vec<Message> {
-        let inventory = match storage.inventory() {
-            Ok(i) => i,
-            Err(e) => {
-                error!("Error getting local inventory for handshake: {}", e);
-                // Other than crashing the node completely, there's nothing we can do
-                // here besides returning an empty inventory and logging an error.
-                vec![]
-            }
-        };
-
+        let ttl = config.announcement_ttl;
         let mut msgs = vec![
-            Message::inventory(gossip::inventory(now, inventory), signer),
+            Message::version(config.network),
+            AnnouncementMessage::from(gossip::inventory(now, inventory))
+                .signed(signer)
+                .with_ttl(ttl)
+                .into(),
             Message::subscribe(
                 filter,
                 now - SUBSCRIBE_BACKLOG_DELTA.as_millis() as u64,
@@ -1525,14 +3142,19 @@ mod gossip {
             ),
         ];
         if let Some(m) = gossip::node(now, config) {
-            msgs.push(Message::node(m, signer));
+            msgs.push(
+                AnnouncementMessage::from(m)
+                    .signed(signer)
+                    .with_ttl(ttl)
+                    .into(),
+            );
         };
 
         msgs
     }
 
     pub fn node(timestamp: Timestamp, config: &Config) -> Option<NodeAnnouncement> {
-        let features = node::Features::SEED;
+        let features = node::Features::SEED | node::Features::PINNED_FETCH;
         let alias = config.alias();
         let addresses: BoundedVec<_, ADDRESS_LIMIT> = config
             .external_addresses
@@ -1572,4 +3194,30 @@ mod gossip {
             timestamp,
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use radicle::crypto::test::signer::MockSigner;
+
+        #[test]
+        fn test_seen_limit() {
+            let signer = MockSigner::default();
+            let mut gossip = Gossip::default();
+
+            for timestamp in 0..=SEEN_LIMIT as Timestamp {
+                let msg = Message::inventory(inventory(timestamp, vec![]), &signer);
+                let Message::Announcement(ann) = msg else {
+                    unreachable!();
+                };
+                gossip.seen(&ann);
+                assert!(gossip.seen.len() <= SEEN_LIMIT);
+            }
+            assert_eq!(gossip.seen.len(), DIGEST_LIMIT);
+            assert!(gossip
+                .seen
+                .values()
+                .all(|t| *t > (SEEN_LIMIT - DIGEST_LIMIT) as Timestamp));
+        }
+    }
 }
@@ -1,18 +1,63 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io, net};
+
+use cyphernet::addr::PeerAddr;
 use localtime::LocalDuration;
+use serde::Deserialize;
+use thiserror::Error;
 
 use radicle::node::Address;
+use radicle::profile::Home;
 
+use crate::service::message::Announcement;
 use crate::service::tracking::Policy;
 use crate::service::NodeId;
 
+/// Name of the node configuration file, under the node directory.
+pub const CONFIG_FILE_NAME: &str = radicle::node::DEFAULT_CONFIG_NAME;
+/// Maximum length of a node alias, in bytes.
+pub const MAX_ALIAS_LENGTH: usize = 32;
+
+/// Error loading a configuration file.
+#[derive(Error, Debug)]
+pub enum LoadError {
+    #[error("failed to read config file {0:?}: {1}")]
+    Io(PathBuf, #[source] io::Error),
+    #[error("failed to parse config file {0:?}: {1}")]
+    Json(PathBuf, #[source] serde_json::Error),
+    #[error("invalid peer `{0}`: {1}")]
+    InvalidPeer(String, String),
+    #[error("invalid address `{0}`: {1}")]
+    InvalidAddress(String, String),
+    #[error("invalid alias: {0}")]
+    InvalidAlias(#[from] AliasError),
+}
+
+/// Error setting the node alias.
+#[derive(Error, Debug)]
+#[error("alias `{0}` is longer than {MAX_ALIAS_LENGTH} bytes")]
+pub struct AliasError(pub String);
+
 /// Peer-to-peer network.
-#[derive(Default, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Network {
     #[default]
     Main,
     Test,
 }
 
+impl Network {
+    /// Magic byte identifying the network. This is sent to peers when connecting, so that
+    /// nodes on different networks don't exchange gossip by accident.
+    pub fn magic(&self) -> u8 {
+        match self {
+            Self::Main => b'm',
+            Self::Test => b't',
+        }
+    }
+}
+
 /// Configuration parameters defining attributes of minima and maxima.
 #[derive(Debug, Clone)]
 pub struct Limits {
@@ -20,6 +65,37 @@ pub struct Limits {
     pub routing_max_size: usize,
     /// How long to keep a routing table entry before being pruned.
     pub routing_max_age: LocalDuration,
+    /// Inbound connection limits per subnet.
+    pub subnet: SubnetLimits,
+    /// Rate at which inbound connections are accepted. Connections beyond this rate are
+    /// closed before the handshake.
+    pub accept: AcceptLimits,
+    /// Maximum number of announcements relayed at once. Relays beyond this number
+    /// are spread over subsequent relay intervals.
+    pub relay_burst: usize,
+    /// Maximum number of peers an announcement is relayed to. When more peers are
+    /// eligible, a random subset of this size is chosen, and the rest of the network
+    /// is reached through them.
+    pub relay_fanout: usize,
+    /// Maximum number of inbound connections. There is no limit by default.
+    pub max_inbound: Option<usize>,
+    /// Number of inbound connection slots reserved for delegates of the repositories we
+    /// track. Other peers can only use the remaining `max_inbound - reserved_delegate_slots`
+    /// slots.
+    pub reserved_delegate_slots: usize,
+    /// Maximum number of bytes received from a peer that can be buffered without being
+    /// decoded. Peers exceeding this are disconnected. This should be well above the
+    /// maximum message size.
+    pub max_inbox_size: usize,
+    /// Maximum size of our storage, in bytes. Once storage reaches this size, we stop
+    /// fetching. There is no limit by default.
+    pub max_storage_bytes: Option<u64>,
+    /// Number of connections established within [`Config::storm_window`] for us to
+    /// consider ourselves in a reconnect storm, eg. after a network partition heals.
+    pub storm_connections: usize,
+    /// Maximum number of fetches served to peers at once. Further fetch requests are
+    /// queued until one of the ongoing fetches completes. There is no limit by default.
+    pub max_uploads: Option<usize>,
 }
 
 impl Default for Limits {
@@ -27,6 +103,94 @@ impl Default for Limits {
         Self {
             routing_max_size: 1000,
             routing_max_age: LocalDuration::from_mins(7 * 24 * 60),
+            subnet: SubnetLimits::default(),
+            accept: AcceptLimits::default(),
+            relay_burst: 32,
+            relay_fanout: 16,
+            max_inbound: None,
+            reserved_delegate_slots: 8,
+            max_inbox_size: 1024 * 1024,
+            max_storage_bytes: None,
+            storm_connections: 8,
+            max_uploads: None,
+        }
+    }
+}
+
+/// Limits on inbound connections originating from the same subnet.
+#[derive(Debug, Clone)]
+pub struct SubnetLimits {
+    /// Prefix length of IPv4 subnets, eg. `24` for a `/24`.
+    pub ipv4_prefix: u8,
+    /// Prefix length of IPv6 subnets, eg. `48` for a `/48`.
+    pub ipv6_prefix: u8,
+    /// Maximum number of inbound connections from a single subnet.
+    pub max_inbound: usize,
+}
+
+impl Default for SubnetLimits {
+    fn default() -> Self {
+        Self {
+            ipv4_prefix: 24,
+            ipv6_prefix: 48,
+            max_inbound: 8,
+        }
+    }
+}
+
+/// A rate limit, enforced with a token bucket.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct RateLimit {
+    /// Number of tokens added to the bucket per second, ie. the sustained rate.
+    pub fill_rate: f64,
+    /// Size of the bucket, ie. the maximum burst.
+    pub capacity: usize,
+}
+
+/// Limits on the rate of inbound connections.
+#[derive(Debug, Clone)]
+pub struct AcceptLimits {
+    /// Rate limit per remote IP address.
+    pub per_ip: RateLimit,
+    /// Rate limit across all inbound connections.
+    pub global: RateLimit,
+}
+
+impl Default for AcceptLimits {
+    fn default() -> Self {
+        Self {
+            per_ip: RateLimit {
+                fill_rate: 0.5,
+                capacity: 8,
+            },
+            global: RateLimit {
+                fill_rate: 16.,
+                capacity: 64,
+            },
+        }
+    }
+}
+
+impl SubnetLimits {
+    /// Check whether two IP addresses are in the same subnet.
+    pub fn is_same_subnet(&self, a: &net::IpAddr, b: &net::IpAddr) -> bool {
+        match (a, b) {
+            (net::IpAddr::V4(a), net::IpAddr::V4(b)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.ipv4_prefix.min(32) as u32)
+                    .unwrap_or(0);
+
+                u32::from(*a) & mask == u32::from(*b) & mask
+            }
+            (net::IpAddr::V6(a), net::IpAddr::V6(b)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.ipv6_prefix.min(128) as u32)
+                    .unwrap_or(0);
+
+                u128::from(*a) & mask == u128::from(*b) & mask
+            }
+            _ => false,
         }
     }
 }
@@ -41,12 +205,46 @@ pub struct Config {
     pub external_addresses: Vec<Address>,
     /// Peer-to-peer network.
     pub network: Network,
+    /// Node alias, announced to peers. At most [`MAX_ALIAS_LENGTH`] bytes of UTF-8.
+    pub alias: String,
     /// Whether or not our node should relay inventories.
     pub relay: bool,
+    /// Interval at which batches of relayed announcements are sent, when there are
+    /// more than [`Limits::relay_burst`] relays pending.
+    pub relay_interval: LocalDuration,
+    /// Number of hops our own announcements may travel, and upper bound on the hop limit
+    /// of announcements we relay. Set this below the network diameter to bound how far
+    /// announcements propagate.
+    pub announcement_ttl: u8,
+    /// Whether to record ourselves in the path of the announcements we relay. This is
+    /// off by default, and is useful when diagnosing how announcements propagate.
+    pub announcement_paths: bool,
     /// Configured service limits.
     pub limits: Limits,
     /// Default tracking policy.
     pub policy: Policy,
+    /// How long the routing table should go without changes to be considered converged.
+    pub routing_quiet_window: LocalDuration,
+    /// Minimum interval between two refs announcements for the same repository.
+    /// Updates within this interval are coalesced into a single announcement.
+    pub refs_announce_interval: LocalDuration,
+    /// During a reconnect storm, inventory announcements are held back for this long,
+    /// and the routing updates they carry are applied together.
+    pub storm_window: LocalDuration,
+    /// Duration to wait on an unresponsive peer before dropping its connection.
+    /// This may need to be raised on high-latency links.
+    pub stale_connection_timeout: LocalDuration,
+    /// Primary node to mirror, if we're running as a hot standby. A standby tracks
+    /// every repository in the primary's inventory, so that it can take over from it.
+    pub standby: Option<NodeId>,
+    /// Repositories with fewer seeds than this in our routing table are considered rare.
+    /// If set, queued fetch requests for rare repositories are served first, to improve
+    /// their replication.
+    pub rare_seed_threshold: Option<usize>,
+    /// Interval at which we re-send our own inventory and node announcements, so that
+    /// peers who missed them learn about us. Off by default; seeds that want to be
+    /// reliably discoverable should set this.
+    pub reannounce_interval: Option<LocalDuration>,
 }
 
 impl Default for Config {
@@ -55,9 +253,20 @@ impl Default for Config {
             connect: Vec::default(),
             external_addresses: vec![],
             network: Network::default(),
+            alias: String::from("anonymous"),
             relay: true,
+            relay_interval: LocalDuration::from_millis(100),
+            announcement_ttl: Announcement::MAX_TTL,
+            announcement_paths: false,
             limits: Limits::default(),
             policy: Policy::Block,
+            routing_quiet_window: LocalDuration::from_mins(1),
+            refs_announce_interval: LocalDuration::from_secs(5),
+            storm_window: LocalDuration::from_secs(3),
+            stale_connection_timeout: LocalDuration::from_secs(60),
+            standby: None,
+            rare_seed_threshold: None,
+            reannounce_interval: None,
         }
     }
 }
@@ -70,6 +279,26 @@ impl Config {
         }
     }
 
+    /// Load the node configuration from the given radicle home.
+    /// Settings missing from the configuration file are set to their defaults.
+    /// If there is no configuration file, the default configuration is returned.
+    pub fn load(home: &Home) -> Result<Self, LoadError> {
+        let path = home.node().join(CONFIG_FILE_NAME);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        Self::from_file(&path)
+    }
+
+    /// Load the node configuration from a file, merging it with the defaults.
+    pub fn from_file(path: &Path) -> Result<Self, LoadError> {
+        let bytes = fs::read(path).map_err(|e| LoadError::Io(path.to_path_buf(), e))?;
+        let file: ConfigFile =
+            serde_json::from_slice(&bytes).map_err(|e| LoadError::Json(path.to_path_buf(), e))?;
+
+        file.merge(Self::default())
+    }
+
     pub fn peer(&self, id: &NodeId) -> Option<&Address> {
         self.connect.iter().find(|(i, _)| i == id).map(|(_, a)| a)
     }
@@ -78,10 +307,311 @@ impl Config {
         self.connect.iter().any(|(i, _)| i == id)
     }
 
-    pub fn alias(&self) -> [u8; 32] {
-        let mut alias = [0u8; 32];
+    /// Set the node alias, checking that it fits in [`MAX_ALIAS_LENGTH`] bytes.
+    pub fn set_alias(&mut self, alias: impl Into<String>) -> Result<(), AliasError> {
+        let alias = alias.into();
+        if alias.len() > MAX_ALIAS_LENGTH {
+            return Err(AliasError(alias));
+        }
+        self.alias = alias;
+
+        Ok(())
+    }
+
+    /// Get the node alias, zero-padded to [`MAX_ALIAS_LENGTH`] bytes.
+    /// Aliases that are too long are truncated on a character boundary.
+    pub fn alias(&self) -> [u8; MAX_ALIAS_LENGTH] {
+        let mut alias = [0u8; MAX_ALIAS_LENGTH];
+        let mut len = self.alias.len().min(MAX_ALIAS_LENGTH);
 
-        alias[..9].copy_from_slice("anonymous".as_bytes());
+        while !self.alias.is_char_boundary(len) {
+            len -= 1;
+        }
+        alias[..len].copy_from_slice(&self.alias.as_bytes()[..len]);
         alias
     }
 }
+
+/// Configuration file format. Every setting is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct ConfigFile {
+    connect: Option<Vec<String>>,
+    external_addresses: Option<Vec<String>>,
+    network: Option<Network>,
+    alias: Option<String>,
+    relay: Option<bool>,
+    /// Relay interval, in milliseconds.
+    relay_interval: Option<u128>,
+    announcement_ttl: Option<u8>,
+    announcement_paths: Option<bool>,
+    policy: Option<Policy>,
+    /// Quiet window, in seconds.
+    routing_quiet_window: Option<u64>,
+    /// Refs announcement interval, in seconds.
+    refs_announce_interval: Option<u64>,
+    /// Storm window, in seconds.
+    storm_window: Option<u64>,
+    /// Stale connection timeout, in seconds.
+    stale_connection_timeout: Option<u64>,
+    standby: Option<String>,
+    rare_seed_threshold: Option<usize>,
+    /// Re-announce interval, in seconds.
+    reannounce_interval: Option<u64>,
+    #[serde(default)]
+    limits: LimitsFile,
+}
+
+/// Configuration file format for [`Limits`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct LimitsFile {
+    routing_max_size: Option<usize>,
+    relay_burst: Option<usize>,
+    relay_fanout: Option<usize>,
+    max_inbound: Option<usize>,
+    reserved_delegate_slots: Option<usize>,
+    max_inbox_size: Option<usize>,
+    max_storage_bytes: Option<u64>,
+    storm_connections: Option<usize>,
+    max_uploads: Option<usize>,
+    /// Maximum age, in seconds.
+    routing_max_age: Option<u64>,
+    #[serde(default)]
+    subnet: SubnetLimitsFile,
+    #[serde(default)]
+    accept: AcceptLimitsFile,
+}
+
+/// Configuration file format for [`AcceptLimits`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct AcceptLimitsFile {
+    per_ip: Option<RateLimit>,
+    global: Option<RateLimit>,
+}
+
+/// Configuration file format for [`SubnetLimits`].
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+struct SubnetLimitsFile {
+    ipv4_prefix: Option<u8>,
+    ipv6_prefix: Option<u8>,
+    max_inbound: Option<usize>,
+}
+
+impl ConfigFile {
+    /// Override the given configuration with the settings from the file.
+    fn merge(self, mut config: Config) -> Result<Config, LoadError> {
+        if let Some(connect) = self.connect {
+            config.connect = connect
+                .into_iter()
+                .map(|s| {
+                    s.parse::<PeerAddr<NodeId, Address>>()
+                        .map(|peer| (peer.id, peer.addr))
+                        .map_err(|e| LoadError::InvalidPeer(s, e.to_string()))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(addrs) = self.external_addresses {
+            config.external_addresses = addrs
+                .into_iter()
+                .map(|s| {
+                    s.parse::<Address>()
+                        .map_err(|e| LoadError::InvalidAddress(s, e.to_string()))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(network) = self.network {
+            config.network = network;
+        }
+        if let Some(alias) = self.alias {
+            config.set_alias(alias)?;
+        }
+        if let Some(relay) = self.relay {
+            config.relay = relay;
+        }
+        if let Some(ms) = self.relay_interval {
+            config.relay_interval = LocalDuration::from_millis(ms);
+        }
+        if let Some(ttl) = self.announcement_ttl {
+            config.announcement_ttl = ttl;
+        }
+        if let Some(paths) = self.announcement_paths {
+            config.announcement_paths = paths;
+        }
+        if let Some(policy) = self.policy {
+            config.policy = policy;
+        }
+        if let Some(secs) = self.routing_quiet_window {
+            config.routing_quiet_window = LocalDuration::from_secs(secs);
+        }
+        if let Some(secs) = self.refs_announce_interval {
+            config.refs_announce_interval = LocalDuration::from_secs(secs);
+        }
+        if let Some(secs) = self.storm_window {
+            config.storm_window = LocalDuration::from_secs(secs);
+        }
+        if let Some(secs) = self.stale_connection_timeout {
+            config.stale_connection_timeout = LocalDuration::from_secs(secs);
+        }
+        if let Some(primary) = self.standby {
+            config.standby = Some(
+                primary
+                    .parse::<NodeId>()
+                    .map_err(|e| LoadError::InvalidPeer(primary, e.to_string()))?,
+            );
+        }
+        if let Some(threshold) = self.rare_seed_threshold {
+            config.rare_seed_threshold = Some(threshold);
+        }
+        if let Some(secs) = self.reannounce_interval {
+            config.reannounce_interval = Some(LocalDuration::from_secs(secs));
+        }
+
+        let limits = self.limits;
+        if let Some(size) = limits.routing_max_size {
+            config.limits.routing_max_size = size;
+        }
+        if let Some(burst) = limits.relay_burst {
+            config.limits.relay_burst = burst;
+        }
+        if let Some(fanout) = limits.relay_fanout {
+            config.limits.relay_fanout = fanout;
+        }
+        if let Some(max) = limits.max_inbound {
+            config.limits.max_inbound = Some(max);
+        }
+        if let Some(slots) = limits.reserved_delegate_slots {
+            config.limits.reserved_delegate_slots = slots;
+        }
+        if let Some(size) = limits.max_inbox_size {
+            config.limits.max_inbox_size = size;
+        }
+        if let Some(bytes) = limits.max_storage_bytes {
+            config.limits.max_storage_bytes = Some(bytes);
+        }
+        if let Some(count) = limits.storm_connections {
+            config.limits.storm_connections = count;
+        }
+        if let Some(max) = limits.max_uploads {
+            config.limits.max_uploads = Some(max);
+        }
+        if let Some(secs) = limits.routing_max_age {
+            config.limits.routing_max_age = LocalDuration::from_secs(secs);
+        }
+
+        let subnet = limits.subnet;
+        if let Some(prefix) = subnet.ipv4_prefix {
+            config.limits.subnet.ipv4_prefix = prefix;
+        }
+        if let Some(prefix) = subnet.ipv6_prefix {
+            config.limits.subnet.ipv6_prefix = prefix;
+        }
+        if let Some(max) = subnet.max_inbound {
+            config.limits.subnet.max_inbound = max;
+        }
+
+        let accept = limits.accept;
+        if let Some(limit) = accept.per_ip {
+            config.limits.accept.per_ip = limit;
+        }
+        if let Some(limit) = accept.global {
+            config.limits.accept.global = limit;
+        }
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_partial() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = Home::new(tmp.path()).unwrap();
+
+        fs::write(
+            home.node().join(CONFIG_FILE_NAME),
+            r#"{ "relay": false, "policy": "track", "limits": { "routingMaxSize": 42 } }"#,
+        )
+        .unwrap();
+
+        let config = Config::load(&home).unwrap();
+        let default = Config::default();
+
+        assert!(!config.relay);
+        assert_eq!(config.policy, Policy::Track);
+        assert_eq!(config.limits.routing_max_size, 42);
+
+        // Everything else is set to the defaults.
+        assert_eq!(config.network, default.network);
+        assert_eq!(config.connect, default.connect);
+        assert_eq!(config.external_addresses, default.external_addresses);
+        assert_eq!(config.routing_quiet_window, default.routing_quiet_window);
+        assert_eq!(config.announcement_ttl, default.announcement_ttl);
+        assert_eq!(
+            config.limits.routing_max_age,
+            default.limits.routing_max_age
+        );
+        assert_eq!(
+            config.limits.subnet.max_inbound,
+            default.limits.subnet.max_inbound
+        );
+    }
+
+    #[test]
+    fn test_load_missing() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = Home::new(tmp.path()).unwrap();
+        let config = Config::load(&home).unwrap();
+
+        assert_eq!(
+            config.limits.routing_max_size,
+            Limits::default().routing_max_size
+        );
+    }
+
+    #[test]
+    fn test_load_invalid() {
+        let tmp = tempfile::tempdir().unwrap();
+        let home = Home::new(tmp.path()).unwrap();
+
+        fs::write(
+            home.node().join(CONFIG_FILE_NAME),
+            r#"{ "connect": ["not-a-peer"] }"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            Config::load(&home),
+            Err(LoadError::InvalidPeer(..))
+        ));
+    }
+
+    #[test]
+    fn test_alias() {
+        let mut config = Config::default();
+
+        // 4 x 8 = 32 bytes.
+        config.set_alias("🌱🌱🌱🌱🌱🌱🌱🌱").unwrap();
+        let alias = config.alias();
+        assert_eq!(std::str::from_utf8(&alias).unwrap(), "🌱🌱🌱🌱🌱🌱🌱🌱");
+
+        config.set_alias("seed").unwrap();
+        let alias = config.alias();
+        assert_eq!(&alias[..4], b"seed");
+        assert!(alias[4..].iter().all(|b| *b == 0));
+
+        // 33 bytes.
+        assert!(config.set_alias("🌱🌱🌱🌱🌱🌱🌱🌱!").is_err());
+        assert_eq!(config.alias, "seed");
+
+        // Aliases set directly are truncated without splitting characters.
+        config.alias = String::from("a🌱🌱🌱🌱🌱🌱🌱🌱");
+        let alias = config.alias();
+        assert_eq!(&alias[..29], "a🌱🌱🌱🌱🌱🌱🌱".as_bytes());
+        assert!(alias[29..].iter().all(|b| *b == 0));
+    }
+}
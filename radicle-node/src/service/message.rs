@@ -1,13 +1,17 @@
 use std::{fmt, io, mem};
 
+use localtime::{LocalDuration, LocalTime};
+
+use crate::clock::TimestampExt as _;
 use crate::crypto;
 use crate::crypto::Unverified;
+use crate::git;
 use crate::identity::Id;
 use crate::node;
 use crate::node::Address;
 use crate::prelude::BoundedVec;
 use crate::service::filter::Filter;
-use crate::service::{NodeId, Timestamp};
+use crate::service::{Network, NodeId, Timestamp};
 use crate::storage;
 use crate::storage::refs::SignedRefs;
 use crate::storage::{ReadRepository, ReadStorage};
@@ -17,8 +21,13 @@ use crate::wire;
 pub const ADDRESS_LIMIT: usize = 16;
 /// Maximum number of repository remotes that can be included in a [`RefsAnnouncement`] message.
 pub const REF_REMOTE_LIMIT: usize = 512;
-/// Maximum number of inventory which can be announced to other nodes.
-pub const INVENTORY_LIMIT: usize = 2973;
+/// Maximum number of inventory which can be announced to other nodes. This leaves room
+/// for a full relay path within the maximum message size.
+pub const INVENTORY_LIMIT: usize = 2950;
+/// Maximum number of relaying nodes recorded in an announcement's path.
+pub const PATH_LIMIT: usize = 16;
+/// Maximum number of announcement fingerprints which can be included in a [`Digest`].
+pub const DIGEST_LIMIT: usize = 4096;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 // TODO: We should check the length and charset when deserializing.
@@ -50,6 +59,16 @@ impl Subscribe {
     }
 }
 
+/// Summary of the announcements a node received recently. Peers receiving it reply
+/// with the announcements the node is missing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digest {
+    /// The digest covers the announcements received since this time.
+    pub since: Timestamp,
+    /// Fingerprints of the announcements, see [`Announcement::fingerprint`].
+    pub fingerprints: BoundedVec<u64, DIGEST_LIMIT>,
+}
+
 /// Node announcing itself to the network.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct NodeAnnouncement {
@@ -174,6 +193,49 @@ impl RefsAnnouncement {
         }
         Ok(false)
     }
+
+    /// Get the object a fetch of this announcement from the given announcer should be pinned
+    /// to: the first of the announcer's own refs that we don't have yet, if any.
+    pub fn target<S: ReadStorage>(&self, storage: S, announcer: &NodeId) -> Option<git::Oid> {
+        let (_, theirs) = self.refs.iter().find(|(remote, _)| remote == announcer)?;
+        let ours = storage
+            .repository(self.rid)
+            .ok()
+            .and_then(|repo| repo.remote(announcer).ok());
+
+        theirs
+            .refs
+            .iter()
+            .find(|(name, oid)| ours.as_ref().and_then(|ours| ours.refs.get(*name)) != Some(*oid))
+            .map(|(_, oid)| *oid)
+    }
+
+    /// Find an announced ref pointing to a commit dated further than `max_skew` in the future,
+    /// relative to `now`. Only commits that are present in storage can be checked, which makes
+    /// this a cheap early rejection: commits we don't have yet are checked by the worker,
+    /// once they're fetched.
+    pub fn future_dated<S: ReadStorage>(
+        &self,
+        storage: S,
+        now: LocalTime,
+        max_skew: LocalDuration,
+    ) -> Option<git::Oid> {
+        let repo = storage.repository(self.rid).ok()?;
+
+        for (_, theirs) in self.refs.iter() {
+            for oid in theirs.refs.values() {
+                let Ok(commit) = repo.commit(*oid) else {
+                    continue;
+                };
+                let time: Timestamp = u64::try_from(commit.time().seconds()).unwrap_or(0) * 1000;
+
+                if time.is_future(now, max_skew) {
+                    return Some(*oid);
+                }
+            }
+        }
+        None
+    }
 }
 
 /// Node announcing its inventory to the network.
@@ -200,13 +262,17 @@ pub enum AnnouncementMessage {
 impl AnnouncementMessage {
     /// Sign this announcement message.
     pub fn signed<G: crypto::Signer>(self, signer: &G) -> Announcement {
-        let msg = wire::serialize(&self);
+        let scheme = SignatureScheme::Ed25519;
+        let msg = Announcement::signed_bytes(scheme, &self);
         let signature = signer.sign(&msg);
 
         Announcement {
             node: *signer.public_key(),
             message: self,
+            scheme,
             signature,
+            ttl: Announcement::MAX_TTL,
+            path: BoundedVec::new(),
         }
     }
 
@@ -261,14 +327,55 @@ impl fmt::Debug for AnnouncementMessage {
     }
 }
 
+/// Scheme used to sign an announcement. Its tag is covered by the signature, and lets
+/// verifiers know how to check it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum SignatureScheme {
+    /// Ed25519 signature by the announcing node's key.
+    #[default]
+    Ed25519,
+    /// Scheme we don't know about, eg. introduced by a later protocol version.
+    Unknown(u8),
+}
+
+impl SignatureScheme {
+    /// Tag identifying the scheme on the wire.
+    pub fn tag(&self) -> u8 {
+        match self {
+            Self::Ed25519 => 1,
+            Self::Unknown(tag) => *tag,
+        }
+    }
+}
+
+impl From<u8> for SignatureScheme {
+    fn from(tag: u8) -> Self {
+        match tag {
+            1 => Self::Ed25519,
+            _ => Self::Unknown(tag),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Announcement {
     /// Node identifier.
     pub node: NodeId,
     /// Unsigned node announcement.
     pub message: AnnouncementMessage,
+    /// Scheme the announcement is signed with.
+    pub scheme: SignatureScheme,
     /// Signature over the announcement.
     pub signature: crypto::Signature,
+    /// Number of hops this announcement may still be relayed. This is decremented
+    /// by every relaying node, and is not covered by the signature.
+    pub ttl: u8,
+    /// Nodes that relayed this announcement, oldest first, if they chose to record
+    /// themselves. Only the most recent [`PATH_LIMIT`] relays are kept. Like the hop
+    /// limit, this is not covered by the signature, so any relay can alter it. It is
+    /// advisory, only meant for diagnostics, and announcements are never rejected
+    /// because of it.
+    pub path: BoundedVec<NodeId, PATH_LIMIT>,
 }
 
 impl Announcement {
@@ -289,11 +396,53 @@ impl Announcement {
     pub const POW_PARAMS: (u8, u32, u32) = (15, 8, 1);
     /// Salt used for generating PoW.
     pub const POW_SALT: &[u8] = &[b'r', b'a', b'd'];
+    /// Maximum, and default, hop limit of announcements.
+    pub const MAX_TTL: u8 = u8::MAX;
+
+    /// Set the hop limit of this announcement.
+    pub fn with_ttl(mut self, ttl: u8) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Record a relaying node in this announcement's path. If the path is full,
+    /// the oldest relay is dropped.
+    pub fn relayed_by(mut self, node: NodeId) -> Self {
+        let mut path = self.path.unbound();
+        if path.len() >= PATH_LIMIT {
+            path.remove(0);
+        }
+        path.push(node);
+        self.path = BoundedVec::truncate(path);
+        self
+    }
+
+    /// Short identifier of this announcement, taken from its signature. Unlike the hop
+    /// limit and path, the signature is the same for every copy of an announcement.
+    pub fn fingerprint(&self) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&self.signature.as_ref()[..8]);
+
+        u64::from_be_bytes(bytes)
+    }
 
-    /// Verify this announcement's signature.
+    /// Verify this announcement's signature. Announcements signed with a scheme we don't
+    /// know about never verify.
     pub fn verify(&self) -> bool {
-        let msg = wire::serialize(&self.message);
-        self.node.verify(msg, &self.signature).is_ok()
+        match self.scheme {
+            SignatureScheme::Ed25519 => {
+                let msg = Self::signed_bytes(self.scheme, &self.message);
+                self.node.verify(msg, &self.signature).is_ok()
+            }
+            SignatureScheme::Unknown(_) => false,
+        }
+    }
+
+    /// Bytes covered by the signature: the scheme tag, followed by the message.
+    fn signed_bytes(scheme: SignatureScheme, message: &AnnouncementMessage) -> Vec<u8> {
+        let mut bytes = vec![scheme.tag()];
+        bytes.extend(wire::serialize(message));
+        bytes
     }
 
     pub fn matches(&self, filter: &Filter) -> bool {
@@ -309,6 +458,15 @@ impl Announcement {
 /// These are the messages peers send to each other.
 #[derive(Clone, PartialEq, Eq)]
 pub enum Message {
+    /// Protocol version and network of the sender. This is the first message sent on a new
+    /// connection.
+    Version {
+        /// Protocol version, see [`wire::PROTOCOL_VERSION`].
+        version: u32,
+        /// Network magic, see [`crate::service::Network::magic`].
+        magic: u8,
+    },
+
     /// Subscribe to gossip messages matching the filter and time range.
     Subscribe(Subscribe),
 
@@ -316,6 +474,11 @@ pub enum Message {
     /// using [`Message::Subscribe`].
     Announcement(Announcement),
 
+    /// Summary of the announcements received recently, sent periodically to our peers.
+    /// Peers reply with the announcements matching our subscription that aren't in the
+    /// digest, so that announcements missed eg. while disconnected are caught up on.
+    Digest(Digest),
+
     /// Ask a connected peer for a Pong.
     ///
     /// Used to check if the remote peer is responsive, or a side-effect free way to keep a
@@ -329,10 +492,34 @@ pub enum Message {
     },
 
     /// Request a session upgrade to the Git protocol and fetch the given repository.
-    Fetch { rid: Id },
+    Fetch {
+        /// Repository to fetch.
+        rid: Id,
+        /// Object the fetch is pinned to, eg. an announced commit. If set, the fetched
+        /// repository is expected to contain this object once the fetch completes.
+        oid: Option<git::Oid>,
+    },
 
     /// Accept a fetch request.
     FetchOk { rid: Id },
+
+    /// Ask the receiving node to fetch the given repository from us now, if it tracks it.
+    /// Nodes act on at most one such request per repository within
+    /// [`crate::service::SEED_REQUEST_INTERVAL`].
+    SeedRequest(Id),
+
+    /// Request a connection reversal: the `target` node is asked to connect to the
+    /// requesting `node`, at the given address. This is sent to our peers when we're unable
+    /// to dial the target, eg. because it is behind a NAT, and is relayed once by peers
+    /// who are connected to the target.
+    ConnectRequest {
+        /// Node requesting the connection.
+        node: NodeId,
+        /// Node asked to connect to the requesting node.
+        target: NodeId,
+        /// Address at which the requesting node can be reached.
+        addr: Address,
+    },
 }
 
 impl Message {
@@ -345,6 +532,9 @@ impl Message {
             node,
             signature,
             message: message.into(),
+            scheme: SignatureScheme::default(),
+            ttl: Announcement::MAX_TTL,
+            path: BoundedVec::new(),
         }
         .into()
     }
@@ -357,6 +547,13 @@ impl Message {
         AnnouncementMessage::from(message).signed(signer).into()
     }
 
+    pub fn version(network: Network) -> Self {
+        Self::Version {
+            version: wire::PROTOCOL_VERSION,
+            magic: network.magic(),
+        }
+    }
+
     pub fn subscribe(filter: Filter, since: Timestamp, until: Timestamp) -> Self {
         Self::Subscribe(Subscribe {
             filter,
@@ -405,16 +602,29 @@ impl From<Announcement> for Message {
 impl fmt::Debug for Message {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Version { version, magic } => write!(f, "Version({version}, {magic:#04x})"),
             Self::Subscribe(Subscribe { since, until, .. }) => {
                 write!(f, "Subscribe({since}..{until})")
             }
             Self::Announcement(Announcement { node, message, .. }) => {
                 write!(f, "Announcement({node}, {message:?})")
             }
+            Self::Digest(Digest {
+                since,
+                fingerprints,
+            }) => write!(f, "Digest({since}.., {})", fingerprints.len()),
             Self::Ping(Ping { ponglen, zeroes }) => write!(f, "Ping({ponglen}, {zeroes:?})"),
             Self::Pong { zeroes } => write!(f, "Pong({zeroes:?})"),
-            Self::Fetch { rid } => write!(f, "Fetch({rid})"),
+            Self::Fetch { rid, oid: None } => write!(f, "Fetch({rid})"),
+            Self::Fetch {
+                rid,
+                oid: Some(oid),
+            } => write!(f, "Fetch({rid}@{oid})"),
             Self::FetchOk { rid } => write!(f, "FetchOk({rid})"),
+            Self::SeedRequest(rid) => write!(f, "SeedRequest({rid})"),
+            Self::ConnectRequest { node, target, addr } => {
+                write!(f, "ConnectRequest({node}@{addr} -> {target})")
+            }
         }
     }
 }
@@ -480,7 +690,7 @@ mod tests {
 
     #[test]
     fn test_inventory_limit() {
-        let msg = Message::inventory(
+        let Message::Announcement(ann) = Message::inventory(
             InventoryAnnouncement {
                 inventory: arbitrary::vec(INVENTORY_LIMIT)
                     .try_into()
@@ -488,7 +698,17 @@ mod tests {
                 timestamp: LocalTime::now().as_millis(),
             },
             &MockSigner::default(),
+        ) else {
+            panic!("expected an announcement");
+        };
+        // The announcement is relayed by enough nodes to fill its path.
+        let msg = Message::Announcement(
+            arbitrary::vec::<NodeId>(PATH_LIMIT)
+                .into_iter()
+                .fold(ann, |ann, node| ann.relayed_by(node)),
         );
+        assert!(matches!(&msg, Message::Announcement(a) if a.path.len() == PATH_LIMIT));
+
         let mut buf: Vec<u8> = Vec::new();
         assert!(
             msg.encode(&mut buf).is_ok(),
@@ -507,6 +727,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_digest_limit() {
+        let msg = Message::Digest(Digest {
+            since: LocalTime::now().as_millis(),
+            fingerprints: arbitrary::vec(DIGEST_LIMIT)
+                .try_into()
+                .expect("size within bounds limit"),
+        });
+        let mut buf: Vec<u8> = Vec::new();
+        assert!(
+            msg.encode(&mut buf).is_ok(),
+            "DIGEST_LIMIT is a valid limit for encoding",
+        );
+        assert_eq!(wire::deserialize::<Message>(buf.as_slice()).unwrap(), msg);
+    }
+
     #[quickcheck]
     fn prop_refs_announcement_signing(rid: Id, refs: Refs) {
         let signer = MockSigner::new(&mut fastrand::Rng::new());
@@ -1,8 +1,10 @@
 use std::collections::{HashMap, VecDeque};
 use std::mem;
 
+use crossbeam_channel as chan;
 use log::*;
 
+use crate::git;
 use crate::prelude::*;
 use crate::service::session::Session;
 use crate::storage::Namespaces;
@@ -37,6 +39,14 @@ pub struct Fetch {
     pub remote: NodeId,
     /// Indicates whether the fetch request was initiated by us.
     pub initiated: bool,
+    /// Object the fetch is pinned to, if any. Only set on fetches initiated by us.
+    pub oid: Option<git::Oid>,
+    /// Whether to only list the remote refs, without fetching them. Only set on fetches
+    /// initiated by us.
+    pub dry_run: bool,
+    /// Where to stream the git protocol log of this fetch, if it's being debugged. Only set
+    /// on fetches initiated by us.
+    pub debug: Vec<chan::Sender<String>>,
 }
 
 /// Interface to the network reactor.
@@ -127,6 +137,9 @@ impl Reactor {
         rid: Id,
         namespaces: Namespaces,
         initiated: bool,
+        oid: Option<git::Oid>,
+        dry_run: bool,
+        debug: Vec<chan::Sender<String>>,
     ) {
         // Transition the session state machine to "fetching".
         remote.to_fetching(rid);
@@ -136,6 +149,9 @@ impl Reactor {
             namespaces,
             remote: remote.id,
             initiated,
+            oid,
+            dry_run,
+            debug,
         }));
     }
 
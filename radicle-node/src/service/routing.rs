@@ -21,6 +21,9 @@ pub enum Error {
     UnitOverflow,
 }
 
+/// A batch of routing table entries, with the time at which each entry was last refreshed.
+pub type Batch = Vec<(Id, NodeId, Timestamp)>;
+
 /// Persistent file storage for a routing table.
 pub struct Table {
     db: sql::Connection,
@@ -38,7 +41,8 @@ impl Table {
     /// Open a routing file store at the given path. Creates a new empty store
     /// if an existing store isn't found.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let db = sql::Connection::open(path)?;
+        let mut db = sql::Connection::open(path)?;
+        db.set_busy_timeout(crate::sql::BUSY_TIMEOUT)?;
         db.execute(Self::SCHEMA)?;
 
         Ok(Self { db })
@@ -57,6 +61,11 @@ impl Table {
 pub trait Store {
     /// Get the nodes seeding the given id.
     fn get(&self, id: &Id) -> Result<HashSet<NodeId>, Error>;
+    /// Get the seeds of the given repository, ie. the nodes that announced it in their
+    /// inventory. This is a lookup by repository, and doesn't scan the table.
+    fn seeds_for(&self, rid: &Id) -> Result<HashSet<NodeId>, Error> {
+        self.get(rid)
+    }
     /// Get the resources seeded by the given node.
     fn get_resources(&self, node_id: &NodeId) -> Result<HashSet<Id>, Error>;
     /// Get a specific entry.
@@ -71,6 +80,9 @@ pub trait Store {
     fn remove(&mut self, id: &Id, node: &NodeId) -> Result<bool, Error>;
     /// Iterate over all entries in the routing table.
     fn entries(&self) -> Result<Box<dyn Iterator<Item = (Id, NodeId)>>, Error>;
+    /// Iterate over all entries in the routing table, in batches of at most the given size.
+    /// Unlike [`Store::entries`], only one batch is loaded in memory at a time.
+    fn iter(&self, size: usize) -> Box<dyn Iterator<Item = Result<Batch, Error>> + '_>;
     /// Get the total number of routing entries.
     fn len(&self) -> Result<usize, Error>;
     /// Prune entries older than the given timestamp.
@@ -152,6 +164,14 @@ impl Store for Table {
         Ok(Box::new(entries.into_iter()))
     }
 
+    fn iter(&self, size: usize) -> Box<dyn Iterator<Item = Result<Batch, Error>> + '_> {
+        Box::new(Batches {
+            db: &self.db,
+            size,
+            cursor: Some(0),
+        })
+    }
+
     fn remove(&mut self, id: &Id, node: &NodeId) -> Result<bool, Error> {
         let mut stmt = self
             .db
@@ -194,6 +214,65 @@ impl Store for Table {
     }
 }
 
+/// Iterator over batches of routing table entries. Each batch is queried separately,
+/// starting after the last row of the previous batch.
+struct Batches<'a> {
+    db: &'a sql::Connection,
+    /// Maximum batch size.
+    size: usize,
+    /// Row id of the last entry returned, or `None` once we're done.
+    cursor: Option<i64>,
+}
+
+impl<'a> Batches<'a> {
+    fn fetch(&mut self, after: i64) -> Result<Batch, Error> {
+        let limit: i64 = self.size.try_into().map_err(|_| Error::UnitOverflow)?;
+        let mut stmt = self.db.prepare(
+            "SELECT rowid, resource, node, time FROM routing
+             WHERE rowid > ? ORDER BY rowid LIMIT ?",
+        )?;
+        stmt.bind((1, after))?;
+        stmt.bind((2, limit))?;
+
+        let mut batch = Vec::with_capacity(self.size);
+        for row in stmt.into_iter() {
+            let row = row?;
+
+            self.cursor = Some(row.read::<i64, _>("rowid"));
+            batch.push((
+                row.read::<Id, _>("resource"),
+                row.read::<NodeId, _>("node"),
+                row.read::<i64, _>("time") as Timestamp,
+            ));
+        }
+        Ok(batch)
+    }
+}
+
+impl<'a> Iterator for Batches<'a> {
+    type Item = Result<Batch, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let after = self.cursor?;
+
+        match self.fetch(after) {
+            Ok(batch) => {
+                if batch.len() < self.size {
+                    self.cursor = None;
+                }
+                if batch.is_empty() {
+                    return None;
+                }
+                Some(Ok(batch))
+            }
+            Err(err) => {
+                self.cursor = None;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use localtime::LocalTime;
@@ -221,6 +300,25 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_seeds_for() {
+        let rid = arbitrary::gen::<Id>(1);
+        let other = arbitrary::gen::<Id>(1);
+        let seeds = arbitrary::set::<NodeId>(3..6);
+        let others = arbitrary::set::<NodeId>(3..6);
+        let mut db = Table::open(":memory:").unwrap();
+
+        for node in &seeds {
+            db.insert(rid, *node, 0).unwrap();
+        }
+        for node in &others {
+            db.insert(other, *node, 0).unwrap();
+        }
+        assert_eq!(db.seeds_for(&rid).unwrap(), seeds.into_iter().collect());
+        assert_eq!(db.seeds_for(&other).unwrap(), others.into_iter().collect());
+        assert!(db.seeds_for(&arbitrary::gen(1)).unwrap().is_empty());
+    }
+
     #[test]
     fn test_insert_and_get_resources() {
         let ids = arbitrary::set::<Id>(5..10);
@@ -262,6 +360,35 @@ mod test {
         assert_eq!(results_ids.len(), ids.len(), "Entries are grouped by id");
     }
 
+    #[test]
+    fn test_iter_batches() {
+        let node = arbitrary::gen::<NodeId>(1);
+        let ids = arbitrary::vec::<Id>(10_000);
+        let mut db = Table::open(":memory:").unwrap();
+
+        for (i, id) in ids.iter().enumerate() {
+            db.insert(*id, node, i as Timestamp).unwrap();
+        }
+
+        let mut count = 0;
+        for batch in db.iter(3_000) {
+            let batch = batch.unwrap();
+            assert!(batch.len() <= 3_000, "Batches are bounded");
+
+            for (id, n, time) in batch {
+                assert_eq!(n, node);
+                assert_eq!(
+                    id, ids[time as usize],
+                    "Entries are returned in insertion order"
+                );
+                count += 1;
+            }
+        }
+        assert_eq!(count, ids.len());
+        assert_eq!(db.iter(3_000).count(), 4);
+        assert_eq!(db.iter(2_500).count(), 4, "No empty trailing batch");
+    }
+
     #[test]
     fn test_insert_and_remove() {
         let ids = arbitrary::set::<Id>(5..10);
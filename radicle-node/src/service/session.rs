@@ -1,9 +1,12 @@
 use std::fmt;
 
+use crate::git;
+use crate::node;
+use crate::node::{Address, Features, SessionInfo, SessionState};
 use crate::service::message;
 use crate::service::message::Message;
 use crate::service::storage;
-use crate::service::{Id, LocalTime, NodeId, Reactor, Rng};
+use crate::service::{Id, LocalDuration, LocalTime, NodeId, Reactor, Rng};
 use crate::Link;
 
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
@@ -12,7 +15,12 @@ pub enum PingState {
     /// The peer has not been sent a ping.
     None,
     /// A ping has been sent and is waiting on the peer's response.
-    AwaitingResponse(u16),
+    AwaitingResponse {
+        /// Length of the expected pong.
+        len: u16,
+        /// Time at which the ping was sent.
+        since: LocalTime,
+    },
     /// The peer was successfully pinged.
     Ok,
 }
@@ -21,7 +29,11 @@ pub enum PingState {
 #[derive(Debug, Copy, PartialEq, Eq, Clone)]
 pub enum Protocol {
     /// The default message-based gossip protocol.
-    Gossip { requested: Option<Id> },
+    /// If a fetch was requested, holds the repository, optional target object,
+    /// and whether it's a dry run.
+    Gossip {
+        requested: Option<(Id, Option<git::Oid>, bool)>,
+    },
     /// Git smart protocol. Used for fetching repository data.
     /// This protocol is used after a connection upgrade via the
     /// [`Message::Fetch`] message.
@@ -88,6 +100,8 @@ pub enum FetchResult {
 pub enum Error {
     #[error("wrong protocol version in message: {0}")]
     WrongVersion(u32),
+    #[error("wrong network magic in message: {0:#04x}")]
+    WrongMagic(u8),
     #[error("invalid announcement timestamp: {0}")]
     InvalidTimestamp(u64),
     #[error("session not found for node `{0}`")]
@@ -98,7 +112,11 @@ pub enum Error {
     Misbehavior,
     #[error("peer timed out")]
     Timeout,
-    #[error("handshake error")]
+    #[error("no connection slots available")]
+    SlotsFull,
+    #[error("node is overloaded")]
+    Overloaded,
+    #[error("handshake error: {0}")]
     Handshake(String),
 }
 
@@ -107,6 +125,8 @@ pub enum Error {
 pub struct Session {
     /// Peer id.
     pub id: NodeId,
+    /// Peer address.
+    pub addr: Address,
     /// Connection direction.
     pub link: Link,
     /// Whether we should attempt to re-connect
@@ -118,6 +138,16 @@ pub struct Session {
     pub subscribe: Option<message::Subscribe>,
     /// Last time a message was received from the peer.
     pub last_active: LocalTime,
+    /// Last time a message was handed to the transport, to be sent to the peer.
+    pub last_sent: LocalTime,
+    /// Features advertized by the peer in its node announcement, for the lifetime
+    /// of the connection.
+    pub features: Features,
+    /// Protocol version sent by the peer in its `version` message. Until it is received,
+    /// the session is pending, and no other message is accepted from the peer.
+    pub version: Option<u32>,
+    /// Estimated round-trip time to the peer, based on ping/pong exchanges.
+    latency: Option<LocalDuration>,
 
     /// Connection attempts. For persistent peers, Tracks
     /// how many times we've attempted to connect. We reset this to zero
@@ -132,6 +162,7 @@ impl fmt::Display for Session {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut attrs = Vec::new();
         let state = self.state.to_string();
+        let latency = self.latency.map(|l| format!("latency={}ms", l.as_millis()));
 
         if self.link.is_inbound() {
             attrs.push("inbound");
@@ -142,28 +173,44 @@ impl fmt::Display for Session {
             attrs.push("persistent");
         }
         attrs.push(state.as_str());
+        if let Some(latency) = &latency {
+            attrs.push(latency.as_str());
+        }
 
         write!(f, "{} [{}]", self.id, attrs.join(" "))
     }
 }
 
 impl Session {
-    pub fn connecting(id: NodeId, persistent: bool, rng: Rng) -> Self {
+    pub fn connecting(id: NodeId, addr: Address, persistent: bool, rng: Rng) -> Self {
         Self {
             id,
+            addr,
             state: State::Connecting,
             link: Link::Outbound,
             subscribe: None,
             persistent,
             last_active: LocalTime::default(),
+            last_sent: LocalTime::default(),
+            features: Features::NONE,
+            version: None,
+            latency: None,
             attempts: 1,
             rng,
         }
     }
 
-    pub fn connected(id: NodeId, link: Link, persistent: bool, rng: Rng, time: LocalTime) -> Self {
+    pub fn connected(
+        id: NodeId,
+        addr: Address,
+        link: Link,
+        persistent: bool,
+        rng: Rng,
+        time: LocalTime,
+    ) -> Self {
         Self {
             id,
+            addr,
             state: State::Connected {
                 since: time,
                 ping: PingState::default(),
@@ -173,6 +220,10 @@ impl Session {
             subscribe: None,
             persistent,
             last_active: LocalTime::default(),
+            last_sent: LocalTime::default(),
+            features: Features::NONE,
+            version: None,
+            latency: None,
             attempts: 0,
             rng,
         }
@@ -182,6 +233,34 @@ impl Session {
         matches!(self.state, State::Connecting { .. })
     }
 
+    /// Get a snapshot of the session, eg. to report it to the operator.
+    pub fn info(&self) -> SessionInfo {
+        SessionInfo {
+            nid: self.id,
+            addr: self.addr.clone(),
+            link: match self.link {
+                Link::Inbound => node::Link::Inbound,
+                Link::Outbound => node::Link::Outbound,
+            },
+            state: match self.state {
+                State::Connecting => SessionState::Connecting,
+                State::Connected {
+                    protocol: Protocol::Gossip { .. },
+                    ..
+                } => SessionState::Connected,
+                State::Connected {
+                    protocol: Protocol::Fetch { .. },
+                    ..
+                } => SessionState::Fetch,
+                State::Disconnected { .. } => SessionState::Disconnected,
+            },
+            persistent: self.persistent,
+            latency: self.latency.map(|l| l.as_millis() as u64),
+            last_active: self.last_active.as_millis(),
+            last_sent: self.last_sent.as_millis(),
+        }
+    }
+
     pub fn is_connected(&self) -> bool {
         matches!(self.state, State::Connected { .. })
     }
@@ -200,18 +279,33 @@ impl Session {
         )
     }
 
+    /// Check whether the peer advertized support for the given features. Behavior that
+    /// not all peers may support should be gated on this.
+    pub fn supports(&self, features: Features) -> bool {
+        self.features.has(features)
+    }
+
+    /// Estimated round-trip time to the peer, if it has answered any of our pings.
+    pub fn latency(&self) -> Option<LocalDuration> {
+        self.latency
+    }
+
     pub fn attempts(&self) -> usize {
         self.attempts
     }
 
-    pub fn fetch(&mut self, rid: Id) -> FetchResult {
+    pub fn fetch(&mut self, rid: Id, oid: Option<git::Oid>) -> FetchResult {
+        // Peers that don't understand pinned fetches are sent an unpinned request. The
+        // fetched object is checked by us either way.
+        let oid = oid.filter(|_| self.supports(Features::PINNED_FETCH));
+
         if let State::Connected { protocol, .. } = &mut self.state {
             match protocol {
                 Protocol::Gossip { requested } => {
-                    if let Some(requested) = requested {
+                    if let Some((requested, _, _)) = requested {
                         FetchResult::AlreadyFetching(*requested)
                     } else {
-                        FetchResult::Ready(Message::Fetch { rid })
+                        FetchResult::Ready(Message::Fetch { rid, oid })
                     }
                 }
                 Protocol::Fetch { rid } => FetchResult::AlreadyFetching(*rid),
@@ -221,12 +315,12 @@ impl Session {
         }
     }
 
-    pub fn to_requesting(&mut self, rid: Id) {
+    pub fn to_requesting(&mut self, rid: Id, oid: Option<git::Oid>, dry_run: bool) {
         let State::Connected { protocol, .. } = &mut self.state else {
             panic!("Session::to_requesting: cannot transition to 'requesting': session is not connected");
         };
         *protocol = Protocol::Gossip {
-            requested: Some(rid),
+            requested: Some((rid, oid, dry_run)),
         };
     }
 
@@ -274,15 +368,46 @@ impl Session {
 
     pub fn to_disconnected(&mut self, since: LocalTime) {
         self.state = State::Disconnected { since };
+        // The peer may run a different version when it reconnects.
+        self.features = Features::NONE;
+        self.version = None;
+        self.latency = None;
     }
 
-    pub fn ping(&mut self, reactor: &mut Reactor) -> Result<(), Error> {
+    pub fn ping(&mut self, reactor: &mut Reactor, now: LocalTime) -> Result<(), Error> {
         if let State::Connected { ping, .. } = &mut self.state {
             let msg = message::Ping::new(&mut self.rng);
-            *ping = PingState::AwaitingResponse(msg.ponglen);
+            *ping = PingState::AwaitingResponse {
+                len: msg.ponglen,
+                since: now,
+            };
 
             reactor.write(self, Message::Ping(msg));
         }
         Ok(())
     }
+
+    /// Handle a pong of the given length, received at the given time. If it answers
+    /// our last ping, the round-trip time is folded into the latency estimate.
+    pub fn pong(&mut self, len: usize, now: LocalTime) {
+        let State::Connected { ping, .. } = &mut self.state else {
+            return;
+        };
+        let PingState::AwaitingResponse { len: ponglen, since } = *ping else {
+            return;
+        };
+        if ponglen as usize != len {
+            return;
+        }
+        *ping = PingState::Ok;
+
+        let rtt = now - since;
+        // Smooth the estimate, giving each new sample a weight of 1/8th.
+        self.latency = Some(match self.latency {
+            Some(latency) => {
+                LocalDuration::from_millis((latency.as_millis() * 7 + rtt.as_millis()) / 8)
+            }
+            None => rtt,
+        });
+    }
 }
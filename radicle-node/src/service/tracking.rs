@@ -3,6 +3,8 @@ mod store;
 use std::ops;
 use std::str::FromStr;
 
+use serde::Deserialize;
+
 use crate::prelude::Id;
 use crate::service::NodeId;
 
@@ -13,7 +15,8 @@ pub use store::Error;
 pub type Alias = String;
 
 /// Tracking policy.
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Policy {
     /// The resource is tracked.
     Track,
@@ -87,6 +90,15 @@ impl Config {
         }
         Ok(self.default)
     }
+
+    /// Get the scope a repository is tracked with.
+    /// Returns [`Scope::All`] if the repo isn't found.
+    pub fn repo_scope(&self, id: &Id) -> Result<Scope, Error> {
+        if let Some((scope, _)) = self.store.repo_entry(id)? {
+            return Ok(scope);
+        }
+        Ok(Scope::All)
+    }
 }
 
 impl ops::Deref for Config {
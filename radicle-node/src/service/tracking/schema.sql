@@ -30,3 +30,20 @@ create table if not exists "repo-policies" (
   "policy"             text      default 'track'
   --
 ) strict;
+
+-- Tracked repositories whose announcements are not relayed, eg. private mirrors.
+create table if not exists "repo-relay-opt-outs" (
+  -- Repository ID.
+  "id"                 text      primary key not null
+  --
+) strict;
+
+-- Remote tracking allow-list, per repository.
+create table if not exists "repo-remotes" (
+  -- Repository ID.
+  "repo"               text      not null,
+  -- Remote (node) ID.
+  "remote"             text      not null,
+  --
+  primary key ("repo", "remote")
+) strict;
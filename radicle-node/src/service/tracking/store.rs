@@ -1,4 +1,5 @@
 #![allow(clippy::type_complexity)]
+use std::collections::BTreeSet;
 use std::path::Path;
 use std::str::FromStr;
 use std::{fmt, io};
@@ -95,7 +96,8 @@ impl Config {
     /// Open a policy store at the given path. Creates a new store if it
     /// doesn't exist.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
-        let db = sql::Connection::open(path)?;
+        let mut db = sql::Connection::open(path)?;
+        db.set_busy_timeout(crate::sql::BUSY_TIMEOUT)?;
         db.execute(Self::SCHEMA)?;
 
         Ok(Self { db })
@@ -141,6 +143,109 @@ impl Config {
         Ok(self.db.change_count() > 0)
     }
 
+    /// Track many repositories at once. Either all repositories are tracked, or none are.
+    /// Returns the number of tracking policies that were updated.
+    pub fn track_repos(&mut self, ids: &[Id], scope: Scope) -> Result<usize, Error> {
+        self.transaction(|db| {
+            let mut updated = 0;
+            for id in ids {
+                if db.track_repo(id, scope)? {
+                    updated += 1;
+                }
+            }
+            Ok(updated)
+        })
+    }
+
+    /// Add a remote to a repository's remote tracking allow-list.
+    pub fn track_remote(&mut self, id: &Id, remote: &NodeId) -> Result<bool, Error> {
+        let mut stmt = self.db.prepare(
+            "INSERT INTO `repo-remotes` (repo, remote)
+             VALUES (?1, ?2)
+             ON CONFLICT DO NOTHING",
+        )?;
+
+        stmt.bind((1, id))?;
+        stmt.bind((2, remote))?;
+        stmt.next()?;
+
+        Ok(self.db.change_count() > 0)
+    }
+
+    /// Track a repository with the given scope, and replace its remote tracking allow-list
+    /// with the given remotes. Either the whole policy is written, or none of it is.
+    pub fn track_repo_remotes(
+        &mut self,
+        id: &Id,
+        scope: Scope,
+        remotes: &[NodeId],
+    ) -> Result<bool, Error> {
+        self.transaction(|db| {
+            let mut updated = db.track_repo(id, scope)?;
+            let previous = db.repo_remotes(id)?;
+
+            let mut stmt = db
+                .db
+                .prepare("DELETE FROM `repo-remotes` WHERE repo = ?1")?;
+            stmt.bind((1, id))?;
+            stmt.next()?;
+
+            for remote in remotes {
+                db.track_remote(id, remote)?;
+            }
+            updated |= previous != remotes.iter().copied().collect();
+
+            Ok(updated)
+        })
+    }
+
+    /// Get the remote tracking allow-list of a repository.
+    pub fn repo_remotes(&self, id: &Id) -> Result<BTreeSet<NodeId>, Error> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT remote FROM `repo-remotes` WHERE repo = ?1")?;
+        stmt.bind((1, id))?;
+
+        let mut remotes = BTreeSet::new();
+        for row in stmt.into_iter() {
+            remotes.insert(row?.read::<NodeId, _>("remote"));
+        }
+        Ok(remotes)
+    }
+
+    /// Remove a remote from a repository's remote tracking allow-list.
+    pub fn untrack_remote(&mut self, id: &Id, remote: &NodeId) -> Result<bool, Error> {
+        let mut stmt = self
+            .db
+            .prepare("DELETE FROM `repo-remotes` WHERE repo = ?1 AND remote = ?2")?;
+
+        stmt.bind((1, id))?;
+        stmt.bind((2, remote))?;
+        stmt.next()?;
+
+        Ok(self.db.change_count() > 0)
+    }
+
+    /// Set whether announcements for a repository should be relayed. Repositories are
+    /// relayed unless they opted out.
+    pub fn set_repo_relay(&mut self, id: &Id, relay: bool) -> Result<bool, Error> {
+        let mut stmt = if relay {
+            self.db
+                .prepare("DELETE FROM `repo-relay-opt-outs` WHERE id = ?1")?
+        } else {
+            self.db.prepare(
+                "INSERT INTO `repo-relay-opt-outs` (id)
+                 VALUES (?1)
+                 ON CONFLICT DO NOTHING",
+            )?
+        };
+
+        stmt.bind((1, id))?;
+        stmt.next()?;
+
+        Ok(self.db.change_count() > 0)
+    }
+
     /// Set a node's tracking policy.
     pub fn set_node_policy(&mut self, id: &NodeId, policy: Policy) -> Result<bool, Error> {
         let mut stmt = self.db.prepare(
@@ -194,7 +299,31 @@ impl Config {
         stmt.bind((1, id))?;
         stmt.next()?;
 
-        Ok(self.db.change_count() > 0)
+        let updated = self.db.change_count() > 0;
+
+        // Tracking the repository again shouldn't bring back its relay opt-out.
+        let mut stmt = self
+            .db
+            .prepare("DELETE FROM `repo-relay-opt-outs` WHERE id = ?")?;
+
+        stmt.bind((1, id))?;
+        stmt.next()?;
+
+        Ok(updated)
+    }
+
+    /// Untrack many repositories at once. Either all repositories are untracked, or none are.
+    /// Returns the number of tracking policies that were removed.
+    pub fn untrack_repos(&mut self, ids: &[Id]) -> Result<usize, Error> {
+        self.transaction(|db| {
+            let mut updated = 0;
+            for id in ids {
+                if db.untrack_repo(id)? {
+                    updated += 1;
+                }
+            }
+            Ok(updated)
+        })
     }
 
     /// Check if a node is tracked.
@@ -207,6 +336,29 @@ impl Config {
         Ok(matches!(self.repo_entry(id)?, Some((_, Policy::Track))))
     }
 
+    /// Check if a remote is in a repository's remote tracking allow-list.
+    pub fn is_remote_tracked(&self, id: &Id, remote: &NodeId) -> Result<bool, Error> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT 1 FROM `repo-remotes` WHERE repo = ?1 AND remote = ?2")?;
+
+        stmt.bind((1, id))?;
+        stmt.bind((2, remote))?;
+
+        Ok(stmt.into_iter().next().transpose()?.is_some())
+    }
+
+    /// Check if announcements for a repository are relayed.
+    pub fn is_repo_relayed(&self, id: &Id) -> Result<bool, Error> {
+        let mut stmt = self
+            .db
+            .prepare("SELECT 1 FROM `repo-relay-opt-outs` WHERE id = ?1")?;
+
+        stmt.bind((1, id))?;
+
+        Ok(stmt.into_iter().next().transpose()?.is_none())
+    }
+
     /// Get a node's tracking information.
     pub fn node_entry(&self, id: &NodeId) -> Result<Option<(Option<Alias>, Policy)>, Error> {
         let mut stmt = self
@@ -282,6 +434,25 @@ impl Config {
         }
         Ok(Box::new(entries.into_iter()))
     }
+
+    /// Run the given function in a transaction, rolling back all changes on error.
+    fn transaction<T>(
+        &mut self,
+        f: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        self.db.execute("BEGIN")?;
+
+        match f(self) {
+            Ok(result) => {
+                self.db.execute("COMMIT")?;
+                Ok(result)
+            }
+            Err(err) => {
+                self.db.execute("ROLLBACK")?;
+                Err(err)
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -315,6 +486,102 @@ mod test {
         assert!(!db.is_repo_tracked(&id).unwrap());
     }
 
+    #[test]
+    fn test_track_and_untrack_repos() {
+        let ids = arbitrary::vec::<Id>(3);
+        let mut db = Config::open(":memory:").unwrap();
+
+        assert!(db.track_repo(&ids[0], Scope::All).unwrap());
+        assert_eq!(db.track_repos(&ids, Scope::All).unwrap(), 2);
+        assert!(ids.iter().all(|id| db.is_repo_tracked(id).unwrap()));
+        assert_eq!(db.track_repos(&ids, Scope::All).unwrap(), 0);
+
+        assert_eq!(db.untrack_repos(&ids[1..]).unwrap(), 2);
+        assert!(db.is_repo_tracked(&ids[0]).unwrap());
+        assert!(!db.is_repo_tracked(&ids[1]).unwrap());
+        assert!(!db.is_repo_tracked(&ids[2]).unwrap());
+    }
+
+    #[test]
+    fn test_track_remote() {
+        let id = arbitrary::gen::<Id>(1);
+        let remotes = arbitrary::vec::<NodeId>(2);
+        let mut db = Config::open(":memory:").unwrap();
+
+        assert!(db.track_remote(&id, &remotes[0]).unwrap());
+        assert!(!db.track_remote(&id, &remotes[0]).unwrap());
+        assert!(db.is_remote_tracked(&id, &remotes[0]).unwrap());
+        assert!(!db.is_remote_tracked(&id, &remotes[1]).unwrap());
+
+        assert!(!db.untrack_remote(&id, &remotes[1]).unwrap());
+        assert!(db.untrack_remote(&id, &remotes[0]).unwrap());
+        assert!(!db.untrack_remote(&id, &remotes[0]).unwrap());
+        assert!(!db.is_remote_tracked(&id, &remotes[0]).unwrap());
+    }
+
+    #[test]
+    fn test_track_repo_remotes() {
+        let id = arbitrary::gen::<Id>(1);
+        let remotes = arbitrary::vec::<NodeId>(3);
+        let mut db = Config::open(":memory:").unwrap();
+
+        assert!(db
+            .track_repo_remotes(&id, Scope::DelegatesOnly, &remotes[..2])
+            .unwrap());
+        assert_eq!(
+            db.repo_entry(&id).unwrap(),
+            Some((Scope::DelegatesOnly, Policy::Track))
+        );
+        assert!(db.is_remote_tracked(&id, &remotes[0]).unwrap());
+        assert!(db.is_remote_tracked(&id, &remotes[1]).unwrap());
+        assert!(!db.is_remote_tracked(&id, &remotes[2]).unwrap());
+        assert!(db
+            .track_repo_remotes(&id, Scope::DelegatesOnly, &remotes[..1])
+            .unwrap());
+        assert!(
+            !db.is_remote_tracked(&id, &remotes[1]).unwrap(),
+            "The allow-list is replaced"
+        );
+        assert!(!db
+            .track_repo_remotes(&id, Scope::DelegatesOnly, &remotes[..1])
+            .unwrap());
+        assert!(db
+            .track_repo_remotes(&id, Scope::DelegatesOnly, &remotes[1..])
+            .unwrap());
+        assert_eq!(
+            db.repo_remotes(&id).unwrap(),
+            remotes[1..].iter().copied().collect()
+        );
+        assert!(db.track_repo_remotes(&id, Scope::All, &[]).unwrap());
+        assert_eq!(db.repo_entry(&id).unwrap().unwrap().0, Scope::All);
+        assert!(db.repo_remotes(&id).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_repo_relay() {
+        let id = arbitrary::gen::<Id>(1);
+        let mut db = Config::open(":memory:").unwrap();
+
+        assert!(
+            db.is_repo_relayed(&id).unwrap(),
+            "Repositories are relayed by default"
+        );
+        assert!(!db.set_repo_relay(&id, true).unwrap());
+        assert!(db.set_repo_relay(&id, false).unwrap());
+        assert!(!db.set_repo_relay(&id, false).unwrap());
+        assert!(!db.is_repo_relayed(&id).unwrap());
+        assert!(db.set_repo_relay(&id, true).unwrap());
+        assert!(db.is_repo_relayed(&id).unwrap());
+
+        db.track_repo(&id, Scope::All).unwrap();
+        db.set_repo_relay(&id, false).unwrap();
+        db.untrack_repo(&id).unwrap();
+        assert!(
+            db.is_repo_relayed(&id).unwrap(),
+            "Untracking clears the opt-out"
+        );
+    }
+
     #[test]
     fn test_node_entries() {
         let ids = arbitrary::vec::<NodeId>(3);
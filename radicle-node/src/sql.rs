@@ -1,5 +1,24 @@
+use std::path::Path;
+use std::{fs, io};
+
 use sqlite as sql;
 
+/// How long to wait for other connections to release a database before compacting it,
+/// in milliseconds.
+pub const COMPACT_BUSY_TIMEOUT: usize = 5_000;
+/// How long the node's own connections wait for a database that is locked by another
+/// connection, eg. during compaction, before failing, in milliseconds.
+pub const BUSY_TIMEOUT: usize = 3_000;
+
+/// Error returned by [`compact`].
+#[derive(thiserror::Error, Debug)]
+pub enum CompactError {
+    #[error("i/o error: {0}")]
+    Io(#[from] io::Error),
+    #[error("database error: {0}")]
+    Sql(#[from] sql::Error),
+}
+
 /// Run an SQL query inside a transaction.
 /// Commits the transaction on success, and rolls back on error.
 pub fn transaction<T>(
@@ -19,3 +38,48 @@ pub fn transaction<T>(
         }
     }
 }
+
+/// Compact the database file at the given path with `VACUUM`, returning the number of bytes
+/// reclaimed. This uses its own connection, so it can run alongside the database's owner.
+pub fn compact<P: AsRef<Path>>(path: P) -> Result<u64, CompactError> {
+    let path = path.as_ref();
+    let before = fs::metadata(path)?.len();
+    let mut db = sql::Connection::open(path)?;
+
+    db.set_busy_timeout(COMPACT_BUSY_TIMEOUT)?;
+    db.execute("VACUUM")?;
+
+    let after = fs::metadata(path)?.len();
+
+    Ok(before.saturating_sub(after))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compact() {
+        let tmp = tempfile::tempdir().unwrap();
+        let path = tmp.path().join("test.db");
+        let db = sql::Connection::open(&path).unwrap();
+
+        db.execute("CREATE TABLE items (data TEXT NOT NULL)")
+            .unwrap();
+        transaction(&db, |db| {
+            for _ in 0..256 {
+                db.execute(format!("INSERT INTO items VALUES ('{}')", "x".repeat(1024)))?;
+            }
+            Ok(())
+        })
+        .unwrap();
+        db.execute("DELETE FROM items").unwrap();
+
+        let before = fs::metadata(&path).unwrap().len();
+        let reclaimed = compact(&path).unwrap();
+        let after = fs::metadata(&path).unwrap().len();
+
+        assert!(after < before, "The database file shrinks");
+        assert_eq!(reclaimed, before - after);
+    }
+}
@@ -2,11 +2,12 @@ use bloomy::BloomFilter;
 use qcheck::Arbitrary;
 
 use crate::crypto;
+use crate::node::Address;
 use crate::prelude::{BoundedVec, Id, NodeId, Timestamp};
 use crate::service::filter::{Filter, FILTER_SIZE_L, FILTER_SIZE_M, FILTER_SIZE_S};
 use crate::service::message::{
-    Announcement, InventoryAnnouncement, Message, NodeAnnouncement, Ping, RefsAnnouncement,
-    Subscribe, ZeroBytes,
+    Announcement, Digest, InventoryAnnouncement, Message, NodeAnnouncement, Ping, RefsAnnouncement,
+    SignatureScheme, Subscribe, ZeroBytes,
 };
 use crate::wire::MessageType;
 
@@ -36,6 +37,12 @@ impl Arbitrary for Message {
                 MessageType::Subscribe,
                 MessageType::Ping,
                 MessageType::Pong,
+                MessageType::ConnectRequest,
+                MessageType::Version,
+                MessageType::SeedRequest,
+                MessageType::Digest,
+                MessageType::Fetch,
+                MessageType::FetchPinned,
             ])
             .unwrap();
 
@@ -47,7 +54,10 @@ impl Arbitrary for Message {
                     timestamp: Timestamp::arbitrary(g),
                 }
                 .into(),
+                scheme: SignatureScheme::from(u8::arbitrary(g)),
                 signature: crypto::Signature::from(<[u8; 64]>::arbitrary(g)),
+                ttl: u8::arbitrary(g),
+                path: BoundedVec::arbitrary(g),
             }
             .into(),
             MessageType::RefsAnnouncement => Announcement {
@@ -58,7 +68,10 @@ impl Arbitrary for Message {
                     timestamp: Timestamp::arbitrary(g),
                 }
                 .into(),
+                scheme: SignatureScheme::from(u8::arbitrary(g)),
                 signature: crypto::Signature::from(<[u8; 64]>::arbitrary(g)),
+                ttl: u8::arbitrary(g),
+                path: BoundedVec::arbitrary(g),
             }
             .into(),
             MessageType::NodeAnnouncement => {
@@ -75,8 +88,11 @@ impl Arbitrary for Message {
 
                 Announcement {
                     node: NodeId::arbitrary(g),
+                    scheme: SignatureScheme::from(u8::arbitrary(g)),
                     signature,
                     message,
+                    ttl: u8::arbitrary(g),
+                    path: BoundedVec::arbitrary(g),
                 }
                 .into()
             }
@@ -93,6 +109,28 @@ impl Arbitrary for Message {
             MessageType::Pong => Self::Pong {
                 zeroes: ZeroBytes::new(u16::arbitrary(g).min(Ping::MAX_PONG_ZEROES)),
             },
+            MessageType::ConnectRequest => Self::ConnectRequest {
+                node: NodeId::arbitrary(g),
+                target: NodeId::arbitrary(g),
+                addr: Address::arbitrary(g),
+            },
+            MessageType::Version => Self::Version {
+                version: u32::arbitrary(g),
+                magic: u8::arbitrary(g),
+            },
+            MessageType::SeedRequest => Self::SeedRequest(Id::arbitrary(g)),
+            MessageType::Fetch => Self::Fetch {
+                rid: Id::arbitrary(g),
+                oid: None,
+            },
+            MessageType::FetchPinned => Self::Fetch {
+                rid: Id::arbitrary(g),
+                oid: Some(*Id::arbitrary(g)),
+            },
+            MessageType::Digest => Self::Digest(Digest {
+                since: Timestamp::arbitrary(g),
+                fingerprints: BoundedVec::arbitrary(g),
+            }),
             _ => unreachable!(),
         }
     }
@@ -14,6 +14,7 @@ use radicle::crypto::{KeyPair, Seed, Signer};
 use radicle::git;
 use radicle::git::refname;
 use radicle::identity::Id;
+use radicle::node::ConnectOptions;
 use radicle::node::Handle as _;
 use radicle::profile::Home;
 use radicle::profile::Profile;
@@ -108,7 +109,8 @@ pub struct NodeHandle<G: Signer + cyphernet::Ecdh + 'static> {
     pub signer: G,
     pub home: Home,
     pub addr: net::SocketAddr,
-    pub thread: ManuallyDrop<thread::JoinHandle<Result<(), runtime::Error>>>,
+    /// Node thread. Taken once the node was joined.
+    pub thread: Option<thread::JoinHandle<Result<(), runtime::Error>>>,
     pub handle: ManuallyDrop<Handle<G>>,
 }
 
@@ -119,17 +121,18 @@ impl<G: Signer + cyphernet::Ecdh + 'static> Drop for NodeHandle<G> {
         unsafe { ManuallyDrop::take(&mut self.handle) }
             .shutdown()
             .unwrap();
-        unsafe { ManuallyDrop::take(&mut self.thread) }
-            .join()
-            .unwrap()
-            .unwrap();
+        if let Some(thread) = self.thread.take() {
+            thread.join().unwrap().unwrap();
+        }
     }
 }
 
 impl<G: Signer + cyphernet::Ecdh> NodeHandle<G> {
     /// Connect this node to another node, and wait for the connection to be established both ways.
     pub fn connect(&mut self, remote: &NodeHandle<G>) -> &mut Self {
-        self.handle.connect(remote.id, remote.addr.into()).unwrap();
+        self.handle
+            .connect(remote.id, remote.addr.into(), ConnectOptions::default())
+            .unwrap();
 
         loop {
             let local_sessions = self.handle.sessions().unwrap();
@@ -263,12 +266,10 @@ impl<G: cyphernet::Ecdh<Pk = NodeId> + Signer + Clone> Node<G> {
         let addr = *rt.local_addrs.first().unwrap();
         let id = *self.signer.public_key();
         let handle = ManuallyDrop::new(rt.handle.clone());
-        let thread = ManuallyDrop::new(
-            thread::Builder::new()
-                .name(id.to_string())
-                .spawn(move || rt.run())
-                .unwrap(),
-        );
+        let thread = thread::Builder::new()
+            .name(id.to_string())
+            .spawn(move || rt.run())
+            .unwrap();
 
         NodeHandle {
             id,
@@ -277,7 +278,7 @@ impl<G: cyphernet::Ecdh<Pk = NodeId> + Signer + Clone> Node<G> {
             home: self.home,
             addr,
             handle,
-            thread,
+            thread: Some(thread),
         }
     }
 
@@ -1,10 +1,10 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
 
 use crossbeam_channel as chan;
 
 use crate::identity::Id;
-use crate::node::FetchResult;
+use crate::node::{ConnectOptions, FetchResult, RemoteTracking, SessionInfo};
 use crate::runtime::HandleError;
 use crate::service;
 use crate::service::NodeId;
@@ -13,8 +13,13 @@ use crate::storage::RefUpdate;
 #[derive(Default, Clone)]
 pub struct Handle {
     pub updates: Arc<Mutex<Vec<Id>>>,
+    pub seed_requests: Arc<Mutex<Vec<Id>>>,
+    pub connections: Arc<Mutex<Vec<(NodeId, radicle::node::Address, ConnectOptions)>>>,
     pub tracking_repos: HashSet<Id>,
     pub tracking_nodes: HashSet<NodeId>,
+    pub tracking_remotes: HashSet<(Id, NodeId)>,
+    pub remote_tracking: HashMap<Id, RemoteTracking>,
+    pub relay_opt_outs: HashSet<Id>,
 }
 
 impl radicle::node::Handle for Handle {
@@ -26,8 +31,24 @@ impl radicle::node::Handle for Handle {
         true
     }
 
-    fn connect(&mut self, _node: NodeId, _addr: radicle::node::Address) -> Result<(), Self::Error> {
-        unimplemented!();
+    fn connect(
+        &mut self,
+        node: NodeId,
+        addr: radicle::node::Address,
+        opts: ConnectOptions,
+    ) -> Result<(), Self::Error> {
+        self.connections.lock().unwrap().push((node, addr, opts));
+
+        Ok(())
+    }
+
+    fn unpersist(&mut self, node: NodeId) -> Result<bool, Self::Error> {
+        let mut connections = self.connections.lock().unwrap();
+        let len = connections.len();
+
+        connections.retain(|(id, _, opts)| !(*id == node && opts.persistent));
+
+        Ok(connections.len() < len)
     }
 
     fn seeds(&mut self, _id: Id) -> Result<Vec<NodeId>, Self::Error> {
@@ -38,11 +59,31 @@ impl radicle::node::Handle for Handle {
         Ok(FetchResult::from(Ok::<Vec<RefUpdate>, Self::Error>(vec![])))
     }
 
+    fn fetch_dry_run(&mut self, _id: Id, _from: NodeId) -> Result<FetchResult, Self::Error> {
+        Ok(FetchResult::from(Ok::<Vec<RefUpdate>, Self::Error>(vec![])))
+    }
+
+    fn fetch_debug(&mut self, _id: Id) -> Result<chan::Receiver<String>, Self::Error> {
+        unimplemented!();
+    }
+
+    fn fetch_hinted(
+        &mut self,
+        _id: Id,
+        _from: NodeId,
+        _addr: radicle::node::Address,
+    ) -> Result<FetchResult, Self::Error> {
+        Ok(FetchResult::from(Ok::<Vec<RefUpdate>, Self::Error>(vec![])))
+    }
+
     fn track_repo(&mut self, id: Id) -> Result<bool, Self::Error> {
         Ok(self.tracking_repos.insert(id))
     }
 
     fn untrack_repo(&mut self, id: Id) -> Result<bool, Self::Error> {
+        self.relay_opt_outs.remove(&id);
+        self.remote_tracking.remove(&id);
+
         Ok(self.tracking_repos.remove(&id))
     }
 
@@ -54,16 +95,57 @@ impl radicle::node::Handle for Handle {
         Ok(self.tracking_nodes.remove(&id))
     }
 
+    fn track_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Self::Error> {
+        Ok(self.tracking_remotes.insert((id, remote)))
+    }
+
+    fn untrack_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Self::Error> {
+        Ok(self.tracking_remotes.remove(&(id, remote)))
+    }
+
+    fn set_remote_tracking(&mut self, id: Id, policy: RemoteTracking) -> Result<bool, Self::Error> {
+        let mut updated = self.tracking_repos.insert(id);
+
+        // The policy replaces the repository's previous one, along with its remotes.
+        self.tracking_remotes.retain(|(rid, _)| *rid != id);
+        if let RemoteTracking::Remotes(remotes) = &policy {
+            self.tracking_remotes
+                .extend(remotes.iter().map(|remote| (id, *remote)));
+        }
+        updated |= self.remote_tracking.get(&id) != Some(&policy);
+        self.remote_tracking.insert(id, policy);
+
+        Ok(updated)
+    }
+
+    fn set_repo_relay(&mut self, id: Id, relay: bool) -> Result<bool, Self::Error> {
+        if relay {
+            Ok(self.relay_opt_outs.remove(&id))
+        } else {
+            Ok(self.relay_opt_outs.insert(id))
+        }
+    }
+
     fn announce_refs(&mut self, id: Id) -> Result<(), Self::Error> {
         self.updates.lock().unwrap().push(id);
 
         Ok(())
     }
 
+    fn request_seeding(&mut self, id: Id) -> Result<(), Self::Error> {
+        self.seed_requests.lock().unwrap().push(id);
+
+        Ok(())
+    }
+
     fn sync_inventory(&mut self) -> Result<bool, Self::Error> {
         unimplemented!()
     }
 
+    fn reconcile(&mut self) -> Result<bool, Self::Error> {
+        unimplemented!()
+    }
+
     fn routing(&self) -> Result<chan::Receiver<(Id, service::NodeId)>, Self::Error> {
         unimplemented!();
     }
@@ -72,10 +154,22 @@ impl radicle::node::Handle for Handle {
         unimplemented!();
     }
 
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>, Self::Error> {
+        unimplemented!();
+    }
+
     fn inventory(&self) -> Result<chan::Receiver<Id>, Self::Error> {
         unimplemented!();
     }
 
+    fn tracked_inventory(&self) -> Result<chan::Receiver<Id>, Self::Error> {
+        unimplemented!();
+    }
+
+    fn compact(&mut self) -> Result<u64, Self::Error> {
+        unimplemented!();
+    }
+
     fn shutdown(self) -> Result<(), Self::Error> {
         Ok(())
     }
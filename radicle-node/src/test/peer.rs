@@ -82,6 +82,7 @@ impl Peer<MockStorage, MockSigner> {
 pub struct Config<G: Signer + 'static> {
     pub config: service::Config,
     pub addrs: address::Book,
+    pub tracking: tracking::Store,
     pub local_time: LocalTime,
     pub policy: Policy,
     pub signer: G,
@@ -96,6 +97,7 @@ impl Default for Config<MockSigner> {
         Config {
             config: service::Config::default(),
             addrs: address::Book::memory().unwrap(),
+            tracking: tracking::Store::memory().unwrap(),
             local_time: LocalTime::now(),
             policy: Policy::Block,
             signer,
@@ -116,8 +118,7 @@ where
         config: Config<G>,
     ) -> Self {
         let routing = routing::Table::memory().unwrap();
-        let tracking = tracking::Store::memory().unwrap();
-        let tracking = tracking::Config::new(config.policy, tracking);
+        let tracking = tracking::Config::new(config.policy, config.tracking);
         let id = *config.signer.public_key();
         let service = Service::new(
             config.config,
@@ -210,12 +211,16 @@ where
     }
 
     pub fn node_announcement(&self) -> Message {
+        self.node_announcement_with(node::Features::SEED)
+    }
+
+    pub fn node_announcement_with(&self, features: node::Features) -> Message {
         let mut alias = [0u8; 32];
         alias[..self.name.len()].copy_from_slice(self.name.as_bytes());
 
         Message::node(
             NodeAnnouncement {
-                features: node::Features::SEED,
+                features,
                 timestamp: self.timestamp(),
                 alias,
                 addresses: Some(net::SocketAddr::from((self.ip, node::DEFAULT_PORT)).into()).into(),
@@ -242,7 +247,12 @@ where
         let remote_id = simulator::Peer::<S, G>::id(peer);
 
         self.initialize();
-        self.service.connected(remote_id, Link::Inbound);
+        self.service
+            .connected(remote_id, peer.address(), Link::Inbound);
+        // Peers are assumed to speak our protocol, on our network.
+        let network = self.service.config().network;
+        self.service
+            .received_message(remote_id, Message::version(network));
 
         let mut msgs = self.messages(remote_id);
         msgs.find(|m| {
@@ -263,7 +273,12 @@ where
 
         self.initialize();
         self.service.attempted(remote_id, &remote_addr);
-        self.service.connected(remote_id, Link::Outbound);
+        self.service
+            .connected(remote_id, remote_addr, Link::Outbound);
+        // Peers are assumed to speak our protocol, on our network.
+        let network = self.service.config().network;
+        self.service
+            .received_message(remote_id, Message::version(network));
 
         let mut msgs = self.messages(remote_id);
         msgs.find(|m| {
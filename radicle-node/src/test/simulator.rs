@@ -368,7 +368,12 @@ impl<S: WriteStorage + 'static, G: Signer> Simulation<S, G> {
             self.time = time;
             self.inbox.messages.remove(&time);
 
-            let Scheduled { input, node, .. } = next;
+            let Scheduled {
+                input,
+                node,
+                remote,
+            } = next;
+            let remote_addr = nodes.get(&remote).map(|r| r.addr());
 
             if let Some(ref mut p) = nodes.get_mut(&node) {
                 p.tick(time);
@@ -386,7 +391,8 @@ impl<S: WriteStorage + 'static, G: Signer> Simulation<S, G> {
                         let attempted = link.is_outbound() && self.attempts.remove(&conn);
                         if attempted || link.is_inbound() {
                             if self.connections.insert(conn) {
-                                p.connected(id, link);
+                                let addr = remote_addr.expect("remote node must exist");
+                                p.connected(id, addr, link);
                             }
                         }
                     }
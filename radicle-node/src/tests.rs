@@ -1,16 +1,21 @@
 mod e2e;
 
+use std::collections::BTreeMap;
 use std::default::*;
 use std::io;
+use std::net;
 use std::sync::Arc;
 
 use crossbeam_channel as chan;
 use netservices::LinkDirection as Link;
 
+use crate::address;
+use crate::address::Store as _;
 use crate::collections::{HashMap, HashSet};
 use crate::crypto::test::signer::MockSigner;
 use crate::identity::Id;
 use crate::node;
+use crate::node::{ConnectOptions, RemoteTracking};
 use crate::prelude::*;
 use crate::prelude::{LocalDuration, Timestamp};
 use crate::service::config::*;
@@ -21,7 +26,7 @@ use crate::service::ServiceState as _;
 use crate::service::*;
 use crate::storage::git::transport::{local, remote};
 use crate::storage::git::Storage;
-use crate::storage::{Namespaces, ReadStorage};
+use crate::storage::{Namespaces, ReadStorage, RefUpdate};
 use crate::test::arbitrary;
 use crate::test::assert_matches;
 use crate::test::fixtures;
@@ -32,8 +37,10 @@ use crate::test::peer::Peer;
 use crate::test::simulator;
 use crate::test::simulator::{Peer as _, Simulation};
 use crate::test::storage::MockStorage;
+use crate::wire;
 use crate::wire::Decode;
 use crate::wire::Encode;
+use crate::worker::FetchError;
 use crate::LocalTime;
 use crate::{git, identity, rad, runtime, service, test};
 
@@ -94,6 +101,82 @@ fn test_ping_response() {
     );
 }
 
+#[test]
+fn test_session_activity() {
+    let mut alice = Peer::new("alice", [8, 8, 8, 8]);
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.elapse(LocalDuration::from_secs(1));
+    alice.outbox().for_each(drop);
+
+    let now = alice.local_time();
+    alice.receive(
+        bob.id(),
+        Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(0),
+        }),
+    );
+    let session = alice.sessions().get(&bob.id()).unwrap();
+    assert_eq!(session.last_active, now);
+    assert_eq!(
+        session.last_sent,
+        LocalTime::default(),
+        "Nothing was handed to the transport yet"
+    );
+
+    alice.elapse(LocalDuration::from_secs(1));
+    let later = alice.local_time();
+    while alice.next().is_some() {}
+
+    let session = alice.sessions().get(&bob.id()).unwrap();
+    assert_eq!(session.last_active, now, "Nothing was received since");
+    assert_eq!(session.last_sent, later, "The pong was sent");
+}
+
+#[test]
+fn test_ping_latency() {
+    let mut alice = Peer::new("alice", [8, 8, 8, 8]);
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    assert_eq!(alice.sessions().get(&bob.id()).unwrap().latency(), None);
+
+    // Ping bob, and have him respond after the given delay.
+    let mut roundtrip = |delay: LocalDuration| {
+        alice.elapse(KEEP_ALIVE_DELTA);
+
+        let ponglen = alice
+            .messages(bob.id())
+            .find_map(|m| match m {
+                Message::Ping(Ping { ponglen, .. }) => Some(ponglen),
+                _ => None,
+            })
+            .expect("bob is pinged");
+
+        alice.elapse(delay);
+        alice.receive(
+            bob.id(),
+            Message::Pong {
+                zeroes: ZeroBytes::new(ponglen),
+            },
+        );
+        alice.sessions().get(&bob.id()).unwrap().latency()
+    };
+
+    assert_eq!(
+        roundtrip(LocalDuration::from_millis(200)),
+        Some(LocalDuration::from_millis(200)),
+        "The first sample is taken as-is"
+    );
+    assert_eq!(
+        roundtrip(LocalDuration::from_millis(600)),
+        Some(LocalDuration::from_millis(250)),
+        "Further samples are smoothed"
+    );
+}
+
 #[test]
 fn test_disconnecting_unresponsive_peer() {
     let mut alice = Peer::new("alice", [8, 8, 8, 8]);
@@ -101,13 +184,78 @@ fn test_disconnecting_unresponsive_peer() {
 
     alice.connect_to(&bob);
     assert_eq!(1, alice.sessions().connected().count(), "bob connects");
-    alice.elapse(STALE_CONNECTION_TIMEOUT + LocalDuration::from_secs(1));
+    alice.elapse(Config::default().stale_connection_timeout + LocalDuration::from_secs(1));
+    alice
+        .outbox()
+        .find(|m| matches!(m, &Io::Disconnect(addr, _) if addr == bob.id()))
+        .expect("disconnect an unresponsive bob");
+}
+
+#[test]
+fn test_stale_connection_timeout() {
+    let default = Config::default().stale_connection_timeout;
+    let mut alice = Peer::config(
+        "alice",
+        [8, 8, 8, 8],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                stale_connection_timeout: LocalDuration::from_mins(5),
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.receive(
+        bob.id(),
+        Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(0),
+        }),
+    );
+    alice.elapse(default + LocalDuration::from_secs(1));
+    assert!(
+        !alice
+            .outbox()
+            .any(|m| matches!(m, Io::Disconnect(addr, _) if addr == bob.id())),
+        "bob is kept connected past the default timeout"
+    );
+
+    alice.elapse(LocalDuration::from_mins(5));
     alice
         .outbox()
         .find(|m| matches!(m, &Io::Disconnect(addr, _) if addr == bob.id()))
         .expect("disconnect an unresponsive bob");
 }
 
+#[test]
+fn test_stale_connection_timeout_early_clock() {
+    // The clock is earlier than the stale connection timeout, eg. on a system that
+    // doesn't have the time yet.
+    let mut alice = Peer::config(
+        "alice",
+        [8, 8, 8, 8],
+        MockStorage::empty(),
+        peer::Config {
+            local_time: LocalTime::from_secs(1),
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.elapse(IDLE_INTERVAL);
+    assert!(
+        !alice
+            .outbox()
+            .any(|m| matches!(m, Io::Disconnect(addr, _) if addr == bob.id())),
+        "bob was active less than the timeout ago"
+    );
+}
+
 #[test]
 fn test_connection_kept_alive() {
     let mut alice = Peer::new("alice", [8, 8, 8, 8]);
@@ -120,13 +268,18 @@ fn test_connection_kept_alive() {
     )
     .initialize([&mut alice, &mut bob]);
 
-    alice.command(service::Command::Connect(bob.id(), bob.address()));
+    alice.command(service::Command::Connect(
+        bob.id(),
+        bob.address(),
+        ConnectOptions::default(),
+    ));
     sim.run_while([&mut alice, &mut bob], |s| !s.is_settled());
     assert_eq!(1, alice.sessions().connected().count(), "bob connects");
 
     let mut elapsed: LocalDuration = LocalDuration::from_secs(0);
-    let step: LocalDuration = STALE_CONNECTION_TIMEOUT / 10;
-    while elapsed < STALE_CONNECTION_TIMEOUT + step {
+    let timeout = Config::default().stale_connection_timeout;
+    let step: LocalDuration = timeout / 10;
+    while elapsed < timeout + step {
         alice.elapse(step);
         bob.elapse(step);
         sim.run_while([&mut alice, &mut bob], |s| !s.is_settled());
@@ -237,280 +390,260 @@ fn test_inventory_sync() {
 }
 
 #[test]
-fn test_inventory_pruning() {
-    struct Test {
-        limits: Limits,
-        /// Number of projects by peer
-        peer_projects: Vec<usize>,
-        wait_time: LocalDuration,
-        expected_routing_table_size: usize,
-    }
-    let tests = [
-        // All zero
-        Test {
-            limits: Limits {
-                routing_max_size: 0,
-                routing_max_age: LocalDuration::from_secs(0),
-            },
-            peer_projects: vec![10; 5],
-            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
-            expected_routing_table_size: 0,
-        },
-        // All entries are too young to expire.
-        Test {
-            limits: Limits {
-                routing_max_size: 0,
-                routing_max_age: LocalDuration::from_mins(7 * 24 * 60),
-            },
-            peer_projects: vec![10; 5],
-            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
-            expected_routing_table_size: 0,
-        },
-        // All entries remain because the table is unconstrained.
-        Test {
-            limits: Limits {
-                routing_max_size: 50,
-                routing_max_age: LocalDuration::from_mins(0),
-            },
-            peer_projects: vec![10; 5],
-            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
-            expected_routing_table_size: 50,
+fn test_standby_mirrors_primary() {
+    let tmp = tempfile::tempdir().unwrap();
+    let bob_signer = MockSigner::default();
+    let bob_storage = fixtures::storage(tmp.path().join("bob"), &bob_signer).unwrap();
+    let bob = Peer::config(
+        "bob",
+        [8, 8, 8, 8],
+        bob_storage,
+        peer::Config {
+            signer: bob_signer,
+            ..peer::Config::default()
         },
-        // Some entries are pruned because the table is constrained.
-        Test {
-            limits: Limits {
-                routing_max_size: 25,
-                routing_max_age: LocalDuration::from_mins(7 * 24 * 60),
+    );
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        Storage::open(tmp.path().join("alice")).unwrap(),
+        peer::Config {
+            config: Config {
+                standby: Some(bob.id()),
+                ..Config::default()
             },
-            peer_projects: vec![10; 5],
-            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
-            expected_routing_table_size: 25,
+            ..peer::Config::default()
         },
-    ];
-
-    for test in tests {
-        let mut alice = Peer::config(
-            "alice",
-            [7, 7, 7, 7],
-            MockStorage::empty(),
-            peer::Config {
-                config: Config {
-                    limits: test.limits,
-                    ..Config::default()
-                },
-                ..peer::Config::default()
-            },
-        );
-
-        let bob = Peer::config(
-            "bob",
-            [8, 8, 8, 8],
-            MockStorage::empty(),
-            peer::Config {
-                local_time: alice.local_time(),
-                ..peer::Config::default()
-            },
-        );
-
-        // Tell Alice about the amazing projects available
-        alice.connect_to(&bob);
-        for num_projs in test.peer_projects {
-            alice.receive(
-                bob.id(),
-                Message::inventory(
-                    InventoryAnnouncement {
-                        inventory: test::arbitrary::vec::<Id>(num_projs).try_into().unwrap(),
-                        timestamp: bob.local_time().as_millis(),
-                    },
-                    &MockSigner::default(),
-                ),
-            );
-        }
-
-        // Wait for things to happen
-        assert!(test.wait_time > PRUNE_INTERVAL, "pruning must be triggered");
-        alice.elapse(test.wait_time);
-
-        assert_eq!(
-            test.expected_routing_table_size,
-            alice.routing().len().unwrap()
-        );
-    }
-}
-
-#[test]
-fn test_tracking() {
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let proj_id: identity::Id = test::arbitrary::gen(1);
-
-    let (sender, receiver) = chan::bounded(1);
-    alice.command(Command::TrackRepo(proj_id, sender));
-    let policy_change = receiver.recv().map_err(runtime::HandleError::from).unwrap();
-    assert!(policy_change);
-    assert!(alice.tracking().is_repo_tracked(&proj_id).unwrap());
-
-    let (sender, receiver) = chan::bounded(1);
-    alice.command(Command::UntrackRepo(proj_id, sender));
-    let policy_change = receiver.recv().map_err(runtime::HandleError::from).unwrap();
-    assert!(policy_change);
-    assert!(!alice.tracking().is_repo_tracked(&proj_id).unwrap());
-}
-
-#[test]
-fn test_inventory_relay_bad_timestamp() {
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let bob = Peer::new("bob", [8, 8, 8, 8]);
-    let two_hours = 3600 * 1000 * 2;
-    let timestamp = alice.timestamp() + two_hours;
+    );
+    let projs = bob.storage().inventory().unwrap();
+    let others = arbitrary::vec::<Id>(3);
 
     alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(
+        eve.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: others.clone().try_into().unwrap(),
+                timestamp: eve.timestamp(),
+            },
+            eve.signer(),
+        ),
+    );
     alice.receive(
         bob.id(),
         Message::inventory(
             InventoryAnnouncement {
-                inventory: BoundedVec::new(),
-                timestamp,
+                inventory: projs.clone().try_into().unwrap(),
+                timestamp: bob.timestamp(),
             },
             bob.signer(),
         ),
     );
+
+    for proj in &projs {
+        assert!(alice.tracking().is_repo_tracked(proj).unwrap());
+    }
+    for other in &others {
+        assert!(!alice.tracking().is_repo_tracked(other).unwrap());
+    }
     assert_matches!(
-        alice.outbox().next(),
-        Some(Io::Disconnect(addr, DisconnectReason::Session(session::Error::InvalidTimestamp(t))))
-        if addr == bob.id() && t == timestamp
+        alice.messages(bob.id()).find(|m| matches!(m, Message::Fetch { .. })),
+        Some(Message::Fetch { rid, .. }) if projs.contains(&rid),
+        "The standby fetches the primary's repositories"
     );
 }
 
 #[test]
-fn test_announcement_rebroadcast() {
+fn test_fetch_sources() {
     let mut alice = Peer::new("alice", [7, 7, 7, 7]);
     let bob = Peer::new("bob", [8, 8, 8, 8]);
     let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
 
     alice.connect_to(&bob);
+    alice.connect_to(&eve);
 
-    let received = test::gossip::messages(6, alice.local_time(), MAX_TIME_DELTA);
-    for msg in received.iter().cloned() {
-        alice.receive(bob.id(), msg);
+    for peer in [&bob, &eve] {
+        alice.receive(
+            peer.id(),
+            Message::inventory(
+                InventoryAnnouncement {
+                    inventory: vec![rid].try_into().unwrap(),
+                    timestamp: peer.timestamp(),
+                },
+                peer.signer(),
+            ),
+        );
     }
+    alice.disconnected(eve.id(), &DisconnectReason::Connection(error));
 
-    alice.connect_from(&eve);
-    alice.receive(
-        eve.id(),
-        Message::Subscribe(Subscribe {
-            filter: Filter::default(),
-            since: Timestamp::MIN,
-            until: Timestamp::MAX,
-        }),
-    );
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::FetchSources(rid, sender));
 
-    let relayed = alice.messages(eve.id()).collect::<Vec<_>>();
-    assert_eq!(relayed, received);
+    let sources = receiver.recv().unwrap();
+    assert_eq!(sources.connected, vec![bob.id()]);
+    assert_eq!(sources.disconnected, vec![eve.id()]);
 }
 
 #[test]
-fn test_announcement_rebroadcast_timestamp_filtered() {
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let bob = Peer::new("bob", [8, 8, 8, 8]);
-    let eve = Peer::new("eve", [9, 9, 9, 9]);
+fn test_inventory_command() {
+    let tmp = tempfile::tempdir().unwrap();
+    let signer = MockSigner::default();
+    let storage = fixtures::storage(tmp.path().join("alice"), &signer).unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        storage,
+        peer::Config {
+            signer,
+            ..peer::Config::default()
+        },
+    );
+    let (sender, receiver) = chan::bounded(1);
 
-    alice.connect_to(&bob);
+    alice.command(Command::Inventory(sender));
 
-    let delta = LocalDuration::from_mins(10);
-    let first = test::gossip::messages(3, alice.local_time() - delta, LocalDuration::from_secs(0));
-    let second = test::gossip::messages(3, alice.local_time(), LocalDuration::from_secs(0));
-    let third = test::gossip::messages(3, alice.local_time() + delta, LocalDuration::from_secs(0));
+    let inventory = receiver.try_recv().unwrap();
+    assert!(!inventory.is_empty());
+    assert_eq!(inventory, alice.storage().inventory().unwrap());
+}
 
-    // Alice receives three batches of messages.
-    for msg in first
-        .iter()
-        .chain(second.iter())
-        .chain(third.iter())
-        .cloned()
-    {
-        alice.receive(bob.id(), msg);
-    }
+#[test]
+fn test_tracked_inventory_command() {
+    let tmp = tempfile::tempdir().unwrap();
+    let signer = MockSigner::default();
+    let storage = fixtures::storage(tmp.path().join("alice"), &signer).unwrap();
+    let inventory = storage.inventory().unwrap();
+    let mut tracking = tracking::Store::memory().unwrap();
 
-    // Eve subscribes to messages within the period of the second batch only.
-    alice.connect_from(&eve);
-    alice.receive(
-        eve.id(),
-        Message::Subscribe(Subscribe {
-            filter: Filter::default(),
-            since: alice.local_time().as_millis(),
-            until: (alice.local_time() + delta).as_millis(),
-        }),
+    tracking
+        .set_repo_policy(&inventory[0], tracking::Policy::Block)
+        .unwrap();
+
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        storage,
+        peer::Config {
+            signer,
+            policy: tracking::Policy::Track,
+            tracking,
+            ..peer::Config::default()
+        },
     );
+    let (sender, receiver) = chan::bounded(1);
 
-    let relayed = alice.messages(eve.id()).collect::<Vec<_>>();
-    assert_eq!(relayed.len(), second.len());
-    assert_eq!(relayed, second);
+    alice.command(Command::TrackedInventory(sender));
+
+    let tracked = receiver.try_recv().unwrap();
+    assert_eq!(tracked, inventory[1..], "Blocked repositories are excluded");
 }
 
 #[test]
-fn test_announcement_relay() {
+fn test_network_time() {
     let mut alice = Peer::new("alice", [7, 7, 7, 7]);
     let mut bob = Peer::new("bob", [8, 8, 8, 8]);
     let mut eve = Peer::new("eve", [9, 9, 9, 9]);
+    let time = |alice: &mut Peer<MockStorage, MockSigner>| {
+        let (sender, receiver) = chan::bounded(1);
+        alice.command(Command::Time(sender));
+        receiver.try_recv().unwrap()
+    };
+
+    let (local, offset) = time(&mut alice);
+    assert_eq!(local, alice.local_time());
+    assert_eq!(offset, 0, "Without peers, the network time is our own");
 
     alice.connect_to(&bob);
     alice.connect_to(&eve);
+
+    bob.elapse(LocalDuration::from_secs(10));
+    eve.elapse(LocalDuration::from_mins(30));
     alice.receive(bob.id(), bob.inventory_announcement());
+    alice.receive(eve.id(), eve.inventory_announcement());
 
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_))
-    );
+    let bob_offset = bob.timestamp() as i64 - alice.timestamp() as i64;
+    let eve_offset = eve.timestamp() as i64 - alice.timestamp() as i64;
+    assert_eq!(time(&mut alice).1, bob_offset.max(eve_offset));
 
-    alice.receive(bob.id(), bob.inventory_announcement());
-    assert!(
-        alice.messages(eve.id()).next().is_none(),
-        "Another inventory with the same timestamp is ignored"
+    // Announcements relayed on behalf of others don't tell us about the relayer's clock.
+    alice.receive(bob.id(), eve.node_announcement());
+    alice.disconnected(
+        eve.id(),
+        &DisconnectReason::Connection(Arc::new(io::Error::from(io::ErrorKind::ConnectionReset))),
     );
+    assert_eq!(time(&mut alice).1, bob_offset);
+}
 
-    bob.elapse(LocalDuration::from_mins(1));
-    alice.receive(bob.id(), bob.inventory_announcement());
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_)),
-        "Another inventory with a fresher timestamp is relayed"
+#[test]
+fn test_routing_converged() {
+    let tmp = tempfile::tempdir().unwrap();
+    let window = LocalDuration::from_mins(1);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        Storage::open(tmp.path().join("alice")).unwrap(),
+        peer::Config {
+            config: Config {
+                routing_quiet_window: window,
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
     );
-
-    alice.receive(bob.id(), bob.node_announcement());
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_)),
-        "A node announcement with the same timestamp as the inventory is relayed"
+    let bob_signer = MockSigner::default();
+    let bob_storage = fixtures::storage(tmp.path().join("bob"), &bob_signer).unwrap();
+    let bob = Peer::config(
+        "bob",
+        [8, 8, 8, 8],
+        bob_storage,
+        peer::Config {
+            signer: bob_signer,
+            ..peer::Config::default()
+        },
     );
+    let projs = bob.storage().inventory().unwrap();
 
-    alice.receive(bob.id(), bob.node_announcement());
-    assert!(alice.messages(eve.id()).next().is_none(), "Only once");
-
-    alice.receive(eve.id(), eve.node_announcement());
-    assert_matches!(
-        alice.messages(bob.id()).next(),
-        Some(Message::Announcement(_)),
-        "A node announcement from Eve is relayed to Bob"
+    alice.connect_to(&bob);
+    alice.elapse(window / 2);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: projs.clone().try_into().unwrap(),
+                timestamp: alice.timestamp(),
+            },
+            bob.signer(),
+        ),
     );
+    alice.elapse(window / 2);
     assert!(
-        alice.messages(eve.id()).next().is_none(),
-        "But not back to Eve"
+        !alice
+            .events()
+            .any(|e| matches!(e, Event::RoutingConverged { .. })),
+        "the routing table changed less than a quiet window ago"
     );
 
-    eve.elapse(LocalDuration::from_mins(1));
-    alice.receive(bob.id(), eve.node_announcement());
-    assert!(
-        alice.messages(bob.id()).next().is_none(),
-        "Bob already know about this message, since he sent it"
-    );
-    assert!(
-        alice.messages(eve.id()).next().is_none(),
-        "Eve already know about this message, since she signed it"
+    alice.elapse(window / 2);
+    assert_matches!(
+        alice
+            .events()
+            .find(|e| matches!(e, Event::RoutingConverged { .. })),
+        Some(Event::RoutingConverged { size }) if size == projs.len()
     );
+
+    // No further events while the routing table stays quiet.
+    alice.elapse(window + window);
+    assert!(!alice
+        .events()
+        .any(|e| matches!(e, Event::RoutingConverged { .. })));
 }
 
 #[test]
-fn test_refs_announcement_relay() {
+fn test_inventory_shutdown() {
     let tmp = tempfile::tempdir().unwrap();
     let mut alice = Peer::config(
         "alice",
@@ -518,85 +651,1949 @@ fn test_refs_announcement_relay() {
         Storage::open(tmp.path().join("alice")).unwrap(),
         peer::Config::default(),
     );
-    let eve = Peer::config(
-        "eve",
+    let bob_signer = MockSigner::default();
+    let bob_storage = fixtures::storage(tmp.path().join("bob"), &bob_signer).unwrap();
+    let mut bob = Peer::config(
+        "bob",
         [8, 8, 8, 8],
-        Storage::open(tmp.path().join("eve")).unwrap(),
-        peer::Config::default(),
+        bob_storage,
+        peer::Config {
+            signer: bob_signer,
+            ..peer::Config::default()
+        },
     );
+    let projs = bob.storage().inventory().unwrap();
 
-    let bob = {
-        let mut rng = fastrand::Rng::new();
-        let signer = MockSigner::new(&mut rng);
-        let storage = fixtures::storage(tmp.path().join("bob"), &signer).unwrap();
+    alice.connect_to(&bob);
+    bob.connect_from(&alice);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: projs.clone().try_into().unwrap(),
+                timestamp: bob.timestamp(),
+            },
+            bob.signer(),
+        ),
+    );
+    assert_eq!(
+        alice.routing().get_resources(&bob.id()).unwrap().len(),
+        projs.len()
+    );
 
-        Peer::config(
-            "bob",
-            [9, 9, 9, 9],
-            storage,
-            peer::Config {
-                signer,
-                rng,
-                ..peer::Config::default()
+    bob.elapse(LocalDuration::from_secs(1));
+    bob.shutdown();
+
+    let msg = bob
+        .messages(alice.id())
+        .find(|m| {
+            matches!(
+                m,
+                Message::Announcement(Announcement {
+                    message: AnnouncementMessage::Inventory(_),
+                    ..
+                })
+            )
+        })
+        .expect("`inventory-announcement` must be sent on shutdown");
+
+    alice.receive(bob.id(), msg);
+
+    for proj in &projs {
+        let seeds = alice.routing().get(proj).unwrap();
+        assert!(!seeds.contains(&bob.node_id()));
+    }
+    assert!(alice.routing().get_resources(&bob.id()).unwrap().is_empty());
+}
+
+#[test]
+fn test_reconnect_storm_coalescing() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    storm_connections: 3,
+                    ..Limits::default()
+                },
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let carol = Peer::new("carol", [10, 10, 10, 10]);
+    let inventory = |peer: &Peer<_, _>, timestamp| {
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: arbitrary::vec::<Id>(3).try_into().unwrap(),
+                timestamp,
             },
+            peer.signer(),
         )
     };
-    let bob_inv = bob.inventory().unwrap();
 
-    alice.track_repo(&bob_inv[0], tracking::Scope::All).unwrap();
-    alice.track_repo(&bob_inv[1], tracking::Scope::All).unwrap();
-    alice.track_repo(&bob_inv[2], tracking::Scope::All).unwrap();
     alice.connect_to(&bob);
     alice.connect_to(&eve);
-    alice.receive(eve.id(), Message::Subscribe(Subscribe::all()));
-    alice.receive(bob.id(), bob.refs_announcement(bob_inv[0]));
+    alice.connect_to(&carol);
 
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_)),
-        "A refs announcement from Bob is relayed to Eve"
-    );
+    // Everyone re-announces their inventory at once, and bob does it twice.
+    alice.receive(bob.id(), inventory(&bob, bob.timestamp()));
+    let latest = inventory(&bob, bob.timestamp() + 1);
+    alice.receive(bob.id(), latest.clone());
+    alice.receive(eve.id(), inventory(&eve, eve.timestamp()));
+    alice.receive(carol.id(), inventory(&carol, carol.timestamp()));
 
-    alice.receive(bob.id(), bob.refs_announcement(bob_inv[0]));
-    assert!(
-        alice.messages(eve.id()).next().is_none(),
-        "The same ref announement is not relayed"
+    assert_eq!(
+        alice.routing().len().unwrap(),
+        0,
+        "Routing updates are held back during the storm"
     );
+    assert!(!alice
+        .messages(eve.id())
+        .any(|m| matches!(m, Message::Announcement(_))));
 
-    alice.receive(bob.id(), bob.refs_announcement(bob_inv[1]));
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_)),
-        "But a different one is"
-    );
+    alice.elapse(Config::default().storm_window);
 
-    alice.receive(bob.id(), bob.refs_announcement(bob_inv[2]));
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(_)),
-        "And a third one is as well"
+    let Message::Announcement(Announcement {
+        message: AnnouncementMessage::Inventory(latest),
+        ..
+    }) = latest else {
+        panic!("expected an inventory announcement");
+    };
+    assert_eq!(
+        alice.routing().get_resources(&bob.id()).unwrap(),
+        latest.inventory.iter().copied().collect(),
+        "Only the latest inventory of each node is applied"
     );
+    assert_eq!(alice.routing().get_resources(&eve.id()).unwrap().len(), 3);
+    assert_eq!(alice.routing().get_resources(&carol.id()).unwrap().len(), 3);
+
+    let relayed = alice
+        .messages(eve.id())
+        .filter(
+            |m| matches!(m, Message::Announcement(Announcement { node, .. }) if *node == bob.id()),
+        )
+        .count();
+    assert_eq!(relayed, 1, "Bob's inventory is relayed once");
 }
 
 #[test]
-fn test_refs_announcement_no_subscribe() {
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let bob = Peer::new("bob", [8, 8, 8, 8]);
-    let eve = Peer::new("eve", [9, 9, 9, 9]);
-    let id = arbitrary::gen(1);
-
-    alice.track_repo(&id, tracking::Scope::All).unwrap();
+fn test_reconcile() {
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let doc = identity::Doc::initial(arbitrary::gen(1), bob.id().into())
+        .verified()
+        .unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::new(vec![(rid, doc)]),
+        peer::Config::default(),
+    );
     alice.connect_to(&bob);
-    alice.connect_to(&eve);
-    alice.receive(bob.id(), bob.refs_announcement(id));
 
-    assert!(alice.messages(eve.id()).next().is_none());
-}
+    let (send, recv) = chan::bounded(1);
+    alice.command(Command::Reconcile(send));
+    recv.recv().unwrap();
 
-#[test]
-fn test_gossip_during_fetch() {
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let mut messages = alice.messages(bob.id());
+    assert_matches!(
+        messages.next(),
+        Some(Message::Announcement(Announcement {
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { inventory, .. }),
+            ..
+        })) if inventory.as_slice() == [rid],
+        "The local inventory is announced even though it didn't change"
+    );
+    assert_matches!(
+        messages.next(),
+        Some(Message::Subscribe(Subscribe { since, .. }))
+            if since < alice.timestamp(),
+        "Peers are asked for their inventories again"
+    );
+}
+
+#[test]
+fn test_announcement_timestamp_clock_backwards() {
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let doc = identity::Doc::initial(arbitrary::gen(1), bob.id().into())
+        .verified()
+        .unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::new(vec![(rid, doc)]),
+        peer::Config::default(),
+    );
+    alice.connect_to(&bob);
+
+    let mut timestamps = Vec::new();
+    for _ in 0..3 {
+        let (send, recv) = chan::bounded(1);
+        alice.command(Command::Reconcile(send));
+        recv.recv().unwrap();
+
+        let timestamp =
+            alice
+                .messages(bob.id())
+                .find_map(|m| match m {
+                    Message::Announcement(Announcement {
+                        message:
+                            AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+                        ..
+                    }) => Some(timestamp),
+                    _ => None,
+                })
+                .expect("the inventory is announced");
+        timestamps.push(timestamp);
+
+        // The clock goes backwards, eg. after an NTP correction.
+        let now = *alice.clock();
+        *alice.clock_mut() = now - LocalDuration::from_mins(10);
+    }
+    assert!(
+        timestamps.windows(2).all(|w| w[0] < w[1]),
+        "Announcement timestamps are monotonic: {timestamps:?}"
+    );
+}
+
+#[test]
+fn test_announcement_timestamp_restart() {
+    let tmp = tempfile::tempdir().unwrap();
+    let db = tmp.path().join(runtime::ADDRESS_DB_FILE);
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let signer = MockSigner::default();
+    let rid = arbitrary::gen::<Id>(1);
+    let doc = identity::Doc::initial(arbitrary::gen(1), bob.id().into())
+        .verified()
+        .unwrap();
+    let now = LocalTime::now();
+    let alice = |local_time| {
+        Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::new(vec![(rid, doc.clone())]),
+            peer::Config {
+                addrs: address::Book::open(&db).unwrap(),
+                signer: signer.clone(),
+                local_time,
+                ..peer::Config::default()
+            },
+        )
+    };
+    let announced = |alice: &mut Peer<MockStorage, MockSigner>| {
+        let (send, recv) = chan::bounded(1);
+        alice.command(Command::Reconcile(send));
+        recv.recv().unwrap();
+
+        alice
+            .messages(bob.id())
+            .find_map(|m| match m {
+                Message::Announcement(Announcement {
+                    message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+                    ..
+                }) => Some(timestamp),
+                _ => None,
+            })
+            .expect("the inventory is announced")
+    };
+
+    let mut a = alice(now);
+    a.connect_to(&bob);
+    let before = announced(&mut a);
+    a.shutdown();
+    drop(a);
+
+    // Alice is restarted with a clock that went backwards.
+    let mut a = alice(now - LocalDuration::from_mins(10));
+    a.connect_to(&bob);
+    let after = announced(&mut a);
+
+    assert!(after > before, "{after} > {before}");
+    assert!(
+        address::Book::open(&db)
+            .unwrap()
+            .get(&a.id())
+            .unwrap()
+            .is_none(),
+        "Alice isn't added to her own address book"
+    );
+}
+
+#[test]
+fn test_reannounce_interval() {
+    let interval = LocalDuration::from_secs(40);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                external_addresses: vec![Address::from(net::SocketAddr::from((
+                    [7, 7, 7, 7],
+                    8776,
+                )))],
+                reannounce_interval: Some(interval),
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let announced = |msgs: Vec<Message>| {
+        let inventory = msgs.iter().find_map(|m| match m {
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+                ..
+            }) => Some(*timestamp),
+            _ => None,
+        });
+        let node = msgs.iter().find_map(|m| match m {
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Node(NodeAnnouncement { timestamp, .. }),
+                ..
+            }) => Some(*timestamp),
+            _ => None,
+        });
+        inventory.zip(node)
+    };
+    alice.connect_to(&bob);
+    alice.elapse(LocalDuration::from_secs(1));
+
+    let (inventory, node) =
+        announced(alice.messages(bob.id()).collect()).expect("we announce ourselves");
+
+    alice.elapse(LocalDuration::from_secs(20));
+    assert_eq!(
+        announced(alice.messages(bob.id()).collect()),
+        None,
+        "We don't re-announce ourselves before the interval is over"
+    );
+
+    alice.elapse(LocalDuration::from_secs(20));
+    let (reinventory, renode) =
+        announced(alice.messages(bob.id()).collect()).expect("we re-announce ourselves");
+    assert!(
+        reinventory > inventory,
+        "The inventory is re-announced with a fresh timestamp"
+    );
+    assert!(
+        renode > node,
+        "The node is re-announced with a fresh timestamp"
+    );
+}
+
+#[test]
+fn test_inventory_pruning() {
+    struct Test {
+        limits: Limits,
+        /// Number of projects by peer
+        peer_projects: Vec<usize>,
+        wait_time: LocalDuration,
+        expected_routing_table_size: usize,
+    }
+    let tests = [
+        // All zero
+        Test {
+            limits: Limits {
+                routing_max_size: 0,
+                routing_max_age: LocalDuration::from_secs(0),
+                ..Limits::default()
+            },
+            peer_projects: vec![10; 5],
+            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
+            expected_routing_table_size: 0,
+        },
+        // All entries are too young to expire.
+        Test {
+            limits: Limits {
+                routing_max_size: 0,
+                routing_max_age: LocalDuration::from_mins(7 * 24 * 60),
+                ..Limits::default()
+            },
+            peer_projects: vec![10; 5],
+            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
+            expected_routing_table_size: 0,
+        },
+        // All entries remain because the table is unconstrained.
+        Test {
+            limits: Limits {
+                routing_max_size: 50,
+                routing_max_age: LocalDuration::from_mins(0),
+                ..Limits::default()
+            },
+            peer_projects: vec![10; 5],
+            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
+            expected_routing_table_size: 50,
+        },
+        // Some entries are pruned because the table is constrained.
+        Test {
+            limits: Limits {
+                routing_max_size: 25,
+                routing_max_age: LocalDuration::from_mins(7 * 24 * 60),
+                ..Limits::default()
+            },
+            peer_projects: vec![10; 5],
+            wait_time: LocalDuration::from_mins(7 * 24 * 60) + LocalDuration::from_secs(1),
+            expected_routing_table_size: 25,
+        },
+    ];
+
+    for test in tests {
+        let mut alice = Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::empty(),
+            peer::Config {
+                config: Config {
+                    limits: test.limits,
+                    ..Config::default()
+                },
+                ..peer::Config::default()
+            },
+        );
+
+        let bob = Peer::config(
+            "bob",
+            [8, 8, 8, 8],
+            MockStorage::empty(),
+            peer::Config {
+                local_time: alice.local_time(),
+                ..peer::Config::default()
+            },
+        );
+
+        // Tell Alice about the amazing projects available
+        alice.connect_to(&bob);
+        for num_projs in test.peer_projects {
+            alice.receive(
+                bob.id(),
+                Message::inventory(
+                    InventoryAnnouncement {
+                        inventory: test::arbitrary::vec::<Id>(num_projs).try_into().unwrap(),
+                        timestamp: bob.local_time().as_millis(),
+                    },
+                    &MockSigner::default(),
+                ),
+            );
+        }
+
+        // Wait for things to happen
+        assert!(test.wait_time > PRUNE_INTERVAL, "pruning must be triggered");
+        alice.elapse(test.wait_time);
+
+        assert_eq!(
+            test.expected_routing_table_size,
+            alice.routing().len().unwrap()
+        );
+    }
+}
+
+#[test]
+fn test_inventory_pruning_event() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    routing_max_size: 4,
+                    routing_max_age: LocalDuration::from_mins(0),
+                    ..Limits::default()
+                },
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(bob.id(), bob.inventory_announcement());
+    alice.receive(eve.id(), eve.inventory_announcement());
+    assert_eq!(alice.routing().len().unwrap(), 6);
+
+    alice.elapse(PRUNE_INTERVAL);
+    assert_eq!(alice.routing().len().unwrap(), 4);
+    assert_matches!(
+        alice
+            .events()
+            .find(|e| matches!(e, Event::RoutingPruned { .. })),
+        Some(Event::RoutingPruned { removed: 2 })
+    );
+
+    alice.elapse(PRUNE_INTERVAL);
+    assert!(
+        !alice
+            .events()
+            .any(|e| matches!(e, Event::RoutingPruned { .. })),
+        "Nothing is pruned while the routing table is within its limits"
+    );
+}
+
+#[test]
+fn test_routing_limits_from_config() {
+    let tmp = tempfile::tempdir().unwrap();
+    let path = tmp.path().join("config.json");
+
+    std::fs::write(
+        &path,
+        r#"{ "limits": { "routingMaxSize": 0, "routingMaxAge": 3600 } }"#,
+    )
+    .unwrap();
+
+    let config = Config::from_file(&path).unwrap();
+    assert_eq!(config.limits.routing_max_age, LocalDuration::from_mins(60));
+    assert_eq!(config.limits.routing_max_size, 0);
+
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config,
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::config(
+        "bob",
+        [8, 8, 8, 8],
+        MockStorage::empty(),
+        peer::Config {
+            local_time: alice.local_time(),
+            ..peer::Config::default()
+        },
+    );
+
+    alice.connect_to(&bob);
+    alice.receive(bob.id(), bob.inventory_announcement());
+    assert_eq!(alice.routing().len().unwrap(), 3);
+
+    alice.elapse(LocalDuration::from_mins(31));
+    assert_eq!(
+        alice.routing().len().unwrap(),
+        3,
+        "Entries younger than the configured age are kept"
+    );
+
+    alice.elapse(LocalDuration::from_mins(31));
+    assert_eq!(
+        alice.routing().len().unwrap(),
+        0,
+        "Entries older than the configured age are pruned"
+    );
+}
+
+#[test]
+fn test_routing_missing_repo() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = arbitrary::gen::<Id>(1);
+
+    alice.track_repo(&rid, tracking::Scope::All).unwrap();
+    alice.connect_to(&bob);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: vec![rid].try_into().unwrap(),
+                timestamp: bob.timestamp(),
+            },
+            bob.signer(),
+        ),
+    );
+
+    for _ in 0..MAX_MISSING_FETCHES {
+        assert!(alice.routing().get(&rid).unwrap().contains(&bob.id()));
+
+        alice.fetch(rid, &bob.id());
+        alice.receive(bob.id(), Message::FetchOk { rid });
+
+        let fetch = alice
+            .outbox()
+            .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+            .expect("the fetch is handed over to the worker");
+
+        alice.fetched(fetch, Err(FetchError::NotFound(rid)));
+    }
+    assert!(
+        !alice.routing().get(&rid).unwrap().contains(&bob.id()),
+        "Bob is removed as a seed after repeatedly not having the repository"
+    );
+}
+
+#[test]
+fn test_routing_missing_repo_transport_error() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = arbitrary::gen::<Id>(1);
+
+    alice.track_repo(&rid, tracking::Scope::All).unwrap();
+    alice.connect_to(&bob);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: vec![rid].try_into().unwrap(),
+                timestamp: bob.timestamp(),
+            },
+            bob.signer(),
+        ),
+    );
+
+    for _ in 0..MAX_MISSING_FETCHES {
+        alice.fetch(rid, &bob.id());
+        alice.receive(bob.id(), Message::FetchOk { rid });
+
+        let fetch = alice
+            .outbox()
+            .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+            .expect("the fetch is handed over to the worker");
+        let err = io::Error::from(io::ErrorKind::NotFound);
+
+        alice.fetched(fetch, Err(FetchError::Io(err)));
+    }
+    assert!(
+        alice.routing().get(&rid).unwrap().contains(&bob.id()),
+        "Failing fetches don't count as the repository missing, unless Bob says so"
+    );
+}
+
+#[test]
+fn test_connection_reversal() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                external_addresses: vec![Address::from(net::SocketAddr::from((
+                    [7, 7, 7, 7],
+                    8776,
+                )))],
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let mut relay = Peer::new("relay", [8, 8, 8, 8]);
+    let mut bob = Peer::new("bob", [9, 9, 9, 9]);
+
+    alice.connect_to(&relay);
+    relay.connect_from(&alice);
+    relay.connect_to(&bob);
+    bob.connect_from(&relay);
+
+    // Alice is unable to dial Bob, eg. because he's behind a NAT.
+    alice.disconnected(
+        bob.id(),
+        &DisconnectReason::Dial(Arc::new(io::Error::from(io::ErrorKind::ConnectionRefused))),
+    );
+    let request = alice
+        .messages(relay.id())
+        .find(|m| matches!(m, Message::ConnectRequest { .. }))
+        .expect("Alice asks the relay to have Bob connect to her");
+
+    // The relay forwards the request to Bob, since it's connected to him.
+    relay.receive(alice.id(), request.clone());
+    let forwarded = relay
+        .messages(bob.id())
+        .find(|m| matches!(m, Message::ConnectRequest { .. }))
+        .expect("The relay forwards the request to Bob");
+
+    // Repeated requests aren't forwarded.
+    relay.receive(alice.id(), request);
+    assert_eq!(
+        relay
+            .messages(bob.id())
+            .filter(|m| matches!(m, Message::ConnectRequest { .. }))
+            .count(),
+        0
+    );
+
+    // Bob doesn't dial addresses that Alice didn't announce herself.
+    bob.receive(relay.id(), forwarded.clone());
+    assert!(!bob.outbox().any(|io| matches!(io, Io::Connect(..))));
+
+    // Once Bob knows Alice's address, he connects to her.
+    bob.elapse(CONNECT_REQUEST_INTERVAL);
+    bob.receive(relay.id(), alice.node_announcement());
+    bob.receive(relay.id(), forwarded);
+    assert_matches!(
+        bob.outbox().find(|io| matches!(io, Io::Connect(..))),
+        Some(Io::Connect(id, addr)) if id == alice.id() && addr == alice.config().external_addresses[0]
+    );
+}
+
+#[test]
+fn test_tracking() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let proj_id: identity::Id = test::arbitrary::gen(1);
+
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::TrackRepo(proj_id, sender));
+    let policy_change = receiver.recv().map_err(runtime::HandleError::from).unwrap();
+    assert!(policy_change);
+    assert!(alice.tracking().is_repo_tracked(&proj_id).unwrap());
+
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::UntrackRepo(proj_id, sender));
+    let policy_change = receiver.recv().map_err(runtime::HandleError::from).unwrap();
+    assert!(policy_change);
+    assert!(!alice.tracking().is_repo_tracked(&proj_id).unwrap());
+}
+
+#[test]
+fn test_set_remote_tracking() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let rid = arbitrary::gen::<Id>(1);
+    let remotes = arbitrary::vec::<NodeId>(2);
+
+    let (send, recv) = chan::bounded(1);
+    alice.command(Command::SetRemoteTracking(
+        rid,
+        RemoteTracking::Remotes(remotes.clone()),
+        send,
+    ));
+    assert!(recv.recv().unwrap());
+    assert!(alice.tracking().is_repo_tracked(&rid).unwrap());
+    assert_eq!(
+        alice.tracking().repo_scope(&rid).unwrap(),
+        tracking::Scope::DelegatesOnly
+    );
+    assert!(remotes
+        .iter()
+        .all(|r| alice.tracking().is_remote_tracked(&rid, r).unwrap()));
+
+    let (send, recv) = chan::bounded(1);
+    alice.command(Command::SetRemoteTracking(rid, RemoteTracking::All, send));
+    assert!(recv.recv().unwrap());
+    assert_eq!(
+        alice.tracking().repo_scope(&rid).unwrap(),
+        tracking::Scope::All
+    );
+
+    let (send, recv) = chan::bounded(1);
+    alice.command(Command::SetRemoteTracking(rid, RemoteTracking::All, send));
+    assert!(!recv.recv().unwrap(), "The policy is unchanged");
+}
+
+#[test]
+fn test_tracking_persisted() {
+    let tmp = tempfile::tempdir().unwrap();
+    let db = tmp.path().join("tracking.db");
+    let rid = arbitrary::gen::<Id>(1);
+    let remote = arbitrary::gen::<NodeId>(1);
+
+    {
+        let mut alice = Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::empty(),
+            peer::Config {
+                tracking: tracking::Store::open(&db).unwrap(),
+                ..peer::Config::default()
+            },
+        );
+        let (send, recv) = chan::bounded(1);
+
+        alice.command(Command::TrackRepo(rid, send));
+        assert!(recv.recv().unwrap());
+
+        let (send, recv) = chan::bounded(1);
+
+        alice.command(Command::TrackRemote(rid, remote, send));
+        assert!(recv.recv().unwrap());
+    }
+
+    // Restart the node with the same tracking database.
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            tracking: tracking::Store::open(&db).unwrap(),
+            ..peer::Config::default()
+        },
+    );
+    assert!(alice.tracking().is_repo_tracked(&rid).unwrap());
+    assert!(alice.tracking().is_remote_tracked(&rid, &remote).unwrap());
+
+    let (send, recv) = chan::bounded(1);
+
+    alice.command(Command::UntrackRemote(rid, remote, send));
+    assert!(recv.recv().unwrap());
+    assert!(alice.tracking().is_repo_tracked(&rid).unwrap());
+    assert!(!alice.tracking().is_remote_tracked(&rid, &remote).unwrap());
+}
+
+#[test]
+fn test_tracking_many() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let rids = arbitrary::vec::<Id>(3);
+
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::TrackRepo(rids[0], sender));
+    assert!(receiver.recv().unwrap());
+
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::TrackMany(rids.clone(), sender));
+    assert_eq!(receiver.recv().unwrap(), 2);
+    for rid in &rids {
+        assert!(alice.tracking().is_repo_tracked(rid).unwrap());
+    }
+
+    let (sender, receiver) = chan::bounded(1);
+    alice.command(Command::UntrackMany(rids.clone(), sender));
+    assert_eq!(receiver.recv().unwrap(), 3);
+    for rid in &rids {
+        assert!(!alice.tracking().is_repo_tracked(rid).unwrap());
+    }
+}
+
+#[test]
+fn test_inventory_relay_bad_timestamp() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let two_hours = 3600 * 1000 * 2;
+    let timestamp = alice.timestamp() + two_hours;
+
+    alice.connect_to(&bob);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: BoundedVec::new(),
+                timestamp,
+            },
+            bob.signer(),
+        ),
+    );
+    assert_matches!(
+        alice.outbox().next(),
+        Some(Io::Disconnect(addr, DisconnectReason::Session(session::Error::InvalidTimestamp(t))))
+        if addr == bob.id() && t == timestamp
+    );
+}
+
+#[test]
+fn test_wrong_peer_version() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let version = wire::PROTOCOL_VERSION + 1;
+
+    alice.connect_to(&bob);
+    alice.receive(bob.id(), Message::version(Network::Main));
+    assert_matches!(
+        alice.outbox().next(),
+        None,
+        "Bob speaks our protocol version"
+    );
+
+    alice.connect_to(&eve);
+    alice.receive(
+        eve.id(),
+        Message::Version {
+            version,
+            magic: Network::Main.magic(),
+        },
+    );
+    assert_matches!(
+        alice.outbox().next(),
+        Some(Io::Disconnect(addr, DisconnectReason::Session(session::Error::WrongVersion(v))))
+        if addr == eve.id() && v == version
+    );
+}
+
+#[test]
+fn test_message_before_version() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+
+    alice.initialize();
+    alice.connected(bob.id(), bob.address(), Link::Inbound);
+    alice.outbox().for_each(drop);
+    alice.receive(bob.id(), bob.inventory_announcement());
+
+    assert_matches!(
+        alice.outbox().next(),
+        Some(Io::Disconnect(addr, DisconnectReason::Session(session::Error::Misbehavior)))
+        if addr == bob.id(),
+        "Bob is disconnected for not sending his version first"
+    );
+    assert!(
+        alice.routing().is_empty().unwrap(),
+        "Bob's gossip is not processed"
+    );
+}
+
+#[test]
+fn test_wrong_peer_magic() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config::new(Network::Test),
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let magic = Network::Main.magic();
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(eve.id(), Message::version(Network::Main));
+    alice.receive(eve.id(), eve.inventory_announcement());
+
+    assert!(
+        alice.messages(bob.id()).next().is_none(),
+        "Gossip from Eve is not processed"
+    );
+    assert_matches!(
+        alice.outbox().next(),
+        Some(Io::Disconnect(addr, DisconnectReason::Session(session::Error::WrongMagic(m))))
+        if addr == eve.id() && m == magic
+    );
+}
+
+#[test]
+fn test_seed_request() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let untracked = arbitrary::gen::<Id>(1);
+    let (send, recv) = chan::bounded(1);
+    let is_fetch = |m: &Message| matches!(m, Message::Fetch { rid: r, .. } if *r == rid);
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.command(Command::TrackRepo(rid, send));
+    assert!(recv.recv().unwrap());
+    alice.messages(bob.id()).for_each(drop);
+    alice.messages(eve.id()).for_each(drop);
+
+    alice.receive(eve.id(), Message::SeedRequest(untracked));
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "Seed requests for untracked repositories are ignored"
+    );
+
+    alice.receive(bob.id(), Message::SeedRequest(rid));
+    assert!(
+        alice.messages(bob.id()).any(|m| is_fetch(&m)),
+        "Alice fetches from Bob when asked to"
+    );
+
+    alice.receive(eve.id(), Message::SeedRequest(rid));
+    assert!(
+        !alice.messages(eve.id()).any(|m| is_fetch(&m)),
+        "Requests within the rate limiting interval are ignored"
+    );
+
+    alice.elapse(SEED_REQUEST_INTERVAL);
+    alice.receive(eve.id(), Message::SeedRequest(rid));
+    assert!(
+        alice.messages(eve.id()).any(|m| is_fetch(&m)),
+        "Requests are honoured again once the interval is over"
+    );
+
+    alice.command(Command::RequestSeeding(rid));
+    for peer in [&bob, &eve] {
+        assert!(
+            alice
+                .messages(peer.id())
+                .any(|m| matches!(m, Message::SeedRequest(r) if r == rid)),
+            "Alice asks her peers to seed the repository"
+        );
+    }
+}
+
+#[test]
+fn test_announcement_rebroadcast() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+
+    let received = test::gossip::messages(6, alice.local_time(), MAX_TIME_DELTA);
+    for msg in received.iter().cloned() {
+        alice.receive(bob.id(), msg);
+    }
+
+    alice.connect_from(&eve);
+    alice.receive(
+        eve.id(),
+        Message::Subscribe(Subscribe {
+            filter: Filter::default(),
+            since: Timestamp::MIN,
+            until: Timestamp::MAX,
+        }),
+    );
+
+    let relayed = alice.messages(eve.id()).collect::<Vec<_>>();
+    assert_eq!(relayed, received);
+}
+
+#[test]
+fn test_announcement_rebroadcast_timestamp_filtered() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+
+    let delta = LocalDuration::from_mins(10);
+    let first = test::gossip::messages(3, alice.local_time() - delta, LocalDuration::from_secs(0));
+    let second = test::gossip::messages(3, alice.local_time(), LocalDuration::from_secs(0));
+    let third = test::gossip::messages(3, alice.local_time() + delta, LocalDuration::from_secs(0));
+
+    // Alice receives three batches of messages.
+    for msg in first
+        .iter()
+        .chain(second.iter())
+        .chain(third.iter())
+        .cloned()
+    {
+        alice.receive(bob.id(), msg);
+    }
+
+    // Eve subscribes to messages within the period of the second batch only.
+    alice.connect_from(&eve);
+    alice.receive(
+        eve.id(),
+        Message::Subscribe(Subscribe {
+            filter: Filter::default(),
+            since: alice.local_time().as_millis(),
+            until: (alice.local_time() + delta).as_millis(),
+        }),
+    );
+
+    let relayed = alice.messages(eve.id()).collect::<Vec<_>>();
+    assert_eq!(relayed.len(), second.len());
+    assert_eq!(relayed, second);
+}
+
+#[test]
+fn test_announcement_relay() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let mut bob = Peer::new("bob", [8, 8, 8, 8]);
+    let mut eve = Peer::new("eve", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(bob.id(), bob.inventory_announcement());
+
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_))
+    );
+
+    alice.receive(bob.id(), bob.inventory_announcement());
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "Another inventory with the same timestamp is ignored"
+    );
+
+    bob.elapse(LocalDuration::from_mins(1));
+    alice.receive(bob.id(), bob.inventory_announcement());
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "Another inventory with a fresher timestamp is relayed"
+    );
+
+    alice.receive(bob.id(), bob.node_announcement());
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "A node announcement with the same timestamp as the inventory is relayed"
+    );
+
+    alice.receive(bob.id(), bob.node_announcement());
+    assert!(alice.messages(eve.id()).next().is_none(), "Only once");
+
+    alice.receive(eve.id(), eve.node_announcement());
+    assert_matches!(
+        alice.messages(bob.id()).next(),
+        Some(Message::Announcement(_)),
+        "A node announcement from Eve is relayed to Bob"
+    );
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "But not back to Eve"
+    );
+
+    eve.elapse(LocalDuration::from_mins(1));
+    alice.receive(bob.id(), eve.node_announcement());
+    assert!(
+        alice.messages(bob.id()).next().is_none(),
+        "Bob already know about this message, since he sent it"
+    );
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "Eve already know about this message, since she signed it"
+    );
+}
+
+#[test]
+fn test_announcement_ttl() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let mut eve = Peer::new("eve", [9, 9, 9, 9]);
+    let carol = Peer::new("carol", [10, 10, 10, 10]);
+
+    let Message::Announcement(ann) = bob.inventory_announcement() else {
+        panic!("expected an announcement");
+    };
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(bob.id(), ann.with_ttl(1).into());
+
+    let relayed = alice
+        .messages(eve.id())
+        .find_map(|m| match m {
+            Message::Announcement(ann) => Some(ann),
+            _ => None,
+        })
+        .expect("Announcements with a TTL of 1 are relayed");
+    assert_eq!(relayed.ttl, 0);
+
+    eve.connect_to(&alice);
+    eve.connect_to(&carol);
+    eve.receive(alice.id(), relayed.into());
+    assert!(
+        !eve.messages(carol.id())
+            .any(|m| matches!(m, Message::Announcement(_))),
+        "Announcements with a TTL of 0 are not relayed"
+    );
+
+    // Our own hop limit caps the one of the announcements we relay.
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                announcement_ttl: 0,
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(bob.id(), bob.node_announcement());
+    assert!(
+        !alice
+            .messages(eve.id())
+            .any(|m| matches!(m, Message::Announcement(_))),
+        "Announcements are not relayed beyond our own hop limit"
+    );
+}
+
+#[test]
+fn test_announcement_path() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                announcement_paths: true,
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let carol = Peer::new("carol", [6, 6, 6, 6]);
+    let ttl = Config::default().announcement_ttl;
+    let alice_id = alice.id();
+    let relayed = |alice: &mut Peer<MockStorage, MockSigner>| {
+        alice
+            .messages(eve.id())
+            .find_map(|m| match m {
+                Message::Announcement(ann) => Some((ann.ttl, ann.path.to_vec())),
+                _ => None,
+            })
+            .expect("the announcement is relayed")
+    };
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+
+    let Message::Announcement(ann) = bob.node_announcement() else {
+        panic!("expected an announcement");
+    };
+    alice.receive(bob.id(), ann.with_ttl(ttl).into());
+    assert_eq!(
+        relayed(&mut alice),
+        (ttl - 1, vec![alice_id]),
+        "We record ourselves in the path of announcements we relay"
+    );
+
+    // The path is unsigned, so a relay could have put our node in it. Such announcements
+    // aren't rejected, but we don't count or record ourselves again.
+    let Message::Announcement(ann) = carol.node_announcement() else {
+        panic!("expected an announcement");
+    };
+    alice.receive(bob.id(), ann.with_ttl(ttl).relayed_by(alice_id).into());
+    assert_eq!(relayed(&mut alice), (ttl, vec![alice_id]));
+}
+
+#[test]
+fn test_announcement_relay_burst() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    relay_burst: 2,
+                    ..Limits::default()
+                },
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let others = (1..=5)
+        .map(|i| Peer::new("other", [9, 9, 9, i]))
+        .collect::<Vec<_>>();
+    let interval = alice.config().relay_interval;
+
+    alice.connect_to(&bob);
+    for other in &others {
+        alice.connect_to(other);
+    }
+    alice.receive(bob.id(), bob.inventory_announcement());
+
+    let relayed = |alice: &mut Peer<MockStorage, MockSigner>| {
+        others
+            .iter()
+            .filter(|other| {
+                alice
+                    .messages(other.id())
+                    .any(|m| matches!(m, Message::Announcement(_)))
+            })
+            .count()
+    };
+    assert_eq!(
+        relayed(&mut alice),
+        2,
+        "Only a burst of relays is sent at once"
+    );
+
+    alice.elapse(interval);
+    assert_eq!(
+        relayed(&mut alice),
+        2,
+        "The next burst is sent after the interval"
+    );
+
+    alice.elapse(interval);
+    assert_eq!(relayed(&mut alice), 1, "The remaining relay is sent");
+
+    alice.elapse(interval);
+    assert_eq!(relayed(&mut alice), 0);
+}
+
+#[test]
+fn test_announcement_relay_fanout() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    relay_fanout: 3,
+                    ..Limits::default()
+                },
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let others = (1..=5)
+        .map(|i| Peer::new("other", [9, 9, 9, i]))
+        .collect::<Vec<_>>();
+
+    alice.connect_to(&bob);
+    for other in &others[..2] {
+        alice.connect_to(other);
+    }
+    let relayed = |alice: &mut Peer<MockStorage, MockSigner>| {
+        others
+            .iter()
+            .filter(|other| {
+                alice
+                    .messages(other.id())
+                    .any(|m| matches!(m, Message::Announcement(_)))
+            })
+            .count()
+    };
+
+    // Below the fan-out limit, announcements are relayed to everyone.
+    alice.receive(bob.id(), bob.inventory_announcement());
+    assert_eq!(relayed(&mut alice), 2);
+
+    for other in &others[2..] {
+        alice.connect_to(other);
+    }
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: arbitrary::vec(3).try_into().unwrap(),
+                timestamp: bob.timestamp() + 1,
+            },
+            bob.signer(),
+        ),
+    );
+    assert_eq!(
+        relayed(&mut alice),
+        3,
+        "Relays are capped at the fan-out limit"
+    );
+}
+
+#[test]
+fn test_announcement_digest_sync() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let carol = Peer::new("carol", [10, 10, 10, 10]);
+    let from_eve = eve.inventory_announcement();
+    let from_carol = carol.inventory_announcement();
+    let fingerprint = |msg: &Message| match msg {
+        Message::Announcement(ann) => ann.fingerprint(),
+        _ => panic!("expected an announcement"),
+    };
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.connect_to(&carol);
+    alice.receive(bob.id(), Message::Subscribe(Subscribe::all()));
+    alice.receive(eve.id(), from_eve.clone());
+    alice.receive(carol.id(), from_carol.clone());
+    // Bob misses these announcements, eg. because the connection dropped.
+    alice.messages(bob.id()).for_each(drop);
+
+    alice.receive(
+        bob.id(),
+        Message::Digest(Digest {
+            since: Timestamp::MIN,
+            fingerprints: vec![fingerprint(&from_eve)].try_into().unwrap(),
+        }),
+    );
+    assert_eq!(
+        alice
+            .messages(bob.id())
+            .map(|m| fingerprint(&m))
+            .collect::<Vec<_>>(),
+        vec![fingerprint(&from_carol)],
+        "Alice sends Bob the announcement missing from his digest"
+    );
+
+    alice.receive(
+        bob.id(),
+        Message::Digest(Digest {
+            since: Timestamp::MIN,
+            fingerprints: BoundedVec::new(),
+        }),
+    );
+    assert!(
+        alice.messages(bob.id()).next().is_none(),
+        "Digests from the same peer are rate-limited"
+    );
+
+    // Alice doesn't store this announcement, since she doesn't track the repository.
+    let untracked = eve.refs_announcement(arbitrary::gen::<Id>(1));
+    alice.receive(eve.id(), untracked.clone());
+
+    alice.elapse(SYNC_INTERVAL);
+    let digest = alice
+        .messages(bob.id())
+        .find_map(|m| match m {
+            Message::Digest(digest) => Some(digest),
+            _ => None,
+        })
+        .expect("Alice sends her own digest to Bob");
+
+    assert!(digest.fingerprints.contains(&fingerprint(&from_eve)));
+    assert!(digest.fingerprints.contains(&fingerprint(&from_carol)));
+    assert!(
+        digest.fingerprints.contains(&fingerprint(&untracked)),
+        "Announcements that weren't stored are in the digest too"
+    );
+}
+
+#[test]
+fn test_refs_announcement_relay() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        Storage::open(tmp.path().join("alice")).unwrap(),
+        peer::Config::default(),
+    );
+    let eve = Peer::config(
+        "eve",
+        [8, 8, 8, 8],
+        Storage::open(tmp.path().join("eve")).unwrap(),
+        peer::Config::default(),
+    );
+
+    let bob = {
+        let mut rng = fastrand::Rng::new();
+        let signer = MockSigner::new(&mut rng);
+        let storage = fixtures::storage(tmp.path().join("bob"), &signer).unwrap();
+
+        Peer::config(
+            "bob",
+            [9, 9, 9, 9],
+            storage,
+            peer::Config {
+                signer,
+                rng,
+                ..peer::Config::default()
+            },
+        )
+    };
+    let bob_inv = bob.inventory().unwrap();
+
+    alice.track_repo(&bob_inv[0], tracking::Scope::All).unwrap();
+    alice.track_repo(&bob_inv[1], tracking::Scope::All).unwrap();
+    alice.track_repo(&bob_inv[2], tracking::Scope::All).unwrap();
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(eve.id(), Message::Subscribe(Subscribe::all()));
+    alice.receive(bob.id(), bob.refs_announcement(bob_inv[0]));
+
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "A refs announcement from Bob is relayed to Eve"
+    );
+
+    alice.receive(bob.id(), bob.refs_announcement(bob_inv[0]));
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "The same ref announement is not relayed"
+    );
+
+    alice.receive(bob.id(), bob.refs_announcement(bob_inv[1]));
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "But a different one is"
+    );
+
+    alice.receive(bob.id(), bob.refs_announcement(bob_inv[2]));
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "And a third one is as well"
+    );
+}
+
+#[test]
+fn test_refs_announcement_relay_opt_out() {
+    let tmp = tempfile::tempdir().unwrap();
+    let storage = Storage::open(tmp.path().join("alice")).unwrap();
+    let (private, _, _, _) =
+        fixtures::project(tmp.path().join("acme"), &storage, &MockSigner::default()).unwrap();
+    let mut alice = Peer::config("alice", [7, 7, 7, 7], storage, peer::Config::default());
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let public = arbitrary::gen::<Id>(1);
+
+    alice.track_repo(&private, tracking::Scope::All).unwrap();
+    alice.track_repo(&public, tracking::Scope::All).unwrap();
+    alice.set_repo_relay(&private, false).unwrap();
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(eve.id(), Message::Subscribe(Subscribe::all()));
+    alice.receive(bob.id(), bob.refs_announcement(private));
+
+    assert_matches!(
+        alice.messages(bob.id()).find(|m| matches!(m, Message::Fetch { .. })),
+        Some(Message::Fetch { rid, .. }) if rid == private,
+        "The private repository is fetched"
+    );
+    assert!(
+        alice.messages(eve.id()).next().is_none(),
+        "But its announcement isn't relayed"
+    );
+
+    alice.receive(bob.id(), Message::FetchOk { rid: private });
+    let fetch = alice
+        .outbox()
+        .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+        .expect("the fetch is handed over to the worker");
+    alice.fetched(fetch, Ok(vec![]));
+
+    let announced = |m: &Message| {
+        matches!(
+            m,
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Refs(RefsAnnouncement { rid, .. }),
+                ..
+            }) if *rid == private
+        )
+    };
+    assert!(
+        !alice.messages(eve.id()).any(|m| announced(&m)),
+        "Nor are its refs announced once fetched"
+    );
+    assert!(!alice.messages(bob.id()).any(|m| announced(&m)));
+
+    alice.receive(bob.id(), bob.refs_announcement(public));
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(_)),
+        "Other repositories are relayed"
+    );
+}
+
+#[test]
+fn test_refs_announcement_unauthorized() {
+    let tmp = tempfile::tempdir().unwrap();
+    let mut rng = fastrand::Rng::new();
+    let bob_signer = MockSigner::new(&mut rng);
+    // Alice has a copy of Bob's repositories, of which Bob is the only delegate.
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        fixtures::storage(tmp.path().join("alice"), &bob_signer).unwrap(),
+        peer::Config::default(),
+    );
+    let bob = Peer::config(
+        "bob",
+        [8, 8, 8, 8],
+        MockStorage::empty(),
+        peer::Config {
+            signer: bob_signer,
+            rng,
+            ..peer::Config::default()
+        },
+    );
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let rid = alice.storage().inventory().unwrap()[0];
+    let announcement = |peer: &Peer<MockStorage, MockSigner>| -> Message {
+        let refs = Refs::from(BTreeMap::from([(
+            git::refname!("refs/heads/master"),
+            arbitrary::oid(),
+        )]))
+        .signed(peer.signer())
+        .unwrap()
+        .unverified();
+
+        AnnouncementMessage::from(RefsAnnouncement {
+            rid,
+            refs: BoundedVec::try_from(vec![(peer.id(), refs)]).unwrap(),
+            timestamp: peer.timestamp(),
+        })
+        .signed(peer.signer())
+        .into()
+    };
+
+    alice
+        .track_repo(&rid, tracking::Scope::DelegatesOnly)
+        .unwrap();
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.outbox().for_each(drop);
+
+    alice.receive(eve.id(), announcement(&eve));
+    assert!(
+        !alice
+            .messages(eve.id())
+            .any(|m| matches!(m, Message::Fetch { .. })),
+        "Refs announced by a non-delegate are not fetched"
+    );
+    assert!(
+        !alice.messages(bob.id()).any(|m| matches!(
+            m,
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Refs(_),
+                ..
+            })
+        )),
+        "Refs announced by a non-delegate are not relayed"
+    );
+
+    let announced = announcement(&bob);
+    let head = match &announced {
+        Message::Announcement(Announcement {
+            message: AnnouncementMessage::Refs(refs),
+            ..
+        }) => refs.refs[0].1.refs[&git::refname!("refs/heads/master")],
+        _ => unreachable!(),
+    };
+
+    alice.receive(bob.id(), announced);
+    assert_matches!(
+        alice.messages(bob.id()).find(|m| matches!(m, Message::Fetch { .. })),
+        Some(Message::Fetch { rid: r, oid: Some(oid) }) if r == rid && oid == head,
+        "Refs announced by a delegate are fetched, up to the announced head"
+    );
+}
+
+#[test]
+fn test_inventory_relay_opt_out() {
+    let (private, public) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+    let doc = identity::Doc::initial(arbitrary::gen(1), arbitrary::gen::<NodeId>(1).into())
+        .verified()
+        .unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::new(vec![(private, doc.clone()), (public, doc)]),
+        peer::Config::default(),
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+
+    alice.track_repo(&private, tracking::Scope::All).unwrap();
+    alice.track_repo(&public, tracking::Scope::All).unwrap();
+    alice.set_repo_relay(&private, false).unwrap();
+    alice.connect_to(&bob);
+
+    assert_matches!(
+        alice.messages(bob.id()).find_map(|m| match m {
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Inventory(inv),
+                ..
+            }) => Some(inv.inventory),
+            _ => None,
+        }),
+        Some(inventory) if inventory.as_slice() == [public],
+        "Repositories that opted out of relaying aren't in our inventory"
+    );
+}
+
+#[test]
+fn test_refs_announcement_no_subscribe() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let id = arbitrary::gen(1);
+
+    alice.track_repo(&id, tracking::Scope::All).unwrap();
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.receive(bob.id(), bob.refs_announcement(id));
+
+    assert!(alice.messages(eve.id()).next().is_none());
+}
+
+#[test]
+fn test_refs_announcement_debounce() {
+    let tmp = tempfile::tempdir().unwrap();
+    let signer = MockSigner::default();
+    let storage = fixtures::storage(tmp.path().join("alice"), &signer).unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        storage,
+        peer::Config {
+            signer,
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = alice.inventory().unwrap()[0];
+    let interval = alice.config().refs_announce_interval;
+    let is_refs = |m: &Message| {
+        matches!(
+            m,
+            Message::Announcement(Announcement {
+                message: AnnouncementMessage::Refs(_),
+                ..
+            })
+        )
+    };
+
+    alice.connect_to(&bob);
+    alice.command(Command::AnnounceRefs(rid));
+    alice.command(Command::AnnounceRefs(rid));
+    alice.command(Command::AnnounceRefs(rid));
+
+    assert_eq!(
+        alice.messages(bob.id()).filter(is_refs).count(),
+        1,
+        "Announcements within the interval are deferred"
+    );
+
+    alice.elapse(interval);
+    assert_eq!(
+        alice.messages(bob.id()).filter(is_refs).count(),
+        1,
+        "Deferred announcements are coalesced into one"
+    );
+
+    alice.elapse(interval);
+    assert_eq!(alice.messages(bob.id()).filter(is_refs).count(), 0);
+}
+
+#[test]
+fn test_refs_announcement_future_dated() {
+    let tmp = tempfile::tempdir().unwrap();
+    let alice_signer = MockSigner::default();
+    let alice_storage = fixtures::storage(tmp.path().join("alice"), &alice_signer).unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        alice_storage,
+        peer::Config {
+            signer: alice_signer,
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = alice.storage().inventory().unwrap()[0];
+
+    // Create a commit dated two hours from now in Alice's copy of the repository.
+    let future = {
+        let repo = alice.storage().repository(rid).unwrap();
+        let head = repo.backend.head().unwrap().peel_to_commit().unwrap();
+        let time = git::raw::Time::new(alice.clock().as_secs() as i64 + 2 * 60 * 60, 0);
+        let sig = git::raw::Signature::new("bob", "bob@radicle.xyz", &time).unwrap();
+
+        repo.backend
+            .commit(
+                None,
+                &sig,
+                &sig,
+                "From the future",
+                &head.tree().unwrap(),
+                &[&head],
+            )
+            .unwrap()
+    };
+    let refs = Refs::from(BTreeMap::from([(
+        git::refname!("refs/heads/master"),
+        git::Oid::from(future),
+    )]))
+    .signed(bob.signer())
+    .unwrap()
+    .unverified();
+    let ann = AnnouncementMessage::from(RefsAnnouncement {
+        rid,
+        refs: BoundedVec::try_from(vec![(bob.id(), refs)]).unwrap(),
+        timestamp: bob.timestamp(),
+    })
+    .signed(bob.signer());
+
+    alice.connect_to(&bob);
+    alice.receive(bob.id(), ann.into());
+
+    alice
+        .outbox()
+        .find(|m| matches!(m, &Io::Disconnect(id, _) if id == bob.id()))
+        .expect("Bob is disconnected for announcing future-dated refs");
+}
+
+#[test]
+fn test_announcement_signature_scheme() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rids = arbitrary::vec::<Id>(2);
+    let inventory = |rid: Id| {
+        AnnouncementMessage::from(InventoryAnnouncement {
+            inventory: BoundedVec::try_from(vec![rid]).unwrap(),
+            timestamp: bob.timestamp(),
+        })
+        .signed(bob.signer())
+    };
+
+    alice.connect_to(&bob);
+
+    let mut ann = inventory(rids[0]);
+    ann.scheme = SignatureScheme::Unknown(0xff);
+    alice.receive(bob.id(), ann.into());
+    assert!(
+        alice.routing().get(&rids[0]).unwrap().is_empty(),
+        "Announcements with an unknown signature scheme are ignored"
+    );
+    assert!(
+        !alice
+            .outbox()
+            .any(|io| matches!(io, Io::Disconnect(id, _) if id == bob.id())),
+        "Bob isn't disconnected for using an unknown signature scheme"
+    );
+
+    let ann = inventory(rids[1]);
+    assert_eq!(ann.scheme, SignatureScheme::Ed25519);
+    alice.receive(bob.id(), ann.into());
+    assert!(alice.routing().get(&rids[1]).unwrap().contains(&bob.id()));
+}
+
+#[test]
+fn test_session_features() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+
+    let supports = |alice: &Peer<_, _>, id: &NodeId| {
+        alice
+            .sessions()
+            .get(id)
+            .map_or(false, |s| s.supports(node::Features::SEED))
+    };
+    assert!(
+        !supports(&alice, &bob.id()),
+        "Nothing is supported until advertized"
+    );
+    assert!(!supports(&alice, &eve.id()));
+
+    // Bob's announcement is relayed by Eve, but it's Bob's features that are recorded.
+    alice.receive(eve.id(), bob.node_announcement());
+    assert!(supports(&alice, &bob.id()));
+    assert!(!supports(&alice, &eve.id()));
+}
+
+#[test]
+fn test_storage_quota() {
+    let quota = 1024;
+    let config = || peer::Config {
+        config: Config {
+            limits: Limits {
+                max_storage_bytes: Some(quota),
+                ..Limits::default()
+            },
+            ..Config::default()
+        },
+        ..peer::Config::default()
+    };
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = arbitrary::gen::<Id>(1);
+
+    // Until storage is measured, fetches are allowed.
+    let mut alice = Peer::config("alice", [7, 7, 7, 7], MockStorage::empty(), config());
+    let (send, _recv) = chan::bounded::<node::FetchResult>(1);
+
+    alice.connect_to(&bob);
+    alice.command(Command::Fetch(rid, bob.id(), send));
+    assert!(alice
+        .messages(bob.id())
+        .any(|m| matches!(m, Message::Fetch { rid: r, .. } if r == rid)));
+
+    // Our storage is over quota: the fetch is refused.
+    let mut alice = Peer::config("alice", [7, 7, 7, 7], MockStorage::empty(), config());
+    let (send, recv) = chan::bounded::<node::FetchResult>(1);
+
+    alice.connect_to(&bob);
+    alice.command(Command::StorageSize(quota));
+    alice.command(Command::Fetch(rid, bob.id(), send));
+    assert_matches!(recv.try_recv(), Ok(node::FetchResult::Failed { .. }));
+    assert!(!alice
+        .messages(bob.id())
+        .any(|m| matches!(m, Message::Fetch { .. })));
+    assert_matches!(
+        alice
+            .events()
+            .find(|e| matches!(e, Event::StorageQuotaExceeded { .. })),
+        Some(Event::StorageQuotaExceeded { rid: r, size, .. }) if r == rid && size == quota
+    );
+
+    // Once a smaller size is measured, fetches proceed.
+    alice.command(Command::StorageSize(quota - 1));
+    let (send, _recv) = chan::bounded::<node::FetchResult>(1);
+    alice.command(Command::Fetch(rid, bob.id(), send));
+    assert!(alice
+        .messages(bob.id())
+        .any(|m| matches!(m, Message::Fetch { rid: r, .. } if r == rid)));
+}
+
+#[test]
+fn test_fetch_dry_run_busy() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let (rid1, rid2) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+    let (send, _recv) = chan::bounded::<node::FetchResult>(1);
+
+    alice.connect_to(&bob);
+    alice.command(Command::Fetch(rid1, bob.id(), send));
+
+    // Bob is busy with our first fetch, so the dry run can't go ahead.
+    let (send, recv) = chan::bounded::<node::FetchResult>(1);
+    alice.command(Command::FetchDryRun(rid2, bob.id(), send));
+    assert_matches!(recv.try_recv(), Ok(node::FetchResult::Failed { .. }));
+
+    // Nor can one from a peer we aren't connected to.
+    let (send, recv) = chan::bounded::<node::FetchResult>(1);
+    alice.command(Command::FetchDryRun(
+        rid2,
+        arbitrary::gen::<NodeId>(1),
+        send,
+    ));
+    assert_matches!(recv.try_recv(), Ok(node::FetchResult::Failed { .. }));
+}
+
+#[test]
+fn test_fetch_hinted_seed() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let (send, _recv) = chan::bounded::<node::FetchResult>(1);
+
+    alice.initialize();
+    assert!(alice.routing().get(&rid).unwrap().is_empty());
+
+    // Bob isn't in our routing table, but we were told to fetch from him.
+    alice.command(Command::FetchHinted(rid, bob.id(), bob.address(), send));
+    assert_matches!(
+        alice.outbox().find(|io| matches!(io, Io::Connect(..))),
+        Some(Io::Connect(id, addr)) if id == bob.id() && addr == bob.address()
+    );
+
+    alice.attempted(bob.id(), &bob.address());
+    alice.connected(bob.id(), bob.address(), Link::Outbound);
+    assert!(
+        alice
+            .messages(bob.id())
+            .any(|m| matches!(m, Message::Fetch { rid: r, .. } if r == rid)),
+        "The hinted seed is fetched from once connected"
+    );
+
+    // If we can't connect to the hinted seed, the fetch fails.
+    let (send, recv) = chan::bounded::<node::FetchResult>(1);
+    alice.command(Command::FetchHinted(rid, eve.id(), eve.address(), send));
+    alice.attempted(eve.id(), &eve.address());
+    alice.disconnected(
+        eve.id(),
+        &DisconnectReason::Dial(Arc::new(io::Error::from(io::ErrorKind::ConnectionRefused))),
+    );
+    assert_matches!(recv.try_recv(), Ok(node::FetchResult::Failed { .. }));
+}
+
+#[test]
+fn test_gossip_during_fetch() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
     let eve = Peer::new("eve", [9, 9, 9, 9]);
     let now = LocalTime::now().as_millis();
     let rid = arbitrary::gen::<Id>(1);
@@ -610,260 +2607,964 @@ fn test_gossip_during_fetch() {
 
     assert_matches!(alice.messages(bob.id).next(), Some(Message::Fetch { .. }));
 
-    logger::init(log::Level::Debug);
+    logger::init(log::Level::Debug);
+
+    alice.receive(
+        eve.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: inventory1.clone(),
+                timestamp: now + 1,
+            },
+            eve.signer(),
+        ),
+    );
+    // We shouldn't relay to Bob while we're fetching from him.
+    assert_matches!(alice.messages(bob.id).next(), None);
+
+    alice.receive(bob.id(), Message::FetchOk { rid });
+    alice.receive(
+        eve.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: inventory2.clone(),
+                timestamp: now + 2,
+            },
+            eve.signer(),
+        ),
+    );
+    // We shouldn't relay to Bob while we're fetching from him.
+    assert_matches!(alice.messages(bob.id).next(), None);
+
+    // Have enough time pass that Alice sends a "ping" to Bob.
+    alice.elapse(KEEP_ALIVE_DELTA);
+
+    // Now that the fetch is done, the messages Bob missed should be relayed to him.
+    alice.fetched(
+        Fetch {
+            rid,
+            namespaces: Namespaces::All,
+            remote: bob.id,
+            initiated: true,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        },
+        Ok(vec![]),
+    );
+    let mut messages = alice.messages(bob.id);
+
+    assert_matches!(
+        messages.next(),
+        Some(Message::Announcement(Announcement {
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { inventory, .. }),
+            ..
+        })) if inventory == inventory1
+    );
+    assert_matches!(
+        messages.next(),
+        Some(Message::Announcement(Announcement {
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { inventory, .. }),
+            ..
+        })) if inventory == inventory2
+    );
+    assert_matches!(messages.next(), Some(Message::Ping { .. }));
+}
+
+#[test]
+fn test_fetch_pinned_oid() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = arbitrary::gen::<Id>(1);
+    let oid = git::Oid::from(
+        git::raw::Oid::from_str("2d52a53ce5e4f141148a5f770cfd3ead2d6a45b8").unwrap(),
+    );
+
+    alice.connect_to(&bob);
+
+    // Bob hasn't told us he understands pinned fetches, so he's sent a plain request.
+    // The worker still checks the fetched object.
+    for (pinned, result) in [
+        (None, Ok(vec![])),
+        (Some(oid), Err(FetchError::MissingObject(oid))),
+    ] {
+        alice.fetch_at(rid, &bob.id(), Some(oid));
+
+        assert_matches!(
+            alice.messages(bob.id()).next(),
+            Some(Message::Fetch { rid: r, oid: o }) if r == rid && o == pinned
+        );
+        alice.receive(bob.id(), Message::FetchOk { rid });
+
+        let fetch = alice
+            .outbox()
+            .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+            .expect("the fetch is handed over to the worker");
+        assert_eq!(fetch.oid, Some(oid));
+
+        let found = result.is_ok();
+        alice.fetched(fetch, result);
+
+        let fetched = alice
+            .events()
+            .any(|e| matches!(e, Event::RefsFetched { rid: r, .. } if r == rid));
+        assert_eq!(fetched, found);
+
+        alice.receive(
+            bob.id(),
+            bob.node_announcement_with(node::Features::SEED | node::Features::PINNED_FETCH),
+        );
+    }
+}
+
+#[test]
+fn test_fetch_debug() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let (rid, other) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+    let (send, recv) = chan::unbounded();
+    let (results, _results) = chan::unbounded::<node::FetchResult>();
+    let fetch = |alice: &mut Peer<MockStorage, MockSigner>, rid: Id, from: &Peer<_, _>| {
+        alice.command(Command::Fetch(rid, from.id(), results.clone()));
+        alice.receive(from.id(), Message::FetchOk { rid });
+        alice
+            .outbox()
+            .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+            .expect("the fetch is handed over to the worker")
+    };
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.command(Command::FetchDebug(rid, send));
+
+    let (other_send, other_recv) = chan::unbounded();
+    alice.command(Command::FetchDebug(rid, other_send));
+
+    let unrelated = fetch(&mut alice, other, &eve);
+    assert!(
+        unrelated.debug.is_empty(),
+        "Other repositories aren't debugged"
+    );
+
+    let debugged = fetch(&mut alice, rid, &bob);
+    assert_eq!(debugged.debug.len(), 2, "Every requester gets the log");
+    for debug in &debugged.debug {
+        debug
+            .send(String::from("packet: fetch< version 2"))
+            .unwrap();
+    }
+    alice.fetched(debugged, Ok(vec![]));
+    alice.fetched(unrelated, Ok(vec![]));
+
+    for recv in [recv, other_recv] {
+        assert_eq!(
+            recv.iter().collect::<Vec<_>>(),
+            vec![String::from("packet: fetch< version 2")],
+            "The log is streamed until the fetch is done"
+        );
+    }
+
+    let next = fetch(&mut alice, rid, &bob);
+    assert!(next.debug.is_empty(), "Only the next fetch is debugged");
+    alice.fetched(next, Ok(vec![]));
+
+    // Requests that aren't followed by a fetch expire.
+    let (send, recv) = chan::unbounded();
+    alice.command(Command::FetchDebug(rid, send));
+    alice.elapse(FETCH_DEBUG_TIMEOUT);
+
+    assert_eq!(
+        recv.try_recv(),
+        Err(chan::TryRecvError::Disconnected),
+        "The log ends without output"
+    );
+}
+
+#[test]
+fn test_storage_changed_event() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let rid = arbitrary::gen::<Id>(1);
+    let (results, _results) = chan::unbounded::<node::FetchResult>();
+    let fetch = |alice: &mut Peer<MockStorage, MockSigner>| {
+        alice.command(Command::Fetch(rid, bob.id(), results.clone()));
+        alice.receive(bob.id(), Message::FetchOk { rid });
+        alice
+            .outbox()
+            .find_map(|io| if let Io::Fetch(f) = io { Some(f) } else { None })
+            .expect("the fetch is handed over to the worker")
+    };
+    let oid = arbitrary::oid();
+
+    alice.connect_to(&bob);
+
+    let skipped = fetch(&mut alice);
+    alice.fetched(
+        skipped,
+        Ok(vec![RefUpdate::Skipped {
+            name: git::refname!("refs/heads/master"),
+            oid,
+        }]),
+    );
+    assert!(
+        !alice
+            .events()
+            .any(|e| matches!(e, Event::StorageChanged { .. })),
+        "Nothing was written to storage"
+    );
+
+    let created = fetch(&mut alice);
+    alice.fetched(
+        created,
+        Ok(vec![RefUpdate::Created {
+            name: git::refname!("refs/heads/master"),
+            oid,
+        }]),
+    );
+    assert_matches!(
+        alice
+            .events()
+            .find(|e| matches!(e, Event::StorageChanged { .. })),
+        Some(Event::StorageChanged { rid: r, updated }) if r == rid && updated.len() == 1
+    );
+}
+
+#[test]
+fn test_serve_rare_repos_first() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    max_uploads: Some(1),
+                    ..Limits::default()
+                },
+                rare_seed_threshold: Some(2),
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let carol = Peer::new("carol", [10, 10, 10, 10]);
+    let (busy, popular, rare) = (
+        arbitrary::gen::<Id>(1),
+        arbitrary::gen::<Id>(1),
+        arbitrary::gen::<Id>(1),
+    );
+    let upload = |io| if let Io::Fetch(f) = io { Some(f) } else { None };
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+    alice.connect_to(&carol);
+
+    // The popular repository is seeded by all of Alice's peers, the rare one by nobody else.
+    for peer in [&bob, &eve, &carol] {
+        alice.receive(
+            peer.id(),
+            Message::inventory(
+                InventoryAnnouncement {
+                    inventory: BoundedVec::try_from(vec![popular]).unwrap(),
+                    timestamp: peer.timestamp(),
+                },
+                peer.signer(),
+            ),
+        );
+    }
+    alice.receive(
+        bob.id(),
+        Message::Fetch {
+            rid: busy,
+            oid: None,
+        },
+    );
+    let ongoing = alice
+        .outbox()
+        .find_map(upload)
+        .expect("Bob's request is served right away");
+
+    alice.receive(
+        eve.id(),
+        Message::Fetch {
+            rid: popular,
+            oid: None,
+        },
+    );
+    alice.receive(
+        carol.id(),
+        Message::Fetch {
+            rid: rare,
+            oid: None,
+        },
+    );
+    assert!(
+        alice.outbox().find_map(upload).is_none(),
+        "Other requests wait for an upload slot"
+    );
+
+    alice.fetched(ongoing, Ok(vec![]));
+    let next = alice.outbox().find_map(upload).unwrap();
+    assert_eq!(next.rid, rare, "The rare repository is served first");
+    assert_eq!(next.remote, carol.id());
+
+    alice.fetched(next, Ok(vec![]));
+    let last = alice.outbox().find_map(upload).unwrap();
+    assert_eq!(last.rid, popular, "The popular repository is served next");
+    assert_eq!(last.remote, eve.id());
+}
+
+#[test]
+fn test_upload_slot_released_on_disconnect() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    max_uploads: Some(1),
+                    ..Limits::default()
+                },
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let (first, second) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+    let upload = |io| if let Io::Fetch(f) = io { Some(f) } else { None };
+
+    alice.connect_to(&bob);
+    alice.connect_to(&eve);
+
+    alice.receive(
+        bob.id(),
+        Message::Fetch {
+            rid: first,
+            oid: None,
+        },
+    );
+    alice
+        .outbox()
+        .find_map(upload)
+        .expect("Bob's request is served right away");
+
+    for rid in [first, second] {
+        alice.receive(eve.id(), Message::Fetch { rid, oid: None });
+    }
+    assert!(
+        alice.outbox().find_map(upload).is_none(),
+        "Eve's requests wait for an upload slot"
+    );
+
+    // Bob disconnects in the middle of the upload.
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
+
+    let next = alice
+        .outbox()
+        .find_map(upload)
+        .expect("Bob's upload slot is released");
+    assert_eq!(next.remote, eve.id());
+    assert_eq!(next.rid, second, "Eve's last request replaced her first");
+    assert!(
+        alice.outbox().find_map(upload).is_none(),
+        "Eve only had one request queued"
+    );
+}
+
+#[test]
+fn test_inventory_relay() {
+    // Topology is eve <-> alice <-> bob
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
+    let inv = BoundedVec::try_from(arbitrary::vec(1)).unwrap();
+    let now = LocalTime::now().as_millis();
+
+    // Inventory from Bob relayed to Eve.
+    alice.connect_to(&bob);
+    alice.connect_from(&eve);
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: inv.clone(),
+                timestamp: now,
+            },
+            bob.signer(),
+        ),
+    );
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(Announcement {
+            node,
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+            ..
+        }))
+        if node == bob.node_id() && timestamp == now
+    );
+    assert_matches!(
+        alice.messages(bob.id()).next(),
+        None,
+        "The inventory is not sent back to Bob"
+    );
+
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: inv.clone(),
+                timestamp: now,
+            },
+            bob.signer(),
+        ),
+    );
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        None,
+        "Sending the same inventory again doesn't trigger a relay"
+    );
 
     alice.receive(
-        eve.id(),
+        bob.id(),
         Message::inventory(
             InventoryAnnouncement {
-                inventory: inventory1.clone(),
+                inventory: inv.clone(),
                 timestamp: now + 1,
             },
-            eve.signer(),
+            bob.signer(),
         ),
     );
-    // We shouldn't relay to Bob while we're fetching from him.
-    assert_matches!(alice.messages(bob.id).next(), None);
+    assert_matches!(
+        alice.messages(eve.id()).next(),
+        Some(Message::Announcement(Announcement {
+            node,
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+            ..
+        }))
+        if node == bob.node_id() && timestamp == now + 1,
+        "Sending a new inventory does trigger the relay"
+    );
 
-    alice.receive(bob.id(), Message::FetchOk { rid });
+    // Inventory from Eve relayed to Bob.
     alice.receive(
         eve.id(),
         Message::inventory(
             InventoryAnnouncement {
-                inventory: inventory2.clone(),
-                timestamp: now + 2,
+                inventory: inv,
+                timestamp: now,
             },
             eve.signer(),
         ),
     );
-    // We shouldn't relay to Bob while we're fetching from him.
-    assert_matches!(alice.messages(bob.id).next(), None);
+    assert_matches!(
+        alice.messages(bob.id()).next(),
+        Some(Message::Announcement(Announcement {
+            node,
+            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
+            ..
+        }))
+        if node == eve.node_id() && timestamp == now
+    );
+}
 
-    // Have enough time pass that Alice sends a "ping" to Bob.
-    alice.elapse(KEEP_ALIVE_DELTA);
+#[test]
+fn test_persistent_peer_reconnect_attempt() {
+    let mut bob = Peer::new("bob", [8, 8, 8, 8]);
+    let mut eve = Peer::new("eve", [9, 9, 9, 9]);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                connect: vec![(bob.id(), bob.address()), (eve.id(), eve.address())],
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
 
-    // Now that the fetch is done, the messages Bob missed should be relayed to him.
-    alice.fetched(
-        Fetch {
-            rid,
-            namespaces: Namespaces::All,
-            remote: bob.id,
-            initiated: true,
+    let mut sim = Simulation::new(
+        LocalTime::now(),
+        alice.rng.clone(),
+        simulator::Options::default(),
+    )
+    .initialize([&mut alice, &mut bob, &mut eve]);
+
+    sim.run_while([&mut alice, &mut bob, &mut eve], |s| !s.is_settled());
+
+    let ips = alice
+        .sessions()
+        .connected()
+        .map(|(id, _)| *id)
+        .collect::<Vec<_>>();
+    assert!(ips.contains(&bob.id()));
+    assert!(ips.contains(&eve.id()));
+
+    // ... Negotiated ...
+    //
+    // Now let's disconnect a peer.
+
+    // A transient error such as this will cause Alice to attempt a reconnection.
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+
+    // A non-transient disconnect, such as one requested by the user will not trigger
+    // a reconnection.
+    alice.disconnected(eve.id(), &DisconnectReason::Dial(error.clone()));
+    assert_matches!(alice.outbox().next(), None);
+
+    for _ in 0..MAX_CONNECTION_ATTEMPTS {
+        alice.disconnected(bob.id(), &DisconnectReason::Connection(error.clone()));
+        assert_matches!(alice.outbox().next(), Some(Io::Connect(a, _)) if a == bob.id());
+        assert_matches!(alice.outbox().next(), None);
+
+        alice.attempted(bob.id(), &bob.address());
+    }
+
+    // After the max connection attempts, a disconnect doesn't trigger a reconnect.
+    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
+    assert_matches!(alice.outbox().next(), None);
+}
+
+#[test]
+fn test_persistent_peer_reconnect_success() {
+    let bob = Peer::config(
+        "bob",
+        [9, 9, 9, 9],
+        MockStorage::empty(),
+        peer::Config::default(),
+    );
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                connect: vec![(bob.id, bob.addr())],
+                ..Config::default()
+            },
+            ..peer::Config::default()
+        },
+    );
+
+    alice.attempted(bob.id(), &bob.addr());
+    alice.connected(bob.id(), bob.addr(), Link::Outbound);
+
+    // A transient error such as this will cause Alice to attempt a reconnection.
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
+
+    alice
+        .outbox()
+        .find_map(|o| match o {
+            Io::Connect(id, _) => Some(id),
+            _ => None,
+        })
+        .expect("Alice attempts a re-connection");
+
+    alice.attempted(bob.id(), &bob.addr());
+    alice.connected(bob.id(), bob.addr(), Link::Outbound);
+}
+
+#[test]
+fn test_persistent_connect_command() {
+    let tmp = tempfile::tempdir().unwrap();
+    let db = tmp.path().join(runtime::ADDRESS_DB_FILE);
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            addrs: address::Book::open(&db).unwrap(),
+            ..peer::Config::default()
         },
-        Ok(vec![]),
     );
-    let mut messages = alice.messages(bob.id);
 
+    alice.command(Command::Connect(
+        bob.id(),
+        bob.address(),
+        ConnectOptions { persistent: true },
+    ));
     assert_matches!(
-        messages.next(),
-        Some(Message::Announcement(Announcement {
-            message: AnnouncementMessage::Inventory(InventoryAnnouncement { inventory, .. }),
-            ..
-        })) if inventory == inventory1
+        alice.outbox().next(),
+        Some(Io::Connect(id, _)) if id == bob.id()
+    );
+    alice.attempted(bob.id(), &bob.address());
+    alice.connected(bob.id(), bob.address(), Link::Outbound);
+    assert!(alice.sessions().get(&bob.id()).unwrap().persistent);
+
+    // A transient error such as this will cause Alice to attempt a reconnection.
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
+
+    alice
+        .outbox()
+        .find_map(|o| match o {
+            Io::Connect(id, addr) if id == bob.id() => Some(addr),
+            _ => None,
+        })
+        .expect("Alice attempts a re-connection");
+
+    // After a restart, Alice connects to Bob again.
+    drop(alice);
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            addrs: address::Book::open(&db).unwrap(),
+            ..peer::Config::default()
+        },
     );
+    alice.initialize();
+
     assert_matches!(
-        messages.next(),
-        Some(Message::Announcement(Announcement {
-            message: AnnouncementMessage::Inventory(InventoryAnnouncement { inventory, .. }),
-            ..
-        })) if inventory == inventory2
+        alice.outbox().next(),
+        Some(Io::Connect(id, addr)) if id == bob.id() && addr == bob.address()
     );
-    assert_matches!(messages.next(), Some(Message::Ping { .. }));
 }
 
 #[test]
-fn test_inventory_relay() {
-    // Topology is eve <-> alice <-> bob
-    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
-    let bob = Peer::new("bob", [8, 8, 8, 8]);
-    let eve = Peer::new("eve", [9, 9, 9, 9]);
-    let inv = BoundedVec::try_from(arbitrary::vec(1)).unwrap();
-    let now = LocalTime::now().as_millis();
+fn test_unpersist_command() {
+    let tmp = tempfile::tempdir().unwrap();
+    let db = tmp.path().join(runtime::ADDRESS_DB_FILE);
+    let bob = Peer::new("bob", [9, 9, 9, 9]);
+    let alice = || {
+        Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::empty(),
+            peer::Config {
+                addrs: address::Book::open(&db).unwrap(),
+                ..peer::Config::default()
+            },
+        )
+    };
+    let mut a = alice();
 
-    // Inventory from Bob relayed to Eve.
-    alice.connect_to(&bob);
-    alice.connect_from(&eve);
-    alice.receive(
+    a.command(Command::Connect(
         bob.id(),
-        Message::inventory(
-            InventoryAnnouncement {
-                inventory: inv.clone(),
-                timestamp: now,
+        bob.address(),
+        ConnectOptions { persistent: true },
+    ));
+    a.attempted(bob.id(), &bob.address());
+    a.connected(bob.id(), bob.address(), Link::Outbound);
+
+    let (send, recv) = chan::bounded(1);
+    a.command(Command::Unpersist(bob.id(), send));
+    assert!(recv.recv().unwrap(), "Bob was persistent");
+    assert!(!a.sessions().get(&bob.id()).unwrap().persistent);
+
+    let (send, recv) = chan::bounded(1);
+    a.command(Command::Unpersist(bob.id(), send));
+    assert!(!recv.recv().unwrap(), "Bob isn't persistent anymore");
+
+    // Alice doesn't reconnect to Bob when the connection drops.
+    a.outbox().for_each(drop);
+    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+    a.disconnected(bob.id(), &DisconnectReason::Connection(error));
+    assert!(!a
+        .outbox()
+        .any(|o| matches!(o, Io::Connect(id, _) if id == bob.id())));
+
+    // Nor after a restart.
+    drop(a);
+    let mut a = alice();
+    a.initialize();
+    assert!(!a
+        .outbox()
+        .any(|o| matches!(o, Io::Connect(id, _) if id == bob.id())));
+}
+
+#[test]
+fn test_inbound_subnet_limit() {
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::empty(),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    subnet: SubnetLimits {
+                        max_inbound: 3,
+                        ..SubnetLimits::default()
+                    },
+                    ..Limits::default()
+                },
+                ..Config::default()
             },
-            bob.signer(),
-        ),
-    );
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(Announcement {
-            node,
-            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
-            ..
-        }))
-        if node == bob.node_id() && timestamp == now
+            ..peer::Config::default()
+        },
     );
-    assert_matches!(
-        alice.messages(bob.id()).next(),
-        None,
-        "The inventory is not sent back to Bob"
+    let peers = (1..=5)
+        .map(|i| Peer::new("peer", [42, 42, 42, i]))
+        .collect::<Vec<_>>();
+
+    for peer in &peers[..3] {
+        assert!(alice.accepted((peer.ip, 8776).into(), &[]));
+        alice.connect_from(peer);
+    }
+    for peer in &peers[3..] {
+        assert!(
+            !alice.accepted((peer.ip, 8776).into(), &[]),
+            "inbound connections from the same /24 are capped"
+        );
+    }
+    let eve = Peer::new("eve", [42, 42, 43, 1]);
+    assert!(
+        alice.accepted((eve.ip, 8776).into(), &[]),
+        "other subnets are not affected"
     );
 
-    alice.receive(
-        bob.id(),
-        Message::inventory(
-            InventoryAnnouncement {
-                inventory: inv.clone(),
-                timestamp: now,
-            },
-            bob.signer(),
-        ),
+    // Once a peer disconnects, there's room for another one.
+    alice.disconnected(
+        peers[0].id(),
+        &DisconnectReason::Connection(Arc::new(io::Error::from(io::ErrorKind::ConnectionReset))),
     );
-    assert_matches!(
-        alice.messages(eve.id()).next(),
-        None,
-        "Sending the same inventory again doesn't trigger a relay"
+    assert!(
+        !alice.accepted((peers[3].ip, 8776).into(), &[peers[4].ip]),
+        "connections that are still handshaking count against the limit"
     );
+    assert!(alice.accepted((peers[3].ip, 8776).into(), &[]));
+}
 
-    alice.receive(
-        bob.id(),
-        Message::inventory(
-            InventoryAnnouncement {
-                inventory: inv.clone(),
-                timestamp: now + 1,
+#[test]
+fn test_reserved_delegate_slots() {
+    let delegate = Peer::new("delegate", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let doc = identity::Doc::initial(arbitrary::gen(1), delegate.id().into())
+        .verified()
+        .unwrap();
+    let mut alice = Peer::config(
+        "alice",
+        [7, 7, 7, 7],
+        MockStorage::new(vec![(rid, doc)]),
+        peer::Config {
+            config: Config {
+                limits: Limits {
+                    max_inbound: Some(2),
+                    reserved_delegate_slots: 1,
+                    ..Limits::default()
+                },
+                ..Config::default()
             },
-            bob.signer(),
-        ),
+            ..peer::Config::default()
+        },
     );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [10, 10, 10, 10]);
+    let (send, recv) = chan::bounded(1);
+
+    alice.command(Command::TrackRepo(rid, send));
+    assert!(recv.recv().unwrap());
+
+    alice.connect_from(&bob);
+    alice.connected(eve.id(), eve.address(), Link::Inbound);
     assert_matches!(
-        alice.messages(eve.id()).next(),
-        Some(Message::Announcement(Announcement {
-            node,
-            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
-            ..
-        }))
-        if node == bob.node_id() && timestamp == now + 1,
-        "Sending a new inventory does trigger the relay"
+        alice.outbox().last(),
+        Some(Io::Disconnect(id, DisconnectReason::Session(session::Error::SlotsFull)))
+        if id == eve.id(),
+        "Eve can't use the slot reserved for delegates"
     );
 
-    // Inventory from Eve relayed to Bob.
-    alice.receive(
-        eve.id(),
-        Message::inventory(
-            InventoryAnnouncement {
-                inventory: inv,
-                timestamp: now,
-            },
-            eve.signer(),
-        ),
-    );
-    assert_matches!(
-        alice.messages(bob.id()).next(),
-        Some(Message::Announcement(Announcement {
-            node,
-            message: AnnouncementMessage::Inventory(InventoryAnnouncement { timestamp, .. }),
-            ..
-        }))
-        if node == eve.node_id() && timestamp == now
+    alice.connect_from(&delegate);
+    assert_eq!(
+        alice
+            .sessions()
+            .connected()
+            .map(|(id, _)| *id)
+            .collect::<HashSet<_>>(),
+        HashSet::from_iter([bob.id(), delegate.id()]),
+        "The delegate connects into the reserved slot"
     );
 }
 
 #[test]
-fn test_persistent_peer_reconnect_attempt() {
-    let mut bob = Peer::new("bob", [8, 8, 8, 8]);
-    let mut eve = Peer::new("eve", [9, 9, 9, 9]);
+fn test_shed_connections() {
+    let carol = Peer::new("carol", [6, 6, 6, 6]);
+    let delegate = Peer::new("delegate", [9, 9, 9, 9]);
+    let rid = arbitrary::gen::<Id>(1);
+    let doc = identity::Doc::initial(arbitrary::gen(1), delegate.id().into())
+        .verified()
+        .unwrap();
     let mut alice = Peer::config(
         "alice",
         [7, 7, 7, 7],
-        MockStorage::empty(),
+        MockStorage::new(vec![(rid, doc)]),
         peer::Config {
             config: Config {
-                connect: vec![(bob.id(), bob.address()), (eve.id(), eve.address())],
+                connect: vec![(carol.id(), carol.address())],
                 ..Config::default()
             },
             ..peer::Config::default()
         },
     );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [10, 10, 10, 10]);
+    let dave = Peer::new("dave", [11, 11, 11, 11]);
+    let (send, recv) = chan::bounded(1);
 
-    let mut sim = Simulation::new(
-        LocalTime::now(),
-        alice.rng.clone(),
-        simulator::Options::default(),
-    )
-    .initialize([&mut alice, &mut bob, &mut eve]);
-
-    sim.run_while([&mut alice, &mut bob, &mut eve], |s| !s.is_settled());
-
-    let ips = alice
-        .sessions()
-        .connected()
-        .map(|(id, _)| *id)
-        .collect::<Vec<_>>();
-    assert!(ips.contains(&bob.id()));
-    assert!(ips.contains(&eve.id()));
+    alice.command(Command::TrackRepo(rid, send));
+    assert!(recv.recv().unwrap());
 
-    // ... Negotiated ...
-    //
-    // Now let's disconnect a peer.
+    alice.connect_to(&carol);
+    alice.connect_to(&dave);
+    alice.connect_from(&delegate);
+    alice.connect_from(&bob);
+    alice.connect_from(&eve);
+    alice.elapse(LocalDuration::from_secs(1));
+    alice.receive(
+        eve.id(),
+        Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(0),
+        }),
+    );
+    alice.outbox().for_each(drop);
 
-    // A transient error such as this will cause Alice to attempt a reconnection.
-    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
+    let shed = |alice: &mut Peer<MockStorage, MockSigner>, target| {
+        alice.shed_connections(target);
+        alice
+            .outbox()
+            .filter_map(|io| match io {
+                Io::Disconnect(id, DisconnectReason::Session(session::Error::Overloaded)) => {
+                    Some(id)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    assert!(
+        shed(&mut alice, 5).is_empty(),
+        "We're already within the target"
+    );
+    assert_eq!(
+        shed(&mut alice, 4),
+        vec![bob.id()],
+        "The idle inbound peer is shed first"
+    );
+    assert_eq!(
+        shed(&mut alice, 0),
+        vec![bob.id(), eve.id(), dave.id()],
+        "Persistent peers and delegates are retained"
+    );
+}
 
-    // A non-transient disconnect, such as one requested by the user will not trigger
-    // a reconnection.
-    alice.disconnected(eve.id(), &DisconnectReason::Dial(error.clone()));
-    assert_matches!(alice.outbox().next(), None);
+#[test]
+fn test_shed_zero_inventory_peers_first() {
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
 
-    for _ in 0..MAX_CONNECTION_ATTEMPTS {
-        alice.disconnected(bob.id(), &DisconnectReason::Connection(error.clone()));
-        assert_matches!(alice.outbox().next(), Some(Io::Connect(a, _)) if a == bob.id());
-        assert_matches!(alice.outbox().next(), None);
+    alice.connect_from(&bob);
+    alice.connect_from(&eve);
+    alice.elapse(LocalDuration::from_secs(1));
+    alice.receive(eve.id(), eve.inventory_announcement());
 
-        alice.attempted(bob.id(), &bob.address());
-    }
+    // Bob is the most recently active peer, but only consumes content.
+    alice.elapse(LocalDuration::from_secs(1));
+    alice.receive(
+        bob.id(),
+        Message::inventory(
+            InventoryAnnouncement {
+                inventory: BoundedVec::new(),
+                timestamp: bob.timestamp(),
+            },
+            bob.signer(),
+        ),
+    );
+    alice.outbox().for_each(drop);
+    alice.shed_connections(1);
 
-    // After the max connection attempts, a disconnect doesn't trigger a reconnect.
-    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
-    assert_matches!(alice.outbox().next(), None);
+    assert_eq!(
+        alice
+            .outbox()
+            .filter_map(|io| match io {
+                Io::Disconnect(id, DisconnectReason::Session(session::Error::Overloaded)) => {
+                    Some(id)
+                }
+                _ => None,
+            })
+            .collect::<Vec<_>>(),
+        vec![bob.id()],
+        "The zero-inventory peer is shed before the content-serving one"
+    );
 }
 
 #[test]
-fn test_persistent_peer_reconnect_success() {
-    let bob = Peer::config(
-        "bob",
-        [9, 9, 9, 9],
-        MockStorage::empty(),
-        peer::Config::default(),
-    );
+fn test_list_sessions() {
+    let carol = Peer::new("carol", [6, 6, 6, 6]);
     let mut alice = Peer::config(
         "alice",
         [7, 7, 7, 7],
         MockStorage::empty(),
         peer::Config {
             config: Config {
-                connect: vec![(bob.id, bob.addr())],
+                connect: vec![(carol.id(), carol.address())],
                 ..Config::default()
             },
             ..peer::Config::default()
         },
     );
+    let bob = Peer::new("bob", [8, 8, 8, 8]);
+    let eve = Peer::new("eve", [9, 9, 9, 9]);
 
-    alice.attempted(bob.id(), &bob.addr());
-    alice.connected(bob.id(), Link::Outbound);
+    alice.connect_to(&bob);
+    alice.connect_from(&eve);
+    alice.receive(
+        bob.id(),
+        Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(0),
+        }),
+    );
+    // Only the pong to Bob is handed to the transport.
+    while alice.next().is_some() {}
 
-    // A transient error such as this will cause Alice to attempt a reconnection.
-    let error = Arc::new(io::Error::from(io::ErrorKind::ConnectionReset));
-    alice.disconnected(bob.id(), &DisconnectReason::Connection(error));
+    let (send, recv) = chan::bounded(1);
+    alice.command(Command::ListSessions(send));
 
-    alice
-        .outbox()
-        .find_map(|o| match o {
-            Io::Connect(id, _) => Some(id),
-            _ => None,
-        })
-        .expect("Alice attempts a re-connection");
+    let sessions = recv
+        .recv()
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.nid, s))
+        .collect::<BTreeMap<_, _>>();
 
-    alice.attempted(bob.id(), &bob.addr());
-    alice.connected(bob.id(), Link::Outbound);
+    assert_eq!(
+        sessions
+            .iter()
+            .map(|(nid, s)| (*nid, (s.link, s.state, s.persistent)))
+            .collect::<BTreeMap<_, _>>(),
+        BTreeMap::from_iter([
+            (
+                carol.id(),
+                (node::Link::Outbound, node::SessionState::Connecting, true),
+            ),
+            (
+                bob.id(),
+                (node::Link::Outbound, node::SessionState::Connected, false),
+            ),
+            (
+                eve.id(),
+                (node::Link::Inbound, node::SessionState::Connected, false),
+            ),
+        ])
+    );
+    assert_eq!(
+        sessions[&bob.id()].last_sent,
+        alice.local_time().as_millis()
+    );
+    assert_eq!(sessions[&eve.id()].last_sent, 0);
+    assert_eq!(sessions[&carol.id()].last_sent, 0);
 }
 
 #[test]
@@ -914,6 +3615,75 @@ fn test_maintain_connections() {
     );
 }
 
+#[test]
+fn test_maintain_connections_prefers_fresh_addresses() {
+    // Peers alice starts out connected to, leaving room for one more outbound peer.
+    let connected = (1..TARGET_OUTBOUND_PEERS as u8)
+        .map(|i| Peer::new("connected", [8, 8, 8, i]))
+        .collect::<Vec<_>>();
+    let unconnected = vec![
+        Peer::new("stale", [9, 9, 9, 1]),
+        Peer::new("fresh", [9, 9, 9, 2]),
+    ];
+    let (stale, fresh) = (&unconnected[0], &unconnected[1]);
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+    let now = alice.local_time();
+
+    alice.import_addresses(&unconnected);
+    for (peer, time) in [
+        (stale, now - LocalDuration::from_mins(24 * 60)),
+        (fresh, now - LocalDuration::from_mins(1)),
+    ] {
+        let addresses = alice.addresses_mut();
+        addresses
+            .attempted(&peer.id(), &peer.address(), time)
+            .unwrap();
+        addresses.connected(&peer.id(), time).unwrap();
+    }
+    for peer in connected.iter() {
+        alice.connect_to(peer);
+    }
+    alice.outbox().for_each(drop);
+    alice.elapse(IDLE_INTERVAL);
+
+    let dialed = alice
+        .outbox()
+        .filter_map(|o| match o {
+            Io::Connect(id, _) => Some(id),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        dialed,
+        vec![fresh.id()],
+        "The most recently connected peer is preferred"
+    );
+}
+
+#[test]
+fn test_maintain_connections_ipv6() {
+    let peers = vec![Peer::new(
+        "bob",
+        net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x8),
+    )];
+    let bob = &peers[0];
+    let mut alice = Peer::new("alice", [7, 7, 7, 7]);
+
+    alice.import_addresses(&peers);
+    alice.initialize();
+    alice.elapse(IDLE_INTERVAL);
+
+    assert_matches!(
+        alice.outbox().find(|o| matches!(o, Io::Connect(..))),
+        Some(Io::Connect(id, addr)) if id == bob.id() && addr == bob.address(),
+        "Alice dials Bob's IPv6 address"
+    );
+    alice.attempted(bob.id(), &bob.address());
+    alice.connected(bob.id(), bob.address(), Link::Outbound);
+
+    assert!(alice.sessions().get(&bob.id()).unwrap().is_connected());
+}
+
 #[test]
 fn test_track_repo_subscribe() {
     let mut alice = Peer::new("alice", [7, 7, 7, 7]);
@@ -960,8 +3730,16 @@ fn test_push_and_pull() {
     local::register(alice.storage().clone());
 
     // Alice and Bob connect to Eve.
-    alice.command(service::Command::Connect(eve.id(), eve.address()));
-    bob.command(service::Command::Connect(eve.id(), eve.address()));
+    alice.command(service::Command::Connect(
+        eve.id(),
+        eve.address(),
+        ConnectOptions::default(),
+    ));
+    bob.command(service::Command::Connect(
+        eve.id(),
+        eve.address(),
+        ConnectOptions::default(),
+    ));
 
     // Alice creates a new project.
     let (proj_id, _, _) = rad::init(
@@ -1029,24 +3807,14 @@ fn test_push_and_pull() {
 fn prop_inventory_exchange_dense() {
     fn property(alice_inv: MockStorage, bob_inv: MockStorage, eve_inv: MockStorage) {
         let rng = fastrand::Rng::new();
-        let alice = Peer::config(
-            "alice",
-            [7, 7, 7, 7],
-            alice_inv.clone(),
-            peer::Config::default(),
-        );
-        let mut bob = Peer::config(
-            "bob",
-            [8, 8, 8, 8],
-            bob_inv.clone(),
-            peer::Config::default(),
-        );
-        let mut eve = Peer::config(
-            "eve",
-            [9, 9, 9, 9],
-            eve_inv.clone(),
-            peer::Config::default(),
-        );
+        let mut signers = MockSigner::sequence(3);
+        let mut config = || peer::Config {
+            signer: signers.next().unwrap(),
+            ..peer::Config::default()
+        };
+        let alice = Peer::config("alice", [7, 7, 7, 7], alice_inv.clone(), config());
+        let mut bob = Peer::config("bob", [8, 8, 8, 8], bob_inv.clone(), config());
+        let mut eve = Peer::config("eve", [9, 9, 9, 9], eve_inv.clone(), config());
         let mut routing = HashMap::with_hasher(rng.clone().into());
 
         for (inv, peer) in &[
@@ -1063,9 +3831,21 @@ fn prop_inventory_exchange_dense() {
         }
 
         // Fully-connected.
-        bob.command(Command::Connect(alice.id(), alice.address()));
-        bob.command(Command::Connect(eve.id(), eve.address()));
-        eve.command(Command::Connect(alice.id(), alice.address()));
+        bob.command(Command::Connect(
+            alice.id(),
+            alice.address(),
+            ConnectOptions::default(),
+        ));
+        bob.command(Command::Connect(
+            eve.id(),
+            eve.address(),
+            ConnectOptions::default(),
+        ));
+        eve.command(Command::Connect(
+            alice.id(),
+            alice.address(),
+            ConnectOptions::default(),
+        ));
 
         let mut peers: HashMap<_, _> = [
             (alice.node_id(), alice),
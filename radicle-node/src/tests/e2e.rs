@@ -270,6 +270,26 @@ fn test_fetch_up_to_date() {
     assert_eq!(result.success(), Some(vec![]));
 }
 
+#[test]
+fn test_shutdown() {
+    logger::init(log::Level::Debug);
+
+    let tmp = tempfile::tempdir().unwrap();
+    let alice = Node::init(tmp.path());
+    let bob = Node::init(tmp.path());
+
+    let mut alice = alice.spawn(service::Config::default());
+    let bob = bob.spawn(service::Config::default());
+
+    alice.connect(&bob);
+
+    // Shutting down through the control socket returns once the node has wound down.
+    radicle::Node::new(alice.home.socket()).shutdown().unwrap();
+
+    let thread = alice.thread.take().unwrap();
+    assert_matches!(thread.join().unwrap(), Ok(()));
+}
+
 #[test]
 #[ignore = "failing"]
 #[should_panic]
@@ -1,3 +1,4 @@
+mod limiter;
 mod message;
 mod protocol;
 
@@ -22,6 +23,10 @@ use crate::service::filter;
 use crate::storage::refs::Refs;
 use crate::storage::refs::SignedRefs;
 
+/// Version of the gossip protocol spoken by this node. Peers speaking a different
+/// version are disconnected.
+pub const PROTOCOL_VERSION: u32 = 1;
+
 /// The default type we use to represent sizes on the wire.
 ///
 /// Since wire messages are limited to 64KB by the transport layer,
@@ -46,6 +51,10 @@ pub enum Error {
     UnknownAddressType(u8),
     #[error("unknown message type `{0}`")]
     UnknownMessageType(u16),
+    #[error("invalid option tag `{0}`")]
+    InvalidOptionTag(u8),
+    #[error("message exceeds the size limit of {0} bytes")]
+    MessageTooLarge(usize),
 }
 
 impl Error {
@@ -66,6 +75,119 @@ pub trait Decode: Sized {
     fn decode<R: io::Read + ?Sized>(reader: &mut R) -> Result<Self, Error>;
 }
 
+/// Things that can be decoded from binary as it arrives, keeping the progress made when
+/// the input runs out, instead of decoding from the start once more input is available.
+pub trait Resume: Sized {
+    /// Progress made decoding a value.
+    type State: Default + std::fmt::Debug;
+
+    /// Decode a value, continuing from the given state. If the input runs out, an end-of-file
+    /// error is returned and the state holds the progress made. Decoding is then resumed
+    /// with the same input and more bytes appended, from the position it was left at.
+    fn resume(state: &mut Self::State, input: &mut Input) -> Result<Self, Error>;
+}
+
+impl Resume for String {
+    type State = ();
+
+    fn resume(_: &mut (), input: &mut Input) -> Result<Self, Error> {
+        input.decode()
+    }
+}
+
+/// Items of a list decoded so far, along with the length of the list.
+pub type Items<T, const N: usize> = Option<(usize, BoundedVec<T, N>)>;
+
+/// Input of a [`Resume`] decoder.
+pub struct Input<'a> {
+    cursor: io::Cursor<&'a [u8]>,
+    /// Number of input bytes needed for decoding to make progress.
+    /// Known once the input ran out, zero until then.
+    needed: usize,
+}
+
+impl<'a> Input<'a> {
+    /// Create a new input, positioned at the given offset.
+    pub fn new(bytes: &'a [u8], offset: usize) -> Self {
+        let mut cursor = io::Cursor::new(bytes);
+        cursor.set_position(offset as u64);
+
+        Self { cursor, needed: 0 }
+    }
+
+    /// Offset of the first byte that wasn't consumed.
+    pub fn position(&self) -> usize {
+        self.cursor.position() as usize
+    }
+
+    /// Number of input bytes needed for decoding to make progress.
+    pub fn needed(&self) -> usize {
+        self.needed
+    }
+
+    /// Decode a value in one go. If this fails, no input is consumed, so that decoding
+    /// can be retried from this value once more input is available.
+    pub fn decode<T: Decode>(&mut self) -> Result<T, Error> {
+        self.atomic(|input| T::decode(input))
+    }
+
+    /// Decode a value without consuming any input.
+    pub fn peek<T: Decode>(&mut self) -> Result<T, Error> {
+        let position = self.cursor.position();
+        let value = T::decode(self);
+        self.cursor.set_position(position);
+
+        value
+    }
+
+    /// Run a decoder, consuming no input if it fails.
+    pub fn atomic<T>(
+        &mut self,
+        decode: impl FnOnce(&mut Self) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let position = self.cursor.position();
+        let result = decode(self);
+        if result.is_err() {
+            self.cursor.set_position(position);
+        }
+        result
+    }
+
+    /// Decode the items of a list, one at a time. The items decoded so far are kept
+    /// in `items` if the input runs out. Returns the list once all items are decoded.
+    pub fn items<'b, T: Decode, const N: usize>(
+        &mut self,
+        items: &'b mut Items<T, N>,
+    ) -> Result<&'b mut BoundedVec<T, N>, Error> {
+        let (len, items) = match items {
+            Some(items) => items,
+            None => {
+                let len = self.decode::<Size>()? as usize;
+                let vec = BoundedVec::with_capacity(len).map_err(|_| Error::InvalidSize {
+                    expected: BoundedVec::<T, N>::max(),
+                    actual: len,
+                })?;
+                items.insert((len, vec))
+            }
+        };
+        while items.len() < *len {
+            let item = self.decode()?;
+            items.push(item).ok();
+        }
+        Ok(items)
+    }
+}
+
+impl io::Read for Input<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = io::Read::read(&mut self.cursor, buf)?;
+        if n < buf.len() {
+            self.needed = self.position() + buf.len() - n;
+        }
+        Ok(n)
+    }
+}
+
 /// Encode an object into a vector.
 pub fn serialize<T: Encode + ?Sized>(data: &T) -> Vec<u8> {
     let mut buffer = Vec::new();
@@ -241,6 +363,15 @@ impl Encode for git::Oid {
     }
 }
 
+impl<T: Encode> Encode for Option<T> {
+    fn encode<W: io::Write + ?Sized>(&self, writer: &mut W) -> Result<usize, io::Error> {
+        match self {
+            None => 0u8.encode(writer),
+            Some(value) => Ok(1u8.encode(writer)? + value.encode(writer)?),
+        }
+    }
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 
 impl Decode for PublicKey {
@@ -308,6 +439,16 @@ impl Decode for git::Oid {
     }
 }
 
+impl<T: Decode> Decode for Option<T> {
+    fn decode<R: io::Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
+        match u8::decode(reader)? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            other => Err(Error::InvalidOptionTag(other)),
+        }
+    }
+}
+
 impl Decode for Signature {
     fn decode<R: io::Read + ?Sized>(reader: &mut R) -> Result<Self, Error> {
         let bytes: [u8; 64] = Decode::decode(reader)?;
@@ -546,6 +687,14 @@ mod tests {
         assert_eq!(deserialize::<git::Oid>(&serialize(&oid)).unwrap(), oid);
     }
 
+    #[quickcheck]
+    fn prop_option(input: Option<u64>) {
+        assert_eq!(
+            deserialize::<Option<u64>>(&serialize(&input)).unwrap(),
+            input
+        );
+    }
+
     #[quickcheck]
     fn prop_signed_refs(input: SignedRefs<Unverified>) {
         assert_eq!(
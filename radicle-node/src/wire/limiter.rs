@@ -0,0 +1,210 @@
+//! Rate limiting of inbound connections.
+use std::net;
+
+use localtime::LocalTime;
+
+use radicle::collections::HashMap;
+
+use crate::service::config::{AcceptLimits, RateLimit};
+
+/// Number of remote IPs we keep buckets for. Once reached, the buckets that are full are
+/// forgotten, and if that isn't enough, the least recently used ones.
+const MAX_TRACKED_IPS: usize = 1024;
+/// Number of buckets kept after forgetting the least recently used ones. This leaves room
+/// for new IPs, so that buckets aren't scanned on every connection once we're at capacity.
+const TRACKED_IPS_LOW_WATERMARK: usize = MAX_TRACKED_IPS * 3 / 4;
+
+/// A token bucket. Each event takes a token, and tokens are added back at a fixed rate.
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    /// Rate limit enforced by this bucket.
+    limit: RateLimit,
+    /// Tokens currently in the bucket.
+    tokens: f64,
+    /// Last time the bucket was refilled.
+    refilled: LocalTime,
+}
+
+impl TokenBucket {
+    /// Create a full bucket.
+    fn new(limit: RateLimit, now: LocalTime) -> Self {
+        Self {
+            limit,
+            tokens: limit.capacity as f64,
+            refilled: now,
+        }
+    }
+
+    /// Add the tokens accumulated since the last refill.
+    fn refill(&mut self, now: LocalTime) {
+        let elapsed = now.as_millis().saturating_sub(self.refilled.as_millis());
+
+        self.tokens = (self.tokens + elapsed as f64 / 1000. * self.limit.fill_rate)
+            .min(self.limit.capacity as f64);
+        self.refilled = now;
+    }
+
+    /// Take a token from the bucket. Returns `false` if the bucket is empty.
+    fn take(&mut self, now: LocalTime) -> bool {
+        self.refill(now);
+
+        if self.tokens >= 1. {
+            self.tokens -= 1.;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Whether the bucket is full, ie. the limit is not being approached.
+    fn is_full(&mut self, now: LocalTime) -> bool {
+        self.refill(now);
+        self.tokens >= self.limit.capacity as f64
+    }
+}
+
+/// Limits the rate of inbound connections, both per remote IP and overall.
+#[derive(Debug)]
+pub struct AcceptLimiter {
+    /// Limits applied to each remote IP.
+    limits: AcceptLimits,
+    /// Bucket shared by all inbound connections.
+    global: TokenBucket,
+    /// Buckets of remote IPs, keyed by [`bucket_key`].
+    per_ip: HashMap<net::IpAddr, TokenBucket>,
+}
+
+impl AcceptLimiter {
+    pub fn new(limits: AcceptLimits, now: LocalTime) -> Self {
+        Self {
+            global: TokenBucket::new(limits.global, now),
+            per_ip: HashMap::default(),
+            limits,
+        }
+    }
+
+    /// Record an inbound connection from the given address. Returns `false` if the
+    /// connection exceeds the limits, and should be dropped.
+    pub fn accept(&mut self, ip: net::IpAddr, now: LocalTime) -> bool {
+        if self.per_ip.len() >= MAX_TRACKED_IPS {
+            self.evict(now);
+        }
+        let per_ip = self.limits.per_ip;
+        let allowed = self
+            .per_ip
+            .entry(bucket_key(ip))
+            .or_insert_with(|| TokenBucket::new(per_ip, now))
+            .take(now);
+
+        // Connections refused because of their IP don't count against the global limit,
+        // so that a single host can't lock others out.
+        allowed && self.global.take(now)
+    }
+
+    /// Forget the buckets that are full. If most buckets are still in use, forget the least
+    /// recently used ones as well.
+    fn evict(&mut self, now: LocalTime) {
+        self.per_ip.retain(|_, bucket| !bucket.is_full(now));
+
+        let Some(excess) = self.per_ip.len().checked_sub(TRACKED_IPS_LOW_WATERMARK) else {
+            return;
+        };
+        let mut buckets = self
+            .per_ip
+            .iter()
+            .map(|(ip, bucket)| (bucket.refilled.as_millis(), *ip))
+            .collect::<Vec<_>>();
+        buckets.sort_unstable();
+
+        for (_, ip) in buckets.into_iter().take(excess) {
+            self.per_ip.remove(&ip);
+        }
+    }
+}
+
+/// Key of the bucket of a remote IP. IPv6 addresses are grouped by their /64 prefix, since
+/// that is usually what a single host is assigned.
+fn bucket_key(ip: net::IpAddr) -> net::IpAddr {
+    match ip {
+        net::IpAddr::V4(_) => ip,
+        net::IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return net::IpAddr::V4(ip);
+            }
+            let prefix = u128::from(ip) & !u128::from(u64::MAX);
+            net::IpAddr::V6(net::Ipv6Addr::from(prefix))
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::LocalDuration;
+
+    #[test]
+    fn test_token_bucket_refill() {
+        let now = LocalTime::now();
+        let limit = RateLimit {
+            fill_rate: 2.,
+            capacity: 4,
+        };
+        let mut bucket = TokenBucket::new(limit, now);
+
+        assert!((0..4).all(|_| bucket.take(now)));
+        assert!(!bucket.take(now), "The bucket is empty");
+
+        let now = now + LocalDuration::from_millis(500);
+        assert!(bucket.take(now), "A token is added every half second");
+        assert!(!bucket.take(now));
+
+        let now = now + LocalDuration::from_secs(60);
+        assert!(bucket.is_full(now), "The bucket doesn't overflow");
+        assert!((0..4).all(|_| bucket.take(now)));
+        assert!(!bucket.take(now));
+    }
+
+    #[test]
+    fn test_tracked_ips_bounded() {
+        let now = LocalTime::now();
+        let mut limiter = AcceptLimiter::new(AcceptLimits::default(), now);
+
+        for i in 0..MAX_TRACKED_IPS as u32 * 4 {
+            let ip = net::IpAddr::V4(net::Ipv4Addr::from(i));
+            let now = now + LocalDuration::from_millis(i as u128);
+
+            limiter.accept(ip, now);
+            assert!(limiter.per_ip.len() <= MAX_TRACKED_IPS);
+        }
+        let last = net::IpAddr::V4(net::Ipv4Addr::from(MAX_TRACKED_IPS as u32 * 4 - 1));
+        assert!(
+            limiter.per_ip.contains_key(&last),
+            "The most recently used buckets are kept"
+        );
+    }
+
+    #[test]
+    fn test_ipv6_prefix() {
+        let now = LocalTime::now();
+        let mut limiter = AcceptLimiter::new(
+            AcceptLimits {
+                per_ip: RateLimit {
+                    fill_rate: 0.,
+                    capacity: 1,
+                },
+                ..AcceptLimits::default()
+            },
+            now,
+        );
+        let a = net::IpAddr::V6(net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        let b = net::IpAddr::V6(net::Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0xf, 0, 0, 2));
+        let c = net::IpAddr::V6(net::Ipv6Addr::new(0x2001, 0xdb8, 0, 1, 0, 0, 0, 1));
+
+        assert!(limiter.accept(a, now));
+        assert!(
+            !limiter.accept(b, now),
+            "Addresses in the same /64 share a bucket"
+        );
+        assert!(limiter.accept(c, now));
+    }
+}
@@ -4,8 +4,11 @@ use byteorder::{NetworkEndian, ReadBytesExt};
 use cyphernet::addr::{Addr, HostName, NetAddr};
 use radicle::node::Address;
 
+use crate::crypto::Unverified;
+use crate::git;
 use crate::prelude::*;
 use crate::service::message::*;
+use crate::storage::refs::SignedRefs;
 use crate::wire;
 use crate::wire::{Decode, Encode};
 
@@ -21,6 +24,13 @@ pub enum MessageType {
     Pong = 12,
     Fetch = 14,
     FetchOk = 16,
+    ConnectRequest = 18,
+    Version = 20,
+    SeedRequest = 22,
+    Digest = 24,
+    /// A [`Message::Fetch`] pinned to an object. Only sent to peers that advertise
+    /// [`radicle::node::Features::PINNED_FETCH`], since others can't decode it.
+    FetchPinned = 26,
 }
 
 impl From<MessageType> for u16 {
@@ -42,6 +52,11 @@ impl TryFrom<u16> for MessageType {
             12 => Ok(MessageType::Pong),
             14 => Ok(MessageType::Fetch),
             16 => Ok(MessageType::FetchOk),
+            18 => Ok(MessageType::ConnectRequest),
+            20 => Ok(MessageType::Version),
+            22 => Ok(MessageType::SeedRequest),
+            24 => Ok(MessageType::Digest),
+            26 => Ok(MessageType::FetchPinned),
             _ => Err(other),
         }
     }
@@ -62,8 +77,13 @@ impl Message {
             },
             Self::Ping { .. } => MessageType::Ping,
             Self::Pong { .. } => MessageType::Pong,
-            Self::Fetch { .. } => MessageType::Fetch,
+            Self::Fetch { oid: None, .. } => MessageType::Fetch,
+            Self::Fetch { oid: Some(_), .. } => MessageType::FetchPinned,
             Self::FetchOk { .. } => MessageType::FetchOk,
+            Self::ConnectRequest { .. } => MessageType::ConnectRequest,
+            Self::Version { .. } => MessageType::Version,
+            Self::SeedRequest(_) => MessageType::SeedRequest,
+            Self::Digest(_) => MessageType::Digest,
         }
         .into()
     }
@@ -203,11 +223,17 @@ impl wire::Encode for Message {
             Self::Announcement(Announcement {
                 node,
                 message,
+                scheme,
                 signature,
+                ttl,
+                path,
             }) => {
                 n += node.encode(writer)?;
                 n += message.encode(writer)?;
+                n += scheme.tag().encode(writer)?;
                 n += signature.encode(writer)?;
+                n += ttl.encode(writer)?;
+                n += path.encode(writer)?;
             }
             Self::Ping(Ping { ponglen, zeroes }) => {
                 n += ponglen.encode(writer)?;
@@ -216,12 +242,34 @@ impl wire::Encode for Message {
             Self::Pong { zeroes } => {
                 n += zeroes.encode(writer)?;
             }
-            Self::Fetch { rid } => {
+            Self::Fetch { rid, oid } => {
                 n += rid.encode(writer)?;
+                if let Some(oid) = oid {
+                    n += oid.encode(writer)?;
+                }
             }
             Self::FetchOk { rid } => {
                 n += rid.encode(writer)?;
             }
+            Self::ConnectRequest { node, target, addr } => {
+                n += node.encode(writer)?;
+                n += target.encode(writer)?;
+                n += addr.encode(writer)?;
+            }
+            Self::Version { version, magic } => {
+                n += version.encode(writer)?;
+                n += magic.encode(writer)?;
+            }
+            Self::SeedRequest(rid) => {
+                n += rid.encode(writer)?;
+            }
+            Self::Digest(Digest {
+                since,
+                fingerprints,
+            }) => {
+                n += since.encode(writer)?;
+                n += fingerprints.encode(writer)?;
+            }
         }
 
         if n > wire::Size::MAX as usize {
@@ -253,36 +301,54 @@ impl wire::Decode for Message {
             Ok(MessageType::NodeAnnouncement) => {
                 let node = NodeId::decode(reader)?;
                 let message = NodeAnnouncement::decode(reader)?.into();
+                let scheme = SignatureScheme::from(u8::decode(reader)?);
                 let signature = Signature::decode(reader)?;
+                let ttl = u8::decode(reader)?;
+                let path = BoundedVec::decode(reader)?;
 
                 Ok(Announcement {
                     node,
                     message,
+                    scheme,
                     signature,
+                    ttl,
+                    path,
                 }
                 .into())
             }
             Ok(MessageType::InventoryAnnouncement) => {
                 let node = NodeId::decode(reader)?;
                 let message = InventoryAnnouncement::decode(reader)?.into();
+                let scheme = SignatureScheme::from(u8::decode(reader)?);
                 let signature = Signature::decode(reader)?;
+                let ttl = u8::decode(reader)?;
+                let path = BoundedVec::decode(reader)?;
 
                 Ok(Announcement {
                     node,
                     message,
+                    scheme,
                     signature,
+                    ttl,
+                    path,
                 }
                 .into())
             }
             Ok(MessageType::RefsAnnouncement) => {
                 let node = NodeId::decode(reader)?;
                 let message = RefsAnnouncement::decode(reader)?.into();
+                let scheme = SignatureScheme::from(u8::decode(reader)?);
                 let signature = Signature::decode(reader)?;
+                let ttl = u8::decode(reader)?;
+                let path = BoundedVec::decode(reader)?;
 
                 Ok(Announcement {
                     node,
                     message,
+                    scheme,
                     signature,
+                    ttl,
+                    path,
                 }
                 .into())
             }
@@ -297,17 +363,192 @@ impl wire::Decode for Message {
             }
             Ok(MessageType::Fetch) => {
                 let rid = Id::decode(reader)?;
-                Ok(Self::Fetch { rid })
+                Ok(Self::Fetch { rid, oid: None })
+            }
+            Ok(MessageType::FetchPinned) => {
+                let rid = Id::decode(reader)?;
+                let oid = git::Oid::decode(reader)?;
+                Ok(Self::Fetch {
+                    rid,
+                    oid: Some(oid),
+                })
             }
             Ok(MessageType::FetchOk) => {
                 let rid = Id::decode(reader)?;
                 Ok(Self::FetchOk { rid })
             }
+            Ok(MessageType::ConnectRequest) => {
+                let node = NodeId::decode(reader)?;
+                let target = NodeId::decode(reader)?;
+                let addr = Address::decode(reader)?;
+                Ok(Self::ConnectRequest { node, target, addr })
+            }
+            Ok(MessageType::Version) => {
+                let version = u32::decode(reader)?;
+                let magic = u8::decode(reader)?;
+                Ok(Self::Version { version, magic })
+            }
+            Ok(MessageType::SeedRequest) => {
+                let rid = Id::decode(reader)?;
+                Ok(Self::SeedRequest(rid))
+            }
+            Ok(MessageType::Digest) => {
+                let since = Timestamp::decode(reader)?;
+                let fingerprints = BoundedVec::decode(reader)?;
+
+                Ok(Self::Digest(Digest {
+                    since,
+                    fingerprints,
+                }))
+            }
             Err(other) => Err(wire::Error::UnknownMessageType(other)),
         }
     }
 }
 
+/// Progress made decoding a [`Message`], see [`wire::Resume`].
+///
+/// Only the messages carrying lists that can grow large keep their progress within a
+/// message. The others are small enough to be decoded in one go.
+#[derive(Debug, Default)]
+pub enum Partial {
+    /// Nothing was decoded yet.
+    #[default]
+    Start,
+    /// Inventory announcement.
+    Inventory {
+        node: Option<NodeId>,
+        inventory: wire::Items<Id, INVENTORY_LIMIT>,
+    },
+    /// Refs announcement.
+    Refs {
+        prefix: Option<(NodeId, Id)>,
+        refs: wire::Items<(NodeId, SignedRefs<Unverified>), REF_REMOTE_LIMIT>,
+    },
+    /// Announcements digest.
+    Digest {
+        since: Option<Timestamp>,
+        fingerprints: wire::Items<u64, DIGEST_LIMIT>,
+    },
+}
+
+/// Fields following the message of an announcement.
+struct Signed {
+    scheme: SignatureScheme,
+    signature: Signature,
+    ttl: u8,
+    path: BoundedVec<NodeId, PATH_LIMIT>,
+}
+
+impl wire::Decode for Signed {
+    fn decode<R: std::io::Read + ?Sized>(reader: &mut R) -> Result<Self, wire::Error> {
+        let scheme = SignatureScheme::from(u8::decode(reader)?);
+        let signature = Signature::decode(reader)?;
+        let ttl = u8::decode(reader)?;
+        let path = BoundedVec::decode(reader)?;
+
+        Ok(Self {
+            scheme,
+            signature,
+            ttl,
+            path,
+        })
+    }
+}
+
+impl wire::Resume for Message {
+    type State = Partial;
+
+    fn resume(state: &mut Partial, input: &mut wire::Input) -> Result<Self, wire::Error> {
+        if let Partial::Start = state {
+            *state = match MessageType::try_from(input.peek::<u16>()?) {
+                Ok(MessageType::InventoryAnnouncement) => Partial::Inventory {
+                    node: None,
+                    inventory: None,
+                },
+                Ok(MessageType::RefsAnnouncement) => Partial::Refs {
+                    prefix: None,
+                    refs: None,
+                },
+                Ok(MessageType::Digest) => Partial::Digest {
+                    since: None,
+                    fingerprints: None,
+                },
+                _ => return input.decode(),
+            };
+            // Consume the message type, which we know is there.
+            input.decode::<u16>()?;
+        }
+
+        let msg = match state {
+            Partial::Start => unreachable!("Message::resume: the message type is known"),
+            Partial::Inventory { node, inventory } => {
+                let node = match *node {
+                    Some(node) => node,
+                    None => *node.insert(input.decode()?),
+                };
+                let inventory = input.items(inventory)?;
+                let (timestamp, signed) = input.decode::<(Timestamp, Signed)>()?;
+
+                Announcement {
+                    node,
+                    message: InventoryAnnouncement {
+                        inventory: mem::take(inventory),
+                        timestamp,
+                    }
+                    .into(),
+                    scheme: signed.scheme,
+                    signature: signed.signature,
+                    ttl: signed.ttl,
+                    path: signed.path,
+                }
+                .into()
+            }
+            Partial::Refs { prefix, refs } => {
+                let (node, rid) = match *prefix {
+                    Some(prefix) => prefix,
+                    None => *prefix.insert(input.decode()?),
+                };
+                let refs = input.items(refs)?;
+                let (timestamp, signed) = input.decode::<(Timestamp, Signed)>()?;
+
+                Announcement {
+                    node,
+                    message: RefsAnnouncement {
+                        rid,
+                        refs: mem::take(refs),
+                        timestamp,
+                    }
+                    .into(),
+                    scheme: signed.scheme,
+                    signature: signed.signature,
+                    ttl: signed.ttl,
+                    path: signed.path,
+                }
+                .into()
+            }
+            Partial::Digest {
+                since,
+                fingerprints,
+            } => {
+                let since = match *since {
+                    Some(since) => since,
+                    None => *since.insert(input.decode()?),
+                };
+                let fingerprints = input.items(fingerprints)?;
+
+                Self::Digest(Digest {
+                    since,
+                    fingerprints: mem::take(fingerprints),
+                })
+            }
+        };
+        *state = Partial::Start;
+
+        Ok(msg)
+    }
+}
+
 impl wire::Encode for Address {
     fn encode<W: std::io::Write + ?Sized>(&self, writer: &mut W) -> Result<usize, std::io::Error> {
         let mut n = 0;
@@ -453,6 +694,30 @@ mod tests {
             .quickcheck(property as fn(items: Vec<Message>));
     }
 
+    #[test]
+    fn prop_message_decoder_partial() {
+        fn property(items: Vec<Message>, chunk_size: usize) {
+            let mut decoder = Deserializer::<Message>::new(8);
+            let mut decoded = Vec::new();
+            let bytes = items.iter().flat_map(wire::serialize).collect::<Vec<_>>();
+
+            // Feed the messages to the decoder in many partial writes.
+            for chunk in bytes.chunks(1 + chunk_size % 64) {
+                decoder.input(chunk);
+
+                for item in decoder.by_ref() {
+                    decoded.push(item.unwrap());
+                }
+            }
+            assert_eq!(decoded, items);
+            assert!(decoder.is_empty());
+        }
+
+        qcheck::QuickCheck::new()
+            .gen(qcheck::Gen::new(16))
+            .quickcheck(property as fn(items: Vec<Message>, chunk_size: usize));
+    }
+
     #[quickcheck]
     fn prop_zero_bytes_encode_decode(zeroes: ZeroBytes) {
         assert_eq!(
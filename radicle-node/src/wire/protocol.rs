@@ -15,24 +15,30 @@ use cyphernet::addr::{HostName, InetHost, NetAddr};
 use cyphernet::encrypt::noise::{HandshakePattern, Keyset, NoiseState};
 use cyphernet::proxy::socks5;
 use cyphernet::{Digest, EcSk, Ecdh, Sha256};
-use localtime::LocalTime;
+use localtime::{LocalDuration, LocalTime};
 use netservices::resource::{ListenerEvent, NetAccept, NetTransport, SessionEvent};
 use netservices::session::{ProtocolArtifact, Socks5Session};
 use netservices::{NetConnection, NetProtocol, NetReader, NetSession, NetWriter};
 
-use radicle::collections::HashMap;
-use radicle::node::NodeId;
+use radicle::collections::{HashMap, HashSet};
+use radicle::node::{Address, NodeId};
 use radicle::storage::WriteStorage;
 
 use crate::crypto::Signer;
+use crate::deserializer::Deserializer;
 use crate::service::reactor::{Fetch, Io};
-use crate::service::{routing, session, DisconnectReason, Message, Service};
+use crate::service::{routing, session, DisconnectReason, Message, Service, ServiceState as _};
 use crate::wire;
-use crate::wire::{Decode, Encode};
-use crate::worker::{Task, TaskResult};
+use crate::wire::limiter::AcceptLimiter;
+use crate::wire::Encode;
+use crate::worker::{FetchError, Task, TaskResult};
 use crate::Link;
 use crate::{address, service};
 
+/// Time given to the reactor to flush a peer's write buffer before a fetch upgrade is
+/// aborted.
+pub const UPGRADE_FLUSH_TIMEOUT: LocalDuration = LocalDuration::from_secs(9);
+
 /// NoiseXK handshake pattern.
 pub const NOISE_XK: HandshakePattern = HandshakePattern {
     initiator: cyphernet::encrypt::noise::InitiatorPattern::Xmitted,
@@ -46,6 +52,9 @@ pub enum Control<G: Signer + Ecdh> {
     User(service::Command),
     /// Message from a worker to the service.
     Worker(TaskResult<G>),
+    /// Request to wind down before shutting down. The sender is notified once
+    /// in-flight fetches have completed.
+    Shutdown(chan::Sender<()>),
 }
 
 impl<G: Signer + Ecdh> fmt::Debug for Control<G> {
@@ -53,6 +62,7 @@ impl<G: Signer + Ecdh> fmt::Debug for Control<G> {
         match self {
             Self::User(cmd) => cmd.fmt(f),
             Self::Worker(resp) => resp.result.fmt(f),
+            Self::Shutdown(_) => write!(f, "Shutdown"),
         }
     }
 }
@@ -70,15 +80,15 @@ type Action<G> = reactor::Action<NetAccept<WireSession<G>>, NetTransport<WireSes
 /// Peer connection state machine.
 enum Peer {
     /// The initial state of an inbound peer before handshake is completed.
-    Inbound {},
+    Inbound { addr: Address },
     /// The initial state of an outbound peer before handshake is completed.
-    Outbound { id: NodeId },
+    Outbound { id: NodeId, addr: Address },
     /// The state after handshake is completed.
     /// Peers in this state are handled by the underlying service.
     Connected {
         link: Link,
         id: NodeId,
-        inbox: VecDeque<u8>,
+        inbox: Deserializer<Message>,
     },
     /// The state after a peer was disconnected, either during handshake,
     /// or once connected.
@@ -92,7 +102,9 @@ enum Peer {
         fetch: Fetch,
         link: Link,
         id: NodeId,
-        inbox: VecDeque<u8>,
+        inbox: Deserializer<Message>,
+        /// Set when the handover was deferred, because the write buffer wasn't flushed.
+        deferred: Option<LocalTime>,
     },
     /// The peer is now upgraded and we are in control of the socket.
     Upgraded { link: Link, id: NodeId },
@@ -101,8 +113,8 @@ enum Peer {
 impl std::fmt::Debug for Peer {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Inbound {} => write!(f, "Inbound"),
-            Self::Outbound { id } => write!(f, "Outbound({id})"),
+            Self::Inbound { addr } => write!(f, "Inbound({addr})"),
+            Self::Outbound { id, addr } => write!(f, "Outbound({id}, {addr})"),
             Self::Connected { link, id, .. } => write!(f, "Connected({link:?}, {id})"),
             Self::Disconnected { .. } => write!(f, "Disconnected"),
             Self::Upgrading {
@@ -130,36 +142,38 @@ impl Peer {
     }
 
     /// Return a new inbound connecting peer.
-    fn inbound() -> Self {
-        Self::Inbound {}
+    fn inbound(addr: Address) -> Self {
+        Self::Inbound { addr }
     }
 
-    /// Return a new inbound connecting peer.
-    fn outbound(id: NodeId) -> Self {
-        Self::Outbound { id }
+    /// Return a new outbound connecting peer.
+    fn outbound(id: NodeId, addr: Address) -> Self {
+        Self::Outbound { id, addr }
     }
 
-    /// Switch to connected state.
-    fn connected(&mut self, id: NodeId) -> Link {
-        if let Self::Inbound {} = self {
+    /// Switch to connected state. Returns the link direction and the peer address.
+    fn connected(&mut self, id: NodeId) -> (Link, Address) {
+        if let Self::Inbound { addr } = self {
             let link = Link::Inbound;
+            let addr = addr.clone();
 
             *self = Self::Connected {
                 link,
                 id,
-                inbox: VecDeque::new(),
+                inbox: inbox(),
             };
-            link
-        } else if let Self::Outbound { id: expected } = self {
+            (link, addr)
+        } else if let Self::Outbound { id: expected, addr } = self {
             assert_eq!(id, *expected);
             let link = Link::Outbound;
+            let addr = addr.clone();
 
             *self = Self::Connected {
                 link,
                 id,
-                inbox: VecDeque::new(),
+                inbox: inbox(),
             };
-            link
+            (link, addr)
         } else {
             panic!("Peer::connected: session for {id} is already established");
         }
@@ -172,9 +186,14 @@ impl Peer {
                 id: Some(*id),
                 reason,
             };
-        } else if let Self::Inbound {} = self {
+        } else if let Self::Inbound { .. } = self {
             *self = Self::Disconnected { id: None, reason };
-        } else if let Self::Outbound { id } = self {
+        } else if let Self::Outbound { id, .. } = self {
+            *self = Self::Disconnected {
+                id: Some(*id),
+                reason,
+            };
+        } else if let Self::Upgrading { id, .. } | Self::Upgraded { id, .. } = self {
             *self = Self::Disconnected {
                 id: Some(*id),
                 reason,
@@ -191,7 +210,8 @@ impl Peer {
                 fetch,
                 id: *id,
                 link: *link,
-                inbox: inbox.clone(),
+                inbox: std::mem::take(inbox),
+                deferred: None,
             };
         } else {
             panic!("Peer::upgrading: session is not fully connected");
@@ -206,10 +226,11 @@ impl Peer {
             id,
             link,
             inbox,
+            ..
         } = self
         {
             let fetch = fetch.clone();
-            let inbox = inbox.drain(..).collect();
+            let inbox = inbox.unparsed();
             log::debug!(target: "wire", "Peer {id} upgraded for fetch {}", fetch.rid);
 
             *self = Self::Upgraded {
@@ -223,12 +244,15 @@ impl Peer {
     }
 
     /// Switch back from upgraded to connected state.
+    ///
+    /// The peer starts with an empty inbox, since any data buffered before the upgrade was
+    /// handed over to the fetch. This ensures the gossip decoder starts from a message boundary.
     fn downgrade(&mut self) {
         if let Self::Upgraded { id, link, .. } = self {
             *self = Self::Connected {
                 id: *id,
                 link: *link,
-                inbox: VecDeque::new(),
+                inbox: inbox(),
             };
         } else {
             panic!("Peer::downgrade: can't downgrade if not in upgraded state");
@@ -236,6 +260,12 @@ impl Peer {
     }
 }
 
+/// Create the inbox of a connected peer. Messages can't be larger than what can be
+/// encoded, so larger ones are rejected early.
+fn inbox() -> Deserializer<Message> {
+    Deserializer::default().with_limit(wire::Size::MAX as usize)
+}
+
 /// Wire protocol implementation for a set of peers.
 pub struct Wire<R, S, W, G: Signer + Ecdh> {
     /// Backing service instance.
@@ -248,8 +278,20 @@ pub struct Wire<R, S, W, G: Signer + Ecdh> {
     actions: VecDeque<Action<G>>,
     /// Peer sessions.
     peers: HashMap<RawFd, Peer>,
+    /// Fetches waiting for a peer session to be established, or for an ongoing fetch
+    /// with the same peer to complete.
+    fetches: HashMap<NodeId, VecDeque<Fetch>>,
     /// SOCKS5 proxy address.
     proxy: net::SocketAddr,
+    /// Addresses of the listeners we registered with the reactor.
+    listeners: Vec<net::SocketAddr>,
+    /// Set when winding down. Notified once in-flight fetches have completed.
+    shutdown: Option<chan::Sender<()>>,
+    /// Limits the rate of inbound connections.
+    limiter: AcceptLimiter,
+    /// Upgrading peers whose transport was handed back to the reactor to flush its write
+    /// buffer. The handover is requested again on the next tick.
+    deferred: HashSet<RawFd>,
 }
 
 impl<R, S, W, G> Wire<R, S, W, G>
@@ -269,6 +311,7 @@ where
         service
             .initialize(clock)
             .expect("Wire::new: error initializing service");
+        let limiter = AcceptLimiter::new(service.config().limits.accept.clone(), clock);
 
         Self {
             service,
@@ -277,13 +320,67 @@ where
             proxy,
             actions: VecDeque::new(),
             peers: HashMap::default(),
+            fetches: HashMap::default(),
+            listeners: Vec::new(),
+            shutdown: None,
+            limiter,
+            deferred: HashSet::default(),
         }
     }
 
+    /// Check an inbound connection against the accept rate limits.
+    fn accept_allowed(&mut self, addr: net::SocketAddr) -> bool {
+        if self.limiter.accept(addr.ip(), self.service.local_time()) {
+            return true;
+        }
+        log::warn!(target: "wire", "Dropping inbound peer connection from {addr}: accept rate limit exceeded");
+
+        false
+    }
+
     pub fn listen(&mut self, socket: NetAccept<WireSession<G>>) {
+        self.listeners.push(socket.local_addr());
         self.actions.push_back(Action::RegisterListener(socket));
     }
 
+    /// Wind down before shutting down: stop accepting connections and let ongoing
+    /// fetches complete, so that they don't get interrupted mid-write. Queued fetches
+    /// are aborted. The given sender is notified once we're done.
+    fn wind_down(&mut self, done: chan::Sender<()>) {
+        log::info!(target: "wire", "Winding down..");
+
+        for addr in self.listeners.drain(..) {
+            self.actions.push_back(Action::UnregisterListener(addr));
+        }
+        for fetch in self.fetches.drain().flat_map(|(_, fetches)| fetches) {
+            let err = io::Error::new(io::ErrorKind::Interrupted, "node is shutting down");
+            self.service.fetch_aborted(fetch, FetchError::Io(err));
+        }
+        self.service.shutdown();
+        self.shutdown = Some(done);
+        self.drained();
+    }
+
+    /// Notify the shutdown requester if there are no more ongoing fetches.
+    fn drained(&mut self) {
+        if self.shutdown.is_none() {
+            return;
+        }
+        let fetching = self
+            .peers
+            .values()
+            .filter(|p| matches!(p, Peer::Upgrading { .. } | Peer::Upgraded { .. }))
+            .count();
+
+        if fetching > 0 {
+            log::debug!(target: "wire", "Waiting for {fetching} fetch(es) to complete..");
+            return;
+        }
+        if let Some(done) = self.shutdown.take() {
+            done.send(()).ok();
+        }
+    }
+
     fn peer_mut_by_fd(&mut self, fd: RawFd) -> &mut Peer {
         self.peers.get_mut(&fd).unwrap_or_else(|| {
             log::error!(target: "wire", "Peer with fd {fd} was not found");
@@ -299,6 +396,33 @@ where
             .unwrap_or_else(|| panic!("Peer {node_id} was expected to be known to the transport"))
     }
 
+    /// Encode the given messages and queue them to be sent to a connected peer.
+    fn write(&mut self, node_id: NodeId, msgs: Vec<Message>) {
+        // Nothing to send. We don't look the peer up, since it may no longer be known
+        // to the transport by the time the service writes to it.
+        if msgs.is_empty() {
+            return;
+        }
+        let fd = match self.fd_by_id(&node_id) {
+            (fd, Peer::Connected { .. }) => fd,
+            (_, peer) => {
+                // If the peer is disconnected by the wire protocol, the service may
+                // not be aware of this yet, and may continue to write messages to it.
+                log::debug!(target: "wire", "Dropping {} message(s) to {node_id} ({peer:?})", msgs.len());
+                return;
+            }
+        };
+        log::trace!(
+            target: "wire", "Writing {} message(s) to {}", msgs.len(), node_id
+        );
+
+        let mut data = Vec::new();
+        for msg in msgs {
+            msg.encode(&mut data).expect("in-memory writes never fail");
+        }
+        self.actions.push_back(reactor::Action::Send(fd, data));
+    }
+
     fn connected_fd_by_id(&self, node_id: &NodeId) -> RawFd {
         match self.fd_by_id(node_id) {
             (fd, Peer::Connected { .. }) => fd,
@@ -312,8 +436,8 @@ where
 
     fn active(&self) -> impl Iterator<Item = (RawFd, &NodeId)> {
         self.peers.iter().filter_map(|(fd, peer)| match peer {
-            Peer::Inbound {} => None,
-            Peer::Outbound { id } => Some((*fd, id)),
+            Peer::Inbound { .. } => None,
+            Peer::Outbound { id, .. } => Some((*fd, id)),
             Peer::Connected { id, .. } => Some((*fd, id)),
             Peer::Upgrading { id, .. } => Some((*fd, id)),
             Peer::Upgraded { id, .. } => Some((*fd, id)),
@@ -338,9 +462,206 @@ where
             return;
         };
         log::debug!(target: "wire", "Disconnecting peer (fd={fd}): {reason}");
+
+        // A fetch waiting for its upgrade won't happen.
+        let fetch = match peer {
+            Peer::Upgrading { fetch, .. } => Some(fetch.clone()),
+            _ => None,
+        };
+        // The transport of an upgraded peer is with the worker, and is only handed back to
+        // us once the fetch completes.
+        let upgraded = matches!(peer, Peer::Upgraded { .. });
+        let err = io::Error::new(io::ErrorKind::ConnectionAborted, reason.to_string());
         peer.disconnected(reason);
 
-        self.actions.push_back(Action::UnregisterTransport(fd));
+        if let Some(fetch) = fetch {
+            self.service.fetched(fetch, Err(FetchError::Io(err)));
+        }
+        if !upgraded {
+            self.actions.push_back(Action::UnregisterTransport(fd));
+        }
+    }
+
+    /// Handle data received from a peer. Complete messages are passed on to the service.
+    /// Peers whose undecoded data exceeds the configured inbox limit are disconnected.
+    fn received(&mut self, fd: RawFd, data: &[u8]) {
+        let limit = self.service.config().limits.max_inbox_size;
+
+        if let Some(Peer::Connected { id, inbox, .. }) = self.peers.get_mut(&fd) {
+            inbox.input(data);
+
+            loop {
+                match inbox.deserialize_next() {
+                    Ok(Some(msg)) => self.service.received_message(*id, msg),
+                    Ok(None) => {
+                        // Buffer is empty, or message isn't complete.
+                        if inbox.len() > limit {
+                            log::error!(target: "wire", "Inbox of {id} exceeds {limit} bytes");
+                            self.disconnect(
+                                fd,
+                                DisconnectReason::Session(session::Error::Misbehavior),
+                            );
+                        }
+                        break;
+                    }
+                    Err(e) => {
+                        log::error!(target: "wire", "Invalid message from {id}: {e}");
+
+                        let mut leftover = if let wire::Error::UnknownMessageType(ty) = e {
+                            ty.to_ne_bytes().to_vec()
+                        } else {
+                            vec![]
+                        };
+                        leftover.extend(inbox.unparsed());
+
+                        if !leftover.is_empty() {
+                            log::debug!(target: "wire", "Dropping read buffer with `{:?}`", &leftover);
+                        }
+                        self.disconnect(
+                            fd,
+                            // TODO(cloudhead): Include error in reason.
+                            DisconnectReason::Session(session::Error::Misbehavior),
+                        );
+                        break;
+                    }
+                }
+            }
+        } else if let Some(Peer::Upgrading { inbox, .. }) = self.peers.get_mut(&fd) {
+            // If somehow the remote peer managed to send git data before the reactor
+            // unregistered our session, we'll hit this branch.
+            inbox.input(data);
+        } else {
+            log::warn!(target: "wire", "Dropping message from unconnected peer (fd={fd})");
+        }
+    }
+
+    /// Handle a session terminated by the transport. If the session was terminated during
+    /// the handshake because of invalid data from the remote, eg. a protocol version
+    /// mismatch or a cryptographic failure, the reason is reported as a handshake error.
+    fn terminated(&mut self, fd: RawFd, err: io::Error) {
+        let handshake = matches!(
+            self.peers.get(&fd),
+            Some(Peer::Inbound { .. } | Peer::Outbound { .. })
+        ) && matches!(
+            err.kind(),
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput
+        );
+        let reason = if handshake {
+            DisconnectReason::Session(session::Error::Handshake(err.to_string()))
+        } else {
+            DisconnectReason::Connection(Arc::new(err))
+        };
+        self.disconnect(fd, reason);
+    }
+
+    /// Connect to a peer. Returns `false` if the connection could not be initiated.
+    fn connect(&mut self, node_id: NodeId, addr: Address) -> bool {
+        match dial::<G>(
+            addr.to_inner(),
+            node_id,
+            self.signer.clone(),
+            self.proxy.into(),
+            false,
+        )
+        .and_then(|session| NetTransport::<WireSession<G>>::with_session(session, Link::Outbound))
+        {
+            Ok(transport) => {
+                self.service.attempted(node_id, &addr);
+                // TODO: Keep track of peer address for when peer disconnects before
+                // handshake is complete.
+                self.peers
+                    .insert(transport.as_raw_fd(), Peer::outbound(node_id, addr));
+
+                self.actions
+                    .push_back(reactor::Action::RegisterTransport(transport));
+
+                true
+            }
+            Err(err) => {
+                log::error!(target: "wire", "Error establishing connection: {err}");
+
+                self.service
+                    .disconnected(node_id, &DisconnectReason::Dial(Arc::new(err)));
+
+                false
+            }
+        }
+    }
+
+    /// Fetch from a peer. If the peer isn't connected, the fetch is queued, and a
+    /// connection is initiated if necessary. The fetch fails if no address is known
+    /// for the peer.
+    fn fetch(&mut self, fetch: Fetch) {
+        let remote = fetch.remote;
+        let peer = self
+            .peers
+            .iter()
+            .filter(|(_, peer)| !matches!(peer, Peer::Disconnected { .. }))
+            .find(|(_, peer)| peer.id() == Some(&remote))
+            .map(|(fd, peer)| (*fd, peer));
+
+        match peer {
+            Some((fd, Peer::Connected { .. })) => {
+                self.upgrade(fd, fetch);
+            }
+            Some((_, Peer::Outbound { .. } | Peer::Upgrading { .. } | Peer::Upgraded { .. })) => {
+                log::debug!(target: "wire", "Queueing fetch of {} from {remote}..", fetch.rid);
+
+                self.fetches.entry(remote).or_default().push_back(fetch);
+            }
+            Some((_, Peer::Inbound { .. } | Peer::Disconnected { .. })) | None => {
+                let addr = self
+                    .service
+                    .addresses()
+                    .get(&remote)
+                    .ok()
+                    .flatten()
+                    .and_then(|node| node.addrs.into_iter().next());
+
+                let Some(known) = addr else {
+                    let err = io::Error::new(io::ErrorKind::NotFound, "no known address");
+                    self.service.fetch_aborted(fetch, FetchError::Io(err));
+
+                    return;
+                };
+                log::debug!(target: "wire", "Connecting to {remote} to fetch {}..", fetch.rid);
+
+                if self.connect(remote, known.addr) {
+                    self.fetches.entry(remote).or_default().push_back(fetch);
+                } else {
+                    let err = io::Error::new(io::ErrorKind::ConnectionRefused, "failed to connect");
+                    self.service.fetch_aborted(fetch, FetchError::Io(err));
+                }
+            }
+        }
+    }
+
+    /// Start the next queued fetch for a peer, if any. The peer must be connected.
+    fn fetch_queued(&mut self, fd: RawFd, id: NodeId) {
+        let Some(queue) = self.fetches.get_mut(&id) else {
+            return;
+        };
+        let fetch = queue.pop_front();
+
+        if queue.is_empty() {
+            self.fetches.remove(&id);
+        }
+        if let Some(fetch) = fetch {
+            self.upgrade(fd, fetch);
+        }
+    }
+
+    /// Abort the fetches queued for a peer, so that their requesters don't wait on them.
+    fn abort_queued(&mut self, id: &NodeId, reason: &str) {
+        let Some(fetches) = self.fetches.remove(id) else {
+            return;
+        };
+        log::debug!(target: "wire", "Aborting {} queued fetch(es) for {id}", fetches.len());
+
+        for fetch in fetches {
+            let err = io::Error::new(io::ErrorKind::ConnectionAborted, reason);
+            self.service.fetch_aborted(fetch, FetchError::Io(err));
+        }
     }
 
     fn upgrade(&mut self, fd: RawFd, fetch: Fetch) {
@@ -357,12 +678,17 @@ where
 
     fn upgraded(&mut self, transport: NetTransport<WireSession<G>>) {
         let fd = transport.as_raw_fd();
-        let peer = self.peer_mut_by_fd(fd);
-        let (fetch, drain) = peer.upgraded();
         let session = match transport.into_session() {
             Ok(session) => session,
-            Err(_) => panic!("Transport::upgraded: peer write buffer not empty on upgrade"),
+            Err(transport) => {
+                // Data we sent to the peer wasn't flushed yet, so the session can't be
+                // handed over without losing it.
+                self.defer_upgrade(fd, transport);
+                return;
+            }
         };
+        let peer = self.peer_mut_by_fd(fd);
+        let (fetch, drain) = peer.upgraded();
 
         if self
             .worker
@@ -377,6 +703,67 @@ where
         }
     }
 
+    /// Hand the transport of an upgrading peer back to the reactor, so that it can flush the
+    /// write buffer, and request the handover again on the next tick. If the buffer can't be
+    /// flushed in time, the upgrade is aborted.
+    fn defer_upgrade(&mut self, fd: RawFd, transport: NetTransport<WireSession<G>>) {
+        let now = self.service.local_time();
+        let Some(Peer::Upgrading { id, deferred, .. }) = self.peers.get_mut(&fd) else {
+            log::error!(target: "wire", "Peer (fd={fd}) is not upgrading");
+            return;
+        };
+        let since = *deferred.get_or_insert(now);
+
+        if now - since >= UPGRADE_FLUSH_TIMEOUT {
+            // Dropping the transport closes the connection.
+            drop(transport);
+
+            self.upgrade_failed(
+                fd,
+                io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "write buffer not flushed on upgrade",
+                ),
+            );
+            return;
+        }
+        log::debug!(target: "wire", "Deferring upgrade of {id} (fd={fd}) until its write buffer is flushed");
+
+        self.deferred.insert(fd);
+        self.actions.push_back(Action::RegisterTransport(transport));
+    }
+
+    /// Request the handover of transports whose upgrade was deferred.
+    fn retry_upgrades(&mut self) {
+        for fd in std::mem::take(&mut self.deferred) {
+            // The peer may have been disconnected in the meantime.
+            if let Some(Peer::Upgrading { .. }) = self.peers.get(&fd) {
+                self.actions.push_back(Action::UnregisterTransport(fd));
+            }
+        }
+    }
+
+    /// Abort the fetch of a peer whose session couldn't be upgraded, and disconnect it.
+    fn upgrade_failed(&mut self, fd: RawFd, err: io::Error) {
+        let peer = self.peer_mut_by_fd(fd);
+        let Peer::Upgrading { fetch, id, .. } = &*peer else {
+            log::error!(target: "wire", "Peer (fd={fd}) is not upgrading");
+            return;
+        };
+        let (fetch, id) = (fetch.clone(), *id);
+        log::error!(target: "wire", "Session upgrade failed for {id}: {err}");
+
+        let fetch_err = FetchError::Io(io::Error::new(err.kind(), err.to_string()));
+        let err = Arc::new(err);
+
+        peer.disconnected(DisconnectReason::Connection(err.clone()));
+        self.abort_queued(&id, "peer disconnected");
+        self.service.fetched(fetch, Err(fetch_err));
+        self.service
+            .disconnected(id, &DisconnectReason::Connection(err));
+        self.drained();
+    }
+
     fn worker_result(&mut self, task: TaskResult<G>) {
         log::debug!(target: "wire", "Fetch completed: {:?}", task.result);
 
@@ -386,22 +773,65 @@ where
 
         let session = if let Peer::Disconnected { .. } = peer {
             log::error!(target: "wire", "Peer with fd {fd} is already disconnected");
+
+            let err = io::Error::new(io::ErrorKind::ConnectionAborted, "peer disconnected");
+            self.service.fetch_aborted(task.fetch, FetchError::Io(err));
+
+            // The transport was with the worker when the peer was disconnected, so it wasn't
+            // handed back to us, and the service wasn't told. Dropping the session closes it.
+            if let Some(Peer::Disconnected {
+                id: Some(id),
+                reason,
+            }) = self.peers.remove(&fd)
+            {
+                self.service.disconnected(id, &reason);
+            }
+            self.drained();
             return;
-        } else if let Peer::Upgraded { link, .. } = peer {
+        } else if let Peer::Upgraded { link, id } = peer {
+            let id = *id;
+
             match NetTransport::with_session(session, *link) {
                 Ok(session) => session,
                 Err(err) => {
                     log::error!(target: "wire", "Session downgrade failed: {err}");
+
+                    // The connection is lost along with the session.
+                    let err: Arc<dyn std::error::Error + Sync + Send> =
+                        Arc::new(io::Error::new(io::ErrorKind::Other, err.to_string()));
+                    peer.disconnected(DisconnectReason::Connection(err.clone()));
+                    self.abort_queued(&id, "peer disconnected");
+                    self.service.fetch_aborted(
+                        task.fetch,
+                        FetchError::Io(io::Error::new(
+                            io::ErrorKind::ConnectionAborted,
+                            err.to_string(),
+                        )),
+                    );
+                    self.service
+                        .disconnected(id, &DisconnectReason::Connection(err));
+                    self.drained();
                     return;
                 }
             }
         } else {
             todo!();
         };
+        let id = task.fetch.remote;
+        let desync = matches!(&task.result, Err(e) if e.is_desync());
         peer.downgrade();
 
         self.actions.push_back(Action::RegisterTransport(session));
         self.service.fetched(task.fetch, task.result);
+
+        if desync {
+            // The gossip decoder can't recover from a desync, so we disconnect the peer. The
+            // transport is registered first, so that it is handed back to us once unregistered.
+            self.disconnect(fd, DisconnectReason::Session(session::Error::Misbehavior));
+        } else {
+            self.fetch_queued(fd, id);
+        }
+        self.drained();
     }
 }
 
@@ -419,6 +849,7 @@ where
     fn tick(&mut self, _time: Duration) {
         // FIXME: Change this once a proper timestamp is passed into the function.
         self.service.tick(LocalTime::from(SystemTime::now()));
+        self.retry_upgrades();
     }
 
     fn handle_timer(&mut self) {
@@ -427,18 +858,42 @@ where
 
     fn handle_listener_event(
         &mut self,
-        socket_addr: net::SocketAddr,
+        _: net::SocketAddr,
         event: ListenerEvent<WireSession<G>>,
         _: Duration,
     ) {
         match event {
             ListenerEvent::Accepted(connection) => {
+                let remote_addr = connection.remote_addr();
+
+                // Dropping the connection closes it, before any handshake takes place.
+                if !self.accept_allowed(remote_addr) {
+                    return;
+                }
                 log::debug!(
                     target: "wire",
                     "Accepting inbound peer connection from {}..",
-                    connection.remote_addr()
+                    remote_addr
                 );
-                self.peers.insert(connection.as_raw_fd(), Peer::inbound());
+                // Inbound peers that are still handshaking don't have a session yet.
+                let pending = self
+                    .peers
+                    .values()
+                    .filter_map(|peer| match peer {
+                        Peer::Inbound { addr } => match &addr.host {
+                            HostName::Ip(ip) => Some(*ip),
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+
+                if !self.service.accepted(remote_addr, &pending) {
+                    log::debug!(target: "wire", "Dropping inbound peer connection from {remote_addr}");
+                    return;
+                }
+                self.peers
+                    .insert(connection.as_raw_fd(), Peer::inbound(remote_addr.into()));
 
                 let session = accept::<G>(connection, self.signer.clone());
                 let transport = match NetTransport::with_session(session, Link::Inbound) {
@@ -448,12 +903,18 @@ where
                         return;
                     }
                 };
-                self.service.accepted(socket_addr);
                 self.actions
                     .push_back(reactor::Action::RegisterTransport(transport))
             }
             ListenerEvent::Failure(err) => {
                 log::error!(target: "wire", "Error listening for inbound connections: {err}");
+
+                // If we've run out of file descriptors, make room by shedding a
+                // quarter of our connections.
+                if matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) {
+                    let connected = self.service.sessions().connected().count();
+                    self.service.shed_connections(connected - connected / 4);
+                }
             }
         }
     }
@@ -493,53 +954,16 @@ where
                     log::error!(target: "wire", "Session not found for fd {fd}");
                     return;
                 };
-                let link = peer.connected(id);
+                let (link, addr) = peer.connected(id);
 
-                self.service.connected(id, link);
+                self.service.connected(id, addr, link);
+                self.fetch_queued(fd, id);
             }
             SessionEvent::Data(data) => {
-                if let Some(Peer::Connected { id, inbox, .. }) = self.peers.get_mut(&fd) {
-                    inbox.extend(data);
-
-                    loop {
-                        match Message::decode(inbox) {
-                            Ok(msg) => self.service.received_message(*id, msg),
-                            Err(err) if err.is_eof() => {
-                                // Buffer is empty, or message isn't complete.
-                                break;
-                            }
-                            Err(e) => {
-                                log::error!(target: "wire", "Invalid message from {id}: {e}");
-
-                                let mut leftover = if let wire::Error::UnknownMessageType(ty) = e {
-                                    ty.to_ne_bytes().to_vec()
-                                } else {
-                                    vec![]
-                                };
-                                leftover.extend(inbox.drain(..));
-
-                                if !leftover.is_empty() {
-                                    log::debug!(target: "wire", "Dropping read buffer with `{:?}`", &leftover);
-                                }
-                                self.disconnect(
-                                    fd,
-                                    // TODO(cloudhead): Include error in reason.
-                                    DisconnectReason::Session(session::Error::Misbehavior),
-                                );
-                                break;
-                            }
-                        }
-                    }
-                } else if let Some(Peer::Upgrading { inbox, .. }) = self.peers.get_mut(&fd) {
-                    // If somehow the remote peer managed to send git data before the reactor
-                    // unregistered our session, we'll hit this branch.
-                    inbox.extend(data);
-                } else {
-                    log::warn!(target: "wire", "Dropping message from unconnected peer (fd={fd})");
-                }
+                self.received(fd, &data);
             }
             SessionEvent::Terminated(err) => {
-                self.disconnect(fd, DisconnectReason::Connection(Arc::new(err)));
+                self.terminated(fd, err);
             }
         }
     }
@@ -548,6 +972,7 @@ where
         match cmd {
             Control::User(cmd) => self.service.command(cmd),
             Control::Worker(result) => self.worker_result(result),
+            Control::Shutdown(done) => self.wind_down(done),
         }
     }
 
@@ -609,6 +1034,12 @@ where
                 drop(transport);
 
                 if let Some(id) = id {
+                    // Nb. The peer is still borrowed, so we can't use `abort_queued`.
+                    for fetch in self.fetches.remove(id).into_iter().flatten() {
+                        let err =
+                            io::Error::new(io::ErrorKind::ConnectionAborted, "peer disconnected");
+                        self.service.fetch_aborted(fetch, FetchError::Io(err));
+                    }
                     self.service.disconnected(*id, reason);
                 } else {
                     // TODO: Handle this case by calling `disconnected` with the address instead of
@@ -641,24 +1072,7 @@ where
         while let Some(ev) = self.service.next() {
             match ev {
                 Io::Write(node_id, msgs) => {
-                    let fd = match self.fd_by_id(&node_id) {
-                        (fd, Peer::Connected { .. }) => fd,
-                        (_, peer) => {
-                            // If the peer is disconnected by the wire protocol, the service may
-                            // not be aware of this yet, and may continue to write messages to it.
-                            log::debug!(target: "wire", "Dropping {} message(s) to {node_id} ({peer:?})", msgs.len());
-                            continue;
-                        }
-                    };
-                    log::trace!(
-                        target: "wire", "Writing {} message(s) to {}", msgs.len(), node_id
-                    );
-
-                    let mut data = Vec::new();
-                    for msg in msgs {
-                        msg.encode(&mut data).expect("in-memory writes never fail");
-                    }
-                    self.actions.push_back(reactor::Action::Send(fd, data));
+                    self.write(node_id, msgs);
                 }
                 Io::Event(_e) => {
                     log::warn!(
@@ -666,6 +1080,10 @@ where
                     );
                 }
                 Io::Connect(node_id, addr) => {
+                    if self.shutdown.is_some() {
+                        log::debug!(target: "wire", "Not connecting to {node_id}: winding down");
+                        continue;
+                    }
                     if self.connected().any(|(_, id)| id == &node_id) {
                         log::error!(
                             target: "wire",
@@ -674,33 +1092,8 @@ where
                         break;
                     }
 
-                    match dial::<G>(
-                        addr.to_inner(),
-                        node_id,
-                        self.signer.clone(),
-                        self.proxy.into(),
-                        false,
-                    )
-                    .and_then(|session| {
-                        NetTransport::<WireSession<G>>::with_session(session, Link::Outbound)
-                    }) {
-                        Ok(transport) => {
-                            self.service.attempted(node_id, &addr);
-                            // TODO: Keep track of peer address for when peer disconnects before
-                            // handshake is complete.
-                            self.peers
-                                .insert(transport.as_raw_fd(), Peer::outbound(node_id));
-
-                            self.actions
-                                .push_back(reactor::Action::RegisterTransport(transport));
-                        }
-                        Err(err) => {
-                            log::error!(target: "wire", "Error establishing connection: {err}");
-
-                            self.service
-                                .disconnected(node_id, &DisconnectReason::Dial(Arc::new(err)));
-                            break;
-                        }
+                    if !self.connect(node_id, addr) {
+                        break;
                     }
                 }
                 Io::Disconnect(node_id, reason) => {
@@ -711,9 +1104,11 @@ where
                     self.actions.push_back(reactor::Action::SetTimer(d.into()));
                 }
                 Io::Fetch(fetch) => {
-                    // TODO: Check that the node_id is connected, queue request otherwise.
-                    let fd = self.connected_fd_by_id(&fetch.remote);
-                    self.upgrade(fd, fetch);
+                    if self.shutdown.is_some() {
+                        log::debug!(target: "wire", "Dropping fetch of {}: winding down", fetch.rid);
+                        continue;
+                    }
+                    self.fetch(fetch);
                 }
             }
         }
@@ -783,3 +1178,556 @@ fn session<G: Signer + Ecdh<Pk = NodeId>>(
     );
     WireSession::with(proxy, noise)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prelude::Id;
+    use crate::service::config::{AcceptLimits, Limits, RateLimit};
+    use crate::service::message::{Ping, ZeroBytes};
+    use crate::storage::Namespaces;
+    use crate::test::arbitrary;
+    use crate::test::peer;
+    use crate::test::storage::MockStorage;
+
+    #[test]
+    fn test_downgrade_resets_inbox() {
+        let id = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([9, 9, 9, 9], 8776)));
+        let mut peer = Peer::inbound(addr);
+        let trailing = vec![0x00, 0x2a, 0xde, 0xad];
+
+        peer.connected(id);
+        if let Peer::Connected { inbox, .. } = &mut peer {
+            inbox.input(&trailing);
+        }
+        peer.upgrading(Fetch {
+            rid: arbitrary::gen::<Id>(1),
+            namespaces: Namespaces::All,
+            remote: id,
+            initiated: false,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        });
+        let (_, drain) = peer.upgraded();
+        assert_eq!(
+            drain, trailing,
+            "buffered bytes are handed over to the fetch"
+        );
+
+        peer.downgrade();
+
+        let Peer::Connected { inbox, .. } = &mut peer else {
+            panic!("peer is connected after downgrade");
+        };
+        assert!(inbox.unparsed().is_empty());
+
+        let msg = Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(42),
+        });
+        let mut bytes = Vec::new();
+        msg.encode(&mut bytes).unwrap();
+        inbox.input(&bytes);
+
+        assert_eq!(inbox.deserialize_next().unwrap(), Some(msg));
+        assert!(inbox.unparsed().is_empty());
+    }
+
+    #[test]
+    fn test_desync_after_upload() {
+        let mut alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let bob = peer::Peer::new("bob", [8, 8, 8, 8]);
+        let signer = alice.signer().clone();
+
+        alice.connect_from(&bob);
+
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(
+            alice.service,
+            worker,
+            signer.clone(),
+            proxy,
+            LocalTime::now(),
+        );
+        let listener = net::TcpListener::bind(net::SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let stream = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let session = accept(stream, signer);
+        let fd = session.as_connection().as_raw_fd();
+        let fetch = Fetch {
+            rid: arbitrary::gen::<Id>(1),
+            namespaces: Namespaces::All,
+            remote: bob.id,
+            initiated: false,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        };
+
+        wire.peers.insert(fd, Peer::inbound(bob.address()));
+        wire.peer_mut_by_fd(fd).connected(bob.id);
+        wire.peer_mut_by_fd(fd).upgrading(fetch.clone());
+        wire.peer_mut_by_fd(fd).upgraded();
+        wire.worker_result(TaskResult {
+            fetch,
+            result: Err(FetchError::Desync(4)),
+            session,
+        });
+
+        assert!(matches!(
+            wire.peers.get(&fd),
+            Some(Peer::Disconnected {
+                reason: DisconnectReason::Session(session::Error::Misbehavior),
+                ..
+            })
+        ));
+        assert!(matches!(
+            wire.actions.back(),
+            Some(Action::UnregisterTransport(f)) if *f == fd
+        ));
+    }
+
+    #[test]
+    fn test_empty_write_is_noop() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+
+        // Bob is not known to the transport, eg. because he just disconnected.
+        let bob = arbitrary::gen::<NodeId>(1);
+        wire.write(bob, Vec::new());
+
+        assert!(wire.actions.is_empty());
+    }
+
+    #[test]
+    fn test_handshake_failure_reason() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+        let bob = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([8, 8, 8, 8], 8776)));
+
+        wire.peers.insert(1, Peer::outbound(bob, addr.clone()));
+        wire.terminated(
+            1,
+            io::Error::new(io::ErrorKind::InvalidData, "noise: decryption failed"),
+        );
+        assert!(matches!(
+            wire.peers.get(&1),
+            Some(Peer::Disconnected {
+                reason: DisconnectReason::Session(session::Error::Handshake(msg)),
+                ..
+            }) if msg.contains("decryption failed")
+        ));
+
+        // Connection errors during the handshake are not handshake failures. Neither are
+        // uncategorized errors, eg. from a proxy, since those may well be transient.
+        for (fd, err) in [
+            (2, io::Error::from(io::ErrorKind::ConnectionReset)),
+            (
+                3,
+                io::Error::new(io::ErrorKind::Other, "socks5: general failure"),
+            ),
+        ] {
+            wire.peers.insert(fd, Peer::outbound(bob, addr.clone()));
+            wire.terminated(fd, err);
+            assert!(matches!(
+                wire.peers.get(&fd),
+                Some(Peer::Disconnected {
+                    reason: DisconnectReason::Connection(_),
+                    ..
+                })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_fetch_queued_until_established() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+
+        // Bob's session is still being established.
+        let bob = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([8, 8, 8, 8], 8776)));
+        let fd = 42;
+        wire.peers.insert(fd, Peer::outbound(bob, addr));
+
+        wire.fetch(Fetch {
+            rid: arbitrary::gen::<Id>(1),
+            namespaces: Namespaces::All,
+            remote: bob,
+            initiated: true,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        });
+        assert!(wire.actions.is_empty(), "The fetch is queued");
+        assert_eq!(wire.fetches.get(&bob).map(|q| q.len()), Some(1));
+
+        // The handshake completes.
+        wire.peer_mut_by_fd(fd).connected(bob);
+        wire.fetch_queued(fd, bob);
+
+        assert!(wire.fetches.is_empty());
+        assert!(matches!(wire.peers.get(&fd), Some(Peer::Upgrading { .. })));
+        assert!(matches!(
+            wire.actions.pop_front(),
+            Some(Action::UnregisterTransport(f)) if f == fd
+        ));
+    }
+
+    #[test]
+    fn test_fetch_queued_aborted() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+
+        let bob = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([8, 8, 8, 8], 8776)));
+        let fd = 42;
+        let fetch = |rid| Fetch {
+            rid,
+            namespaces: Namespaces::All,
+            remote: bob,
+            initiated: true,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        };
+        wire.peers.insert(fd, Peer::outbound(bob, addr));
+        wire.peer_mut_by_fd(fd).connected(bob);
+
+        // A fetch from Bob is ongoing, and another one is queued behind it.
+        let (rid1, rid2) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+        let (send, recv) = chan::bounded(1);
+
+        wire.service
+            .command(service::Command::Fetch(rid2, bob, send));
+        wire.fetch(fetch(rid1));
+        wire.fetch(fetch(rid2));
+        assert!(matches!(wire.peers.get(&fd), Some(Peer::Upgrading { .. })));
+        assert_eq!(wire.fetches.get(&bob).map(|q| q.len()), Some(1));
+
+        // The upgrade fails, and Bob is disconnected.
+        wire.upgrade_failed(fd, io::Error::from(io::ErrorKind::BrokenPipe));
+
+        assert!(wire.fetches.is_empty());
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(crate::node::FetchResult::Failed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_wind_down_aborts_queued_fetches() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+
+        let bob = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([8, 8, 8, 8], 8776)));
+        let fd = 42;
+        let fetch = |rid| Fetch {
+            rid,
+            namespaces: Namespaces::All,
+            remote: bob,
+            initiated: true,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        };
+        wire.peers.insert(fd, Peer::outbound(bob, addr));
+        wire.peer_mut_by_fd(fd).connected(bob);
+
+        let (rid1, rid2) = (arbitrary::gen::<Id>(1), arbitrary::gen::<Id>(1));
+        let (send, recv) = chan::bounded(1);
+
+        wire.service
+            .command(service::Command::Fetch(rid2, bob, send));
+        wire.fetch(fetch(rid1));
+        wire.fetch(fetch(rid2));
+
+        // The queued fetch is aborted, while the ongoing one is waited on.
+        let (done, shutdown) = chan::bounded(1);
+        wire.wind_down(done);
+
+        assert!(wire.fetches.is_empty());
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(crate::node::FetchResult::Failed { .. })
+        ));
+        assert!(shutdown.try_recv().is_err());
+
+        // Once the ongoing fetch ends, even if its upgrade failed, we're done.
+        wire.upgrade_failed(fd, io::Error::from(io::ErrorKind::BrokenPipe));
+        assert!(shutdown.try_recv().is_ok());
+    }
+
+    #[test]
+    fn test_fetch_no_address() {
+        let alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+
+        // We don't know of any address for Bob.
+        let bob = arbitrary::gen::<NodeId>(1);
+        let rid = arbitrary::gen::<Id>(1);
+        let (send, recv) = chan::bounded(1);
+
+        wire.service
+            .command(service::Command::Fetch(rid, bob, send));
+        wire.fetch(Fetch {
+            rid,
+            namespaces: Namespaces::All,
+            remote: bob,
+            initiated: true,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        });
+        assert!(wire.fetches.is_empty());
+        assert!(wire.actions.is_empty());
+        assert!(matches!(
+            recv.try_recv(),
+            Ok(crate::node::FetchResult::Failed { .. })
+        ));
+    }
+
+    #[test]
+    fn test_upgrade_pending_write() {
+        use std::io::Write as _;
+
+        let mut alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let bob = peer::Peer::new("bob", [8, 8, 8, 8]);
+        let signer = alice.signer().clone();
+
+        alice.connect_from(&bob);
+
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(
+            alice.service,
+            worker,
+            signer.clone(),
+            proxy,
+            LocalTime::now(),
+        );
+        let listener = net::TcpListener::bind(net::SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+        let stream = net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let session = accept(stream, signer);
+        let fd = session.as_connection().as_raw_fd();
+        let mut transport = NetTransport::with_session(session, Link::Inbound).unwrap();
+
+        // Leave data in the write buffer, as if the reactor hadn't flushed it yet.
+        transport.write_all(&[0; 16]).unwrap();
+
+        wire.peers.insert(fd, Peer::inbound(bob.address()));
+        wire.peer_mut_by_fd(fd).connected(bob.id);
+        wire.peer_mut_by_fd(fd).upgrading(Fetch {
+            rid: arbitrary::gen::<Id>(1),
+            namespaces: Namespaces::All,
+            remote: bob.id,
+            initiated: false,
+            oid: None,
+            dry_run: false,
+            debug: vec![],
+        });
+        wire.upgraded(transport);
+
+        assert!(matches!(
+            wire.peers.get(&fd),
+            Some(Peer::Upgrading {
+                deferred: Some(_),
+                ..
+            })
+        ));
+        let Some(Action::RegisterTransport(transport)) = wire.actions.pop_back() else {
+            panic!("The transport is handed back to the reactor to be flushed");
+        };
+        assert_eq!(transport.as_raw_fd(), fd);
+
+        wire.retry_upgrades();
+        assert!(matches!(
+            wire.actions.pop_back(),
+            Some(Action::UnregisterTransport(f)) if f == fd
+        ));
+
+        // The write buffer wasn't flushed in time.
+        if let Some(Peer::Upgrading { deferred, .. }) = wire.peers.get_mut(&fd) {
+            *deferred = Some(LocalTime::default());
+        }
+        wire.upgraded(transport);
+
+        assert!(matches!(
+            wire.peers.get(&fd),
+            Some(Peer::Disconnected {
+                reason: DisconnectReason::Connection(_),
+                ..
+            })
+        ));
+        assert!(
+            !wire.service.sessions().is_connected(&bob.id),
+            "The service is told about the disconnection"
+        );
+        assert!(wire.deferred.is_empty());
+    }
+
+    #[test]
+    fn test_interleaved_peer_data() {
+        let mut alice = peer::Peer::new("alice", [7, 7, 7, 7]);
+        let bob = peer::Peer::new("bob", [8, 8, 8, 8]);
+        let eve = peer::Peer::new("eve", [9, 9, 9, 9]);
+        let signer = alice.signer().clone();
+
+        alice.connect_from(&bob);
+        alice.connect_from(&eve);
+        alice.outbox().for_each(drop);
+
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+        let ping = |ponglen| {
+            wire::serialize(&Message::Ping(Ping {
+                ponglen,
+                zeroes: ZeroBytes::new(8),
+            }))
+        };
+        let (from_bob, from_eve) = (ping(1), ping(2));
+
+        for (fd, peer) in [(1, &bob), (2, &eve)] {
+            wire.peers.insert(fd, Peer::inbound(peer.address()));
+            wire.peer_mut_by_fd(fd).connected(peer.id);
+        }
+        // Both peers' messages arrive in parts, interleaved with each other.
+        wire.received(1, &from_bob[..5]);
+        wire.received(2, &from_eve[..3]);
+        wire.received(1, &from_bob[5..]);
+        wire.received(2, &from_eve[3..]);
+
+        // Each peer is answered with the pong it asked for.
+        let pongs = wire
+            .service
+            .reactor()
+            .outbox()
+            .drain(..)
+            .filter_map(|io| match io {
+                Io::Write(id, msgs) => Some((id, msgs)),
+                _ => None,
+            })
+            .flat_map(|(id, msgs)| msgs.into_iter().map(move |msg| (id, msg)))
+            .filter_map(|(id, msg)| match msg {
+                Message::Pong { zeroes } => Some((id, zeroes.len())),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(pongs, vec![(bob.id, 1), (eve.id, 2)]);
+    }
+
+    #[test]
+    fn test_inbox_limit() {
+        let alice = peer::Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::empty(),
+            peer::Config {
+                config: service::Config {
+                    limits: Limits {
+                        max_inbox_size: 16,
+                        ..Limits::default()
+                    },
+                    ..service::Config::default()
+                },
+                ..peer::Config::default()
+            },
+        );
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+        let bob = arbitrary::gen::<NodeId>(1);
+        let addr = Address::from(net::SocketAddr::from(([8, 8, 8, 8], 8776)));
+        let fd = 42;
+        let msg = wire::serialize(&Message::Ping(Ping {
+            ponglen: 0,
+            zeroes: ZeroBytes::new(42),
+        }));
+
+        wire.peers.insert(fd, Peer::inbound(addr));
+        wire.peer_mut_by_fd(fd).connected(bob);
+
+        // Messages larger than the limit are fine, as long as they can be decoded.
+        wire.received(fd, &msg);
+        assert!(matches!(wire.peers.get(&fd), Some(Peer::Connected { .. })));
+
+        // Undecoded data beyond the limit isn't.
+        wire.received(fd, &msg[..20]);
+        assert!(matches!(
+            wire.peers.get(&fd),
+            Some(Peer::Disconnected {
+                reason: DisconnectReason::Session(session::Error::Misbehavior),
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_accept_rate_limit() {
+        let alice = peer::Peer::config(
+            "alice",
+            [7, 7, 7, 7],
+            MockStorage::empty(),
+            peer::Config {
+                config: service::Config {
+                    limits: Limits {
+                        accept: AcceptLimits {
+                            per_ip: RateLimit {
+                                fill_rate: 1.,
+                                capacity: 3,
+                            },
+                            global: RateLimit {
+                                fill_rate: 1.,
+                                capacity: 5,
+                            },
+                        },
+                        ..Limits::default()
+                    },
+                    ..service::Config::default()
+                },
+                ..peer::Config::default()
+            },
+        );
+        let signer = alice.signer().clone();
+        let (worker, _) = chan::unbounded();
+        let proxy = net::SocketAddr::from(([127, 0, 0, 1], 9050));
+        let mut wire = Wire::new(alice.service, worker, signer, proxy, LocalTime::now());
+        let flooder = net::SocketAddr::from(([8, 8, 8, 8], 41000));
+
+        // A single host flooding us only gets through its own allowance.
+        let accepted = (0..100).filter(|_| wire.accept_allowed(flooder)).count();
+        assert_eq!(accepted, 3);
+
+        // Other hosts share the remaining global allowance.
+        let accepted = (1..=10)
+            .map(|i| net::SocketAddr::from(([9, 9, 9, i], 41000)))
+            .filter(|addr| wire.accept_allowed(*addr))
+            .count();
+        assert_eq!(accepted, 2);
+    }
+}
@@ -1,9 +1,12 @@
+use std::collections::BTreeMap;
 use std::io::{prelude::*, BufReader};
+use std::str::FromStr;
 use std::thread::JoinHandle;
 use std::{env, io, net, process, thread, time};
 
 use crossbeam_channel as chan;
 use cyphernet::Ecdh;
+use localtime::{LocalDuration, LocalTime};
 use netservices::tunnel::Tunnel;
 use netservices::{NetSession, SplitIo};
 
@@ -13,8 +16,10 @@ use radicle::storage::{Namespaces, ReadRepository, RefUpdate, WriteRepository, W
 use radicle::{git, Storage};
 use reactor::poller::popol;
 
+use crate::clock::{Timestamp, TimestampExt as _};
 use crate::runtime::Handle;
 use crate::service::reactor::Fetch;
+use crate::service::MAX_TIME_DELTA;
 use crate::storage;
 use crate::wire::{WireReader, WireSession, WireWriter};
 
@@ -47,6 +52,14 @@ pub enum FetchError {
     Io(#[from] io::Error),
     #[error(transparent)]
     Project(#[from] storage::ProjectError),
+    #[error("fetched repository is missing the requested object {0}")]
+    MissingObject(git::Oid),
+    #[error("repository {0} was not found on the remote")]
+    NotFound(Id),
+    #[error("fetched commit {0} is dated in the future")]
+    FutureDated(git::Oid),
+    #[error("{0} trailing byte(s) left over from fetch, the session is out of sync")]
+    Desync(usize),
 }
 
 impl FetchError {
@@ -54,6 +67,17 @@ impl FetchError {
     pub fn is_timeout(&self) -> bool {
         matches!(self, FetchError::Io(e) if e.kind() == io::ErrorKind::TimedOut)
     }
+
+    /// Check if the error indicates that the session can't be used for gossip anymore.
+    pub fn is_desync(&self) -> bool {
+        matches!(self, FetchError::Desync(_))
+    }
+
+    /// Check if the error indicates that the repository doesn't exist on the remote.
+    /// Only the remote telling us so counts, local and transport errors don't.
+    pub fn is_not_found(&self) -> bool {
+        matches!(self, FetchError::NotFound(_))
+    }
 }
 
 /// Task to be accomplished on a worker thread.
@@ -128,7 +152,11 @@ impl<G: Signer + Ecdh + 'static> Worker<G> {
                 Ok(tunnel) => tunnel,
                 Err((session, err)) => return (session, Err(err.into())),
             };
-            let result = self.fetch(fetch, &mut tunnel);
+            let result = if fetch.dry_run {
+                self.ls_refs(fetch, &mut tunnel)
+            } else {
+                self.fetch(fetch, &mut tunnel)
+            };
             let mut session = tunnel.into_session();
 
             // If there are no errors, send a `done` special packet. We don't send this on error,
@@ -204,16 +232,8 @@ impl<G: Signer + Ecdh + 'static> Worker<G> {
             .stderr(process::Stdio::piped())
             .stdin(process::Stdio::piped());
 
-        log::debug!(target: "worker", "Running command: {:?}", cmd);
-
-        let mut child = cmd.spawn()?;
-        let stderr = child.stderr.take().unwrap();
-
-        thread::Builder::new().name(self.name.clone()).spawn(|| {
-            for line in BufReader::new(stderr).lines().flatten() {
-                log::debug!(target: "worker", "Git: {}", line);
-            }
-        })?;
+        let before = namespace_refs(&repo.backend, &fetch.namespaces)?;
+        let (mut child, remote_error) = self.spawn(fetch, &mut cmd)?;
 
         let _ = tunnel.tunnel_once(popol::Poller::new(), self.timeout)?;
 
@@ -222,6 +242,39 @@ impl<G: Signer + Ecdh + 'static> Worker<G> {
             log::debug!(target: "worker", "Fetch for {} exited successfully", fetch.rid);
         } else {
             log::error!(target: "worker", "Fetch for {} failed", fetch.rid);
+
+            let remote_error = remote_error.join().ok().flatten();
+            if remote_error.as_deref().map_or(false, is_remote_not_found) {
+                return Err(FetchError::NotFound(fetch.rid));
+            }
+        }
+
+        // Commits from the future could be used to appear fresher than they are, so we don't
+        // accept any of the fetched refs if one of them points to such a commit.
+        let after = namespace_refs(&repo.backend, &fetch.namespaces)?;
+        if let Some(oid) = future_dated(
+            &repo.backend,
+            &before,
+            &after,
+            LocalTime::now(),
+            MAX_TIME_DELTA,
+        ) {
+            log::error!(target: "worker", "Fetch for {} returned future-dated commit {oid}", fetch.rid);
+
+            restore(&repo.backend, &before, &after)?;
+
+            return Err(FetchError::FutureDated(oid));
+        }
+        // If the fetch was pinned to an object, eg. an announced commit, make sure we got it.
+        // Otherwise, the remote is lagging behind or lying, and we don't keep what it sent.
+        if let Some(oid) = fetch.oid {
+            if repo.backend.find_object(*oid, None).is_err() {
+                log::error!(target: "worker", "Fetch for {} is missing object {oid}", fetch.rid);
+
+                restore(&repo.backend, &before, &after)?;
+
+                return Err(FetchError::MissingObject(oid));
+            }
         }
         let head = repo.set_head()?;
 
@@ -230,6 +283,115 @@ impl<G: Signer + Ecdh + 'static> Worker<G> {
         Ok(vec![])
     }
 
+    /// List the remote refs a fetch would retrieve, without fetching anything. Since nothing
+    /// is written to storage, the refs are returned as skipped updates.
+    fn ls_refs(
+        &self,
+        fetch: &Fetch,
+        tunnel: &mut Tunnel<WireSession<G>>,
+    ) -> Result<Vec<RefUpdate>, FetchError> {
+        let tunnel_addr = tunnel.local_addr()?;
+        let mut cmd = process::Command::new("git");
+        cmd.current_dir(self.storage.path())
+            .env_clear()
+            .envs(env::vars().filter(|(k, _)| k == "PATH" || k.starts_with("GIT_TRACE")))
+            .envs(git::env::GIT_DEFAULT_CONFIG)
+            .args(["-c", "protocol.version=2"])
+            .arg("ls-remote")
+            .arg(format!("git://{tunnel_addr}/{}", fetch.rid.canonical()))
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .stdin(process::Stdio::piped());
+
+        let (mut child, _) = self.spawn(fetch, &mut cmd)?;
+        let stdout = child.stdout.take().unwrap();
+        // Collect the listing on its own thread, so that git never blocks on a full pipe
+        // while we're tunneling.
+        let listing = thread::Builder::new().name(self.name.clone()).spawn(|| {
+            BufReader::new(stdout)
+                .lines()
+                .collect::<io::Result<Vec<_>>>()
+        })?;
+
+        let _ = tunnel.tunnel_once(popol::Poller::new(), self.timeout)?;
+
+        if !child.wait()?.success() {
+            log::error!(target: "worker", "Listing refs of {} failed", fetch.rid);
+
+            return Err(FetchError::Io(io::Error::new(
+                io::ErrorKind::Other,
+                format!("failed to list refs of {}", fetch.rid),
+            )));
+        }
+        let listing = listing
+            .join()
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "ref listing thread panicked"))??;
+        let prefix = match &fetch.namespaces {
+            Namespaces::All => String::from("refs/namespaces/"),
+            Namespaces::One(pk) => format!("refs/namespaces/{pk}/"),
+        };
+
+        // Each line is of the form `<oid> TAB <refname>`.
+        Ok(listing
+            .iter()
+            .filter_map(|line| line.split_once('\t'))
+            .filter(|(_, name)| name.starts_with(&prefix))
+            .filter_map(|(oid, name)| {
+                let oid = git::Oid::from_str(oid).ok()?;
+                let name = git::RefString::try_from(name).ok()?;
+
+                Some(RefUpdate::Skipped { name, oid })
+            })
+            .collect())
+    }
+
+    /// Spawn a git command, logging its standard error from another thread. If the fetch is
+    /// being debugged, git's protocol trace is enabled for this command only, and its log is
+    /// also sent to the requester. The returned thread yields the last error reported by the
+    /// remote, if any.
+    fn spawn(
+        &self,
+        fetch: &Fetch,
+        cmd: &mut process::Command,
+    ) -> io::Result<(process::Child, JoinHandle<Option<String>>)> {
+        let debug = fetch.debug.clone();
+        if !debug.is_empty() {
+            cmd.env("GIT_TRACE_PACKET", "1");
+        }
+        for debug in &debug {
+            debug.send(format!("Running command: {cmd:?}")).ok();
+        }
+        log::debug!(target: "worker", "Running command: {:?}", cmd);
+
+        let mut child = cmd.spawn()?;
+        let stderr = child.stderr.take().unwrap();
+
+        let remote_error = thread::Builder::new()
+            .name(self.name.clone())
+            .spawn(move || {
+                let mut remote_error = None;
+
+                for line in BufReader::new(stderr).lines().flatten() {
+                    // When the fetch is debugged, every packet line is traced, and those
+                    // already go to the requesters. Keep them out of the debug log.
+                    if debug.is_empty() {
+                        log::debug!(target: "worker", "Git: {}", line);
+                    } else {
+                        log::trace!(target: "worker", "Git: {}", line);
+                    }
+
+                    if let Some((_, err)) = line.split_once(REMOTE_ERROR_PREFIX) {
+                        remote_error = Some(err.to_owned());
+                    }
+                    for debug in &debug {
+                        debug.send(line.clone()).ok();
+                    }
+                }
+                remote_error
+            })?;
+        Ok((child, remote_error))
+    }
+
     fn upload_pack(
         &self,
         fetch: &Fetch,
@@ -295,6 +457,16 @@ impl<G: Signer + Ecdh + 'static> Worker<G> {
         }
         log::debug!(target: "worker", "Upload of {} to {} exited successfully", fetch.rid, fetch.remote);
 
+        // Any bytes left over from the fetch protocol are not valid gossip messages, and would
+        // desync the gossip decoder once the session is downgraded.
+        if stream_r.remaining() > 0 {
+            log::error!(
+                target: "worker",
+                "Found {} trailing byte(s) after fetch of {}", stream_r.remaining(), fetch.rid
+            );
+            return Err(FetchError::Desync(stream_r.remaining()));
+        }
+
         // When we aren't the one fetching, no refs are updated.
         Ok(vec![])
     }
@@ -348,6 +520,80 @@ impl Pool {
     }
 }
 
+/// Prefix of errors reported by the remote git daemon, as printed by git.
+const REMOTE_ERROR_PREFIX: &str = "remote error: ";
+
+/// Check whether an error reported by the remote git daemon means that it doesn't have the
+/// requested repository. This relies on the daemon running with `--informative-errors`.
+fn is_remote_not_found(err: &str) -> bool {
+    err.starts_with("no such repository")
+}
+
+/// Get the direct refs under the given namespaces.
+fn namespace_refs(
+    repo: &git::raw::Repository,
+    namespaces: &Namespaces,
+) -> Result<BTreeMap<String, git::Oid>, git::raw::Error> {
+    let glob = match namespaces {
+        Namespaces::All => String::from("refs/namespaces/*"),
+        Namespaces::One(pk) => format!("refs/namespaces/{pk}/*"),
+    };
+    let mut refs = BTreeMap::new();
+
+    for r in repo.references_glob(&glob)? {
+        let r = r?;
+        if let (Some(name), Some(oid)) = (r.name(), r.target()) {
+            refs.insert(name.to_owned(), oid.into());
+        }
+    }
+    Ok(refs)
+}
+
+/// Find a ref that was updated between `before` and `after`, and points to a commit dated
+/// further than `max_skew` in the future, relative to `now`.
+fn future_dated(
+    repo: &git::raw::Repository,
+    before: &BTreeMap<String, git::Oid>,
+    after: &BTreeMap<String, git::Oid>,
+    now: LocalTime,
+    max_skew: LocalDuration,
+) -> Option<git::Oid> {
+    after
+        .iter()
+        .filter(|(name, oid)| before.get(*name) != Some(*oid))
+        .find(|(_, oid)| {
+            repo.find_commit(***oid)
+                .map(|c| {
+                    let time: Timestamp = u64::try_from(c.time().seconds()).unwrap_or(0) * 1000;
+                    time.is_future(now, max_skew)
+                })
+                .unwrap_or(false)
+        })
+        .map(|(_, oid)| *oid)
+}
+
+/// Restore refs to their state before a fetch.
+fn restore(
+    repo: &git::raw::Repository,
+    before: &BTreeMap<String, git::Oid>,
+    after: &BTreeMap<String, git::Oid>,
+) -> Result<(), git::raw::Error> {
+    for (name, oid) in before {
+        if after.get(name) != Some(oid) {
+            repo.reference(
+                name,
+                **oid,
+                true,
+                "radicle: restore ref after rejected fetch",
+            )?;
+        }
+    }
+    for name in after.keys().filter(|name| !before.contains_key(*name)) {
+        repo.find_reference(name)?.delete()?;
+    }
+    Ok(())
+}
+
 mod pktline {
     use std::io;
     use std::io::Read;
@@ -385,6 +631,11 @@ mod pktline {
             Self { drain, stream }
         }
 
+        /// Number of drained bytes that haven't been read yet.
+        pub fn remaining(&self) -> usize {
+            self.drain.len()
+        }
+
         /// Parse a Git request packet-line.
         ///
         /// Example: `0032git-upload-pack /project.git\0host=myserver.com\0`
@@ -498,3 +749,66 @@ mod pktline {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test::arbitrary;
+    use radicle::test::fixtures;
+
+    #[test]
+    fn test_is_not_found() {
+        let rid = arbitrary::gen::<Id>(1);
+
+        assert!(is_remote_not_found(
+            "no such repository: /rad:z3gqcJUoA1n9HaHKufZs5FCSGazv5.git"
+        ));
+        assert!(!is_remote_not_found(
+            "access denied or repository not exported"
+        ));
+        assert!(FetchError::NotFound(rid).is_not_found());
+        assert!(!FetchError::Io(io::ErrorKind::NotFound.into()).is_not_found());
+        assert!(!FetchError::Io(io::ErrorKind::ConnectionReset.into()).is_not_found());
+    }
+
+    #[test]
+    fn test_future_dated_refs_are_restored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (repo, head) = fixtures::repository(tmp.path());
+        let head = repo.find_commit(head).unwrap();
+        let name =
+            "refs/namespaces/z6MknSLrJoTcukLrE435hVNQT4JUhbvWLX4kUzqkEStBU8Vi/refs/heads/master";
+        let now = LocalTime::now();
+
+        repo.reference(name, head.id(), true, "test").unwrap();
+        let before = namespace_refs(&repo, &Namespaces::All).unwrap();
+
+        // Simulate a fetch of a commit dated two hours from now.
+        let time = git::raw::Time::new(now.as_secs() as i64 + 2 * 60 * 60, 0);
+        let sig = git::raw::Signature::new("bob", "bob@radicle.xyz", &time).unwrap();
+        let future = repo
+            .commit(
+                None,
+                &sig,
+                &sig,
+                "From the future",
+                &head.tree().unwrap(),
+                &[&head],
+            )
+            .unwrap();
+        repo.reference(name, future, true, "test").unwrap();
+
+        let after = namespace_refs(&repo, &Namespaces::All).unwrap();
+        assert_eq!(
+            future_dated(&repo, &before, &before, now, MAX_TIME_DELTA),
+            None
+        );
+        assert_eq!(
+            future_dated(&repo, &before, &after, now, MAX_TIME_DELTA),
+            Some(future.into())
+        );
+
+        restore(&repo, &before, &after).unwrap();
+        assert_eq!(namespace_refs(&repo, &Namespaces::All).unwrap(), before);
+    }
+}
@@ -9,7 +9,7 @@ pub mod thread;
 #[cfg(test)]
 pub mod test;
 
-pub use cob::{create, get, list, remove, update};
+pub use cob::{create, get, info, list, remove, update};
 pub use cob::{
     identity::Identity, object::collaboration::error, CollaborativeObject, Contents, Create, Entry,
     History, ObjectId, TypeName, Update,
@@ -408,6 +408,32 @@ mod test {
         assert_eq!(id.urn(), String::from("rad:z42hL2jL4XNk6K8oHQaSWfMgCL7ji"));
     }
 
+    #[test]
+    fn test_from_json_field_order() {
+        let json = br#"{
+            "payload": {
+                "xyz.radicle.project": {
+                    "defaultBranch": "master",
+                    "description": "Radicle Heartwood Protocol & Stack",
+                    "name": "heartwood"
+                }
+            },
+            "verified": null,
+            "threshold": 1,
+            "delegates": ["did:key:z6MknSLrJoTcukLrE435hVNQT4JUhbvWLX4kUzqkEStBU8Vi"]
+        }"#;
+        let doc = Doc::<Unverified>::from_json(json).unwrap();
+        let value = serde_json::to_value(&doc).unwrap();
+
+        assert_eq!(doc.threshold, 1);
+        assert_eq!(doc.delegates.len(), 1);
+        assert!(value.get("verified").is_none());
+        assert_eq!(
+            Doc::<Unverified>::from_json(&serde_json::to_vec(&value).unwrap()).unwrap(),
+            doc
+        );
+    }
+
     #[test]
     fn test_not_found() {
         let tempdir = tempfile::tempdir().unwrap();
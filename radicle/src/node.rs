@@ -3,7 +3,8 @@ mod features;
 use std::io::{BufRead, BufReader};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::{fmt, io, net};
+use std::str::FromStr;
+use std::{fmt, io, net, thread};
 
 use amplify::WrapperMut;
 use crossbeam_channel as chan;
@@ -20,6 +21,8 @@ pub use features::Features;
 
 /// Default name for control socket file.
 pub const DEFAULT_SOCKET_NAME: &str = "radicle.sock";
+/// Name of the node configuration file, under the node directory.
+pub const DEFAULT_CONFIG_NAME: &str = "config.json";
 /// Default radicle protocol port.
 pub const DEFAULT_PORT: u16 = 8776;
 
@@ -77,7 +80,7 @@ impl From<CommandResult> for Result<bool, Error> {
 
 /// Peer public protocol address.
 #[derive(Wrapper, WrapperMut, Clone, Eq, PartialEq, Debug, From)]
-#[wrapper(Deref, Display, FromStr)]
+#[wrapper(Deref)]
 #[wrapper_mut(DerefMut)]
 pub struct Address(NetAddr<HostName>);
 
@@ -102,20 +105,63 @@ impl From<net::SocketAddr> for Address {
     }
 }
 
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.host {
+            // IPv6 addresses are written in brackets, eg. `[::1]:8776`, otherwise the port
+            // can't be told apart from the last segment of the address.
+            HostName::Ip(ip) => net::SocketAddr::new(ip, self.0.port).fmt(f),
+            _ => self.0.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Address {
+    type Err = <NetAddr<HostName> as FromStr>::Err;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(addr) = net::SocketAddr::from_str(s) {
+            return Ok(addr.into());
+        }
+        NetAddr::from_str(s).map(Self)
+    }
+}
+
+/// Options of the "connect" command.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectOptions {
+    /// Maintain the connection, reconnecting to the peer when it drops, as with the peers
+    /// the node is configured to connect to.
+    pub persistent: bool,
+}
+
 /// Command name.
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum CommandName {
     /// Announce repository references for given repository to peers.
     AnnounceRefs,
+    /// Ask connected peers to fetch the given repository from the node.
+    RequestSeeding,
     /// Sync local inventory with node.
     SyncInventory,
+    /// Reconcile the node's inventory and routing table with the network.
+    Reconcile,
     /// Connect to node with the given address.
     Connect,
+    /// Stop maintaining a connection to the given node.
+    Unpersist,
     /// Lookup seeds for the given repository in the routing table.
     Seeds,
     /// Fetch the given repository from the network.
     Fetch,
+    /// List the refs a fetch of the given repository would retrieve, without fetching them.
+    FetchDryRun,
+    /// Stream the git protocol log of the next fetch of the given repository.
+    FetchDebug,
+    /// Fetch the given repository from a seed at the given address.
+    FetchHinted,
     /// Track the given repository.
     TrackRepo,
     /// Untrack the given repository.
@@ -124,12 +170,26 @@ pub enum CommandName {
     TrackNode,
     /// Untrack the given node.
     UntrackNode,
+    /// Add a remote to a repository's remote tracking allow-list.
+    TrackRemote,
+    /// Remove a remote from a repository's remote tracking allow-list.
+    UntrackRemote,
+    /// Set which remotes of a repository are tracked.
+    SetRemoteTracking,
+    /// Set whether announcements for a repository are relayed.
+    SetRepoRelay,
     /// Get the node's inventory.
     Inventory,
+    /// Get the repositories of the node's inventory that are tracked.
+    TrackedInventory,
     /// Get the node's routing table.
     Routing,
+    /// List the node's peer sessions.
+    Sessions,
     /// Get the node's status.
     Status,
+    /// Compact the node's databases.
+    Compact,
     /// Shutdown the node.
     Shutdown,
 }
@@ -211,6 +271,95 @@ impl<S: ToString> From<Result<Vec<RefUpdate>, S>> for FetchResult {
     }
 }
 
+/// Direction of a peer connection.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Link {
+    /// The peer connected to us.
+    Inbound,
+    /// We connected to the peer.
+    Outbound,
+}
+
+/// State of a peer session.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SessionState {
+    /// We're connecting to the peer.
+    Connecting,
+    /// Connected, and exchanging gossip messages with the peer.
+    Connected,
+    /// Connected, and fetching from or serving a fetch to the peer.
+    Fetch,
+    /// Disconnected, eg. while waiting to re-connect to a persistent peer.
+    Disconnected,
+}
+
+/// Snapshot of a peer session.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionInfo {
+    /// Peer id.
+    pub nid: NodeId,
+    /// Peer address.
+    #[serde(with = "crate::serde_ext::string")]
+    pub addr: Address,
+    /// Connection direction.
+    pub link: Link,
+    /// Session state.
+    pub state: SessionState,
+    /// Whether we re-connect to the peer when the connection drops.
+    pub persistent: bool,
+    /// Estimated round-trip time to the peer, in milliseconds, if it was measured.
+    pub latency: Option<u64>,
+    /// Last time a message was received from the peer, in milliseconds since epoch.
+    pub last_active: u64,
+    /// Last time a message was sent to the peer, in milliseconds since epoch.
+    pub last_sent: u64,
+}
+
+/// Which remotes of a repository are tracked, ie. fetched and accepted announcements from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteTracking {
+    /// Only track the remotes of the repository delegates.
+    DelegatesOnly,
+    /// Track all remotes.
+    All,
+    /// Track the remotes of the repository delegates, and of the given nodes.
+    Remotes(Vec<NodeId>),
+}
+
+impl fmt::Display for RemoteTracking {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::DelegatesOnly => write!(f, "delegates-only"),
+            Self::All => write!(f, "all"),
+            Self::Remotes(remotes) => {
+                let remotes = remotes.iter().map(|r| r.to_human()).collect::<Vec<_>>();
+                write!(f, "{}", remotes.join(","))
+            }
+        }
+    }
+}
+
+impl FromStr for RemoteTracking {
+    type Err = crate::crypto::PublicKeyError;
+
+    /// Parse a remote tracking policy: either `delegates-only`, `all`, or a comma-separated
+    /// list of node ids.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "delegates-only" => Ok(Self::DelegatesOnly),
+            "all" => Ok(Self::All),
+            _ => s
+                .split(',')
+                .map(|nid| NodeId::from_str(nid.trim()))
+                .collect::<Result<_, _>>()
+                .map(Self::Remotes),
+        }
+    }
+}
+
 /// Error returned by [`Handle`] functions.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -249,11 +398,35 @@ pub trait Handle {
     /// Check if the node is running. to a peer.
     fn is_running(&self) -> bool;
     /// Connect to a peer.
-    fn connect(&mut self, node: NodeId, addr: Address) -> Result<(), Self::Error>;
+    fn connect(
+        &mut self,
+        node: NodeId,
+        addr: Address,
+        opts: ConnectOptions,
+    ) -> Result<(), Self::Error>;
+    /// Stop maintaining a connection to a peer, so that it isn't reconnected to when the
+    /// connection drops, or after a restart. The current connection, if any, is kept.
+    /// Returns whether the peer was persistent.
+    fn unpersist(&mut self, node: NodeId) -> Result<bool, Self::Error>;
     /// Lookup the seeds of a given repository in the routing table.
     fn seeds(&mut self, id: Id) -> Result<Vec<NodeId>, Self::Error>;
     /// Fetch a repository from the network.
     fn fetch(&mut self, id: Id, from: NodeId) -> Result<Self::FetchResult, Self::Error>;
+    /// List the refs a fetch of a repository would retrieve, without fetching them.
+    /// On success, every ref is reported as skipped.
+    fn fetch_dry_run(&mut self, id: Id, from: NodeId) -> Result<Self::FetchResult, Self::Error>;
+    /// Stream the git protocol log of the next fetch of a repository initiated by the node.
+    /// The log ends when that fetch does, or without output if there is no such fetch
+    /// within a few minutes; other fetches aren't affected.
+    fn fetch_debug(&mut self, id: Id) -> Result<chan::Receiver<String>, Self::Error>;
+    /// Fetch a repository from a seed at the given address, connecting to it first if
+    /// necessary. The seed doesn't need to be known to our routing table.
+    fn fetch_hinted(
+        &mut self,
+        id: Id,
+        from: NodeId,
+        addr: Address,
+    ) -> Result<Self::FetchResult, Self::Error>;
     /// Start tracking the given project. Doesn't do anything if the project is already
     /// tracked.
     fn track_repo(&mut self, id: Id) -> Result<bool, Self::Error>;
@@ -263,18 +436,39 @@ pub trait Handle {
     fn untrack_repo(&mut self, id: Id) -> Result<bool, Self::Error>;
     /// Untrack the given node.
     fn untrack_node(&mut self, id: NodeId) -> Result<bool, Self::Error>;
+    /// Add the given remote to a project's remote tracking allow-list.
+    fn track_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Self::Error>;
+    /// Remove the given remote from a project's remote tracking allow-list.
+    fn untrack_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Self::Error>;
+    /// Set which remotes of a project are tracked, tracking the project if it isn't already.
+    /// The scope and allow-list of the project are updated together, or not at all.
+    fn set_remote_tracking(&mut self, id: Id, policy: RemoteTracking) -> Result<bool, Self::Error>;
+    /// Set whether announcements for a tracked project are relayed to the node's peers.
+    /// Projects that aren't relayed are still fetched.
+    fn set_repo_relay(&mut self, id: Id, relay: bool) -> Result<bool, Self::Error>;
     /// Notify the service that a project has been updated.
     fn announce_refs(&mut self, id: Id) -> Result<(), Self::Error>;
+    /// Ask the node's connected peers to fetch the given project from it, if they track it.
+    fn request_seeding(&mut self, id: Id) -> Result<(), Self::Error>;
     /// Notify the service that our inventory was updated.
     fn sync_inventory(&mut self) -> Result<bool, Self::Error>;
+    /// Force a full reconciliation of our inventory with the network: re-announce it, and
+    /// request our peers' inventories. Returns whether the routing table was updated.
+    fn reconcile(&mut self) -> Result<bool, Self::Error>;
     /// Ask the service to shutdown.
     fn shutdown(self) -> Result<(), Self::Error>;
     /// Query the routing table entries.
     fn routing(&self) -> Result<chan::Receiver<(Id, NodeId)>, Self::Error>;
     /// Query the peer session state.
     fn sessions(&self) -> Result<Self::Sessions, Self::Error>;
+    /// List a snapshot of the peer sessions.
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>, Self::Error>;
     /// Query the inventory.
     fn inventory(&self) -> Result<chan::Receiver<Id>, Self::Error>;
+    /// Query the tracked repositories of the inventory.
+    fn tracked_inventory(&self) -> Result<chan::Receiver<Id>, Self::Error>;
+    /// Compact the node's databases, returning the number of bytes reclaimed.
+    fn compact(&mut self) -> Result<u64, Self::Error>;
 }
 
 /// Public node & device identifier.
@@ -331,8 +525,30 @@ impl Handle for Node {
         matches!(result, CommandResult::Okay { .. })
     }
 
-    fn connect(&mut self, _node: NodeId, _addr: Address) -> Result<(), Error> {
-        todo!()
+    fn connect(&mut self, node: NodeId, addr: Address, opts: ConnectOptions) -> Result<(), Error> {
+        let mut line = self.call(
+            CommandName::Connect,
+            [
+                node.to_human(),
+                addr.to_string(),
+                opts.persistent.to_string(),
+            ],
+        )?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::Connect,
+        })??;
+        let result: Result<bool, Error> = response.into();
+
+        result.map(|_| ())
+    }
+
+    fn unpersist(&mut self, node: NodeId) -> Result<bool, Error> {
+        let mut line = self.call(CommandName::Unpersist, [node])?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::Unpersist,
+        })??;
+
+        response.into()
     }
 
     fn seeds(&mut self, id: Id) -> Result<Vec<NodeId>, Error> {
@@ -357,6 +573,53 @@ impl Handle for Node {
         Ok(result)
     }
 
+    fn fetch_dry_run(&mut self, id: Id, from: NodeId) -> Result<Self::FetchResult, Error> {
+        let result = self
+            .call(CommandName::FetchDryRun, [id.urn(), from.to_human()])?
+            .next()
+            .ok_or(Error::EmptyResponse {
+                cmd: CommandName::FetchDryRun,
+            })??;
+
+        Ok(result)
+    }
+
+    fn fetch_debug(&mut self, id: Id) -> Result<chan::Receiver<String>, Error> {
+        let lines = self.call::<_, String>(CommandName::FetchDebug, [id.urn()])?;
+        let (sender, receiver) = chan::unbounded();
+
+        thread::spawn(move || {
+            for line in lines {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(receiver)
+    }
+
+    fn fetch_hinted(
+        &mut self,
+        id: Id,
+        from: NodeId,
+        addr: Address,
+    ) -> Result<Self::FetchResult, Error> {
+        let result = self
+            .call(
+                CommandName::FetchHinted,
+                [id.urn(), from.to_human(), addr.to_string()],
+            )?
+            .next()
+            .ok_or(Error::EmptyResponse {
+                cmd: CommandName::FetchHinted,
+            })??;
+
+        Ok(result)
+    }
+
     fn track_node(&mut self, id: NodeId, alias: Option<String>) -> Result<bool, Error> {
         let id = id.to_human();
         let args = if let Some(alias) = alias.as_deref() {
@@ -391,6 +654,45 @@ impl Handle for Node {
         response.into()
     }
 
+    fn track_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Error> {
+        let mut line = self.call(CommandName::TrackRemote, [id.urn(), remote.to_human()])?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::TrackRemote,
+        })??;
+
+        response.into()
+    }
+
+    fn untrack_remote(&mut self, id: Id, remote: NodeId) -> Result<bool, Error> {
+        let mut line = self.call(CommandName::UntrackRemote, [id.urn(), remote.to_human()])?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::UntrackRemote,
+        })??;
+
+        response.into()
+    }
+
+    fn set_remote_tracking(&mut self, id: Id, policy: RemoteTracking) -> Result<bool, Error> {
+        let mut line = self.call(
+            CommandName::SetRemoteTracking,
+            [id.urn(), policy.to_string()],
+        )?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::SetRemoteTracking,
+        })??;
+
+        response.into()
+    }
+
+    fn set_repo_relay(&mut self, id: Id, relay: bool) -> Result<bool, Error> {
+        let mut line = self.call(CommandName::SetRepoRelay, [id.urn(), relay.to_string()])?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::SetRepoRelay,
+        })??;
+
+        response.into()
+    }
+
     fn untrack_repo(&mut self, id: Id) -> Result<bool, Error> {
         let mut line = self.call(CommandName::UntrackRepo, [id.urn()])?;
         let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
@@ -407,6 +709,13 @@ impl Handle for Node {
         Ok(())
     }
 
+    fn request_seeding(&mut self, id: Id) -> Result<(), Error> {
+        for line in self.call::<_, CommandResult>(CommandName::RequestSeeding, [id.urn()])? {
+            line?;
+        }
+        Ok(())
+    }
+
     fn sync_inventory(&mut self) -> Result<bool, Error> {
         let mut line = self.call::<&str, _>(CommandName::SyncInventory, [])?;
         let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
@@ -416,6 +725,15 @@ impl Handle for Node {
         response.into()
     }
 
+    fn reconcile(&mut self) -> Result<bool, Error> {
+        let mut line = self.call::<&str, _>(CommandName::Reconcile, [])?;
+        let response: CommandResult = line.next().ok_or(Error::EmptyResponse {
+            cmd: CommandName::Reconcile,
+        })??;
+
+        response.into()
+    }
+
     fn routing(&self) -> Result<chan::Receiver<(Id, NodeId)>, Error> {
         todo!();
     }
@@ -424,12 +742,66 @@ impl Handle for Node {
         todo!();
     }
 
+    fn list_sessions(&self) -> Result<Vec<SessionInfo>, Error> {
+        let sessions: Vec<SessionInfo> = self
+            .call::<&str, _>(CommandName::Sessions, [])?
+            .next()
+            .ok_or(Error::EmptyResponse {
+            cmd: CommandName::Sessions,
+        })??;
+
+        Ok(sessions)
+    }
+
     fn inventory(&self) -> Result<chan::Receiver<Id>, Error> {
-        todo!();
+        let inventory: Vec<Id> = self
+            .call::<&str, _>(CommandName::Inventory, [])?
+            .next()
+            .ok_or(Error::EmptyResponse {
+                cmd: CommandName::Inventory,
+            })??;
+
+        let (sender, receiver) = chan::unbounded();
+        for id in inventory {
+            sender.send(id).ok();
+        }
+        Ok(receiver)
+    }
+
+    fn tracked_inventory(&self) -> Result<chan::Receiver<Id>, Error> {
+        let inventory: Vec<Id> = self
+            .call::<&str, _>(CommandName::TrackedInventory, [])?
+            .next()
+            .ok_or(Error::EmptyResponse {
+                cmd: CommandName::TrackedInventory,
+            })??;
+
+        let (sender, receiver) = chan::unbounded();
+        for id in inventory {
+            sender.send(id).ok();
+        }
+        Ok(receiver)
+    }
+
+    fn compact(&mut self) -> Result<u64, Error> {
+        let reclaimed: u64 = self
+            .call::<&str, _>(CommandName::Compact, [])?
+            .next()
+            .ok_or(Error::EmptyResponse {
+                cmd: CommandName::Compact,
+            })??;
+
+        Ok(reclaimed)
     }
 
     fn shutdown(self) -> Result<(), Error> {
-        todo!();
+        let mut stream = UnixStream::connect(&self.socket)?;
+        Command::SHUTDOWN.to_writer(&stream)?;
+
+        // The node closes the connection once it's done winding down.
+        io::copy(&mut stream, &mut io::sink())?;
+
+        Ok(())
     }
 }
 
@@ -441,4 +813,42 @@ mod test {
     fn test_command_name_display() {
         assert_eq!(CommandName::TrackNode.to_string(), "track-node");
     }
+
+    #[test]
+    fn test_address_ipv6_roundtrip() {
+        let addr = Address::from(net::SocketAddr::from((net::Ipv6Addr::LOCALHOST, 8776)));
+
+        assert_eq!(addr.to_string(), "[::1]:8776");
+        assert_eq!(Address::from_str("[::1]:8776").unwrap(), addr);
+        assert_eq!(
+            Address::from_str("[2001:db8::7]:8776").unwrap().to_string(),
+            "[2001:db8::7]:8776"
+        );
+        assert_eq!(
+            Address::from_str("7.7.7.7:8776").unwrap().to_string(),
+            "7.7.7.7:8776"
+        );
+    }
+
+    #[test]
+    fn test_remote_tracking_roundtrip() {
+        let remotes = crate::test::arbitrary::vec::<NodeId>(2);
+        let policies = [
+            RemoteTracking::DelegatesOnly,
+            RemoteTracking::All,
+            RemoteTracking::Remotes(remotes.clone()),
+        ];
+
+        for policy in policies {
+            let parsed = RemoteTracking::from_str(&policy.to_string()).unwrap();
+            assert_eq!(parsed, policy);
+        }
+        let spaced = format!("{}, {}", remotes[0], remotes[1]);
+        assert_eq!(
+            RemoteTracking::from_str(&spaced).unwrap(),
+            RemoteTracking::Remotes(remotes),
+            "Whitespace around node ids is ignored"
+        );
+        assert!(RemoteTracking::from_str("everyone").is_err());
+    }
 }
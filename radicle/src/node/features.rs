@@ -12,6 +12,9 @@ impl Features {
     /// `SEED` is the base feature set all seed nodes must support.
     pub const SEED: Features = Features(0b00000001);
 
+    /// `PINNED_FETCH` means the node understands fetch requests pinned to an object.
+    pub const PINNED_FETCH: Features = Features(0b00000010);
+
     /// Returns [`Features`] with the other features added.
     #[must_use]
     pub fn with(self, other: Features) -> Features {
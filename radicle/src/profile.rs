@@ -9,10 +9,12 @@
 //!       radicle.pub                            # Public key (PKCS 8)
 //!     node/
 //!       radicle.sock                           # Node control socket
+//!       config.json                            # Node configuration
 //!
 use std::path::{Path, PathBuf};
 use std::{fs, io};
 
+use serde::Deserialize;
 use thiserror::Error;
 
 use crate::crypto::ssh::agent::Agent;
@@ -52,6 +54,8 @@ pub enum Error {
     MemorySigner(#[from] keystore::MemorySignerError),
     #[error("no profile found at the filepath '{0}'")]
     NotFound(PathBuf),
+    #[error("failed to parse node configuration {0:?}: {1}")]
+    Config(PathBuf, #[source] serde_json::Error),
     #[error("error connecting to ssh-agent: {0}")]
     Agent(#[from] crate::crypto::ssh::agent::Error),
     #[error("profile key `{0}` is not registered with ssh-agent")]
@@ -141,6 +145,24 @@ impl Profile {
     pub fn socket(&self) -> PathBuf {
         self.home.socket()
     }
+
+    /// Get the node alias from the node configuration file, if one is set.
+    pub fn alias(&self) -> Result<Option<String>, Error> {
+        /// The subset of the node configuration we're interested in.
+        #[derive(Deserialize)]
+        struct Config {
+            alias: Option<String>,
+        }
+
+        let path = self.home.node().join(node::DEFAULT_CONFIG_NAME);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&path)?;
+        let config: Config = serde_json::from_slice(&bytes).map_err(|e| Error::Config(path, e))?;
+
+        Ok(config.alias)
+    }
 }
 
 /// Get the path to the radicle home folder.
@@ -68,6 +68,8 @@ pub enum Error {
     Id(#[from] IdError),
     #[error("i/o: {0}")]
     Io(#[from] io::Error),
+    #[error("storage was opened read-only")]
+    ReadOnly,
 }
 
 impl Error {
@@ -272,6 +274,8 @@ pub trait ReadStorage {
     fn contains(&self, rid: &Id) -> Result<bool, ProjectError>;
     /// Get the inventory of repositories hosted under this storage.
     fn inventory(&self) -> Result<Inventory, Error>;
+    /// Get the total size of the storage on disk, in bytes.
+    fn size(&self) -> Result<u64, Error>;
     /// Open or create a read-only repository.
     fn repository(&self, rid: Id) -> Result<Self::Repository, Error>;
 }
@@ -388,6 +392,10 @@ where
         self.deref().inventory()
     }
 
+    fn size(&self) -> Result<u64, Error> {
+        self.deref().size()
+    }
+
     fn get(
         &self,
         remote: &RemoteId,
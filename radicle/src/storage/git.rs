@@ -92,6 +92,8 @@ impl ProjectError {
 #[derive(Debug, Clone)]
 pub struct Storage {
     path: PathBuf,
+    /// Whether write access to repositories is disallowed.
+    read_only: bool,
 }
 
 impl ReadStorage for Storage {
@@ -126,7 +128,28 @@ impl ReadStorage for Storage {
         self.repositories()
     }
 
+    fn size(&self) -> Result<u64, Error> {
+        fn dir_size(path: &Path) -> io::Result<u64> {
+            let mut size = 0;
+            for entry in fs::read_dir(path)? {
+                let entry = entry?;
+                let meta = entry.metadata()?;
+
+                if meta.is_dir() {
+                    size += dir_size(&entry.path())?;
+                } else {
+                    size += meta.len();
+                }
+            }
+            Ok(size)
+        }
+        dir_size(&self.path).map_err(Error::from)
+    }
+
     fn repository(&self, rid: Id) -> Result<Self::Repository, Error> {
+        if self.read_only {
+            return Repository::open_read_only(paths::repository(self, &rid), rid);
+        }
         Repository::open(paths::repository(self, &rid), rid)
     }
 }
@@ -135,10 +158,16 @@ impl WriteStorage for Storage {
     type RepositoryMut = Repository;
 
     fn repository_mut(&self, rid: Id) -> Result<Self::RepositoryMut, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
         Repository::open(paths::repository(self, &rid), rid)
     }
 
     fn create(&self, rid: Id) -> Result<Self::RepositoryMut, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
         Repository::create(paths::repository(self, &rid), rid)
     }
 }
@@ -154,7 +183,34 @@ impl Storage {
             Ok(()) => {}
         }
 
-        Ok(Self { path })
+        Ok(Self {
+            path,
+            read_only: false,
+        })
+    }
+
+    /// Open existing storage for reading only. Unlike [`Storage::open`], this doesn't create
+    /// the storage directory, and [`WriteStorage`] operations on the returned storage fail
+    /// with [`Error::ReadOnly`]. This is useful for inspecting storage that is being written
+    /// to by a running node.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
+        let path = path.as_ref().to_path_buf();
+
+        if !path.is_dir() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("storage not found at {}", path.display()),
+            ));
+        }
+        Ok(Self {
+            path,
+            read_only: true,
+        })
+    }
+
+    /// Whether this storage was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
     pub fn path(&self) -> &Path {
@@ -198,6 +254,8 @@ impl Storage {
 pub struct Repository {
     pub id: Id,
     pub backend: git2::Repository,
+    /// Whether [`WriteRepository`] operations are disallowed.
+    read_only: bool,
 }
 
 #[derive(Debug, Error)]
@@ -223,7 +281,28 @@ impl Repository {
     pub fn open<P: AsRef<Path>>(path: P, id: Id) -> Result<Self, Error> {
         let backend = git2::Repository::open_bare(path.as_ref())?;
 
-        Ok(Self { id, backend })
+        Ok(Self {
+            id,
+            backend,
+            read_only: false,
+        })
+    }
+
+    /// Open an existing repository for reading only. [`WriteRepository`] operations on the
+    /// returned repository fail with [`Error::ReadOnly`].
+    pub fn open_read_only<P: AsRef<Path>>(path: P, id: Id) -> Result<Self, Error> {
+        let backend = git2::Repository::open_bare(path.as_ref())?;
+
+        Ok(Self {
+            id,
+            backend,
+            read_only: true,
+        })
+    }
+
+    /// Whether this repository was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
     /// Create a new repository.
@@ -241,7 +320,11 @@ impl Repository {
         config.set_str("user.name", "radicle")?;
         config.set_str("user.email", "radicle@localhost")?;
 
-        Ok(Self { id, backend })
+        Ok(Self {
+            id,
+            backend,
+            read_only: false,
+        })
     }
 
     /// Create the repository's identity branch.
@@ -592,6 +675,9 @@ impl ReadRepository for Repository {
 
 impl WriteRepository for Repository {
     fn set_head(&self) -> Result<Oid, ProjectError> {
+        if self.read_only {
+            return Err(Error::ReadOnly.into());
+        }
         let head_ref = refname!("HEAD");
         let (branch_ref, head) = self.canonical_head()?;
 
@@ -607,6 +693,9 @@ impl WriteRepository for Repository {
     }
 
     fn sign_refs<G: Signer>(&self, signer: &G) -> Result<SignedRefs<Verified>, Error> {
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
         let remote = signer.public_key();
         let refs = self.references_of(remote)?;
         let signed = refs.signed(signer)?;
@@ -711,6 +800,42 @@ mod tests {
         assert_eq!(refs, remotes);
     }
 
+    #[test]
+    fn test_open_read_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let signer = MockSigner::default();
+        let storage = fixtures::storage(dir.path(), &signer).unwrap();
+        let inv = storage.inventory().unwrap();
+        let read_only = Storage::open_read_only(storage.path()).unwrap();
+
+        assert!(read_only.is_read_only());
+        assert_eq!(read_only.inventory().unwrap(), inv);
+
+        for rid in &inv {
+            // Writes are still possible through the original handle.
+            assert!(storage.repository_mut(*rid).is_ok());
+
+            let repo = read_only.repository(*rid).unwrap();
+            assert!(repo.is_read_only());
+            assert!(repo.head().is_ok());
+            assert!(matches!(repo.sign_refs(&signer), Err(Error::ReadOnly)));
+            assert!(matches!(
+                repo.set_head(),
+                Err(ProjectError::Storage(Error::ReadOnly))
+            ));
+            assert!(read_only.get(signer.public_key(), *rid).unwrap().is_some());
+            assert!(matches!(
+                read_only.repository_mut(*rid),
+                Err(Error::ReadOnly)
+            ));
+        }
+        assert!(matches!(
+            read_only.create(arbitrary::gen(1)),
+            Err(Error::ReadOnly)
+        ));
+        assert!(Storage::open_read_only(dir.path().join("missing")).is_err());
+    }
+
     #[test]
     fn test_namespaced_references() {
         let tmp = tempfile::tempdir().unwrap();
@@ -1,5 +1,6 @@
 use std::path::Path;
 
+use crate::crypto::test::signer::MockSigner;
 use crate::crypto::{Signer, Verified};
 use crate::git;
 use crate::identity::Id;
@@ -31,6 +32,52 @@ pub fn storage<P: AsRef<Path>, G: Signer>(path: P, signer: &G) -> Result<Storage
     Ok(storage)
 }
 
+/// Create a new storage with `repos` projects, each having `remotes_per_repo` remote namespaces
+/// besides the delegate's own. Keys and project names are all derived from `seed`, so the same
+/// seed always yields the same set of delegates and remotes.
+///
+/// Returns the storage, along with each project's signers, starting with the delegate.
+pub fn network_storage<P: AsRef<Path>>(
+    path: P,
+    seed: u64,
+    repos: usize,
+    remotes_per_repo: usize,
+) -> (Storage, Vec<(Id, Vec<MockSigner>)>) {
+    let path = path.as_ref();
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let storage = Storage::open(path.join("storage")).unwrap();
+    let mut projects = Vec::with_capacity(repos);
+
+    transport::local::register(storage.clone());
+
+    for _ in 0..repos {
+        let name = std::iter::repeat_with(|| rng.alphabetic())
+            .take(8)
+            .collect::<String>();
+        let delegate = MockSigner::new(&mut rng);
+        let (repo, _) = repository(path.join("workdir").join(&name));
+        let (id, _, _) = rad::init(
+            &repo,
+            &name,
+            "A repository",
+            git::refname!("master"),
+            &delegate,
+            &storage,
+        )
+        .unwrap();
+
+        let mut signers = vec![delegate];
+        for _ in 0..remotes_per_repo {
+            let remote = MockSigner::new(&mut rng);
+
+            rad::fork(id, &remote, &storage).unwrap();
+            signers.push(remote);
+        }
+        projects.push((id, signers));
+    }
+    (storage, projects)
+}
+
 /// Create a new repository at the given path, and initialize it into a project.
 pub fn project<P: AsRef<Path>, G: Signer>(
     path: P,
@@ -127,3 +174,43 @@ pub mod gen {
         (repo, oid)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::storage::ReadStorage;
+
+    #[test]
+    fn test_network_storage() {
+        let tmp = tempfile::tempdir().unwrap();
+        let (storage, projects) = network_storage(tmp.path().join("a"), 42, 3, 2);
+
+        assert_eq!(storage.inventory().unwrap().len(), 3);
+        assert_eq!(projects.len(), 3);
+
+        for (rid, signers) in &projects {
+            let repo = storage.repository(*rid).unwrap();
+            let mut remotes = repo
+                .remote_ids()
+                .unwrap()
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap();
+            let mut expected = signers.iter().map(|s| *s.public_key()).collect::<Vec<_>>();
+
+            remotes.sort();
+            expected.sort();
+
+            assert_eq!(remotes, expected);
+        }
+
+        // The same seed yields the same keys.
+        let (_, other) = network_storage(tmp.path().join("b"), 42, 3, 2);
+        let keys = |projects: &[(Id, Vec<MockSigner>)]| {
+            projects
+                .iter()
+                .flat_map(|(_, signers)| signers.iter().map(|s| *s.public_key()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(keys(&projects), keys(&other));
+    }
+}
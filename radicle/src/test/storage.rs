@@ -13,6 +13,8 @@ pub use crate::storage::*;
 pub struct MockStorage {
     pub path: PathBuf,
     pub inventory: HashMap<Id, Doc<Verified>>,
+    /// Reported size of the storage, in bytes.
+    pub size: u64,
 }
 
 impl MockStorage {
@@ -20,6 +22,7 @@ impl MockStorage {
         Self {
             path: PathBuf::default(),
             inventory: inventory.into_iter().collect(),
+            size: 0,
         }
     }
 
@@ -27,6 +30,7 @@ impl MockStorage {
         Self {
             path: PathBuf::default(),
             inventory: HashMap::new(),
+            size: 0,
         }
     }
 }
@@ -54,6 +58,10 @@ impl ReadStorage for MockStorage {
         Ok(self.inventory.keys().cloned().collect::<Vec<_>>())
     }
 
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.size)
+    }
+
     fn repository(&self, _proj: Id) -> Result<Self::Repository, Error> {
         Ok(MockRepository {})
     }